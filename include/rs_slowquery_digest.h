@@ -0,0 +1,57 @@
+#ifndef RS_SLOWQUERY_DIGEST_H
+#define RS_SLOWQUERY_DIGEST_H
+
+/*
+ * C interface to rs-slowquery-digest.
+ * Build the library with: cargo build --release --features ffi
+ */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SqdParser SqdParser;
+
+/* A parsed event. String pointers stay valid until the next call on the same parser. */
+typedef struct SqdEvent {
+    double query_time;
+    double lock_time;
+    uint64_t rows_sent;
+    uint64_t rows_examined;
+    /* Microseconds since the Unix epoch, or -1 if the event has no timestamp. */
+    int64_t timestamp_us;
+    const char *user_host;
+    const char *sql_text;
+    const char *fingerprint;
+} SqdEvent;
+
+/* Creates a parser over a copy of buf. Returns NULL if buf is NULL. */
+SqdParser *sqd_parser_new(const uint8_t *buf, size_t len);
+
+/* Fills event with the next parsed event. Returns 1 on success, 0 at end of input
+ * and -1 if parsing panicked, after which the parser must only be freed. */
+int32_t sqd_parser_next(SqdParser *parser, SqdEvent *event);
+
+/* Releases a parser. */
+void sqd_parser_free(SqdParser *parser);
+
+/* Returns the fingerprint of a SQL string, or NULL on invalid UTF-8 or a panic.
+ * Free with sqd_string_free. */
+char *sqd_fingerprint(const char *sql);
+
+/* Digests a slow log buffer into a JSON array of report items (limit 0 = all),
+ * DDL statements included.
+ * Free with sqd_string_free. Returns NULL on error or a panic. */
+char *sqd_digest_json(const uint8_t *buf, size_t len, size_t limit);
+
+/* Releases a string returned by this library. */
+void sqd_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RS_SLOWQUERY_DIGEST_H */
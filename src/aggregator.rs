@@ -1,10 +1,12 @@
 use crate::parser::Query;
 use crate::fingerprint::fingerprint;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 /// Aggregated statistics for a specific query fingerprint.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryStats {
     pub count: u64,
     pub total_time: f64,
@@ -13,11 +15,88 @@ pub struct QueryStats {
     pub total_lock_time: f64,
     pub total_rows_sent: u64,
     pub total_rows_examined: u64,
-    pub example_query: String,
+    /// First statement of the fingerprint; shares its text with `worst_example_query` when they are the same.
+    pub example_query: Arc<str>,
     pub all_query_times: Vec<f64>,
     pub first_seen: Option<DateTime<Utc>>,
     pub last_seen: Option<DateTime<Utc>>,
-    pub worst_example_query: String,
+    pub worst_example_query: Arc<str>,
+    /// Distinct `User@Host` values that ran the query, interned across fingerprints.
+    #[serde(default)]
+    pub users: BTreeSet<Arc<str>>,
+    /// Distinct databases selected by `USE` statements before the query, interned across fingerprints.
+    #[serde(default)]
+    pub databases: BTreeSet<Arc<str>>,
+    /// Count and total time per input, when several inputs were digested.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<Arc<str>, SourceStats>,
+    /// Executions served from the query cache, and executions whose log said
+    /// whether they were (MariaDB's `QC_hit`).
+    #[serde(default)]
+    pub qc_hits: u64,
+    #[serde(default)]
+    pub qc_reported: u64,
+    /// Executions that ended with an error (a non-zero `Errno`), and that were killed.
+    #[serde(default)]
+    pub errors: u64,
+    #[serde(default)]
+    pub kills: u64,
+    /// Failed executions by error number.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub error_codes: BTreeMap<u32, u64>,
+    /// Totals of the further numeric headers, such as Percona's `Bytes_sent`, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, MetricStats>,
+    /// Executions with each `Yes`/`No` query plan header set, such as Percona's `Full_scan`, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plan_flags: BTreeMap<String, FlagStats>,
+}
+
+/// Total and maximum of a numeric header, over the executions that logged it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricStats {
+    pub count: u64,
+    pub total: f64,
+    pub max: f64,
+}
+
+impl MetricStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.total += value;
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &MetricStats) {
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.total / self.count.max(1) as f64
+    }
+}
+
+/// Executions that logged a `Yes`/`No` header, and how many of them said yes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlagStats {
+    pub count: u64,
+    pub set: u64,
+}
+
+impl FlagStats {
+    /// Returns the share of executions that said yes, in percent.
+    pub fn rate(&self) -> f64 {
+        self.set as f64 / self.count.max(1) as f64 * 100.0
+    }
+}
+
+/// Count and total time of a query in one input.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceStats {
+    pub count: u64,
+    pub total_time: f64,
 }
 
 impl Default for QueryStats {
@@ -30,50 +109,349 @@ impl Default for QueryStats {
             total_lock_time: 0.0,
             total_rows_sent: 0,
             total_rows_examined: 0,
-            example_query: String::new(),
+            example_query: Arc::default(),
             all_query_times: Vec::new(),
             first_seen: None,
             last_seen: None,
-            worst_example_query: String::new(),
+            worst_example_query: Arc::default(),
+            users: BTreeSet::new(),
+            databases: BTreeSet::new(),
+            sources: BTreeMap::new(),
+            qc_hits: 0,
+            qc_reported: 0,
+            errors: 0,
+            kills: 0,
+            error_codes: BTreeMap::new(),
+            metrics: BTreeMap::new(),
+            plan_flags: BTreeMap::new(),
+        }
+    }
+}
+
+impl QueryStats {
+    /// Returns the `Rows_affected` totals, over the executions that logged it.
+    pub fn rows_affected(&self) -> Option<&MetricStats> {
+        self.metrics.get("Rows_affected")
+    }
+
+    /// Returns the `Bytes_sent` totals, over the executions that logged it.
+    pub fn bytes_sent(&self) -> Option<&MetricStats> {
+        self.metrics.get("Bytes_sent")
+    }
+}
+
+/// When example statements are stored. Storing fewer trims memory on logs
+/// with many cheap fingerprints and keeps the examples representative of the
+/// slow executions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExampleThresholds {
+    /// Only executions taking at least this long, in seconds, become the example or worst example.
+    pub min_time: f64,
+    /// Fingerprints whose total time, in seconds, is below this store no examples.
+    pub floor: f64,
+}
+
+/// Shares one copy of strings that repeat across fingerprints, such as users and databases.
+#[derive(Debug, Default)]
+pub struct Interner(HashSet<Arc<str>>);
+
+impl Interner {
+    /// Returns the shared copy of `s`, adding it on first use.
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(shared) = self.0.get(s) {
+            return shared.clone();
         }
+        let shared: Arc<str> = Arc::from(s);
+        self.0.insert(shared.clone());
+        shared
+    }
+
+    /// Estimates the memory used by the interned strings, in bytes.
+    pub fn estimated_size(&self) -> usize {
+        self.0.iter().map(|s| s.len() + 2 * std::mem::size_of::<usize>() + std::mem::size_of::<Arc<str>>()).sum()
     }
 }
 
 /// Aggregates a stream of parsed queries into statistics grouped by fingerprint.
 pub fn aggregate(queries: impl Iterator<Item = anyhow::Result<Query>>) -> HashMap<String, QueryStats> {
     let mut stats_map: HashMap<String, QueryStats> = HashMap::new();
+    let mut interner = Interner::default();
 
     for query in queries.flatten() {
         let fp = fingerprint(&query.sql_text);
-        let stats = stats_map.entry(fp).or_default();
+        add_interned_query(&mut stats_map, &mut interner, fp, query, &ExampleThresholds::default());
+    }
+
+    stats_map
+}
+
+/// Folds a single query into the statistics stored under `key`.
+///
+/// Users and databases are not shared with other fingerprints; use
+/// [`add_interned_query`] when aggregating many queries.
+pub fn add_query(stats_map: &mut HashMap<String, QueryStats>, key: String, query: Query) {
+    add_interned_query(stats_map, &mut Interner::default(), key, query, &ExampleThresholds::default())
+}
+
+/// Folds a single query into the statistics stored under `key`, sharing user
+/// and database strings through `interner` and storing examples as `examples` allows.
+pub fn add_interned_query(stats_map: &mut HashMap<String, QueryStats>, interner: &mut Interner, key: String, query: Query, examples: &ExampleThresholds) {
+    let stats = stats_map.entry(key).or_default();
+
+    stats.count += 1;
+    stats.total_time += query.query_time;
+    if query.query_time < stats.min_time {
+        stats.min_time = query.query_time;
+    }
+    let is_worst = query.query_time > stats.max_time;
+    if is_worst {
+        stats.max_time = query.query_time;
+    }
+    stats.total_lock_time += query.lock_time;
+    stats.total_rows_sent += query.rows_sent;
+    stats.total_rows_examined += query.rows_examined;
+    stats.all_query_times.push(query.query_time);
+    if let Some(hit) = query.qc_hit {
+        stats.qc_hits += hit as u64;
+        stats.qc_reported += 1;
+    }
+    if let Some(errno) = query.errno.filter(|errno| *errno != 0) {
+        stats.errors += 1;
+        *stats.error_codes.entry(errno).or_default() += 1;
+    }
+    if query.killed.is_some_and(|killed| killed != 0) {
+        stats.kills += 1;
+    }
+    for (name, value) in &query.metrics {
+        // Look up before inserting so the name is only copied once per fingerprint
+        let metric = match stats.metrics.get_mut(name) {
+            Some(metric) => metric,
+            None => stats.metrics.entry(name.clone()).or_default(),
+        };
+        metric.add(*value);
+    }
+    for (name, set) in &query.plan_flags {
+        let flag = match stats.plan_flags.get_mut(name) {
+            Some(flag) => flag,
+            None => stats.plan_flags.entry(name.clone()).or_default(),
+        };
+        flag.count += 1;
+        flag.set += *set as u64;
+    }
 
-        stats.count += 1;
-        stats.total_time += query.query_time;
-        if query.query_time < stats.min_time {
-            stats.min_time = query.query_time;
+    if let Some(ts) = query.timestamp {
+        if stats.first_seen.is_none() || ts < stats.first_seen.unwrap() {
+            stats.first_seen = Some(ts);
         }
-        if query.query_time > stats.max_time {
-            stats.max_time = query.query_time;
-            stats.worst_example_query = query.sql_text.clone();
+        if stats.last_seen.is_none() || ts > stats.last_seen.unwrap() {
+            stats.last_seen = Some(ts);
         }
-        stats.total_lock_time += query.lock_time;
-        stats.total_rows_sent += query.rows_sent;
-        stats.total_rows_examined += query.rows_examined;
-        stats.all_query_times.push(query.query_time);
-
-        if let Some(ts) = query.timestamp {
-            if stats.first_seen.is_none() || ts < stats.first_seen.unwrap() {
-                stats.first_seen = Some(ts);
-            }
-            if stats.last_seen.is_none() || ts > stats.last_seen.unwrap() {
-                stats.last_seen = Some(ts);
-            }
+    }
+
+    if let Some(db) = query.database() {
+        if !stats.databases.contains(db) {
+            stats.databases.insert(interner.intern(db));
         }
+    }
+    if !stats.users.contains(query.user_host.as_str()) {
+        stats.users.insert(interner.intern(&query.user_host));
+    }
 
-        if stats.example_query.is_empty() {
-            stats.example_query = query.sql_text;
+    if query.query_time < examples.min_time || stats.total_time < examples.floor {
+        return;
+    }
+    // The slowest execution may have been skipped, so the first stored one stands in for it
+    let is_worst = is_worst || stats.worst_example_query.is_empty();
+    // Most fingerprints of high-cardinality logs are seen once, so their example
+    // and worst example are the same statement: store its text once
+    let is_example = stats.example_query.is_empty();
+    if is_worst || is_example {
+        let sql: Arc<str> = Arc::from(query.sql_text);
+        if is_worst {
+            stats.worst_example_query = sql.clone();
+        }
+        if is_example {
+            stats.example_query = sql;
         }
     }
+}
 
-    stats_map
+/// Merges the statistics of `other` into `into`, as if both had been aggregated together.
+pub fn merge_stats(into: &mut HashMap<String, QueryStats>, other: HashMap<String, QueryStats>) {
+    for (key, theirs) in other {
+        merge_entry(into, key, theirs);
+    }
+}
+
+/// Merges the statistics of a single key into `into`.
+pub fn merge_entry(into: &mut HashMap<String, QueryStats>, key: String, theirs: QueryStats) {
+    let ours = into.entry(key).or_default();
+    ours.count += theirs.count;
+    ours.total_time += theirs.total_time;
+    ours.min_time = ours.min_time.min(theirs.min_time);
+    // Either side may have skipped storing examples (see `ExampleThresholds`)
+    if !theirs.worst_example_query.is_empty() && (theirs.max_time > ours.max_time || ours.worst_example_query.is_empty()) {
+        ours.worst_example_query = theirs.worst_example_query;
+    }
+    ours.max_time = ours.max_time.max(theirs.max_time);
+    ours.total_lock_time += theirs.total_lock_time;
+    ours.total_rows_sent += theirs.total_rows_sent;
+    ours.total_rows_examined += theirs.total_rows_examined;
+    ours.all_query_times.extend(theirs.all_query_times);
+    ours.qc_hits += theirs.qc_hits;
+    ours.qc_reported += theirs.qc_reported;
+    ours.errors += theirs.errors;
+    ours.kills += theirs.kills;
+    for (errno, count) in &theirs.error_codes {
+        *ours.error_codes.entry(*errno).or_default() += count;
+    }
+    for (name, metric) in &theirs.metrics {
+        ours.metrics.entry(name.clone()).or_default().merge(metric);
+    }
+    for (name, flag) in &theirs.plan_flags {
+        let ours = ours.plan_flags.entry(name.clone()).or_default();
+        ours.count += flag.count;
+        ours.set += flag.set;
+    }
+
+    ours.first_seen = match (ours.first_seen, theirs.first_seen) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    ours.last_seen = match (ours.last_seen, theirs.last_seen) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    };
+
+    ours.users.extend(theirs.users);
+    ours.databases.extend(theirs.databases);
+    for (source, stats) in theirs.sources {
+        let entry = ours.sources.entry(source).or_default();
+        entry.count += stats.count;
+        entry.total_time += stats.total_time;
+    }
+
+    if ours.example_query.is_empty() {
+        ours.example_query = theirs.example_query;
+    }
+}
+
+/// Estimates the heap and inline memory used by one map entry, in bytes.
+pub fn estimated_size(key: &str, stats: &QueryStats) -> usize {
+    // Interned users and databases are counted by `Interner::estimated_size`
+    let shared = |set: &BTreeSet<Arc<str>>| set.len() * (std::mem::size_of::<Arc<str>>() + 32);
+    let worst = if Arc::ptr_eq(&stats.example_query, &stats.worst_example_query) { 0 } else { stats.worst_example_query.len() };
+    std::mem::size_of::<(String, QueryStats)>()
+        + key.len()
+        + stats.example_query.len()
+        + worst
+        + stats.all_query_times.capacity() * std::mem::size_of::<f64>()
+        + shared(&stats.users)
+        + shared(&stats.databases)
+        + stats.sources.len() * (std::mem::size_of::<(Arc<str>, SourceStats)>() + 32)
+        + stats.metrics.keys().map(|name| name.len() + std::mem::size_of::<(String, MetricStats)>() + 32).sum::<usize>()
+        + stats.plan_flags.keys().map(|name| name.len() + std::mem::size_of::<(String, FlagStats)>() + 32).sum::<usize>()
+        + stats.error_codes.len() * (std::mem::size_of::<(u32, u64)>() + 32)
+}
+
+/// Reduces the recorded query times to at most `cap` values spread evenly over
+/// their sorted order, so percentiles stay approximately the same.
+pub fn thin_query_times(stats: &mut QueryStats, cap: usize) {
+    let times = &mut stats.all_query_times;
+    if times.len() <= cap || cap == 0 {
+        return;
+    }
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let step = times.len() as f64 / cap as f64;
+    let thinned: Vec<f64> = (0..cap).map(|i| times[((i as f64 + 0.5) * step) as usize]).collect();
+    *times = thinned;
+}
+
+/// Returns the overall event count and total query time across all fingerprints.
+pub fn totals(stats: &HashMap<String, QueryStats>) -> (u64, f64) {
+    stats.values().fold((0, 0.0), |(count, time), s| (count + s.count, time + s.total_time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(sql: &str, query_time: f64) -> Query {
+        Query {
+            query_time,
+            lock_time: 0.0,
+            rows_sent: 1,
+            rows_examined: 10,
+            timestamp: None,
+            user_host: String::new(),
+            sql_text: sql.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_thin_query_times_keeps_percentiles() {
+        let mut stats = QueryStats { all_query_times: (1..=10_000).rev().map(|i| i as f64).collect(), ..Default::default() };
+        let before = estimated_size("k", &stats);
+        thin_query_times(&mut stats, 100);
+        stats.all_query_times.shrink_to_fit();
+
+        assert_eq!(stats.all_query_times.len(), 100);
+        assert!((crate::report::percentile(&stats.all_query_times, 0.95) - 9_500.0).abs() <= 100.0);
+        assert!(estimated_size("k", &stats) < before / 50);
+    }
+
+    #[test]
+    fn test_interned_strings_are_shared() {
+        let mut stats = HashMap::new();
+        let mut interner = Interner::default();
+        for sql in ["use shop;\nSELECT 1", "use shop;\nSELECT 2 FROM t"] {
+            let query = Query { user_host: "app[app] @ localhost []".to_string(), ..query(sql, 1.0) };
+            add_interned_query(&mut stats, &mut interner, fingerprint(sql), query, &ExampleThresholds::default());
+        }
+        let (a, b) = (&stats["select ?"], &stats["select ? from t"]);
+        assert!(Arc::ptr_eq(a.users.first().unwrap(), b.users.first().unwrap()));
+        assert!(Arc::ptr_eq(a.databases.first().unwrap(), b.databases.first().unwrap()));
+        assert!(Arc::ptr_eq(&a.example_query, &a.worst_example_query));
+    }
+
+    #[test]
+    fn test_example_thresholds() {
+        let mut stats = HashMap::new();
+        let mut interner = Interner::default();
+        let examples = ExampleThresholds { min_time: 0.5, floor: 2.0 };
+        for (sql, time) in [("SELECT 1", 1.5), ("SELECT 2", 0.2), ("SELECT 3", 0.6), ("SELECT 4", 1.0), ("SELECT 1 FROM t", 1.0)] {
+            add_interned_query(&mut stats, &mut interner, fingerprint(sql), query(sql, time), &examples);
+        }
+        // Below the floor until the third execution, which is still not the slowest
+        let ours = &stats["select ?"];
+        assert_eq!((&*ours.example_query, &*ours.worst_example_query), ("SELECT 3", "SELECT 3"));
+        assert_eq!(ours.max_time, 1.5);
+        assert!(stats["select ? from t"].example_query.is_empty());
+
+        let mut merged = HashMap::new();
+        merge_entry(&mut merged, "k".to_string(), QueryStats { max_time: 1.0, worst_example_query: Arc::from("SELECT 1"), ..Default::default() });
+        merge_entry(&mut merged, "k".to_string(), QueryStats { max_time: 2.0, ..Default::default() });
+        assert_eq!((&*merged["k"].worst_example_query, merged["k"].max_time), ("SELECT 1", 2.0));
+    }
+
+    #[test]
+    fn test_merge_stats_matches_single_aggregation() {
+        let first = vec![query("SELECT 1", 1.0), query("SELECT 2", 3.0)];
+        let second = vec![query("SELECT 3", 2.0), query("UPDATE t SET a = 1", 0.5)];
+
+        let combined = aggregate(first.iter().chain(second.iter()).cloned().map(Ok));
+        let mut merged = aggregate(first.into_iter().map(Ok));
+        merge_stats(&mut merged, aggregate(second.into_iter().map(Ok)));
+
+        let ours = &merged["select ?"];
+        let expected = &combined["select ?"];
+        assert_eq!(ours.count, expected.count);
+        assert_eq!(ours.total_time, expected.total_time);
+        assert_eq!(ours.min_time, expected.min_time);
+        assert_eq!(ours.max_time, expected.max_time);
+        assert_eq!(&*ours.worst_example_query, "SELECT 2");
+        assert_eq!(ours.total_rows_examined, 30);
+        assert_eq!(merged.len(), 2);
+    }
 }
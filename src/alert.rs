@@ -0,0 +1,283 @@
+use crate::aggregator::{self, QueryStats};
+use crate::check::{Metric, Rule, Scope, Violation};
+use crate::daemon::{format_interval, parse_interval};
+use crate::digest::DigestConfig;
+use crate::notify;
+use crate::parser::Query;
+use crate::report::{self, ReportItem};
+use crate::sink::QuerySink;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Span of arrival time grouped into one bucket of the alert window.
+const BUCKET: Duration = Duration::from_secs(10);
+
+/// Alert rules evaluated continuously by the daemon, loaded from YAML.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRules {
+    /// How often the rules are evaluated.
+    #[serde(default = "default_check_every", deserialize_with = "interval")]
+    pub check_every: Duration,
+    pub alerts: Vec<AlertRule>,
+}
+
+/// A threshold rule checked against the queries logged within a sliding window.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// Description shown when the alert fires.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub scope: Scope,
+    pub metric: Metric,
+    /// The largest allowed value (inclusive).
+    pub max: f64,
+    /// How far back the rule looks.
+    #[serde(default = "default_window", deserialize_with = "interval")]
+    pub window: Duration,
+    /// Minimum time between two alerts of the rule for the same fingerprint.
+    #[serde(default = "default_cooldown", deserialize_with = "interval")]
+    pub cooldown: Duration,
+    /// Webhook URL the alert is posted to; without one alerts are only logged.
+    pub notify: Option<String>,
+}
+
+fn default_check_every() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_cooldown() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+/// Reads an interval given in seconds or as text such as "5m".
+fn interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Interval {
+        Seconds(u64),
+        Text(String),
+    }
+    match Interval::deserialize(deserializer)? {
+        Interval::Seconds(seconds) => parse_interval(&seconds.to_string()),
+        Interval::Text(text) => parse_interval(&text),
+    }
+    .map_err(serde::de::Error::custom)
+}
+
+impl AlertRules {
+    /// Loads alert rules from a YAML file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Could not read alerts file {:?}", path))?;
+        Self::from_yaml(&text).with_context(|| format!("Invalid alerts file {:?}", path))
+    }
+
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        let rules: Self = serde_yaml::from_str(text)?;
+        for alert in &rules.alerts {
+            alert.rule().validate()?;
+        }
+        Ok(rules)
+    }
+
+    /// Returns the longest window of any rule, which is how long queries must be kept.
+    pub fn longest_window(&self) -> Duration {
+        self.alerts.iter().map(|alert| alert.window).max().unwrap_or_default()
+    }
+}
+
+impl AlertRule {
+    fn rule(&self) -> Rule {
+        Rule { name: self.name.clone(), scope: self.scope, metric: self.metric, max: self.max }
+    }
+}
+
+/// Statistics of the queries that arrived from the given instant on.
+type Bucket = (Instant, HashMap<String, QueryStats>);
+
+/// Recently logged queries, grouped into buckets by arrival time.
+///
+/// Each bucket holds per-fingerprint statistics for ten seconds of arrivals,
+/// and buckets older than the retention are dropped, so memory is bounded by
+/// the window rather than the log. It is fed as a sink of the daemon's digest
+/// while the [`Alerter`] thread reads a clone of the same buckets.
+#[derive(Clone)]
+pub struct AlertWindow {
+    buckets: Arc<Mutex<VecDeque<Bucket>>>,
+    retention: Duration,
+}
+
+impl AlertWindow {
+    /// Creates a window keeping queries for at least `retention`.
+    pub fn new(retention: Duration) -> Self {
+        Self { buckets: Arc::default(), retention }
+    }
+
+    /// Records a query that arrived at `at`, dropping buckets older than the retention.
+    pub fn record(&self, key: &str, query: &Query, at: Instant) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.back().is_none_or(|(start, _)| at.duration_since(*start) >= BUCKET) {
+            buckets.push_back((at, HashMap::new()));
+        }
+        if let Some((_, stats)) = buckets.back_mut() {
+            aggregator::add_query(stats, key.to_string(), query.clone());
+        }
+        while buckets.front().is_some_and(|(start, _)| at.duration_since(*start) > self.retention + BUCKET) {
+            buckets.pop_front();
+        }
+    }
+
+    /// Returns the merged statistics of the buckets started within `window` before `now`.
+    pub fn stats(&self, window: Duration, now: Instant) -> HashMap<String, QueryStats> {
+        let mut merged = HashMap::new();
+        for (_, stats) in self.buckets.lock().unwrap().iter().filter(|(start, _)| now.duration_since(*start) < window) {
+            aggregator::merge_stats(&mut merged, stats.clone());
+        }
+        merged
+    }
+}
+
+impl QuerySink for AlertWindow {
+    fn on_query(&mut self, key: &str, query: &Query) {
+        self.record(key, query, Instant::now());
+    }
+}
+
+/// A rule violation that is due to be reported.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub violation: Violation,
+    /// Window the violation was found in.
+    pub window: Duration,
+    /// Webhook URL to post the alert to.
+    pub notify: Option<String>,
+}
+
+impl Alert {
+    /// Returns the one-line alert message.
+    pub fn message(&self) -> String {
+        format!("Slow query alert (last {}): {}", format_interval(self.window), self.violation)
+    }
+}
+
+/// Evaluates alert rules and keeps track of their cooldowns.
+pub struct Alerter {
+    rules: AlertRules,
+    config: DigestConfig,
+    /// When each rule last fired, per fingerprint (`None` for total scope).
+    last_fired: HashMap<(usize, Option<String>), Instant>,
+}
+
+impl Alerter {
+    /// Creates an alerter ranking the windowed statistics with `config`.
+    pub fn new(rules: AlertRules, config: &DigestConfig) -> Self {
+        Self { rules, config: DigestConfig { limit: 0, ..config.clone() }, last_fired: HashMap::new() }
+    }
+
+    /// Returns the violations at `now`, leaving out those whose rule already
+    /// fired for the same fingerprint within its cooldown.
+    pub fn evaluate(&mut self, window: &AlertWindow, now: Instant) -> Vec<Alert> {
+        let mut items: BTreeMap<Duration, Vec<ReportItem>> = BTreeMap::new();
+        let mut alerts = Vec::new();
+        for (index, alert) in self.rules.alerts.iter().enumerate() {
+            let items = items.entry(alert.window).or_insert_with(|| report::prepare_report_items(window.stats(alert.window, now), &self.config));
+            for violation in alert.rule().evaluate(items) {
+                let key = (index, violation.query_id.clone());
+                if self.last_fired.get(&key).is_some_and(|fired| now.duration_since(*fired) < alert.cooldown) {
+                    continue;
+                }
+                self.last_fired.insert(key, now);
+                alerts.push(Alert { violation, window: alert.window, notify: alert.notify.clone() });
+            }
+        }
+        let alerts_config = &self.rules.alerts;
+        self.last_fired.retain(|(index, _), fired| now.duration_since(*fired) < alerts_config[*index].cooldown);
+        alerts
+    }
+}
+
+/// Logs the alert and posts it to its webhook, if it has one.
+///
+/// The payload carries the message as `text`, which Slack renders directly,
+/// and the violation details under `alert`.
+pub fn send(alert: &Alert) -> anyhow::Result<()> {
+    let message = alert.message();
+    tracing::warn!("{}", message);
+    if let Some(url) = &alert.notify {
+        let payload = serde_json::json!({ "text": message, "alert": alert.violation, "window_seconds": alert.window.as_secs() });
+        notify::post_json(url, payload)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(sql: &str, query_time: f64) -> Query {
+        Query { query_time, lock_time: 0.0, rows_sent: 1, rows_examined: 1, timestamp: None, user_host: String::new(), sql_text: sql.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_alerts_respect_window_and_cooldown() {
+        let rules = AlertRules::from_yaml(
+            r#"
+check_every: 30s
+alerts:
+  - name: Slow orders lookup
+    metric: total_time
+    max: 5
+    window: 1m
+    cooldown: 10m
+  - scope: total
+    metric: count
+    max: 2
+    window: 120
+"#,
+        )
+        .unwrap();
+        assert_eq!(rules.check_every, Duration::from_secs(30));
+        assert_eq!(rules.alerts[1].cooldown, Duration::from_secs(1800));
+        assert_eq!(rules.longest_window(), Duration::from_secs(120));
+
+        let window = AlertWindow::new(rules.longest_window());
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        window.record("select * from orders where id = ?", &query("SELECT * FROM orders WHERE id = 1", 4.0), at(0));
+        window.record("select * from orders where id = ?", &query("SELECT * FROM orders WHERE id = 2", 3.0), at(30));
+        window.record("select ?", &query("SELECT 1", 0.1), at(40));
+
+        let mut alerter = Alerter::new(rules, &DigestConfig::default());
+        let alerts = alerter.evaluate(&window, at(50));
+        assert_eq!(alerts.len(), 2);
+        assert!(alerts[0].message().starts_with("Slow query alert (last 1m): Slow orders lookup: total_time = 7 exceeds 5 ["));
+        assert_eq!(alerts[1].message(), "Slow query alert (last 2m): count <= 2: count = 3 exceeds 2");
+
+        // Still violated a minute later, but both rules are cooling down
+        window.record("select * from orders where id = ?", &query("SELECT * FROM orders WHERE id = 3", 6.0), at(100));
+        assert!(alerter.evaluate(&window, at(110)).is_empty());
+
+        // Only the latest orders query is still in the one-minute window
+        assert_eq!(window.stats(Duration::from_secs(60), at(110))["select * from orders where id = ?"].count, 1);
+        let alerts = alerter.evaluate(&window, at(40 * 60));
+        assert!(alerts.is_empty());
+        window.record("select * from orders where id = ?", &query("SELECT * FROM orders WHERE id = 4", 9.0), at(40 * 60));
+        assert_eq!(alerter.evaluate(&window, at(40 * 60 + 5)).len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_invalid_alerts() {
+        assert!(AlertRules::from_yaml("alerts:\n  - scope: total\n    metric: p95\n    max: 1\n").is_err());
+        assert!(AlertRules::from_yaml("alerts:\n  - metric: p95\n    max: 1\n    window: 5w\n").is_err());
+        assert!(AlertRules::from_yaml("alerts:\n  - metric: p95\n    max: 1\n    severity: high\n").is_err());
+    }
+}
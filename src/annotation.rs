@@ -0,0 +1,68 @@
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A labeled point in time, such as a deploy or a configuration change.
+///
+/// Annotations are marked on the HTML timeline and listed under the time
+/// range of every query logged around them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub time: DateTime<Utc>,
+    pub label: String,
+}
+
+/// Parses one annotation per line: an RFC 3339 time, then the label after a
+/// space, tab or comma. Blank lines and lines starting with `#` are skipped.
+pub fn parse(text: &str) -> anyhow::Result<Vec<Annotation>> {
+    let mut annotations = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (time, label) = line.split_once(|c: char| c == ',' || c.is_whitespace()).unwrap_or((line, ""));
+        let time = DateTime::parse_from_rfc3339(time).with_context(|| format!("Line {}: invalid time {:?}, expected RFC 3339", number + 1, time))?;
+        let label = label.trim().trim_matches('"').trim();
+        if label.is_empty() {
+            bail!("Line {}: missing label after the time", number + 1);
+        }
+        annotations.push(Annotation { time: time.with_timezone(&Utc), label: label.to_string() });
+    }
+    annotations.sort_by_key(|annotation| annotation.time);
+    Ok(annotations)
+}
+
+/// Loads an annotations file, see [`parse`].
+pub fn load(path: &Path) -> anyhow::Result<Vec<Annotation>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Could not read annotations file {:?}", path))?;
+    parse(&text).with_context(|| format!("Invalid annotations file {:?}", path))
+}
+
+/// Returns the annotations between `first` and `last`, inclusive.
+pub fn between(annotations: &[Annotation], first: DateTime<Utc>, last: DateTime<Utc>) -> impl Iterator<Item = &Annotation> {
+    annotations.iter().filter(move |annotation| first <= annotation.time && annotation.time <= last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# deploys
+2024-05-01T12:00:00Z deploy api v1.4.2
+
+2024-05-01T09:30:00+02:00,\"innodb_buffer_pool_size raised\"
+";
+        let annotations = parse(text).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0], Annotation { time: "2024-05-01T07:30:00Z".parse().unwrap(), label: "innodb_buffer_pool_size raised".to_string() });
+        assert_eq!(annotations[1].label, "deploy api v1.4.2");
+        assert_eq!(between(&annotations, "2024-05-01T08:00:00Z".parse().unwrap(), "2024-05-01T12:00:00Z".parse().unwrap()).count(), 1);
+
+        assert!(parse("2024-05-01 12:00:00 deploy").unwrap_err().to_string().starts_with("Line 1: invalid time \"2024-05-01\""));
+        assert!(parse("2024-05-01T12:00:00Z").is_err());
+    }
+}
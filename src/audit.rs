@@ -0,0 +1,439 @@
+use crate::parser::{Query, QueryReader, RejectedBlock};
+use crate::timezone::Timezone;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::OnceLock;
+
+/// How the records of an audit log are written, told apart by its first character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    /// One JSON object per record, on one line (Percona) or spread over several
+    /// lines inside an array (MySQL Enterprise).
+    Json,
+    /// `<AUDIT_RECORD>` elements, with the fields as attributes or child elements.
+    Xml,
+}
+
+/// Parses the query records of a Percona or MySQL Enterprise audit log.
+///
+/// Connect, quit and other non-query records are ignored. The audit plugins
+/// only log execution times and row counts when configured to (MySQL Enterprise
+/// `query_statistics`); records without them count as 0s.
+pub struct AuditLogParser<R> {
+    reader: R,
+    line: String,
+    record: String,
+    syntax: Option<Syntax>,
+    /// Brace depth and string state while scanning a JSON record.
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    log_timezone: Timezone,
+    lines_read: u64,
+    /// Line the record being read starts on.
+    record_line: u64,
+    rejected: Option<Vec<RejectedBlock>>,
+}
+
+impl<R: BufRead> AuditLogParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            record: String::new(),
+            syntax: None,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            bytes_read: 0,
+            skipped_blocks: 0,
+            log_timezone: Timezone::default(),
+            lines_read: 0,
+            record_line: 0,
+            rejected: None,
+        }
+    }
+
+    /// Sets the timezone that timestamps without an offset are logged in (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Reads up to the end of the next complete record and returns its text.
+    fn next_record(&mut self) -> Option<Result<String>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(n) => {
+                    self.bytes_read += n as u64;
+                    self.lines_read += 1;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+            if self.record.is_empty() {
+                self.record_line = self.lines_read;
+            }
+            let syntax = match self.syntax {
+                Some(syntax) => syntax,
+                None => match self.line.trim_start().chars().next() {
+                    Some('<') => *self.syntax.insert(Syntax::Xml),
+                    Some(_) => *self.syntax.insert(Syntax::Json),
+                    None => continue,
+                },
+            };
+            let complete = match syntax {
+                Syntax::Json => self.scan_json_line(),
+                Syntax::Xml => self.scan_xml_line(),
+            };
+            if complete {
+                return Some(Ok(std::mem::take(&mut self.record)));
+            }
+        }
+    }
+
+    /// Adds the JSON object text of the current line to the record, and returns
+    /// true once the object is closed. Text between objects (array brackets,
+    /// commas) is dropped.
+    fn scan_json_line(&mut self) -> bool {
+        let mut complete = false;
+        for c in self.line.chars() {
+            if self.depth == 0 && c != '{' {
+                continue;
+            }
+            self.record.push(c);
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' => self.depth += 1,
+                '}' => {
+                    self.depth -= 1;
+                    complete = self.depth == 0;
+                }
+                _ => {}
+            }
+        }
+        complete
+    }
+
+    /// Adds the current line to the record if it belongs to an `<AUDIT_RECORD>`,
+    /// and returns true once the record is closed.
+    fn scan_xml_line(&mut self) -> bool {
+        if self.record.is_empty() && !self.line.contains("<AUDIT_RECORD") {
+            return false;
+        }
+        self.record.push_str(&self.line);
+        let Some(start) = self.record.find("<AUDIT_RECORD") else { return false };
+        let tag = &self.record[start..];
+        match tag.find('>') {
+            Some(end) if tag[..end].ends_with('/') => true,
+            Some(_) => tag.contains("</AUDIT_RECORD>"),
+            None => false,
+        }
+    }
+
+    fn parse_record(&self, record: &str) -> Result<Option<Query>> {
+        match self.syntax {
+            Some(Syntax::Xml) => Ok(xml_query(record, self.log_timezone)),
+            _ => Ok(json_query(&serde_json::from_str(record)?, self.log_timezone)),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for AuditLogParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match self.next_record()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.parse_record(&record) {
+                Ok(Some(query)) => return Some(Ok(query)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.skipped_blocks += 1;
+                    tracing::debug!("Skipping invalid audit record: {:#}", e);
+                    if let Some(rejected) = &mut self.rejected {
+                        rejected.push(RejectedBlock { line: self.record_line, reason: format!("{:#}", e), raw: record });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> QueryReader for AuditLogParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    fn keep_rejected(&mut self) {
+        self.rejected.get_or_insert_with(Vec::new);
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.rejected.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+/// Returns the query of a JSON record: a Percona `audit_record`, or a MySQL
+/// Enterprise `general` class event.
+fn json_query(value: &Value, log_timezone: Timezone) -> Option<Query> {
+    let text = |v: &Value| v.as_str().map(str::to_string);
+    let number = |v: &Value| v.as_u64().or_else(|| v.as_str()?.parse().ok());
+
+    if let Some(record) = value.get("audit_record") {
+        if !matches!(record["name"].as_str(), Some("Query" | "Execute")) {
+            return None;
+        }
+        return build_query(
+            text(&record["sqltext"])?,
+            record["db"].as_str(),
+            text(&record["user"]).unwrap_or_default(),
+            record["timestamp"].as_str().and_then(|ts| parse_time(ts, log_timezone)),
+            number(&record["connection_id"]),
+            &HashMap::new(),
+        );
+    }
+
+    if value["class"].as_str() != Some("general") {
+        return None;
+    }
+    let general = &value["general_data"];
+    if !matches!(general["command"].as_str(), Some("Query" | "Execute")) {
+        return None;
+    }
+    let account = &value["account"];
+    let user = account["user"].as_str().unwrap_or_default();
+    let host = account["host"].as_str().unwrap_or_default();
+    let statistics = value["query_statistics"].as_object().map(|stats| {
+        stats.iter().filter_map(|(key, v)| Some((key.to_ascii_uppercase(), v.as_f64()?.to_string()))).collect()
+    });
+    build_query(
+        text(&general["query"])?,
+        None,
+        format!("{}[{}] @ {} []", user, user, host),
+        value["timestamp"].as_str().and_then(|ts| parse_time(ts, log_timezone)),
+        number(&value["connection_id"]),
+        &statistics.unwrap_or_default(),
+    )
+}
+
+/// Returns the query of an XML record, with its fields given as attributes (Percona
+/// "OLD" format) or child elements (Percona "NEW" and MySQL Enterprise formats).
+fn xml_query(record: &str, log_timezone: Timezone) -> Option<Query> {
+    static RE_ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    static RE_ELEMENT: OnceLock<Regex> = OnceLock::new();
+    let attribute = RE_ATTRIBUTE.get_or_init(|| Regex::new(r#"([A-Z_]+)="([^"]*)""#).unwrap());
+    let element = RE_ELEMENT.get_or_init(|| Regex::new(r"<([A-Z_]+)>([^<]*)</[A-Z_]+>").unwrap());
+
+    let fields: HashMap<String, String> = attribute.captures_iter(record)
+        .chain(element.captures_iter(record))
+        .map(|c| (c[1].to_string(), unescape_xml(&c[2])))
+        .collect();
+    if !matches!(fields.get("NAME").map(String::as_str), Some("Query" | "Execute")) {
+        return None;
+    }
+    build_query(
+        fields.get("SQLTEXT")?.clone(),
+        fields.get("DB").map(String::as_str),
+        fields.get("USER").cloned().unwrap_or_default(),
+        fields.get("TIMESTAMP").and_then(|ts| parse_time(ts, log_timezone)),
+        fields.get("CONNECTION_ID").and_then(|id| id.parse().ok()),
+        &fields,
+    )
+}
+
+/// Builds a query, prefixing the statement with `USE <db>;` so the database is
+/// reported as it is for slow logs. `statistics` may hold `QUERY_TIME`,
+/// `LOCK_TIME`, `ROWS_SENT` and `ROWS_EXAMINED`.
+pub(crate) fn build_query(sql: String, db: Option<&str>, user_host: String, timestamp: Option<DateTime<Utc>>, thread_id: Option<u64>, statistics: &HashMap<String, String>) -> Option<Query> {
+    let sql = sql.trim();
+    if sql.is_empty() {
+        return None;
+    }
+    let sql_text = match db.filter(|db| !db.is_empty()) {
+        Some(db) => format!("use {};\n{}", db, sql),
+        None => sql.to_string(),
+    };
+    let stat = |key: &str| statistics.get(key).and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.0);
+    Some(Query {
+        query_time: stat("QUERY_TIME"),
+        lock_time: stat("LOCK_TIME"),
+        rows_sent: stat("ROWS_SENT") as u64,
+        rows_examined: stat("ROWS_EXAMINED") as u64,
+        timestamp,
+        user_host,
+        sql_text,
+        thread_id,
+        ..Default::default()
+    })
+}
+
+/// Parses audit timestamps such as `2024-05-01T10:00:00 UTC`,
+/// `2024-05-01T10:00:00Z` or `2024-05-01 10:00:00` (in the log timezone).
+pub(crate) fn parse_time(s: &str, log_timezone: Timezone) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    let (s, utc) = match s.strip_suffix(" UTC") {
+        Some(s) => (s, true),
+        None => (s, false),
+    };
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .ok()?;
+    Some(if utc { naive.and_utc() } else { log_timezone.to_utc(&naive) })
+}
+
+/// Replaces the predefined XML entities and character references.
+fn unescape_xml(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let Some(end) = rest.find(';') else { break };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| char::from_u32(code.ok()?)),
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(log: &str) -> Vec<Query> {
+        AuditLogParser::new(log.as_bytes()).flatten().collect()
+    }
+
+    #[test]
+    fn test_percona_json() {
+        let log = r#"{"audit_record":{"name":"Connect","record":"1_2024-05-01T10:00:00","timestamp":"2024-05-01T10:00:00 UTC","connection_id":"7","status":0,"user":"app","host":"localhost","db":"shop"}}
+{"audit_record":{"name":"Query","record":"2_2024-05-01T10:00:01","timestamp":"2024-05-01T10:00:01 UTC","command_class":"select","connection_id":"7","status":0,"sqltext":"SELECT * FROM orders WHERE note = \"a}b\"","user":"app[app] @ localhost []","host":"localhost","db":"shop"}}
+"#;
+        let queries = parse(log);
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].sql_text, "use shop;\nSELECT * FROM orders WHERE note = \"a}b\"");
+        assert_eq!(queries[0].database(), Some("shop"));
+        assert_eq!(queries[0].user_host, "app[app] @ localhost []");
+        assert_eq!(queries[0].thread_id, Some(7));
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T10:00:01Z".parse().unwrap()));
+        assert_eq!(queries[0].query_time, 0.0);
+    }
+
+    #[test]
+    fn test_enterprise_json_with_statistics() {
+        let log = r#"[
+  {
+    "timestamp": "2024-05-01 10:00:02",
+    "id": 1,
+    "class": "general",
+    "event": "status",
+    "connection_id": 11,
+    "account": { "user": "report", "host": "10.0.0.5" },
+    "general_data": { "command": "Query", "sql_command": "select", "query": "SELECT COUNT(*) FROM orders", "status": 0 },
+    "query_statistics": { "query_time": 1.25, "bytes_sent": 120, "rows_sent": 1, "rows_examined": 50000 }
+  },
+  {
+    "timestamp": "2024-05-01 10:00:03",
+    "id": 2,
+    "class": "connection",
+    "event": "disconnect",
+    "connection_id": 11
+  }
+]
+"#;
+        let mut parser = AuditLogParser::new(log.as_bytes());
+        let queries: Vec<Query> = parser.by_ref().flatten().collect();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].sql_text, "SELECT COUNT(*) FROM orders");
+        assert_eq!(queries[0].query_time, 1.25);
+        assert_eq!(queries[0].rows_examined, 50000);
+        assert_eq!(queries[0].user_host, "report[report] @ 10.0.0.5 []");
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T10:00:02Z".parse().unwrap()));
+        assert_eq!(parser.bytes_read(), log.len() as u64);
+        assert_eq!(parser.skipped_blocks(), 0);
+    }
+
+    #[test]
+    fn test_xml_formats() {
+        let log = r#"<?xml version="1.0" encoding="UTF-8"?>
+<AUDIT>
+<AUDIT_RECORD
+  NAME="Query"
+  RECORD="3_2024-05-01T10:00:04"
+  TIMESTAMP="2024-05-01T10:00:04 UTC"
+  COMMAND_CLASS="select"
+  CONNECTION_ID="8"
+  STATUS="0"
+  SQLTEXT="SELECT * FROM t WHERE a &gt; 1 AND b = &apos;x&apos;"
+  USER="app[app] @ localhost []"
+  HOST="localhost"
+  DB=""
+/>
+<AUDIT_RECORD>
+  <NAME>Query</NAME>
+  <TIMESTAMP>2024-05-01T10:00:05 UTC</TIMESTAMP>
+  <CONNECTION_ID>9</CONNECTION_ID>
+  <SQLTEXT>UPDATE t SET a = 2&#10;WHERE id = 3</SQLTEXT>
+  <USER>app[app] @ localhost []</USER>
+  <DB>shop</DB>
+</AUDIT_RECORD>
+<AUDIT_RECORD NAME="Quit" CONNECTION_ID="9"/>
+</AUDIT>
+"#;
+        let queries = parse(log);
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].sql_text, "SELECT * FROM t WHERE a > 1 AND b = 'x'");
+        assert_eq!(queries[0].thread_id, Some(8));
+        assert_eq!(queries[1].sql_text, "use shop;\nUPDATE t SET a = 2\nWHERE id = 3");
+        assert_eq!(queries[1].timestamp, Some("2024-05-01T10:00:05Z".parse().unwrap()));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::report::ReportItem;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// A set of threshold rules, loaded from YAML (or TOML, by extension).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+/// An upper limit on one metric, applied to every fingerprint or to the run total.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Description shown when the rule is violated.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub scope: Scope,
+    pub metric: Metric,
+    /// The largest allowed value (inclusive).
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    #[default]
+    Fingerprint,
+    Total,
+}
+
+/// Metrics a rule can limit. Times are in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Count,
+    TotalTime,
+    MeanTime,
+    P95,
+    P99,
+    LockTime,
+    RowsSent,
+    RowsExamined,
+    /// Rows examined per row sent.
+    Ratio,
+    /// Number of distinct fingerprints (total scope only).
+    Fingerprints,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Count => "count",
+            Metric::TotalTime => "total_time",
+            Metric::MeanTime => "mean_time",
+            Metric::P95 => "p95",
+            Metric::P99 => "p99",
+            Metric::LockTime => "lock_time",
+            Metric::RowsSent => "rows_sent",
+            Metric::RowsExamined => "rows_examined",
+            Metric::Ratio => "ratio",
+            Metric::Fingerprints => "fingerprints",
+        }
+    }
+
+    fn of_item(self, item: &ReportItem) -> Option<f64> {
+        Some(match self {
+            Metric::Count => item.count as f64,
+            Metric::TotalTime => item.total_time,
+            Metric::MeanTime => item.mean_time,
+            Metric::P95 => item.p95,
+            Metric::P99 => item.p99,
+            Metric::LockTime => item.total_lock_time,
+            Metric::RowsSent => item.rows_sent as f64,
+            Metric::RowsExamined => item.rows_examined as f64,
+            Metric::Ratio => item.ratio,
+            Metric::Fingerprints => return None,
+        })
+    }
+
+    fn of_total(self, items: &[ReportItem]) -> Option<f64> {
+        Some(match self {
+            Metric::Count => items.iter().map(|i| i.count as f64).sum(),
+            Metric::TotalTime => items.iter().map(|i| i.total_time).sum(),
+            Metric::LockTime => items.iter().map(|i| i.total_lock_time).sum(),
+            Metric::RowsSent => items.iter().map(|i| i.rows_sent as f64).sum(),
+            Metric::RowsExamined => items.iter().map(|i| i.rows_examined as f64).sum(),
+            Metric::Fingerprints => items.len() as f64,
+            Metric::MeanTime | Metric::P95 | Metric::P99 | Metric::Ratio => return None,
+        })
+    }
+}
+
+/// A rule that was exceeded, by the run total or by one fingerprint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub rule: String,
+    pub metric: &'static str,
+    pub value: f64,
+    pub max: f64,
+    pub query_id: Option<String>,
+    pub fingerprint: Option<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} = {} exceeds {}", self.rule, self.metric, format_value(self.value), format_value(self.max))?;
+        if let (Some(id), Some(fingerprint)) = (&self.query_id, &self.fingerprint) {
+            let mut query: String = fingerprint.chars().take(60).collect();
+            if query.len() < fingerprint.len() {
+                query.push_str("...");
+            }
+            write!(f, " [{}] {}", id, query)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 { format!("{}", value) } else { format!("{:.3}", value) }
+}
+
+impl RuleSet {
+    /// Loads rules from a `.toml` file, or YAML for any other extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Could not read rules file {:?}", path))?;
+        let rules = if path.extension().is_some_and(|e| e == "toml") {
+            Self::from_toml(&text)
+        } else {
+            Self::from_yaml(&text)
+        };
+        rules.with_context(|| format!("Invalid rules file {:?}", path))
+    }
+
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        Self::validate(serde_yaml::from_str(text)?)
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Self::validate(toml::from_str(text)?)
+    }
+
+    fn validate(rules: Self) -> anyhow::Result<Self> {
+        for rule in &rules.rules {
+            rule.validate()?;
+        }
+        Ok(rules)
+    }
+
+    /// Checks every rule against the report items, which must cover all fingerprints.
+    pub fn evaluate(&self, items: &[ReportItem]) -> Vec<Violation> {
+        self.rules.iter().flat_map(|rule| rule.evaluate(items)).collect()
+    }
+}
+
+impl Rule {
+    /// Fails if the metric cannot be used with the rule's scope.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let applies = match self.scope {
+            Scope::Fingerprint => self.metric != Metric::Fingerprints,
+            Scope::Total => !matches!(self.metric, Metric::MeanTime | Metric::P95 | Metric::P99 | Metric::Ratio),
+        };
+        if !applies {
+            anyhow::bail!("Metric '{}' cannot be used with scope '{:?}'", self.metric.name(), self.scope);
+        }
+        Ok(())
+    }
+
+    /// Checks the rule against the report items, which must cover all fingerprints.
+    pub fn evaluate(&self, items: &[ReportItem]) -> Vec<Violation> {
+        let name = self.name.clone().unwrap_or_else(|| format!("{} <= {}", self.metric.name(), format_value(self.max)));
+        let violation = |value: f64, item: Option<&ReportItem>| Violation {
+            rule: name.clone(),
+            metric: self.metric.name(),
+            value,
+            max: self.max,
+            query_id: item.map(|i| i.query_id.clone()),
+            fingerprint: item.map(|i| i.normalized_query.clone()),
+        };
+        match self.scope {
+            Scope::Total => self.metric.of_total(items).filter(|v| *v > self.max).map(|value| violation(value, None)).into_iter().collect(),
+            Scope::Fingerprint => items.iter()
+                .filter_map(|item| self.metric.of_item(item).filter(|v| *v > self.max).map(|value| violation(value, Some(item))))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, count: u64, total_time: f64, p95: f64) -> ReportItem {
+        ReportItem {
+            rank: 1,
+            query_id: id.to_string(),
+            count,
+            total_time,
+            mean_time: total_time / count as f64,
+            p95,
+            p99: p95,
+            percentiles: Vec::new(),
+            total_lock_time: 0.0,
+            mean_lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            ratio: 0.0,
+            score: 0.0,
+            badges: Vec::new(),
+            sources: Default::default(),
+            time_range: "N/A".to_string(),
+            example_query: String::new(),
+            worst_example_query: String::new(),
+            normalized_query: format!("select {}", id),
+            users: Vec::new(),
+            databases: Vec::new(),
+            explain: None,
+            explain_analyze: None,
+            first_seen: None,
+            last_seen: None,
+            review: None,
+            triage: None,
+            server: None,
+            query_cache: None,
+            errors: None,
+            metrics: Default::default(),
+            plan_flags: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_rules() {
+        let rules = RuleSet::from_yaml(
+            r#"
+rules:
+  - name: No fingerprint with p95 over 2s
+    metric: p95
+    max: 2
+  - scope: total
+    metric: total_time
+    max: 300
+  - scope: total
+    metric: fingerprints
+    max: 10
+"#,
+        )
+        .unwrap();
+        let items = vec![item("a", 100, 250.0, 3.5), item("b", 10, 60.0, 1.0)];
+        let violations = rules.evaluate(&items);
+
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].rule, "No fingerprint with p95 over 2s");
+        assert_eq!(violations[0].query_id.as_deref(), Some("a"));
+        assert_eq!(violations[1].to_string(), "total_time <= 300: total_time = 310 exceeds 300");
+    }
+
+    #[test]
+    fn test_rejects_invalid_rules() {
+        assert!(RuleSet::from_yaml("rules:\n  - metric: fingerprints\n    max: 1\n").is_err());
+        assert!(RuleSet::from_yaml("rules:\n  - scope: total\n    metric: p95\n    max: 1\n").is_err());
+        assert!(RuleSet::from_yaml("rules:\n  - metric: p50\n    max: 1\n").is_err());
+        assert!(RuleSet::from_toml("[[rules]]\nmetric = \"count\"\nmax = 5\n").is_ok());
+    }
+}
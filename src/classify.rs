@@ -0,0 +1,104 @@
+use crate::fingerprint::{comment_regex, string_regex};
+use regex::Regex;
+use std::sync::OnceLock;
+
+static RE_VERB: OnceLock<Regex> = OnceLock::new();
+static RE_CTE_VERB: OnceLock<Regex> = OnceLock::new();
+static RE_TABLE_KEYWORD: OnceLock<Regex> = OnceLock::new();
+static RE_IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+static RE_ALIAS: OnceLock<Regex> = OnceLock::new();
+
+/// Words that can follow `FROM`, `INTO` and the like without being a table or alias.
+const NOT_TABLES: &[&str] = &[
+    "select", "set", "values", "value", "where", "dual", "lateral", "outfile", "dumpfile", "join", "inner", "outer", "left", "right",
+    "cross", "natural", "straight_join", "on", "using", "group", "order", "limit", "having", "window", "union", "for", "lock",
+    "partition", "force", "use", "ignore", "into", "as", "with", "if", "exists", "not",
+];
+
+/// Returns the verb of a statement in upper case (`SELECT`, `INSERT`, `UPDATE`, ...),
+/// skipping leading comments, `USE`/`SET` statements and parentheses.
+///
+/// A `WITH` query counts as the statement its common table expressions feed,
+/// `SELECT` unless it modifies data. Returns `OTHER` if no keyword is found.
+pub fn verb(sql: &str) -> String {
+    let re_verb = RE_VERB.get_or_init(|| {
+        Regex::new(r"(?is)^\s*(?:(?:use|set)\s[^;]*;\s*|/\*.*?\*/\s*|(?:--|#)[^\n]*\n\s*|\(\s*)*([a-z_]+)").unwrap()
+    });
+    let re_cte_verb = RE_CTE_VERB.get_or_init(|| Regex::new(r"(?i)\b(insert|replace|update|delete)\b").unwrap());
+    let Some(verb) = re_verb.captures(sql).and_then(|c| c.get(1)) else {
+        return "OTHER".to_string();
+    };
+    let verb = verb.as_str().to_ascii_uppercase();
+    if verb != "WITH" {
+        return verb;
+    }
+    let sql = string_regex().replace_all(sql, "?");
+    re_cte_verb.captures(&sql).and_then(|c| c.get(1)).map_or_else(|| "SELECT".to_string(), |m| m.as_str().to_ascii_uppercase())
+}
+
+/// Returns the tables a statement reads or writes, in order of appearance and
+/// without duplicates, lowercased and without identifier quotes (`db.table`
+/// keeps its database).
+///
+/// Tables are the names after `FROM` (including comma-separated lists),
+/// `JOIN`, `INTO`, `UPDATE` and `TABLE`; derived tables are left out.
+pub fn tables(sql: &str) -> Vec<String> {
+    let re_keyword = RE_TABLE_KEYWORD.get_or_init(|| Regex::new(r"(?i)\b(from|join|into|update|table|tables)\s+").unwrap());
+    let re_identifier = RE_IDENTIFIER.get_or_init(|| Regex::new(r"^(?:`[^`]+`|[\w$]+)(?:\s*\.\s*(?:`[^`]+`|[\w$]+))?").unwrap());
+    let re_alias = RE_ALIAS.get_or_init(|| Regex::new(r"(?i)^\s+(?:as\s+)?(`[^`]+`|\w+)").unwrap());
+
+    let sql = comment_regex().replace_all(sql, " ");
+    let sql = string_regex().replace_all(&sql, "?");
+    let mut tables = Vec::new();
+    for keyword in re_keyword.captures_iter(&sql) {
+        let list = matches!(keyword[1].to_ascii_lowercase().as_str(), "from" | "update" | "tables");
+        let mut rest = &sql[keyword.get(0).unwrap().end()..];
+        while let Some(identifier) = re_identifier.find(rest) {
+            let name: String = identifier.as_str().chars().filter(|c| *c != '`' && !c.is_whitespace()).collect::<String>().to_lowercase();
+            if NOT_TABLES.contains(&name.as_str()) {
+                break;
+            }
+            if !tables.contains(&name) {
+                tables.push(name);
+            }
+            rest = &rest[identifier.end()..];
+            if !list {
+                break;
+            }
+            if let Some(alias) = re_alias.captures(rest) {
+                if !NOT_TABLES.contains(&alias[1].to_ascii_lowercase().as_str()) {
+                    rest = &rest[alias.get(0).unwrap().end()..];
+                }
+            }
+            match rest.trim_start().strip_prefix(',') {
+                Some(next) => rest = next.trim_start(),
+                None => break,
+            }
+        }
+    }
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verb() {
+        assert_eq!(verb("select * from t"), "SELECT");
+        assert_eq!(verb("USE shop;\n/* app */ (SELECT 1) UNION (SELECT 2)"), "SELECT");
+        assert_eq!(verb("WITH x AS (SELECT 1) DELETE FROM t WHERE id IN (SELECT * FROM x)"), "DELETE");
+        assert_eq!(verb("WITH x AS (SELECT 'update') SELECT * FROM x"), "SELECT");
+        assert_eq!(verb(""), "OTHER");
+    }
+
+    #[test]
+    fn test_tables() {
+        assert_eq!(tables("SELECT * FROM `shop`.`orders` o JOIN customers AS c ON c.id = o.customer_id"), vec!["shop.orders", "customers"]);
+        assert_eq!(tables("select * from a, b x, c where a.id = x.id"), vec!["a", "b", "c"]);
+        assert_eq!(tables("INSERT INTO audit (a) SELECT a FROM (SELECT a FROM staging) s"), vec!["audit", "staging"]);
+        assert_eq!(tables("UPDATE t SET a = 'from x' WHERE id = ?"), vec!["t"]);
+        assert_eq!(tables("SELECT 1 FROM DUAL"), Vec::<String>::new());
+        assert_eq!(tables("alter table Users add column x int"), vec!["users"]);
+    }
+}
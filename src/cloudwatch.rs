@@ -0,0 +1,201 @@
+use crate::parser::{LogParser, Query, QueryReader, RejectedBlock};
+use crate::timezone::Timezone;
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Parses slow logs exported from CloudWatch Logs, as RDS and Aurora publish
+/// them: JSON log events whose `message` holds a slow log block.
+///
+/// The events can be one JSON object per line, a JSON array of them, the
+/// output of `aws logs get-log-events` or `filter-log-events` (an object with
+/// an `events` array), or a subscription payload (with `logEvents`). The
+/// messages are joined in order and parsed as one slow log, so a block split
+/// over several events is put back together.
+pub struct CloudWatchParser<R: BufRead> {
+    parser: LogParser<MessageReader<R>>,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: BufRead> CloudWatchParser<R> {
+    pub fn new(reader: R) -> Self {
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let reader = Counting { inner: reader, bytes_read: bytes_read.clone() };
+        let messages = MessageReader {
+            events: serde_json::Deserializer::from_reader(reader).into_iter(),
+            messages: VecDeque::new(),
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        };
+        Self { parser: LogParser::new(messages), bytes_read }
+    }
+
+    /// Sets the timezone of log timestamps without an offset (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.parser = self.parser.with_log_timezone(timezone);
+        self
+    }
+}
+
+impl<R: BufRead> Iterator for CloudWatchParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next()
+    }
+}
+
+impl<R: BufRead> QueryReader for CloudWatchParser<R> {
+    /// Returns the bytes of JSON read so far, rather than of the messages in it.
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.parser.skipped_blocks()
+    }
+
+    fn noise_lines(&self) -> u64 {
+        self.parser.noise_lines()
+    }
+
+    fn keep_rejected(&mut self) {
+        self.parser.keep_rejected();
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.parser.take_rejected()
+    }
+}
+
+/// Counts the bytes read through it.
+struct Counting<R> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Reads the messages of a stream of log events as text, one after another.
+struct MessageReader<R: Read> {
+    events: serde_json::StreamDeserializer<'static, serde_json::de::IoRead<Counting<R>>, Value>,
+    /// Messages of the last JSON value not read yet.
+    messages: VecDeque<String>,
+    /// The message being read.
+    buf: Vec<u8>,
+    pos: usize,
+    /// Set once the JSON ended or turned out invalid.
+    done: bool,
+}
+
+impl<R: Read> MessageReader<R> {
+    fn next_message(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(message) = self.messages.pop_front() {
+                return Ok(Some(message));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.events.next() {
+                Some(Ok(value)) => self.queue(value),
+                Some(Err(e)) => {
+                    // The deserializer cannot resume after an error
+                    self.done = true;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid CloudWatch JSON: {}", e)));
+                }
+                None => self.done = true,
+            }
+        }
+    }
+
+    /// Queues the messages of an event, or of the events a value holds.
+    fn queue(&mut self, value: Value) {
+        match value {
+            Value::Array(events) => events.into_iter().for_each(|event| self.queue(event)),
+            Value::Object(mut object) => match object.remove("message") {
+                Some(Value::String(message)) => self.messages.push_back(message),
+                _ => {
+                    if let Some(events) = object.remove("events").or_else(|| object.remove("logEvents")) {
+                        self.queue(events);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+impl<R: Read> Read for MessageReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for MessageReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.buf.len() {
+            let Some(message) = self.next_message()? else { break };
+            self.buf = message.into_bytes();
+            if !self.buf.ends_with(b"\n") {
+                self.buf.push(b'\n');
+            }
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos.min(self.buf.len())..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos += amount;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // One event per line, with a block split over two events
+        let log = r##"{"timestamp":1714557600000,"message":"# Time: 2024-05-01T10:00:00.000000Z\n# User@Host: app[app] @ [10.0.0.5]  Id: 42\n# Query_time: 1.5  Lock_time: 0.000100 Rows_sent: 1  Rows_examined: 100\nSET timestamp=1714557598;\nSELECT * FROM orders WHERE id = 1;","logStreamName":"db-1"}
+{"timestamp":1714557601000,"message":"# Time: 2024-05-01T10:00:01.000000Z\n# User@Host: app[app] @ [10.0.0.5]  Id: 42\n# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 10"}
+{"timestamp":1714557601000,"message":"SET timestamp=1714557599;\nUPDATE carts SET state = 'open'\nWHERE id = 2;"}
+"##;
+        let mut parser = CloudWatchParser::new(log.as_bytes());
+        let queries: Vec<Query> = parser.by_ref().map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 2);
+        assert_eq!((queries[0].query_time, queries[0].thread_id), (1.5, Some(42)));
+        assert_eq!(queries[0].sql_text, "SELECT * FROM orders WHERE id = 1;");
+        assert_eq!(queries[1].sql_text, "UPDATE carts SET state = 'open'\nWHERE id = 2;");
+        assert_eq!(queries[1].timestamp, Some("2024-05-01T10:00:01Z".parse().unwrap()));
+        assert_eq!(parser.bytes_read(), log.len() as u64);
+
+        // The output of `aws logs filter-log-events`
+        let log = r##"{
+    "events": [
+        {"logStreamName": "db-1", "timestamp": 1714557600000, "message": "# User@Host: app[app] @ [10.0.0.5]  Id: 42\n# Query_time: 1.5\nSELECT 1;", "eventId": "1"},
+        {"logStreamName": "db-1", "timestamp": 1714557601000, "message": "# User@Host: app[app] @ [10.0.0.5]  Id: 42\n# Query_time: 0.5\nSELECT 2;", "eventId": "2"}
+    ],
+    "searchedLogStreams": []
+}"##;
+        assert_eq!(CloudWatchParser::new(log.as_bytes()).map(Result::unwrap).count(), 2);
+
+        let mut parser = CloudWatchParser::new("# Time: 2024-05-01T10:00:00Z\n".as_bytes());
+        assert!(parser.next().unwrap().is_err());
+        assert!(parser.next().is_none());
+    }
+}
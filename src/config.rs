@@ -0,0 +1,203 @@
+use crate::digest::{GroupBy, HourWindow, TxnControl};
+use crate::host::HostMapping;
+use crate::report::{BadgeThresholds, OutputFormat, ScoreWeights, SortBy, TimeUnit};
+use crate::fingerprint::Dialect;
+use crate::parser::InputFormat;
+use crate::queue::QueueFull;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// File name looked up in the current directory when no `--config` is given.
+pub const DEFAULT_FILE_NAME: &str = "rs-slowquery-digest.toml";
+
+/// Options loaded from a TOML configuration file.
+///
+/// Every value is optional; anything given on the command line takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub input: InputSection,
+    pub report: ReportSection,
+    pub filter: FilterSection,
+    pub fingerprint: FingerprintSection,
+    pub host: HostSection,
+    pub email: EmailSection,
+    pub webhook: WebhookSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputSection {
+    pub files: Option<Vec<PathBuf>>,
+    pub log_timezone: Option<String>,
+    pub format: Option<InputFormat>,
+    pub queue_size: Option<usize>,
+    pub queue_full: Option<QueueFull>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportSection {
+    pub format: Option<OutputFormat>,
+    pub output: Option<PathBuf>,
+    pub timezone: Option<String>,
+    pub limit: Option<usize>,
+    pub detail_limit: Option<usize>,
+    pub group_by: Option<GroupBy>,
+    pub percentiles: Option<Vec<f64>>,
+    pub sort_by: Option<SortBy>,
+    pub score_weights: Option<ScoreWeights>,
+    pub time_unit: Option<TimeUnit>,
+    pub long_query_time_what_if: Option<Vec<f64>>,
+    /// Lock time, in seconds, of the waits clustered, see `--lock-clusters`.
+    pub lock_clusters: Option<f64>,
+    pub lock_cluster_min_events: Option<usize>,
+    /// Report connection churn by `User@Host`, see `--connection-stats`.
+    pub connection_stats: Option<bool>,
+    /// Report queries per second, see `--throughput`.
+    pub throughput: Option<bool>,
+    /// Executions sampled per fingerprint for the HTML CSV download, see `--execution-samples`.
+    pub execution_samples: Option<usize>,
+    pub badges: Option<BadgeThresholds>,
+    pub pager: Option<bool>,
+    /// Deploys and other events to mark in the report, see `--annotations`.
+    pub annotations: Option<PathBuf>,
+    /// Owner, ticket and status by query ID, see `--triage`.
+    pub triage: Option<PathBuf>,
+    /// Only executions at least this slow, in seconds, are stored as examples.
+    pub example_min_time: Option<f64>,
+    /// Fingerprints with less total time, in seconds, store no examples.
+    pub example_floor: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterSection {
+    pub min_query_time: Option<f64>,
+    pub user: Option<String>,
+    pub exclude_system_users: Option<bool>,
+    /// Extra user name patterns excluded along with the built-in system users.
+    pub system_users: Option<Vec<String>>,
+    pub exclude_admin_commands: Option<bool>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub between_hours: Option<HourWindow>,
+    pub include_ddl: Option<bool>,
+    pub txn_control: Option<TxnControl>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FingerprintSection {
+    pub keep_use: Option<bool>,
+    pub raw: Option<bool>,
+    pub dialect: Option<Dialect>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostSection {
+    pub strip_port: Option<bool>,
+    pub subnet: Option<bool>,
+    /// Rewrites as `<regex>=<replacement>`, the first match wins.
+    pub map: Option<Vec<HostMapping>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailSection {
+    pub to: Option<Vec<String>>,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub smtp_server: Option<String>,
+    pub sendmail_path: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSection {
+    pub url: Option<String>,
+    pub top: Option<usize>,
+    pub template: Option<String>,
+    /// Endpoint the JSON report is POSTed to (`--post-json`).
+    pub json_url: Option<String>,
+    pub json_summary: Option<bool>,
+    /// Request headers of the JSON report POST, as `"Name: value"`.
+    pub json_headers: Option<Vec<String>>,
+}
+
+/// Loads and parses a configuration file.
+pub fn load(path: &Path) -> anyhow::Result<FileConfig> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Could not read config file {:?}", path))?;
+    toml::from_str(&text).with_context(|| format!("Invalid config file {:?}", path))
+}
+
+/// Returns the first existing file on the default search path.
+///
+/// The search order is `./rs-slowquery-digest.toml`, then
+/// `$XDG_CONFIG_HOME/rs-slowquery-digest/config.toml` (falling back to
+/// `~/.config/rs-slowquery-digest/config.toml`).
+pub fn find_default() -> Option<PathBuf> {
+    let mut candidates = vec![PathBuf::from(DEFAULT_FILE_NAME)];
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    if let Some(dir) = config_home {
+        candidates.push(dir.join("rs-slowquery-digest").join("config.toml"));
+    }
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sections() {
+        let config: FileConfig = toml::from_str(r#"
+[input]
+files = ["/var/log/mysql/slow.log"]
+
+[report]
+format = "html"
+limit = 50
+group_by = "user"
+percentiles = [0.5, 0.99]
+sort_by = "score"
+score_weights = "time=1,rows=1"
+example_floor = 10
+
+[filter]
+min_query_time = 0.5
+since = "2024-05-01T00:00:00Z"
+between_hours = "22:00-06:00"
+
+[email]
+to = ["dba@example.com"]
+
+[webhook]
+json_url = "https://ingest.example.com/digests"
+json_headers = ["Authorization: Bearer abc"]
+"#).unwrap();
+
+        assert_eq!(config.input.files.unwrap().len(), 1);
+        assert!(matches!(config.report.format, Some(OutputFormat::Html)));
+        assert_eq!(config.report.limit, Some(50));
+        assert_eq!(config.report.group_by, Some(GroupBy::User));
+        assert_eq!(config.report.sort_by, Some(SortBy::Score));
+        assert_eq!(config.report.score_weights.unwrap().count, 0.0);
+        assert_eq!(config.report.example_floor, Some(10.0));
+        assert_eq!(config.filter.min_query_time, Some(0.5));
+        assert!(config.filter.since.is_some());
+        assert_eq!(config.filter.between_hours, Some("22:00-06:00".parse().unwrap()));
+        assert_eq!(config.email.to.unwrap(), vec!["dba@example.com"]);
+        assert_eq!(config.webhook.json_headers.unwrap(), vec!["Authorization: Bearer abc"]);
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        assert!(toml::from_str::<FileConfig>("[report]\nlimt = 5\n").is_err());
+    }
+}
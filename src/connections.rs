@@ -0,0 +1,195 @@
+use crate::parser::Query;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Connections followed at once; statements on further ones are ignored with a warning.
+pub const MAX_OPEN_CONNECTIONS: usize = 200_000;
+
+/// How one `User@Host` used its connections, as far as the log shows.
+///
+/// Only logged statements are seen, so a connection that ran fast statements
+/// before a slow one counts them as one statement.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub user_host: String,
+    /// Distinct connections (thread IDs) that logged a statement.
+    pub connections: u64,
+    pub statements: u64,
+    /// Connections that logged exactly one statement.
+    pub single_statement: u64,
+    /// Query time of the statements.
+    pub total_time: f64,
+    /// Connections per minute between the first and last statement of the user,
+    /// if they span a minute or more.
+    pub per_minute: Option<f64>,
+}
+
+impl ConnectionStats {
+    pub fn statements_per_connection(&self) -> f64 {
+        self.statements as f64 / self.connections.max(1) as f64
+    }
+
+    /// Returns the share of connections that logged one statement, in percent.
+    pub fn single_statement_share(&self) -> f64 {
+        self.single_statement as f64 / self.connections.max(1) as f64 * 100.0
+    }
+}
+
+/// Statements logged so far on an open connection.
+#[derive(Debug, Clone)]
+struct Connection {
+    user_host: Arc<str>,
+    statements: u64,
+    total_time: f64,
+}
+
+/// Totals of the connections of one `User@Host` that have been replaced by a
+/// new connection with the same thread ID.
+#[derive(Debug, Clone, Default)]
+struct Closed {
+    connections: u64,
+    statements: u64,
+    single_statement: u64,
+    total_time: f64,
+}
+
+/// Follows statements per connection by `Thread_id` (or the `Id:` of the
+/// `User@Host` line) and summarizes connection churn per `User@Host`.
+///
+/// A thread ID that shows up with another `User@Host` starts a new
+/// connection, since the server reuses IDs after a restart.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionTracker {
+    open: HashMap<u64, Connection>,
+    closed: HashMap<Arc<str>, Closed>,
+    /// First and last statement time per `User@Host`.
+    spans: HashMap<Arc<str>, (DateTime<Utc>, DateTime<Utc>)>,
+    omitted: u64,
+}
+
+impl ConnectionTracker {
+    /// Records a statement, unless it has no thread ID.
+    pub fn add(&mut self, query: &Query) {
+        let Some(id) = query.thread_id else { return };
+        let user_host = match self.spans.get_key_value(query.user_host.as_str()) {
+            Some((user_host, _)) => user_host.clone(),
+            None => Arc::from(query.user_host.as_str()),
+        };
+        if let Some(time) = query.timestamp {
+            let span = self.spans.entry(user_host.clone()).or_insert((time, time));
+            span.0 = span.0.min(time);
+            span.1 = span.1.max(time);
+        }
+
+        let full = self.open.len() >= MAX_OPEN_CONNECTIONS;
+        match self.open.get_mut(&id) {
+            Some(connection) if connection.user_host == user_host => {
+                connection.statements += 1;
+                connection.total_time += query.query_time;
+                return;
+            }
+            Some(_) => {
+                let replaced = self.open.remove(&id).unwrap();
+                self.close(replaced);
+            }
+            None if full => {
+                if self.omitted == 0 {
+                    tracing::warn!("More than {} connections; statements on later ones are left out of the connection stats", MAX_OPEN_CONNECTIONS);
+                }
+                self.omitted += 1;
+                return;
+            }
+            None => {}
+        }
+        self.open.insert(id, Connection { user_host, statements: 1, total_time: query.query_time });
+    }
+
+    fn close(&mut self, connection: Connection) {
+        let closed = self.closed.entry(connection.user_host).or_default();
+        closed.connections += 1;
+        closed.statements += connection.statements;
+        closed.single_statement += (connection.statements == 1) as u64;
+        closed.total_time += connection.total_time;
+    }
+
+    /// Returns the stats per `User@Host`, most connections first, up to `limit` (0 for all).
+    pub fn stats(&self, limit: usize) -> Vec<ConnectionStats> {
+        let mut totals = self.closed.clone();
+        for connection in self.open.values() {
+            let closed = totals.entry(connection.user_host.clone()).or_default();
+            closed.connections += 1;
+            closed.statements += connection.statements;
+            closed.single_statement += (connection.statements == 1) as u64;
+            closed.total_time += connection.total_time;
+        }
+
+        let mut stats: Vec<ConnectionStats> = totals
+            .into_iter()
+            .map(|(user_host, closed)| {
+                let minutes = self.spans.get(&user_host).map(|(first, last)| (*last - *first).num_seconds() as f64 / 60.0);
+                ConnectionStats {
+                    user_host: user_host.to_string(),
+                    connections: closed.connections,
+                    statements: closed.statements,
+                    single_statement: closed.single_statement,
+                    total_time: closed.total_time,
+                    per_minute: minutes.filter(|minutes| *minutes >= 1.0).map(|minutes| closed.connections as f64 / minutes),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| b.connections.cmp(&a.connections).then_with(|| a.user_host.cmp(&b.user_host)));
+        if limit > 0 {
+            stats.truncate(limit);
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(time: &str, user_host: &str, thread_id: Option<u64>) -> Query {
+        Query {
+            query_time: 1.0,
+            lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            timestamp: Some(time.parse().unwrap()),
+            user_host: user_host.to_string(),
+            sql_text: "SELECT 1".to_string(),
+            thread_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_churn() {
+        let mut tracker = ConnectionTracker::default();
+        // A pooled user running three statements on one connection
+        for time in ["2024-05-01T10:00:00Z", "2024-05-01T10:01:00Z", "2024-05-01T10:02:00Z"] {
+            tracker.add(&query(time, "pool[pool] @ app1 []", Some(1)));
+        }
+        // A user opening a connection per statement over four minutes
+        for (i, time) in ["2024-05-01T10:00:00Z", "2024-05-01T10:01:00Z", "2024-05-01T10:02:00Z", "2024-05-01T10:03:00Z", "2024-05-01T10:04:00Z"].into_iter().enumerate() {
+            tracker.add(&query(time, "php[php] @ web1 []", Some(100 + i as u64)));
+        }
+        // A reused thread ID with another user is a new connection
+        tracker.add(&query("2024-05-01T10:05:00Z", "cron[cron] @ batch []", Some(1)));
+        tracker.add(&query("2024-05-01T10:05:00Z", "cron[cron] @ batch []", None));
+
+        let stats = tracker.stats(0);
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].user_host, "php[php] @ web1 []");
+        assert_eq!((stats[0].connections, stats[0].statements, stats[0].single_statement), (5, 5, 5));
+        assert_eq!(stats[0].per_minute, Some(1.25));
+        assert_eq!(stats[0].single_statement_share(), 100.0);
+        let pool = stats.iter().find(|stats| stats.user_host.starts_with("pool")).unwrap();
+        assert_eq!((pool.connections, pool.statements, pool.statements_per_connection()), (1, 3, 3.0));
+        let cron = stats.iter().find(|stats| stats.user_host.starts_with("cron")).unwrap();
+        assert_eq!((cron.connections, cron.statements, cron.per_minute), (1, 1, None));
+        assert_eq!(tracker.stats(1).len(), 1);
+    }
+}
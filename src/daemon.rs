@@ -0,0 +1,94 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Parses an interval such as `90s`, `15m`, `1h` or `1d`. A bare number is seconds.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid interval '{}'", s))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("invalid interval unit '{}' (expected s, m, h or d)", unit)),
+    };
+    let seconds = number.checked_mul(multiplier).ok_or_else(|| format!("interval '{}' is too long", s))?;
+    if seconds == 0 {
+        return Err("interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Formats an interval the way [`parse_interval`] reads it, in the largest whole unit up to hours.
+pub fn format_interval(interval: Duration) -> String {
+    match interval.as_secs() {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Shifts `path` to `path.1`, `path.1` to `path.2` and so on, keeping at most
+/// `keep` old copies. Does nothing when `keep` is 0.
+pub fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    let oldest = numbered(path, keep);
+    if oldest.exists() {
+        std::fs::remove_file(&oldest)?;
+    }
+    for n in (1..keep).rev() {
+        let from = numbered(path, n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(path, 1))
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("300000000000000d").is_err());
+        assert!(parse_interval("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_rotate_keeps_limited_copies() {
+        let dir = std::env::temp_dir().join(format!("sqd-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.html");
+
+        for run in 1..=4 {
+            rotate(&path, 2).unwrap();
+            std::fs::write(&path, run.to_string()).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(std::fs::read_to_string(dir.join("report.html.1")).unwrap(), "3");
+        assert_eq!(std::fs::read_to_string(dir.join("report.html.2")).unwrap(), "2");
+        assert!(!dir.join("report.html.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use crate::aggregator::QueryStats;
+use crate::report::{percentile, query_id, truncate_chars};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use tabled::builder::Builder;
+use tabled::{Table, Tabled};
+
+/// Output formats supported by the diff report.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Debug)]
+pub enum DiffFormat {
+    Table,
+    Json,
+}
+
+/// Per-fingerprint metrics on one side of a comparison.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffSide {
+    pub count: u64,
+    pub total_time: f64,
+    pub mean_time: f64,
+    pub p95: f64,
+}
+
+/// A fingerprint compared between two runs. A missing side means the
+/// fingerprint did not appear in that run.
+#[derive(Debug, Serialize)]
+pub struct DiffItem {
+    pub query_id: String,
+    pub fingerprint: String,
+    pub before: Option<DiffSide>,
+    pub after: Option<DiffSide>,
+    /// Change in total time (after - before).
+    pub delta_total_time: f64,
+}
+
+/// A fingerprint compared across labeled groups of inputs, with one side per
+/// label (in label order). A missing side means it did not appear there.
+#[derive(Debug, Serialize)]
+pub struct LabeledItem {
+    pub query_id: String,
+    pub fingerprint: String,
+    pub sides: Vec<Option<DiffSide>>,
+}
+
+/// Represents a row in the diff table.
+#[derive(Tabled)]
+struct Row {
+    #[tabled(rename = "Query ID")]
+    query_id: String,
+    #[tabled(rename = "Count")]
+    count: String,
+    #[tabled(rename = "Total Time")]
+    total_time: String,
+    #[tabled(rename = "Delta")]
+    delta: String,
+    #[tabled(rename = "P95")]
+    p95: String,
+    #[tabled(rename = "Query")]
+    query: String,
+}
+
+/// Compares two sets of statistics, ranking fingerprints by the absolute change
+/// in total time (ties broken by query ID).
+pub fn diff(mut before: HashMap<String, QueryStats>, mut after: HashMap<String, QueryStats>) -> Vec<DiffItem> {
+    let mut keys: Vec<String> = before.keys().chain(after.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut items: Vec<DiffItem> = keys.into_iter().map(|fp| {
+        let before = before.remove(&fp).map(side);
+        let after = after.remove(&fp).map(side);
+        let delta_total_time = after.as_ref().map_or(0.0, |s| s.total_time) - before.as_ref().map_or(0.0, |s| s.total_time);
+        DiffItem {
+            query_id: query_id(&fp),
+            fingerprint: fp,
+            before,
+            after,
+            delta_total_time,
+        }
+    }).collect();
+
+    items.sort_by(|a, b| {
+        b.delta_total_time.abs().partial_cmp(&a.delta_total_time.abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.query_id.cmp(&b.query_id))
+    });
+    items
+}
+
+/// Compares the statistics of several labeled groups side by side, ranking
+/// fingerprints by their total time over all groups (ties broken by query ID).
+pub fn compare(mut groups: Vec<HashMap<String, QueryStats>>) -> Vec<LabeledItem> {
+    let mut keys: Vec<String> = groups.iter().flat_map(|stats| stats.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut items: Vec<(f64, LabeledItem)> = keys.into_iter().map(|fp| {
+        let sides: Vec<Option<DiffSide>> = groups.iter_mut().map(|stats| stats.remove(&fp).map(side)).collect();
+        let total: f64 = sides.iter().flatten().map(|s| s.total_time).sum();
+        (total, LabeledItem { query_id: query_id(&fp), fingerprint: fp, sides })
+    }).collect();
+
+    items.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.1.query_id.cmp(&b.1.query_id))
+    });
+    items.into_iter().map(|(_, item)| item).collect()
+}
+
+fn side(mut stats: QueryStats) -> DiffSide {
+    stats.all_query_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    DiffSide {
+        count: stats.count,
+        total_time: stats.total_time,
+        mean_time: if stats.count > 0 { stats.total_time / stats.count as f64 } else { 0.0 },
+        p95: percentile(&stats.all_query_times, 0.95),
+    }
+}
+
+/// Writes the first `limit` diff items (0 for all) in the given format.
+pub fn write_diff(items: &[DiffItem], limit: usize, format: &DiffFormat, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let items = if limit == 0 { items } else { &items[..limit.min(items.len())] };
+
+    match format {
+        DiffFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, items)?;
+            writeln!(writer)?;
+        }
+        DiffFormat::Table => {
+            let rows: Vec<Row> = items.iter().map(|item| {
+                let query = truncate_chars(&item.fingerprint, 50);
+                Row {
+                    query_id: item.query_id.clone(),
+                    count: format!("{} -> {}", format_side(&item.before, |s| s.count.to_string()), format_side(&item.after, |s| s.count.to_string())),
+                    total_time: format!("{} -> {}", format_side(&item.before, |s| format!("{:.3}s", s.total_time)), format_side(&item.after, |s| format!("{:.3}s", s.total_time))),
+                    delta: format!("{:+.3}s", item.delta_total_time),
+                    p95: format!("{} -> {}", format_side(&item.before, |s| format!("{:.3}s", s.p95)), format_side(&item.after, |s| format!("{:.3}s", s.p95))),
+                    query,
+                }
+            }).collect();
+            writeln!(writer, "{}", Table::new(rows))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the first `limit` compared items (0 for all) with one column per label.
+///
+/// In the table, each label's cell reads `count / total time / P95`.
+pub fn write_comparison(labels: &[String], items: &[LabeledItem], limit: usize, format: &DiffFormat, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let items = if limit == 0 { items } else { &items[..limit.min(items.len())] };
+
+    match format {
+        DiffFormat::Json => {
+            #[derive(Serialize)]
+            struct LabeledSide<'a> {
+                label: &'a str,
+                #[serde(flatten)]
+                side: Option<&'a DiffSide>,
+            }
+            #[derive(Serialize)]
+            struct Item<'a> {
+                query_id: &'a str,
+                fingerprint: &'a str,
+                sides: Vec<LabeledSide<'a>>,
+            }
+            let items: Vec<Item> = items.iter().map(|item| Item {
+                query_id: &item.query_id,
+                fingerprint: &item.fingerprint,
+                sides: labels.iter().zip(&item.sides).map(|(label, side)| LabeledSide { label, side: side.as_ref() }).collect(),
+            }).collect();
+            serde_json::to_writer_pretty(&mut *writer, &items)?;
+            writeln!(writer)?;
+        }
+        DiffFormat::Table => {
+            let mut builder = Builder::default();
+            builder.push_record(std::iter::once("Query ID".to_string()).chain(labels.iter().cloned()).chain(std::iter::once("Query".to_string())));
+            for item in items {
+                let query = truncate_chars(&item.fingerprint, 50);
+                let cells = item.sides.iter().map(|side| format_side(side, |s| format!("{} / {:.3}s / {:.3}s", s.count, s.total_time, s.p95)));
+                builder.push_record(std::iter::once(item.query_id.clone()).chain(cells).chain(std::iter::once(query)));
+            }
+            writeln!(writer, "Per label: count / total time / P95")?;
+            writeln!(writer, "{}", builder.build())?;
+        }
+    }
+    Ok(())
+}
+
+fn format_side(side: &Option<DiffSide>, f: impl Fn(&DiffSide) -> String) -> String {
+    side.as_ref().map(f).unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(total_time: f64) -> QueryStats {
+        QueryStats {
+            count: 1,
+            total_time,
+            all_query_times: vec![total_time],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_ranks_by_absolute_delta() {
+        let before = HashMap::from([
+            ("select a".to_string(), stats(1.0)),
+            ("select gone".to_string(), stats(5.0)),
+        ]);
+        let after = HashMap::from([
+            ("select a".to_string(), stats(3.0)),
+            ("select new".to_string(), stats(0.5)),
+        ]);
+
+        let items = diff(before, after);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].fingerprint, "select gone");
+        assert_eq!(items[0].delta_total_time, -5.0);
+        assert!(items[0].after.is_none());
+        assert_eq!(items[1].fingerprint, "select a");
+        assert_eq!(items[2].fingerprint, "select new");
+        assert!(items[2].before.is_none());
+    }
+
+    #[test]
+    fn test_multibyte_fingerprints_are_truncated() {
+        let fingerprint = "select 顧客名, 住所, 電話番号, 注文番号, 金額 from 顧客テーブル where id = ?".to_string();
+        let items = diff(HashMap::from([(fingerprint.clone(), stats(1.0))]), HashMap::from([(fingerprint.clone(), stats(2.0))]));
+        let mut out = Vec::new();
+        write_diff(&items, 0, &DiffFormat::Table, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("| select 顧客名, 住所, 電話番号, 注文番号, 金額 from 顧客テーブル wher... |"), "{}", text);
+
+        let items = compare(vec![HashMap::from([(fingerprint, stats(1.0))])]);
+        write_comparison(&["a".to_string()], &items, 0, &DiffFormat::Table, &mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn test_compare_side_by_side() {
+        let primary = HashMap::from([("select a".to_string(), stats(1.0)), ("select b".to_string(), stats(0.5))]);
+        let replica = HashMap::from([("select a".to_string(), stats(4.0))]);
+
+        let items = compare(vec![primary, replica]);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].fingerprint, "select a");
+        assert_eq!(items[0].sides.iter().map(|s| s.as_ref().map(|s| s.total_time)).collect::<Vec<_>>(), [Some(1.0), Some(4.0)]);
+        assert!(items[1].sides[1].is_none());
+
+        let mut out = Vec::new();
+        write_comparison(&["primary".to_string(), "replica".to_string()], &items, 0, &DiffFormat::Table, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("| primary "), "{}", text);
+        assert!(text.contains("| 1 / 1.000s / 1.000s | 1 / 4.000s / 4.000s |"), "{}", text);
+        assert!(text.contains("| 1 / 0.500s / 0.500s | -"), "{}", text);
+    }
+}
@@ -0,0 +1,1691 @@
+use crate::aggregator::{self, ExampleThresholds, Interner, QueryStats, SourceStats};
+use crate::classify;
+use crate::fingerprint::{fingerprint_with, FingerprintOptions};
+use crate::connections::ConnectionTracker;
+use crate::lock_cluster::{LockClusterOptions, LockClusters};
+use crate::host::HostNormalizer;
+use crate::parser::{self, InputFormat, Query, QueryReader};
+use crate::report::{self, BadgeThresholds, LongQueryTimeWhatIf, RenderOptions, ExecutionSample, ReportItem, SchemaChange, ScoreWeights, SortBy, Throughput, TimeUnit, Timeline, TxnControlStats, ZeroRowWork};
+use crate::sink::QuerySink;
+use crate::spill::{self, SpillStore};
+use crate::timezone::Timezone;
+use anyhow::bail;
+use chrono::{DateTime, NaiveTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::cell::Cell;
+use std::io::{BufRead, Read};
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The key queries are grouped by during aggregation.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// Group by normalized SQL fingerprint.
+    #[default]
+    Fingerprint,
+    /// Group by the `User@Host` header.
+    User,
+}
+
+/// How transaction-control statements (`BEGIN`, `COMMIT`, `ROLLBACK`, `SAVEPOINT`) are handled.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxnControl {
+    /// Rank them like any other statement.
+    #[default]
+    Include,
+    /// Skip them.
+    Exclude,
+    /// Summarize them in their own report section.
+    Separate,
+    /// Like `separate`, but spread the time of each `COMMIT` and `ROLLBACK` over
+    /// the statements logged before it on the same connection.
+    Attribute,
+}
+
+/// Account names excluded by [`DigestConfigBuilder::exclude_system_users`]:
+/// replication, monitoring agents, backup tools and heartbeat writers.
+///
+/// Each pattern must match the whole user name, case-insensitively.
+pub const SYSTEM_USERS: &[&str] = &[
+    // Replication
+    "repl.*", ".*_repl", "replica.*", "slave.*", "sql_slave", "system user", "rdsrepladmin",
+    // Monitoring agents
+    "monitor.*", ".*_monitor", "pmm.*", "datadog", "zabbix.*", "nagios", ".*exporter.*", "prometheus", "newrelic", "orchestrator", "proxysql.*",
+    // Backups
+    "backup.*", ".*_backup", "xtrabackup", "mariabackup", "mysqlbackup", "mydumper", "mysqldump",
+    // Heartbeat and internal accounts
+    ".*heartbeat.*", "rdsadmin", "event_scheduler",
+];
+
+/// Heartbeat statements (pt-heartbeat and similar) excluded along with the system users,
+/// whichever account runs them.
+pub const HEARTBEAT_SQL: &str = r"(?i)\b(?:update|into|from)\s+(?:`?\w+`?\s*\.\s*)?`?heartbeat`?(?:\s|;|$)";
+
+/// A time-of-day range such as `09:00-18:00`, start included and end excluded.
+///
+/// A range ending before it starts, such as `22:00-06:00`, wraps past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HourWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl HourWindow {
+    /// Returns true if the time of day falls within the range.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl std::str::FromStr for HourWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |time: &str| {
+            let time = time.trim();
+            match time {
+                "24" | "24:00" => Some(NaiveTime::MIN),
+                _ => NaiveTime::parse_from_str(time, "%H:%M").or_else(|_| NaiveTime::parse_from_str(&format!("{}:00", time), "%H:%M")).ok(),
+            }
+        };
+        let (start, end) = s.split_once('-')
+            .and_then(|(start, end)| Some((parse(start)?, parse(end)?)))
+            .ok_or_else(|| format!("Invalid hour range {:?}: expected HH:MM-HH:MM (e.g., 09:00-18:00)", s))?;
+        if start == end {
+            return Err(format!("Invalid hour range {:?}: start and end are the same", s));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for HourWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Conditions a query must satisfy to be aggregated.
+#[derive(Debug, Clone, Default)]
+pub struct QueryFilter {
+    /// Minimum query time in seconds.
+    pub min_query_time: Option<f64>,
+    /// Pattern the `User@Host` value must match.
+    pub user: Option<Regex>,
+    /// Only include queries logged at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only include queries logged before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Only include queries logged at these times of day, in `timezone`.
+    pub between_hours: Option<HourWindow>,
+    /// Timezone of `between_hours`.
+    pub timezone: Timezone,
+    /// Database selected by a `USE` statement in the query's log entry.
+    pub database: Option<String>,
+    /// Pattern the SQL text must match.
+    pub sql: Option<Regex>,
+    /// Pattern of the system accounts excluded, matched against [`Query::user`].
+    pub exclude_users: Option<Regex>,
+    /// Pattern of the heartbeat statements excluded.
+    pub exclude_sql: Option<Regex>,
+    /// Exclude `# administrator command:` entries, such as `Ping` and `Quit`.
+    pub exclude_admin_commands: bool,
+}
+
+impl QueryFilter {
+    /// Returns true if the query passes every configured condition.
+    ///
+    /// Queries without a timestamp are excluded when a time window is set.
+    pub fn matches(&self, query: &Query) -> bool {
+        self.rejection(query).is_none()
+    }
+
+    /// Returns the first condition the query fails, or `None` if it passes.
+    pub fn rejection(&self, query: &Query) -> Option<&'static str> {
+        if let Some(min) = self.min_query_time {
+            if query.query_time < min {
+                return Some("below the minimum query time");
+            }
+        }
+        if let Some(re) = &self.user {
+            if !re.is_match(&query.user_host) {
+                return Some("user does not match");
+            }
+        }
+        if self.exclude_users.as_ref().is_some_and(|re| re.is_match(query.user())) {
+            return Some("system user");
+        }
+        if self.exclude_sql.as_ref().is_some_and(|re| re.is_match(&query.sql_text)) {
+            return Some("heartbeat query");
+        }
+        if self.exclude_admin_commands && query.admin_command.is_some() {
+            return Some("administrator command");
+        }
+        if let Some(db) = &self.database {
+            if !query.database().is_some_and(|d| d.eq_ignore_ascii_case(db)) {
+                return Some("database does not match");
+            }
+        }
+        if let Some(re) = &self.sql {
+            if !re.is_match(&query.sql_text) {
+                return Some("SQL does not match");
+            }
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(ts) = query.timestamp else {
+                return Some("no timestamp to check against the time window");
+            };
+            if self.since.is_some_and(|since| ts < since) || self.until.is_some_and(|until| ts >= until) {
+                return Some("outside the time window");
+            }
+        }
+        if let Some(window) = &self.between_hours {
+            let Some(ts) = query.timestamp else {
+                return Some("no timestamp to check against the hour range");
+            };
+            if !window.contains(self.timezone.to_local(&ts).time()) {
+                return Some("outside the hour range");
+            }
+        }
+        None
+    }
+}
+
+/// Configuration of the whole digest pipeline.
+///
+/// Use [`DigestConfig::builder`] to construct a validated configuration.
+#[derive(Debug, Clone)]
+pub struct DigestConfig {
+    pub fingerprint: FingerprintOptions,
+    pub filter: QueryFilter,
+    pub group_by: GroupBy,
+    /// Rewrites of client hosts applied before grouping.
+    pub hosts: HostNormalizer,
+    /// Percentiles reported per query, as fractions in (0, 1].
+    pub percentiles: Vec<f64>,
+    /// Number of queries in the summary. 0 means all.
+    pub limit: usize,
+    /// Number of detailed sections. 0 means all.
+    pub detail_limit: usize,
+    /// The key queries are ranked by.
+    pub sort_by: SortBy,
+    /// Weights of the impact score.
+    pub score_weights: ScoreWeights,
+    /// Unit durations are displayed in.
+    pub time_unit: TimeUnit,
+    /// Alternative `long_query_time` settings, in seconds, to report the capture of.
+    pub long_query_time_what_if: Vec<f64>,
+    /// Thresholds at which report rows get badges.
+    pub badge_thresholds: BadgeThresholds,
+    /// Detect clusters of overlapping lock waits, listed in their own section.
+    pub lock_clusters: Option<LockClusterOptions>,
+    /// Executions sampled per fingerprint for the CSV download in the HTML
+    /// report. 0 disables sampling.
+    pub execution_samples: usize,
+    /// Report connections and statements per connection by `User@Host`.
+    pub connection_stats: bool,
+    /// Report the query rate over the logged time span, overall and per query.
+    pub throughput: bool,
+    /// When example statements are stored.
+    pub examples: ExampleThresholds,
+    /// Rank DDL statements with the other queries instead of listing them as schema changes.
+    pub include_ddl: bool,
+    /// How transaction-control statements are handled.
+    pub txn_control: TxnControl,
+    /// Timezone used to display timestamps: an offset (e.g., "+09:00") or IANA name (e.g., "Asia/Tokyo").
+    pub timezone: String,
+    /// Timezone that log timestamps without an offset are in.
+    pub log_timezone: Timezone,
+    /// Format of the logs read.
+    pub input_format: InputFormat,
+    /// Approximate memory budget for aggregation, in bytes. See [`Digest`].
+    pub max_memory: Option<usize>,
+    /// Directory to spill statistics to once the memory budget is reached,
+    /// instead of sampling and dropping them. See [`Digest`].
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            fingerprint: FingerprintOptions::default(),
+            filter: QueryFilter::default(),
+            group_by: GroupBy::default(),
+            hosts: HostNormalizer::default(),
+            percentiles: vec![0.95, 0.99],
+            limit: 20,
+            detail_limit: 20,
+            sort_by: SortBy::default(),
+            score_weights: ScoreWeights::default(),
+            time_unit: TimeUnit::default(),
+            long_query_time_what_if: Vec::new(),
+            badge_thresholds: BadgeThresholds::default(),
+            lock_clusters: None,
+            execution_samples: 0,
+            connection_stats: false,
+            throughput: false,
+            examples: ExampleThresholds::default(),
+            include_ddl: false,
+            txn_control: TxnControl::default(),
+            timezone: "+00:00".to_string(),
+            log_timezone: Timezone::default(),
+            input_format: InputFormat::default(),
+            max_memory: None,
+            spill_dir: None,
+        }
+    }
+}
+
+impl DigestConfig {
+    /// Returns a builder starting from the default configuration.
+    pub fn builder() -> DigestConfigBuilder {
+        DigestConfigBuilder::default()
+    }
+
+    /// Returns the aggregation key of a query according to `group_by`.
+    pub fn key_for(&self, query: &Query) -> String {
+        match self.group_by {
+            GroupBy::Fingerprint => fingerprint_with(&query.sql_text, &self.fingerprint),
+            GroupBy::User => query.user_host.clone(),
+        }
+    }
+
+    /// Returns the rendering options derived from this configuration.
+    pub fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            detail_limit: self.detail_limit,
+            score_weights: self.score_weights,
+            color: false,
+            schema_changes: Vec::new(),
+            transaction_control: Vec::new(),
+            inputs: Vec::new(),
+            refresh: None,
+            timezone: self.timezone.parse().unwrap_or_default(),
+            time_unit: self.time_unit,
+            long_query_time: None,
+            zero_rows: Vec::new(),
+            lock_clusters: Vec::new(),
+            connections: Vec::new(),
+            throughput: None,
+            samples: HashMap::new(),
+            timeline: Timeline::default(),
+            annotations: Vec::new(),
+        }
+    }
+}
+
+/// Builder for [`DigestConfig`].
+#[derive(Debug, Default)]
+pub struct DigestConfigBuilder {
+    config: DigestConfig,
+    user_pattern: Option<String>,
+    sql_pattern: Option<String>,
+    system_users: Option<Vec<String>>,
+    log_timezone: Option<String>,
+    detail_limit: Option<usize>,
+}
+
+impl DigestConfigBuilder {
+    pub fn fingerprint(mut self, options: FingerprintOptions) -> Self {
+        self.config.fingerprint = options;
+        self
+    }
+
+    pub fn group_by(mut self, group_by: GroupBy) -> Self {
+        self.config.group_by = group_by;
+        self
+    }
+
+    pub fn percentiles(mut self, percentiles: Vec<f64>) -> Self {
+        self.config.percentiles = percentiles;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.config.limit = limit;
+        self
+    }
+
+    /// Sets the detailed section limit. Defaults to the summary limit.
+    pub fn detail_limit(mut self, limit: usize) -> Self {
+        self.detail_limit = Some(limit);
+        self
+    }
+
+    pub fn sort_by(mut self, sort_by: SortBy) -> Self {
+        self.config.sort_by = sort_by;
+        self
+    }
+
+    pub fn score_weights(mut self, weights: ScoreWeights) -> Self {
+        self.config.score_weights = weights;
+        self
+    }
+
+    pub fn time_unit(mut self, unit: TimeUnit) -> Self {
+        self.config.time_unit = unit;
+        self
+    }
+
+    pub fn badge_thresholds(mut self, thresholds: BadgeThresholds) -> Self {
+        self.config.badge_thresholds = thresholds;
+        self
+    }
+
+    /// Ranks DDL statements with the other queries instead of listing them separately.
+    pub fn include_ddl(mut self, include: bool) -> Self {
+        self.config.include_ddl = include;
+        self
+    }
+
+    pub fn txn_control(mut self, mode: TxnControl) -> Self {
+        self.config.txn_control = mode;
+        self
+    }
+
+    /// Sets the display timezone: an offset or IANA name. An invalid one fails
+    /// [`build`](Self::build), like an invalid log timezone, as `between_hours`
+    /// filters in it.
+    pub fn timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.config.timezone = timezone.into();
+        self
+    }
+
+    /// Sets the timezone of log timestamps without an offset: an offset or IANA name.
+    pub fn log_timezone(mut self, timezone: impl Into<String>) -> Self {
+        self.log_timezone = Some(timezone.into());
+        self
+    }
+
+    /// Rewrites the host part of `User@Host` (after filtering, before grouping).
+    pub fn hosts(mut self, hosts: HostNormalizer) -> Self {
+        self.config.hosts = hosts;
+        self
+    }
+
+    /// Sets the format of the logs read (slow query log by default).
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.config.input_format = format;
+        self
+    }
+
+    /// Reports how many events and how much time each of these `long_query_time`
+    /// settings (in seconds) would capture.
+    pub fn long_query_time_what_if(mut self, thresholds: Vec<f64>) -> Self {
+        self.config.long_query_time_what_if = thresholds;
+        self
+    }
+
+    /// Reports clusters of overlapping lock waits and the data changes that
+    /// may have caused them.
+    pub fn lock_clusters(mut self, options: LockClusterOptions) -> Self {
+        self.config.lock_clusters = Some(options);
+        self
+    }
+
+    /// Samples up to this many executions per fingerprint, offered as a CSV
+    /// download in the detailed sections of HTML reports.
+    pub fn execution_samples(mut self, samples: usize) -> Self {
+        self.config.execution_samples = samples;
+        self
+    }
+
+    /// Reports connections, statements per connection and connection churn by `User@Host`.
+    pub fn connection_stats(mut self, enabled: bool) -> Self {
+        self.config.connection_stats = enabled;
+        self
+    }
+
+    /// Reports queries per second over the logged time span, overall and per query.
+    pub fn throughput(mut self, enabled: bool) -> Self {
+        self.config.throughput = enabled;
+        self
+    }
+
+    /// Caps the estimated memory used by aggregation, in bytes.
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.config.max_memory = Some(bytes);
+        self
+    }
+
+    /// Sets when example statements are stored.
+    pub fn examples(mut self, examples: ExampleThresholds) -> Self {
+        self.config.examples = examples;
+        self
+    }
+
+    /// Spills statistics to partitioned files under `dir` when the memory budget
+    /// (`max_memory`, or 1 GiB) is reached.
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.spill_dir = Some(dir.into());
+        self
+    }
+
+    pub fn min_query_time(mut self, seconds: f64) -> Self {
+        self.config.filter.min_query_time = Some(seconds);
+        self
+    }
+
+    /// Only aggregate queries whose `User@Host` matches this regular expression.
+    pub fn user_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.user_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Only aggregate queries logged after `USE <database>;` (case-insensitive).
+    pub fn database(mut self, database: impl Into<String>) -> Self {
+        self.config.filter.database = Some(database.into());
+        self
+    }
+
+    /// Only aggregate queries whose SQL text matches this regular expression.
+    pub fn sql_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.sql_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Excludes the accounts in [`SYSTEM_USERS`] and these extra patterns, which
+    /// must match the whole user name, as well as heartbeat statements.
+    pub fn exclude_system_users(mut self, extra: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.system_users = Some(extra.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Excludes `# administrator command:` entries, such as `Ping` and `Quit`.
+    pub fn exclude_admin_commands(mut self, exclude: bool) -> Self {
+        self.config.filter.exclude_admin_commands = exclude;
+        self
+    }
+
+    pub fn since(mut self, since: DateTime<Utc>) -> Self {
+        self.config.filter.since = Some(since);
+        self
+    }
+
+    pub fn until(mut self, until: DateTime<Utc>) -> Self {
+        self.config.filter.until = Some(until);
+        self
+    }
+
+    /// Only aggregate queries logged within this time of day, in the display timezone.
+    pub fn between_hours(mut self, window: HourWindow) -> Self {
+        self.config.filter.between_hours = Some(window);
+        self
+    }
+
+    /// Validates the settings and returns the configuration.
+    pub fn build(mut self) -> anyhow::Result<DigestConfig> {
+        if let Some(p) = self.config.percentiles.iter().find(|p| !(**p > 0.0 && **p <= 1.0)) {
+            bail!("Invalid percentile {}: must be in (0, 1]", p);
+        }
+        if let Some(t) = self.config.long_query_time_what_if.iter().find(|t| !(t.is_finite() && **t >= 0.0)) {
+            bail!("Invalid long_query_time {}: must be a non-negative number of seconds", t);
+        }
+        if let Some(locks) = &self.config.lock_clusters {
+            if !(locks.min_lock_time.is_finite() && locks.min_lock_time > 0.0) {
+                bail!("Invalid lock cluster threshold {}: must be a positive number of seconds", locks.min_lock_time);
+            }
+        }
+        match self.config.timezone.parse::<Timezone>() {
+            Ok(timezone) => self.config.filter.timezone = timezone,
+            Err(e) => bail!(e),
+        }
+        if let Some(timezone) = self.log_timezone {
+            self.config.log_timezone = timezone.parse().map_err(anyhow::Error::msg)?;
+        }
+        if let Some(pattern) = self.user_pattern {
+            self.config.filter.user = Some(Regex::new(&pattern)?);
+        }
+        if let Some(pattern) = self.sql_pattern {
+            self.config.filter.sql = Some(Regex::new(&pattern)?);
+        }
+        if let Some(extra) = self.system_users {
+            let patterns: Vec<&str> = SYSTEM_USERS.iter().copied().chain(extra.iter().map(String::as_str)).collect();
+            self.config.filter.exclude_users = Some(Regex::new(&format!("(?i)^(?:{})$", patterns.join("|")))?);
+            self.config.filter.exclude_sql = Some(Regex::new(HEARTBEAT_SQL).unwrap());
+        }
+        self.config.detail_limit = self.detail_limit.unwrap_or(self.config.limit);
+        Ok(self.config)
+    }
+}
+
+/// Parses a log stream and passes every query that passes the filter to `sink`,
+/// without aggregating anything.
+pub fn stream<R: BufRead>(reader: R, config: &DigestConfig, sink: &mut dyn QuerySink) {
+    for query in parser::parse_input(reader, config.input_format, config.log_timezone).flatten() {
+        if config.filter.matches(&query) {
+            let key = config.key_for(&query);
+            sink.on_query(&key, &query);
+        }
+    }
+}
+
+/// What happened to the input of one [`Digest::add_reader`] call.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadStats {
+    /// Bytes read from the input.
+    pub bytes: u64,
+    /// Queries parsed from the input.
+    pub parsed: u64,
+    /// Log blocks without SQL (e.g., stray headers) that were skipped.
+    pub skipped_blocks: u64,
+    /// Lines outside any event, such as server startup banners, that were skipped.
+    pub noise_lines: u64,
+    /// Lines that could not be read (e.g., invalid UTF-8).
+    pub unreadable: u64,
+    /// Queries rejected by the filter, by reason.
+    pub filtered: BTreeMap<&'static str, u64>,
+    /// Queries that were aggregated.
+    pub aggregated: u64,
+    pub elapsed: Duration,
+}
+
+impl ReadStats {
+    /// Adds another input's counts to these.
+    pub fn merge(&mut self, other: &ReadStats) {
+        self.bytes += other.bytes;
+        self.parsed += other.parsed;
+        self.skipped_blocks += other.skipped_blocks;
+        self.noise_lines += other.noise_lines;
+        self.unreadable += other.unreadable;
+        for (reason, count) in &other.filtered {
+            *self.filtered.entry(reason).or_default() += count;
+        }
+        self.aggregated += other.aggregated;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// Parses a size such as `512M`, `2G` or `1048576` (bytes) into bytes.
+///
+/// Suffixes `K`, `M`, `G` and `T` (optionally followed by `B` or `iB`) are powers of 1024.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = match digits.chars().last() {
+        Some('K') => (&digits[..digits.len() - 1], 1u64 << 10),
+        Some('M') => (&digits[..digits.len() - 1], 1 << 20),
+        Some('G') => (&digits[..digits.len() - 1], 1 << 30),
+        Some('T') => (&digits[..digits.len() - 1], 1 << 40),
+        _ => (digits, 1),
+    };
+    let value: f64 = number.trim().parse().map_err(|_| format!("Invalid size '{}': expected e.g. 512M or 2G", s))?;
+    if value <= 0.0 {
+        return Err(format!("Invalid size '{}': must be positive", s));
+    }
+    Ok((value * multiplier as f64) as usize)
+}
+
+/// Number of queries aggregated between memory estimates.
+const MEMORY_CHECK_INTERVAL: u64 = 4096;
+/// Query times kept per fingerprint once the memory budget forces sampling.
+const SAMPLED_QUERY_TIMES: usize = 1024;
+/// Memory budget used with `spill_dir` when `max_memory` is not set.
+const DEFAULT_SPILL_MEMORY: usize = 1 << 30;
+/// Schema changes kept for the report.
+const MAX_SCHEMA_CHANGES: usize = 1000;
+/// Statements remembered per connection for attributing `COMMIT` time.
+const MAX_OPEN_STATEMENTS: usize = 64;
+
+/// Runs queries through filtering, grouping and aggregation.
+///
+/// With `max_memory` set, the digest degrades instead of growing past the budget:
+/// first it keeps a uniform sample of query times per fingerprint (making
+/// percentiles approximate), then it drops the fingerprints with the least total
+/// time. Dropped queries still count towards [`Digest::totals`].
+///
+/// With `spill_dir` set, the statistics are instead written to partitioned files
+/// whenever the budget is reached, and merged one partition at a time by
+/// [`Digest::finish`], which keeps only the top `limit` entries in memory.
+/// Results stay exact at the cost of speed. [`Digest::stats`] and
+/// [`Digest::report`] only see what has not been spilled yet.
+pub struct Digest {
+    config: DigestConfig,
+    stats: HashMap<String, QueryStats>,
+    sinks: Vec<Box<dyn QuerySink + Send>>,
+    since_memory_check: u64,
+    /// Query times kept per fingerprint, once sampling is on.
+    time_sample_cap: Option<usize>,
+    rng: u64,
+    /// Count and total time of queries whose fingerprints were dropped.
+    dropped: (u64, f64),
+    spill: Option<SpillStore>,
+    schema_changes: Vec<SchemaChange>,
+    omitted_schema_changes: u64,
+    txn_control: BTreeMap<&'static str, TxnControlStats>,
+    /// Input that queries are currently attributed to, see [`Digest::set_source`].
+    source: Option<Arc<str>>,
+    sources: Vec<String>,
+    /// Users and databases shared by the statistics of all fingerprints.
+    interner: Interner,
+    /// Keys of the statements logged on each connection since its last transaction boundary.
+    open_transactions: HashMap<u64, Vec<String>>,
+    long_query_time: Option<LongQueryTimeWhatIf>,
+    timeline: Timeline,
+    /// Count, total time and rows examined of the reads per key that examined
+    /// at least the no-rows-sent badge threshold without sending a row.
+    zero_rows: HashMap<String, (u64, f64, u64)>,
+    lock_clusters: Option<LockClusters>,
+    connections: Option<ConnectionTracker>,
+    throughput: Option<Throughput>,
+    /// Executions seen and a uniform sample of them per key, if `execution_samples` is set.
+    samples: HashMap<String, (u64, Vec<ExecutionSample>)>,
+    /// Time spent per stage, if measured; see [`Digest::with_stage_times`].
+    stages: Option<StageTimes>,
+}
+
+/// Time a [`Digest`] spent in each stage of digesting its inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StageTimes {
+    /// Waiting for input data, including decompression and network reads.
+    pub read: Duration,
+    /// Splitting the input into queries.
+    pub parse: Duration,
+    /// Computing the grouping keys of queries.
+    pub fingerprint: Duration,
+    /// Filtering queries and adding them to the statistics and sinks.
+    pub aggregate: Duration,
+}
+
+/// Measures the time spent in the reads of a reader, if `time` is set.
+struct TimedReader<R> {
+    inner: R,
+    time: Option<Rc<Cell<Duration>>>,
+}
+
+impl<R: BufRead> TimedReader<R> {
+    fn timed<T>(&mut self, f: impl FnOnce(&mut R) -> T) -> T {
+        let Some(time) = &self.time else { return f(&mut self.inner) };
+        let start = Instant::now();
+        let result = f(&mut self.inner);
+        time.set(time.get() + start.elapsed());
+        result
+    }
+}
+
+impl<R: BufRead> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.timed(|inner| inner.read(buf))
+    }
+}
+
+impl<R: BufRead> BufRead for TimedReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        // The borrow of the buffer outlives the closure, so the time is taken here
+        let Some(time) = self.time.clone() else { return self.inner.fill_buf() };
+        let start = Instant::now();
+        let result = self.inner.fill_buf();
+        time.set(time.get() + start.elapsed());
+        result
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount)
+    }
+}
+
+impl Digest {
+    pub fn new(config: DigestConfig) -> Self {
+        Self {
+            stats: HashMap::new(),
+            sinks: Vec::new(),
+            since_memory_check: 0,
+            time_sample_cap: None,
+            rng: 0x9E37_79B9_7F4A_7C15,
+            dropped: (0, 0.0),
+            spill: None,
+            schema_changes: Vec::new(),
+            omitted_schema_changes: 0,
+            txn_control: BTreeMap::new(),
+            source: None,
+            sources: Vec::new(),
+            open_transactions: HashMap::new(),
+            interner: Interner::default(),
+            long_query_time: (!config.long_query_time_what_if.is_empty()).then(|| LongQueryTimeWhatIf::new(&config.long_query_time_what_if)),
+            timeline: Timeline::default(),
+            zero_rows: HashMap::new(),
+            lock_clusters: config.lock_clusters.map(LockClusters::new),
+            connections: config.connection_stats.then(ConnectionTracker::default),
+            throughput: config.throughput.then(Throughput::default),
+            samples: HashMap::new(),
+            stages: None,
+            config,
+        }
+    }
+
+    /// Registers a sink notified of every aggregated query and, on `finish`,
+    /// of every finalized `QueryStats`.
+    pub fn with_sink(mut self, sink: Box<dyn QuerySink + Send>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Measures the time spent reading, parsing, fingerprinting and aggregating,
+    /// at the cost of a few clock reads per query.
+    pub fn with_stage_times(mut self) -> Self {
+        self.stages = Some(StageTimes::default());
+        self
+    }
+
+    /// Returns the time spent per stage so far, if measured.
+    pub fn stage_times(&self) -> Option<StageTimes> {
+        self.stages
+    }
+
+    pub fn config(&self) -> &DigestConfig {
+        &self.config
+    }
+
+    /// Aggregates a single query if it passes the filter.
+    pub fn add_query(&mut self, query: Query) {
+        let _ = self.try_add_query(query);
+    }
+
+    /// Aggregates the query, or returns why the filter rejected it.
+    fn try_add_query(&mut self, query: Query) -> Result<(), &'static str> {
+        let Some(before) = self.stages else { return self.aggregate_query(query) };
+        let start = Instant::now();
+        let result = self.aggregate_query(query);
+        if let Some(stages) = &mut self.stages {
+            stages.aggregate += start.elapsed().saturating_sub(stages.fingerprint - before.fingerprint);
+        }
+        result
+    }
+
+    fn aggregate_query(&mut self, mut query: Query) -> Result<(), &'static str> {
+        if let Some(reason) = self.config.filter.rejection(&query) {
+            tracing::trace!("Skipping query ({}): {}", reason, query.sql_text.lines().next().unwrap_or_default());
+            return Err(reason);
+        }
+        if self.config.hosts.is_enabled() {
+            query.user_host = self.config.hosts.normalize(&query.user_host);
+        }
+        let txn_statement = match self.config.txn_control {
+            TxnControl::Include => None,
+            _ => query.transaction_control(),
+        };
+        if txn_statement.is_some() && self.config.txn_control == TxnControl::Exclude {
+            return Err("transaction control statement");
+        }
+        let start = self.stages.is_some().then(Instant::now);
+        let key = self.config.key_for(&query);
+        if let (Some(start), Some(stages)) = (start, &mut self.stages) {
+            stages.fingerprint += start.elapsed();
+        }
+        for sink in &mut self.sinks {
+            sink.on_query(&key, &query);
+        }
+        if let Some(connections) = &mut self.connections {
+            connections.add(&query);
+        }
+        if let Some(what_if) = &mut self.long_query_time {
+            what_if.add(query.query_time);
+        }
+        if !self.config.include_ddl && query.is_schema_change() {
+            self.add_schema_change(query);
+            return Ok(());
+        }
+        if let Some(statement) = txn_statement {
+            self.add_transaction_control(statement, &query);
+            return Ok(());
+        }
+        if let (TxnControl::Attribute, Some(id)) = (self.config.txn_control, query.thread_id) {
+            let open = self.open_transactions.entry(id).or_default();
+            if open.len() < MAX_OPEN_STATEMENTS {
+                open.push(key.clone());
+            }
+        }
+        let query_time = query.query_time;
+        if let Some(time) = query.timestamp {
+            self.timeline.add(time, query_time);
+        }
+        if let Some(throughput) = &mut self.throughput {
+            throughput.add(query.timestamp);
+        }
+        let examined_threshold = self.config.badge_thresholds.examined_without_rows;
+        if examined_threshold > 0 && query.rows_sent == 0 && query.rows_examined >= examined_threshold && classify::verb(&query.sql_text) == "SELECT" {
+            let (count, total_time, rows_examined) = self.zero_rows.entry(key.clone()).or_default();
+            *count += 1;
+            *total_time += query_time;
+            *rows_examined += query.rows_examined;
+        }
+        if let Some(locks) = &mut self.lock_clusters {
+            locks.add(&key, &query);
+        }
+        if self.config.execution_samples > 0 {
+            self.sample_execution(&key, &query);
+        }
+        match (self.time_sample_cap, &self.source) {
+            (None, None) => aggregator::add_interned_query(&mut self.stats, &mut self.interner, key, query, &self.config.examples),
+            (cap, source) => {
+                aggregator::add_interned_query(&mut self.stats, &mut self.interner, key.clone(), query, &self.config.examples);
+                if let (Some(source), Some(stats)) = (source, self.stats.get_mut(&key)) {
+                    let entry = stats.sources.entry(source.clone()).or_default();
+                    entry.count += 1;
+                    entry.total_time += query_time;
+                }
+                if let Some(cap) = cap {
+                    self.sample_query_times(&key, cap);
+                }
+            }
+        }
+
+        if self.config.max_memory.is_some() || self.config.spill_dir.is_some() {
+            self.since_memory_check += 1;
+            if self.since_memory_check >= MEMORY_CHECK_INTERVAL {
+                self.since_memory_check = 0;
+                self.enforce_memory_budget();
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps a uniform sample of `cap` query times (reservoir sampling) for `key`,
+    /// whose latest time was just appended.
+    fn sample_query_times(&mut self, key: &str, cap: usize) {
+        let Some(count) = self.stats.get(key).filter(|stats| stats.all_query_times.len() > cap).map(|stats| stats.count) else { return };
+        let slot = (self.next_random() % count.max(1)) as usize;
+        let Some(stats) = self.stats.get_mut(key) else { return };
+        let latest = stats.all_query_times.pop().unwrap_or_default();
+        if slot < cap {
+            stats.all_query_times[slot] = latest;
+        }
+    }
+
+    /// Keeps a uniform sample of `execution_samples` executions (reservoir sampling) for `key`.
+    fn sample_execution(&mut self, key: &str, query: &Query) {
+        let cap = self.config.execution_samples;
+        let seen = self.samples.get(key).map_or(0, |(seen, _)| *seen);
+        let slot = if seen < cap as u64 { seen as usize } else { (self.next_random() % (seen + 1)) as usize };
+        let (seen, samples) = self.samples.entry(key.to_string()).or_default();
+        *seen += 1;
+        if slot >= cap {
+            return;
+        }
+        let sample = ExecutionSample {
+            timestamp: query.timestamp,
+            query_time: query.query_time,
+            lock_time: query.lock_time,
+            rows_sent: query.rows_sent,
+            rows_examined: query.rows_examined,
+            user_host: query.user_host.clone(),
+        };
+        match samples.get_mut(slot) {
+            Some(kept) => *kept = sample,
+            None => samples.push(sample),
+        }
+    }
+
+    /// Advances the xorshift64 generator used for sampling.
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Returns the estimated memory used by the aggregated statistics, in bytes.
+    pub fn estimated_memory(&self) -> usize {
+        self.stats.iter().map(|(key, stats)| aggregator::estimated_size(key, stats)).sum::<usize>() + self.interner.estimated_size()
+    }
+
+    /// Spills to disk, or samples query times and then drops low-impact
+    /// fingerprints, while the estimate exceeds the memory budget.
+    fn enforce_memory_budget(&mut self) {
+        let default_budget = self.config.spill_dir.as_ref().map(|_| DEFAULT_SPILL_MEMORY);
+        let Some(budget) = self.config.max_memory.or(default_budget) else { return };
+        let mut estimate = self.estimated_memory();
+        if estimate <= budget {
+            return;
+        }
+
+        if let Some(dir) = self.config.spill_dir.clone() {
+            match self.spill_to(&dir) {
+                Ok(()) => return,
+                Err(e) => {
+                    tracing::error!("Could not spill statistics to {:?}: {:#}; continuing in memory", dir, e);
+                    self.config.spill_dir = None;
+                }
+            }
+        }
+
+        if self.time_sample_cap.is_none() {
+            tracing::warn!("Memory estimate {} MiB exceeds --max-memory; sampling query times, percentiles become approximate", estimate >> 20);
+            self.time_sample_cap = Some(SAMPLED_QUERY_TIMES);
+            for stats in self.stats.values_mut() {
+                aggregator::thin_query_times(stats, SAMPLED_QUERY_TIMES);
+            }
+            estimate = self.estimated_memory();
+            if estimate <= budget {
+                return;
+            }
+        }
+
+        // Drop the fingerprints with the least total time until 3/4 of the budget is used
+        let target = budget / 4 * 3;
+        let mut keys: Vec<(f64, String)> = self.stats.iter().map(|(key, stats)| (stats.total_time, key.clone())).collect();
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.1.cmp(&b.1)));
+        let first_drop = self.dropped.0 == 0;
+        let mut dropped = 0;
+        for (_, key) in keys {
+            if estimate <= target {
+                break;
+            }
+            if let Some(stats) = self.stats.remove(&key) {
+                estimate = estimate.saturating_sub(aggregator::estimated_size(&key, &stats));
+                self.dropped.0 += stats.count;
+                self.dropped.1 += stats.total_time;
+                dropped += 1;
+            }
+        }
+        if first_drop {
+            tracing::warn!("Memory estimate exceeds --max-memory; dropped {} low-impact fingerprints, more may follow", dropped);
+        } else {
+            tracing::debug!("Memory estimate exceeds --max-memory; dropped {} low-impact fingerprints", dropped);
+        }
+    }
+
+    fn spill_to(&mut self, dir: &std::path::Path) -> anyhow::Result<()> {
+        let store = match &mut self.spill {
+            Some(store) => store,
+            None => self.spill.insert(SpillStore::create(dir, spill::DEFAULT_PARTITIONS)?),
+        };
+        let fingerprints = self.stats.len();
+        store.spill(std::mem::take(&mut self.stats))?;
+        tracing::debug!("Spilled {} fingerprints to {:?} (spill {})", fingerprints, store.dir(), store.spills());
+        Ok(())
+    }
+
+    /// Aggregates queries read from somewhere other than a log, such as a server's statement history.
+    pub fn add_queries(&mut self, queries: impl IntoIterator<Item = Query>) -> ReadStats {
+        let start = Instant::now();
+        let mut stats = ReadStats::default();
+        for query in queries {
+            stats.parsed += 1;
+            match self.try_add_query(query) {
+                Ok(()) => stats.aggregated += 1,
+                Err(reason) => *stats.filtered.entry(reason).or_default() += 1,
+            }
+        }
+        stats.elapsed = start.elapsed();
+        stats
+    }
+
+    /// Merges statistics aggregated elsewhere, such as a server's statement
+    /// digests, under `key`, attributed to the current input (see [`Digest::set_source`]).
+    /// The filter and the per-query sections do not see them.
+    pub fn add_stats(&mut self, key: String, mut stats: QueryStats) {
+        if let Some(source) = &self.source {
+            stats.sources = BTreeMap::from([(source.clone(), SourceStats { count: stats.count, total_time: stats.total_time })]);
+        }
+        aggregator::merge_entry(&mut self.stats, key, stats);
+    }
+
+    /// Parses a log stream in the configured input format and aggregates every query in it.
+    ///
+    /// Unreadable lines (e.g., invalid UTF-8) are logged and skipped. Skipped
+    /// blocks are passed to the sinks' [`QuerySink::on_rejected`].
+    pub fn add_reader<R: BufRead>(&mut self, reader: R) -> ReadStats {
+        let start = Instant::now();
+        let mut stats = ReadStats::default();
+        let read_time = Rc::new(Cell::new(Duration::ZERO));
+        let reader = TimedReader { inner: reader, time: self.stages.is_some().then(|| read_time.clone()) };
+        let mut parser = parser::parse_input(reader, self.config.input_format, self.config.log_timezone);
+        if !self.sinks.is_empty() {
+            parser.keep_rejected();
+        }
+        loop {
+            let parse_start = self.stages.is_some().then(|| (Instant::now(), read_time.get()));
+            let Some(result) = parser.next() else { break };
+            if let (Some((parse_start, read_before)), Some(stages)) = (parse_start, &mut self.stages) {
+                stages.parse += parse_start.elapsed().saturating_sub(read_time.get() - read_before);
+            }
+            self.pass_rejected(&mut *parser);
+            match result {
+                Ok(query) => {
+                    stats.parsed += 1;
+                    match self.try_add_query(query) {
+                        Ok(()) => stats.aggregated += 1,
+                        Err(reason) => *stats.filtered.entry(reason).or_default() += 1,
+                    }
+                }
+                Err(e) => {
+                    stats.unreadable += 1;
+                    tracing::warn!("Skipping unreadable input: {:#}", e);
+                }
+            }
+        }
+        self.pass_rejected(&mut *parser);
+        if let Some(stages) = &mut self.stages {
+            stages.read += read_time.get();
+        }
+        stats.bytes = parser.bytes_read();
+        stats.skipped_blocks = parser.skipped_blocks();
+        stats.noise_lines = parser.noise_lines();
+        stats.elapsed = start.elapsed();
+
+        tracing::debug!("Parsed {} queries, aggregated {}, in {:.2?}", stats.parsed, stats.aggregated, stats.elapsed);
+        for (reason, count) in &stats.filtered {
+            tracing::debug!("Skipped {} queries: {}", count, reason);
+        }
+        stats
+    }
+
+    /// Hands the blocks the parser skipped to the sinks.
+    fn pass_rejected(&mut self, parser: &mut dyn QueryReader) {
+        for block in parser.take_rejected() {
+            for sink in &mut self.sinks {
+                sink.on_rejected(&block);
+            }
+        }
+    }
+
+    /// Records a DDL statement for the schema changes section, up to [`MAX_SCHEMA_CHANGES`].
+    fn add_schema_change(&mut self, query: Query) {
+        if self.schema_changes.len() >= MAX_SCHEMA_CHANGES {
+            if self.omitted_schema_changes == 0 {
+                tracing::warn!("More than {} schema changes; only the first {} are listed", MAX_SCHEMA_CHANGES, MAX_SCHEMA_CHANGES);
+            }
+            self.omitted_schema_changes += 1;
+            return;
+        }
+        self.schema_changes.push(SchemaChange {
+            timestamp: query.timestamp,
+            query_time: query.query_time,
+            user_host: query.user_host,
+            statement: query.sql_text,
+        });
+    }
+
+    /// Adds a transaction-control statement to its section and, with
+    /// [`TxnControl::Attribute`], spreads the time of a `COMMIT` or `ROLLBACK`
+    /// over the statements of the transaction it ends.
+    fn add_transaction_control(&mut self, statement: &'static str, query: &Query) {
+        let entry = self.txn_control.entry(statement).or_insert_with(|| TxnControlStats { statement: statement.to_string(), ..Default::default() });
+        entry.count += 1;
+        entry.total_time += query.query_time;
+        if self.config.txn_control != TxnControl::Attribute || statement == "SAVEPOINT" {
+            return;
+        }
+        let open = query.thread_id.and_then(|id| self.open_transactions.remove(&id)).unwrap_or_default();
+        if statement == "BEGIN" || open.is_empty() {
+            return;
+        }
+        let share = query.query_time / open.len() as f64;
+        for key in &open {
+            // Fingerprints dropped or spilled to stay within the memory budget keep their time in the section
+            if let Some(stats) = self.stats.get_mut(key) {
+                stats.total_time += share;
+                entry.attributed_time += share;
+            }
+        }
+    }
+
+    /// Returns the DDL statements seen so far, unless `include_ddl` is set.
+    pub fn schema_changes(&self) -> &[SchemaChange] {
+        &self.schema_changes
+    }
+
+    /// Attributes the queries added from now on to the named input, for the
+    /// per-input breakdown of each query in the report.
+    pub fn set_source(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if !self.sources.contains(&name) {
+            self.sources.push(name.clone());
+        }
+        self.source = Some(Arc::from(name));
+    }
+
+    /// Returns the rendering options, with the schema changes and transaction-control
+    /// statements seen so far.
+    pub fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            schema_changes: self.schema_changes.clone(),
+            transaction_control: self.txn_control.values().cloned().collect(),
+            inputs: self.sources.clone(),
+            long_query_time: self.long_query_time.clone(),
+            timeline: self.timeline.clone(),
+            zero_rows: self.zero_row_work(),
+            lock_clusters: self.lock_clusters.as_ref().map(|locks| locks.clusters(self.config.limit)).unwrap_or_default(),
+            connections: self.connections.as_ref().map(|connections| connections.stats(self.config.limit)).unwrap_or_default(),
+            throughput: self.throughput.clone().map(|throughput| Throughput { busiest_minute: self.timeline.busiest_minute(), ..throughput }),
+            samples: self.samples.iter().map(|(key, (_, samples))| (report::query_id(key), samples.clone())).collect(),
+            ..self.config.render_options()
+        }
+    }
+
+    /// Returns the fingerprints with zero-row reads, most time first, up to the report limit.
+    fn zero_row_work(&self) -> Vec<ZeroRowWork> {
+        let mut work: Vec<ZeroRowWork> = self.zero_rows.iter().map(|(key, &(count, total_time, rows_examined))| ZeroRowWork {
+            query_id: report::query_id(key),
+            fingerprint: key.clone(),
+            count,
+            total_time,
+            rows_examined,
+            fingerprint_time: self.stats.get(key).map(|stats| stats.total_time),
+        }).collect();
+        work.sort_by(|a, b| b.total_time.total_cmp(&a.total_time).then_with(|| a.fingerprint.cmp(&b.fingerprint)));
+        if self.config.limit > 0 {
+            work.truncate(self.config.limit);
+        }
+        work
+    }
+
+    /// Returns the statistics aggregated so far.
+    pub fn stats(&self) -> &HashMap<String, QueryStats> {
+        &self.stats
+    }
+
+    /// Returns the statistics aggregated so far, without those spilled to disk.
+    pub fn into_stats(self) -> HashMap<String, QueryStats> {
+        self.stats
+    }
+
+    /// Returns the overall event count and total query time, including queries
+    /// of fingerprints dropped to stay within `max_memory`.
+    pub fn totals(&self) -> (u64, f64) {
+        let (count, time) = aggregator::totals(&self.stats);
+        let (spilled_count, spilled_time) = self.spill.as_ref().map_or((0, 0.0), SpillStore::totals);
+        (count + self.dropped.0 + spilled_count, time + self.dropped.1 + spilled_time)
+    }
+
+    /// Ranks a copy of the statistics aggregated so far, leaving the digest usable.
+    pub fn report(&self) -> Vec<ReportItem> {
+        report::prepare_report_items(self.stats.clone(), &self.config)
+    }
+
+    /// Ranks the aggregated statistics into report items.
+    ///
+    /// Registered sinks receive each finalized `QueryStats` first, ordered by key
+    /// (within each partition, if statistics were spilled).
+    pub fn finish(mut self) -> Vec<ReportItem> {
+        let start = Instant::now();
+        let stats = match self.spill.take() {
+            Some(store) => {
+                let mut fingerprints = 0;
+                let mut top = HashMap::new();
+                // Scores depend on the maxima over all fingerprints, so nothing can be dropped early
+                let limit = if self.config.sort_by == SortBy::Score { 0 } else { self.config.limit };
+                let merged = store.merge(std::mem::take(&mut self.stats), |partition| {
+                    fingerprints += partition.len();
+                    notify_stats(&mut self.sinks, &partition);
+                    top.extend(partition);
+                    if limit > 0 && top.len() > limit {
+                        keep_top(&mut top, limit, self.config.sort_by);
+                    }
+                });
+                if let Err(e) = merged {
+                    tracing::error!("Could not merge spilled statistics: {:#}; the report is incomplete", e);
+                }
+                tracing::debug!("Merged {} spilled fingerprints in {:.2?}", fingerprints, start.elapsed());
+                top
+            }
+            None => {
+                notify_stats(&mut self.sinks, &self.stats);
+                self.stats
+            }
+        };
+        let fingerprints = stats.len();
+        let items = report::prepare_report_items(stats, &self.config);
+        tracing::debug!("Ranked {} fingerprints in {:.2?}", fingerprints, start.elapsed());
+        items
+    }
+}
+
+/// Passes every entry to the sinks' `on_stats`, ordered by key.
+fn notify_stats(sinks: &mut [Box<dyn QuerySink + Send>], stats: &HashMap<String, QueryStats>) {
+    if sinks.is_empty() {
+        return;
+    }
+    let mut keys: Vec<&String> = stats.keys().collect();
+    keys.sort();
+    for key in keys {
+        for sink in sinks.iter_mut() {
+            sink.on_stats(key, &stats[key]);
+        }
+    }
+}
+
+/// Keeps the `limit` entries ranked first by [`report::prepare_report_items`].
+fn keep_top(stats: &mut HashMap<String, QueryStats>, limit: usize, sort_by: SortBy) {
+    let mut entries: Vec<(String, String, QueryStats)> = stats.drain().map(|(key, stat)| (report::query_id(&key), key, stat)).collect();
+    entries.sort_by(|a, b| {
+        let order = match sort_by {
+            SortBy::Count => b.2.count.cmp(&a.2.count),
+            sort_by => sort_by.value(&b.2).partial_cmp(&sort_by.value(&a.2)).unwrap_or(std::cmp::Ordering::Equal),
+        };
+        order.then_with(|| a.0.cmp(&b.0))
+    });
+    entries.truncate(limit);
+    stats.extend(entries.into_iter().map(|(_, key, stat)| (key, stat)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "# Time: 2023-10-27T10:00:00Z
+# User@Host: app[app] @ localhost []
+# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 1;
+# Time: 2023-10-27T11:00:00Z
+# User@Host: app[app] @ localhost []
+# Query_time: 0.5  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 2;
+# Time: 2023-10-27T12:00:00Z
+# User@Host: batch[batch] @ localhost []
+# Query_time: 3.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+DELETE FROM t WHERE id = 3;
+";
+
+    #[test]
+    fn test_builder_validation() {
+        assert!(DigestConfig::builder().percentiles(vec![0.5, 1.5]).build().is_err());
+        assert!(DigestConfig::builder().timezone("Mars/Olympus").build().is_err());
+        assert!(DigestConfig::builder().log_timezone("Mars/Olympus").build().is_err());
+        assert_eq!(DigestConfig::builder().log_timezone("+09:00").build().unwrap().log_timezone.to_string(), "+09:00");
+        assert!(DigestConfig::builder().user_pattern("(").build().is_err());
+
+        let config = DigestConfig::builder().limit(7).build().unwrap();
+        assert_eq!(config.detail_limit, 7);
+    }
+
+    #[test]
+    fn test_digest_filters() {
+        let config = DigestConfig::builder()
+            .min_query_time(1.0)
+            .user_pattern("^app")
+            .build()
+            .unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(LOG.as_bytes());
+
+        assert_eq!(digest.totals(), (1, 2.0));
+    }
+
+    #[test]
+    fn test_schema_changes_kept_out_of_ranking() {
+        let log = format!("{}# Time: 2023-10-27T13:00:00Z\n# User@Host: migrate[migrate] @ localhost []\n# Query_time: 90.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 0\nALTER TABLE t ADD COLUMN c INT;\n", LOG);
+
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        assert_eq!(digest.schema_changes().len(), 1);
+        assert_eq!(digest.schema_changes()[0].query_time, 90.0);
+        assert_eq!(digest.finish().len(), 2);
+
+        let mut digest = Digest::new(DigestConfig::builder().include_ddl(true).build().unwrap());
+        digest.add_reader(log.as_bytes());
+        assert!(digest.schema_changes().is_empty());
+        assert!(digest.finish()[0].normalized_query.starts_with("alter table"));
+    }
+
+    #[test]
+    fn test_transaction_control_modes() {
+        let block = |id: u32, time: f64, sql: &str| format!("# User@Host: app[app] @ localhost []  Id: {}\n# Query_time: {}  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1\n{}\n", id, time, sql);
+        let log = [
+            block(1, 0.1, "BEGIN;"),
+            block(1, 1.0, "UPDATE a SET x = 1 WHERE id = 1;"),
+            block(2, 2.0, "UPDATE b SET x = 1 WHERE id = 1;"),
+            block(1, 1.0, "INSERT INTO c VALUES (1);"),
+            block(1, 4.0, "COMMIT;"),
+            block(3, 3.0, "COMMIT;"),
+        ].concat();
+        let run = |mode: TxnControl| {
+            let mut digest = Digest::new(DigestConfig::builder().txn_control(mode).build().unwrap());
+            let read = digest.add_reader(log.as_bytes());
+            (read, digest.render_options().transaction_control, digest.finish())
+        };
+
+        let (_, section, items) = run(TxnControl::Include);
+        assert!(section.is_empty());
+        assert_eq!(items[0].normalized_query, "commit;");
+
+        let (read, section, items) = run(TxnControl::Exclude);
+        assert!(section.is_empty());
+        assert_eq!(read.filtered["transaction control statement"], 3);
+        assert_eq!(items.len(), 3);
+
+        let (_, section, items) = run(TxnControl::Attribute);
+        let commit = section.iter().find(|stats| stats.statement == "COMMIT").unwrap();
+        assert_eq!((commit.count, commit.total_time, commit.attributed_time), (2, 7.0, 4.0));
+        assert_eq!(section.iter().find(|stats| stats.statement == "BEGIN").unwrap().count, 1);
+        assert_eq!(items.len(), 3);
+        let total = |sql: &str| items.iter().find(|item| item.normalized_query.starts_with(sql)).unwrap().total_time;
+        assert_eq!(total("update a"), 3.0);
+        assert_eq!(total("insert into c"), 3.0);
+        assert_eq!(total("update b"), 2.0);
+    }
+
+    #[test]
+    fn test_per_input_breakdown() {
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.set_source("replica1.log");
+        digest.add_reader(LOG.as_bytes());
+        digest.set_source("replica2.log");
+        digest.add_reader(LOG.lines().take(4).collect::<Vec<_>>().join("\n").as_bytes());
+
+        assert_eq!(digest.render_options().inputs, vec!["replica1.log", "replica2.log"]);
+        let items = digest.finish();
+        let select = &items[0].sources;
+        assert_eq!(select.len(), 2);
+        assert_eq!((select["replica1.log"].count, select["replica2.log"].count), (2, 1));
+        assert_eq!(select["replica2.log"].total_time, 2.0);
+        assert_eq!(items[1].sources.keys().collect::<Vec<_>>(), vec!["replica1.log"]);
+    }
+
+    #[test]
+    fn test_digest_database_and_sql_filters() {
+        let log = "# User@Host: app[app] @ localhost []\n# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1\nuse shop;\nSELECT * FROM orders;\n# User@Host: app[app] @ localhost []\n# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1\nuse auth;\nSELECT * FROM users;\n";
+
+        let config = DigestConfig::builder().database("SHOP").build().unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        assert_eq!(digest.totals(), (1, 1.0));
+
+        let config = DigestConfig::builder().sql_pattern("(?i)from users").build().unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        assert_eq!(digest.totals(), (1, 2.0));
+
+        assert!(DigestConfig::builder().sql_pattern("(").build().is_err());
+    }
+
+    #[test]
+    fn test_digest_time_window() {
+        let config = DigestConfig::builder()
+            .since("2023-10-27T10:30:00Z".parse().unwrap())
+            .until("2023-10-27T12:00:00Z".parse().unwrap())
+            .build()
+            .unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(LOG.as_bytes());
+
+        assert_eq!(digest.totals(), (1, 0.5));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("1.5k"), Ok(1536));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("0").is_err());
+    }
+
+    #[test]
+    fn test_memory_budget_degrades_instead_of_growing() {
+        let config = DigestConfig::builder().max_memory(256 << 10).build().unwrap();
+        let mut digest = Digest::new(config);
+        let query = |sql: String, query_time: f64| Query {
+            query_time,
+            lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            timestamp: None,
+            user_host: String::new(),
+            sql_text: sql,
+            ..Default::default()
+        };
+        for i in 0..100_000u64 {
+            digest.add_query(query("SELECT * FROM hot WHERE id = 1".to_string(), (i % 100) as f64 / 10.0));
+            if i % 10 == 0 {
+                digest.add_query(query(format!("SELECT * FROM cold_{} WHERE id = 1", i), 0.001));
+            }
+        }
+
+        // The budget is checked periodically, so it can be overshot in between
+        assert!(digest.estimated_memory() <= 2 * (256 << 10), "{}", digest.estimated_memory());
+        assert_eq!(digest.totals().0, 110_000);
+        let hot = &digest.stats()["select * from hot where id = ?"];
+        assert_eq!(hot.count, 100_000);
+        assert_eq!(hot.all_query_times.len(), SAMPLED_QUERY_TIMES);
+        let items = digest.finish();
+        assert!((items[0].p95 - 9.5).abs() < 0.3, "{}", items[0].p95);
+    }
+
+    #[test]
+    fn test_spill_dir_keeps_results_exact() {
+        let log: String = (0..20_000)
+            .map(|i| format!("# User@Host: app[app] @ h []\n# Query_time: {}  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1\nSELECT * FROM t{} WHERE id = {};\n", (i % 7) as f64 / 10.0 + if i % 500 == 0 { 5.0 } else { 0.0 }, i % 5000, i))
+            .collect();
+        let in_memory = Digest::new(DigestConfig::builder().limit(5).build().unwrap());
+        let spilling = Digest::new(DigestConfig::builder().limit(5).max_memory(64 << 10).spill_dir(std::env::temp_dir()).build().unwrap());
+
+        let mut results = Vec::new();
+        for mut digest in [in_memory, spilling] {
+            digest.add_reader(log.as_bytes());
+            let totals = digest.totals();
+            let spilled = digest.spill.as_ref().map(|store| store.dir().to_path_buf());
+            let items = digest.finish();
+            assert!(spilled.as_ref().is_none_or(|dir| !dir.exists()));
+            results.push((spilled.is_some(), totals, items.iter().map(|i| (i.query_id.clone(), i.count, format!("{:.6}", i.total_time))).collect::<Vec<_>>()));
+        }
+        assert!(!results[0].0 && results[1].0);
+        assert_eq!(results[0].1 .0, results[1].1 .0);
+        assert!((results[0].1 .1 - results[1].1 .1).abs() < 1e-6);
+        assert_eq!(results[0].2, results[1].2);
+        assert_eq!(results[1].2.len(), 5);
+    }
+
+    #[test]
+    fn test_add_reader_stats() {
+        let config = DigestConfig::builder().min_query_time(1.0).build().unwrap();
+        let mut digest = Digest::new(config);
+        let stats = digest.add_reader(LOG.as_bytes());
+
+        assert_eq!(stats.bytes, LOG.len() as u64);
+        assert_eq!((stats.parsed, stats.aggregated, stats.skipped_blocks, stats.unreadable), (3, 2, 0, 0));
+        assert_eq!(stats.filtered.get("below the minimum query time"), Some(&1));
+    }
+
+    #[test]
+    fn test_stage_times() {
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.add_reader(LOG.as_bytes());
+        assert_eq!(digest.stage_times(), None);
+
+        let mut timed = Digest::new(DigestConfig::default()).with_stage_times();
+        timed.add_reader(LOG.as_bytes());
+        let stages = timed.stage_times().unwrap();
+        assert!(stages.parse > Duration::ZERO && stages.fingerprint > Duration::ZERO && stages.aggregate > Duration::ZERO);
+        assert_eq!(timed.totals(), digest.totals());
+    }
+
+    #[test]
+    fn test_quarantine() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Buffer {
+            fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(bytes);
+                Ok(bytes.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut log = LOG.as_bytes().to_vec();
+        log.extend_from_slice(b"SELECT '\xff';\n# Time: 2023-10-27T13:00:00Z\n# User@Host: app[app] @ localhost []\n");
+        let buffer = Buffer::default();
+        let quarantine = crate::sink::Quarantine::new(buffer.clone());
+        quarantine.set_input("slow.log");
+        let mut digest = Digest::new(DigestConfig::default()).with_sink(Box::new(quarantine.clone()));
+        let stats = digest.add_reader(&log[..]);
+
+        assert_eq!((stats.parsed, stats.skipped_blocks, stats.unreadable), (3, 1, 1));
+        assert_eq!(quarantine.count(), 2);
+        assert_eq!(String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), "### slow.log:13: invalid UTF-8
+SELECT '\u{FFFD}';
+### slow.log:14: no SQL statement
+# Time: 2023-10-27T13:00:00Z
+# User@Host: app[app] @ localhost []
+");
+    }
+
+    #[test]
+    fn test_filter_rejection_reason() {
+        let config = DigestConfig::builder().min_query_time(1.0).user_pattern("^app").build().unwrap();
+        let queries: Vec<Query> = parser::parse_log(LOG.as_bytes()).flatten().collect();
+
+        assert_eq!(config.filter.rejection(&queries[0]), None);
+        assert_eq!(config.filter.rejection(&queries[1]), Some("below the minimum query time"));
+        assert_eq!(config.filter.rejection(&queries[2]), Some("user does not match"));
+    }
+
+    #[test]
+    fn test_hosts_normalized_before_grouping() {
+        let log = LOG.replace("app[app] @ localhost []", "app[app] @  [10.0.0.7]").replacen("10.0.0.7", "10.0.0.8", 1);
+        let config = DigestConfig::builder()
+            .group_by(GroupBy::User)
+            .hosts(HostNormalizer { subnet: true, ..Default::default() })
+            .build()
+            .unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(log.as_bytes());
+
+        let items = digest.finish();
+        assert_eq!(items.len(), 2);
+        assert!(items.iter().any(|item| item.normalized_query == "app[app] @  [10.0.0.0/24]" && item.count == 2));
+    }
+
+    #[test]
+    fn test_between_hours() {
+        let window: HourWindow = "22:00-06:00".parse().unwrap();
+        assert!(window.contains(NaiveTime::from_hms_opt(23, 30, 0).unwrap()));
+        assert!(window.contains(NaiveTime::from_hms_opt(5, 59, 59).unwrap()));
+        assert!(!window.contains(NaiveTime::from_hms_opt(6, 0, 0).unwrap()));
+        assert_eq!("18-24".parse::<HourWindow>().unwrap().end, NaiveTime::MIN);
+        assert!("09:00".parse::<HourWindow>().is_err());
+        assert!("09:00-09:00".parse::<HourWindow>().is_err());
+
+        // The first query of LOG is logged at 10:00 UTC, 19:00 in Tokyo
+        let config = DigestConfig::builder().timezone("Asia/Tokyo").between_hours("09:00-18:00".parse().unwrap()).build().unwrap();
+        let queries: Vec<Query> = parser::parse_log(LOG.as_bytes()).flatten().collect();
+        assert_eq!(config.filter.rejection(&queries[0]), Some("outside the hour range"));
+        let config = DigestConfig::builder().between_hours("09:00-18:00".parse().unwrap()).build().unwrap();
+        assert_eq!(config.filter.rejection(&queries[0]), None);
+    }
+
+    #[test]
+    fn test_exclude_system_users() {
+        let config = DigestConfig::builder().exclude_system_users(["etl_.*"]).build().unwrap();
+        let query = |user_host: &str, sql: &str| Query { user_host: user_host.to_string(), sql_text: sql.to_string(), ..parser::parse_log(LOG.as_bytes()).flatten().next().unwrap() };
+
+        assert_eq!(config.filter.rejection(&query("repl[repl] @ replica1 [10.0.0.2]", "SELECT 1")), Some("system user"));
+        assert_eq!(config.filter.rejection(&query("[SQL_SLAVE] @  []", "UPDATE t SET a = 1")), Some("system user"));
+        assert_eq!(config.filter.rejection(&query("PMM_Agent[pmm_agent] @ localhost []", "SHOW GLOBAL STATUS")), Some("system user"));
+        assert_eq!(config.filter.rejection(&query("etl_nightly[etl_nightly] @ localhost []", "SELECT 1")), Some("system user"));
+        assert_eq!(config.filter.rejection(&query("app[app] @ localhost []", "REPLACE INTO `percona`.`heartbeat` (ts, server_id) VALUES (NOW(), 1)")), Some("heartbeat query"));
+        assert_eq!(config.filter.rejection(&query("app[app] @ localhost []", "SELECT * FROM heartbeats")), None);
+        assert_eq!(config.filter.rejection(&query("reporting[reporting] @ localhost []", "SELECT 1")), None);
+        assert!(DigestConfig::builder().exclude_system_users(["("]).build().is_err());
+    }
+
+    #[test]
+    fn test_admin_commands() {
+        let log = "# Time: 2023-10-27T10:00:00Z
+# User@Host: app[app] @ localhost []  Id: 7
+# Query_time: 0.000012  Lock_time: 0.000000 Rows_sent: 0  Rows_examined: 0
+# administrator command: Ping;
+# Time: 2023-10-27T10:00:01Z
+# User@Host: app[app] @ localhost []  Id: 7
+# Query_time: 0.000020  Lock_time: 0.000000 Rows_sent: 0  Rows_examined: 0
+# administrator command: Ping;
+# User@Host: repl[repl] @ replica1 []  Id: 9
+# Query_time: 3600.0  Lock_time: 0.000000 Rows_sent: 0  Rows_examined: 0
+# administrator command: Binlog Dump;
+";
+        let queries: Vec<Query> = parser::parse_log(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 3);
+        assert_eq!((queries[0].sql_text.as_str(), queries[0].admin_command.as_deref(), queries[0].query_time), ("ADMIN PING", Some("Ping"), 0.000012));
+        assert_eq!(queries[2].sql_text, "ADMIN BINLOG DUMP");
+
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let items = digest.finish();
+        assert_eq!(items.len(), 2);
+        let ping = items.iter().find(|item| item.example_query == "ADMIN PING").unwrap();
+        assert_eq!(ping.count, 2);
+
+        let mut digest = Digest::new(DigestConfig::builder().exclude_admin_commands(true).build().unwrap());
+        let stats = digest.add_reader(log.as_bytes());
+        assert_eq!(stats.filtered.get("administrator command"), Some(&3));
+        assert!(digest.finish().is_empty());
+    }
+
+    #[test]
+    fn test_digest_group_by_user() {
+        let config = DigestConfig::builder()
+            .group_by(GroupBy::User)
+            .percentiles(vec![0.5])
+            .build()
+            .unwrap();
+        let mut digest = Digest::new(config);
+        digest.add_reader(LOG.as_bytes());
+
+        let items = digest.finish();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].normalized_query, "batch[batch] @ localhost []");
+        assert_eq!(items[1].count, 2);
+        assert_eq!(items[1].percentiles, vec![(0.5, 0.5)]);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        queries: Vec<String>,
+        stats: Vec<(String, u64)>,
+    }
+
+    impl QuerySink for std::sync::Arc<std::sync::Mutex<Recorder>> {
+        fn on_query(&mut self, key: &str, _query: &Query) {
+            self.lock().unwrap().queries.push(key.to_string());
+        }
+
+        fn on_stats(&mut self, key: &str, stats: &QueryStats) {
+            self.lock().unwrap().stats.push((key.to_string(), stats.count));
+        }
+    }
+
+    #[test]
+    fn test_stream_without_aggregation() {
+        let config = DigestConfig::builder().user_pattern("^app").build().unwrap();
+        let mut recorder = std::sync::Arc::new(std::sync::Mutex::new(Recorder::default()));
+        stream(LOG.as_bytes(), &config, &mut recorder);
+
+        let recorded = recorder.lock().unwrap();
+        assert_eq!(recorded.queries, vec!["select * from t where id = ?;", "select * from t where id = ?;"]);
+        assert!(recorded.stats.is_empty());
+    }
+
+    #[test]
+    fn test_digest_sink_receives_queries_and_stats() {
+        let recorder = std::sync::Arc::new(std::sync::Mutex::new(Recorder::default()));
+        let mut digest = Digest::new(DigestConfig::default()).with_sink(Box::new(recorder.clone()));
+        digest.add_reader(LOG.as_bytes());
+        digest.finish();
+
+        let recorded = recorder.lock().unwrap();
+        assert_eq!(recorded.queries.len(), 3);
+        assert_eq!(recorded.stats, vec![
+            ("delete from t where id = ?;".to_string(), 1),
+            ("select * from t where id = ?;".to_string(), 2),
+        ]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::parser::Query;
+use crate::report::{query_id, ReportItem};
+use anyhow::Context;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// Documents sent per bulk request.
+const BATCH: usize = 1000;
+
+/// Settings for indexing a digest into Elasticsearch or OpenSearch.
+#[derive(Debug, Clone)]
+pub struct ElasticOptions {
+    /// Base URL of the cluster, with credentials if needed ("https://user:pass@es:9200").
+    pub url: String,
+    /// Index of the per-fingerprint documents; strftime patterns are expanded with the run time.
+    pub index: String,
+    /// Index of the per-event documents; strftime patterns are expanded with each event's time.
+    pub event_index: String,
+}
+
+impl ElasticOptions {
+    /// Fails if an index pattern contains an invalid strftime specifier.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for pattern in [&self.index, &self.event_index] {
+            if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+                anyhow::bail!("Invalid index pattern '{}'", pattern);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the document of one fingerprint: the report item with the run time as `@timestamp`.
+pub fn fingerprint_document(item: &ReportItem, run_at: DateTime<Utc>) -> anyhow::Result<Value> {
+    let mut document = serde_json::to_value(item)?;
+    if let Value::Object(fields) = &mut document {
+        fields.insert("@timestamp".to_string(), json!(run_at));
+    }
+    Ok(document)
+}
+
+/// Returns the document of one logged query, timestamped with its log time (or
+/// the run time for entries without one).
+pub fn event_document(key: &str, query: &Query, run_at: DateTime<Utc>) -> Value {
+    json!({
+        "@timestamp": query.timestamp.unwrap_or(run_at),
+        "query_id": query_id(key),
+        "fingerprint": key,
+        "query_time": query.query_time,
+        "lock_time": query.lock_time,
+        "rows_sent": query.rows_sent,
+        "rows_examined": query.rows_examined,
+        "user_host": query.user_host,
+        "database": query.database(),
+        "thread_id": query.thread_id,
+        "sql": query.sql_text,
+    })
+}
+
+/// Indexes a document per report item, and one per event, through the bulk API.
+///
+/// Returns the number of documents indexed. Fails if the cluster rejected any of them.
+pub fn export(items: &[ReportItem], events: &[(String, Query)], options: &ElasticOptions, run_at: DateTime<Utc>) -> anyhow::Result<usize> {
+    let index = run_at.format(&options.index).to_string();
+    let mut documents = Vec::with_capacity(items.len() + events.len());
+    for item in items {
+        documents.push((index.clone(), fingerprint_document(item, run_at)?));
+    }
+    for (key, query) in events {
+        let index = query.timestamp.unwrap_or(run_at).format(&options.event_index).to_string();
+        documents.push((index, event_document(key, query, run_at)));
+    }
+
+    let url = format!("{}/_bulk", options.url.trim_end_matches('/'));
+    for batch in documents.chunks(BATCH) {
+        let response: Value = ureq::post(&url)
+            .set("Content-Type", "application/x-ndjson")
+            .send_string(&bulk_body(batch)?)
+            .with_context(|| format!("Bulk request to {} failed", options.url))?
+            .into_json()?;
+        check_response(&response, batch.len())?;
+    }
+    Ok(documents.len())
+}
+
+/// Builds the newline-delimited body of a bulk request indexing the documents.
+fn bulk_body(documents: &[(String, Value)]) -> anyhow::Result<String> {
+    let mut body = String::new();
+    for (index, document) in documents {
+        writeln!(body, "{}", json!({ "index": { "_index": index } }))?;
+        writeln!(body, "{}", serde_json::to_string(document)?)?;
+    }
+    Ok(body)
+}
+
+/// Fails with the first error reason if the bulk response reports errors.
+fn check_response(response: &Value, sent: usize) -> anyhow::Result<()> {
+    if response["errors"].as_bool() != Some(true) {
+        return Ok(());
+    }
+    let errors: Vec<&Value> = response["items"].as_array().into_iter().flatten()
+        .filter_map(|item| item["index"].get("error"))
+        .collect();
+    let reason = errors.first().and_then(|error| error["reason"].as_str()).unwrap_or("unknown error");
+    anyhow::bail!("Elasticsearch rejected {} of {} documents: {}", errors.len(), sent, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::{Digest, DigestConfig};
+    use crate::sink::EventCollector;
+
+    const LOG: &str = "# Time: 2024-05-01T10:00:00.000000Z
+# User@Host: app[app] @ localhost []
+# Query_time: 1.000000  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: 10
+SELECT * FROM t WHERE id = 1;
+# Time: 2024-05-02T10:00:00.000000Z
+# User@Host: app[app] @ localhost []
+# Query_time: 3.000000  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: 10
+SELECT * FROM t WHERE id = 2;
+";
+
+    #[test]
+    fn test_bulk_body() {
+        let collector = EventCollector::default();
+        let mut digest = Digest::new(DigestConfig::default()).with_sink(Box::new(collector.clone()));
+        digest.add_reader(LOG.as_bytes());
+        let items = digest.finish();
+        let run_at: DateTime<Utc> = "2024-05-03T00:00:00Z".parse().unwrap();
+        let options = ElasticOptions { url: String::new(), index: "slowquery-digest-%Y.%m.%d".to_string(), event_index: "slowquery-events-%Y.%m.%d".to_string() };
+        options.validate().unwrap();
+
+        let mut documents = vec![(run_at.format(&options.index).to_string(), fingerprint_document(&items[0], run_at).unwrap())];
+        for (key, query) in collector.take() {
+            documents.push((query.timestamp.unwrap().format(&options.event_index).to_string(), event_document(&key, &query, run_at)));
+        }
+        let body = bulk_body(&documents).unwrap();
+        let lines: Vec<Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0]["index"]["_index"], "slowquery-digest-2024.05.03");
+        assert_eq!(lines[1]["@timestamp"], "2024-05-03T00:00:00Z");
+        assert_eq!(lines[1]["count"], 2);
+        assert_eq!(lines[1]["query_id"], items[0].query_id);
+        assert_eq!(lines[4]["index"]["_index"], "slowquery-events-2024.05.02");
+        assert_eq!(lines[5]["query_id"], items[0].query_id);
+        assert_eq!(lines[5]["sql"], "SELECT * FROM t WHERE id = 2;");
+        assert!(body.ends_with('\n'));
+
+        assert!(ElasticOptions { index: "digest-%Q".to_string(), ..options }.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(&json!({ "errors": false, "items": [] }), 1).is_ok());
+        let response = json!({ "errors": true, "items": [
+            { "index": { "status": 201 } },
+            { "index": { "status": 400, "error": { "type": "mapper_parsing_exception", "reason": "failed to parse field [count]" } } },
+        ] });
+        let error = check_response(&response, 2).unwrap_err();
+        assert_eq!(error.to_string(), "Elasticsearch rejected 1 of 2 documents: failed to parse field [count]");
+    }
+}
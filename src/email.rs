@@ -0,0 +1,169 @@
+use crate::report::{self, OutputFormat, RenderOptions, ReportItem};
+use anyhow::{anyhow, bail, Context};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+const BOUNDARY: &str = "rs-slowquery-digest-boundary";
+
+/// Settings for delivering the report by email.
+#[derive(Debug, Clone)]
+pub struct EmailOptions {
+    pub to: Vec<String>,
+    pub from: String,
+    pub subject: String,
+    /// SMTP relay as `host:port`. When `None`, the message is piped to sendmail.
+    pub smtp_server: Option<String>,
+    pub sendmail_path: String,
+}
+
+/// Sends the report by email.
+///
+/// The message is a `multipart/alternative` mail whose plain-text part is the
+/// table report and whose HTML part is the HTML report, so mail clients show
+/// the richer version inline when they can.
+pub fn send_report(items: &[ReportItem], render_options: &RenderOptions, options: &EmailOptions) -> anyhow::Result<()> {
+    if options.to.is_empty() {
+        bail!("No email recipients specified");
+    }
+
+    let text = report::render_report(items, &OutputFormat::Table, render_options)?;
+    let html = report::render_report(items, &OutputFormat::Html, render_options)?;
+    let message = build_message(options, &text, &html);
+
+    match &options.smtp_server {
+        Some(server) => send_smtp(server, options, &message),
+        None => send_sendmail(&options.sendmail_path, options, &message),
+    }
+}
+
+/// Builds the raw RFC 5322 message with CRLF line endings.
+fn build_message(options: &EmailOptions, text: &str, html: &str) -> String {
+    let mut msg = String::new();
+    msg.push_str(&format!("From: {}\r\n", options.from));
+    msg.push_str(&format!("To: {}\r\n", options.to.join(", ")));
+    msg.push_str(&format!("Subject: {}\r\n", options.subject));
+    msg.push_str(&format!("Date: {}\r\n", chrono::Utc::now().to_rfc2822()));
+    msg.push_str("MIME-Version: 1.0\r\n");
+    msg.push_str(&format!("Content-Type: multipart/alternative; boundary=\"{}\"\r\n", BOUNDARY));
+    msg.push_str("\r\n");
+
+    for (content_type, body) in [("text/plain", text), ("text/html", html)] {
+        msg.push_str(&format!("--{}\r\n", BOUNDARY));
+        msg.push_str(&format!("Content-Type: {}; charset=utf-8\r\n", content_type));
+        msg.push_str("Content-Transfer-Encoding: 8bit\r\n");
+        msg.push_str("\r\n");
+        for line in body.lines() {
+            // Dot-stuffing keeps lines starting with '.' intact over SMTP
+            if line.starts_with('.') {
+                msg.push('.');
+            }
+            msg.push_str(line);
+            msg.push_str("\r\n");
+        }
+    }
+    msg.push_str(&format!("--{}--\r\n", BOUNDARY));
+    msg
+}
+
+/// Delivers the message through a local sendmail-compatible binary.
+fn send_sendmail(sendmail_path: &str, options: &EmailOptions, message: &str) -> anyhow::Result<()> {
+    let mut child = Command::new(sendmail_path)
+        .arg("-i")
+        .arg("-f")
+        .arg(&options.from)
+        .args(&options.to)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Could not run {}", sendmail_path))?;
+
+    // sendmail -i treats the input literally, so undo SMTP dot-stuffing
+    let unstuffed = message.replace("\r\n..", "\r\n.");
+    child.stdin.take().ok_or_else(|| anyhow!("sendmail stdin unavailable"))?.write_all(unstuffed.as_bytes())?;
+
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{} exited with {}", sendmail_path, status);
+    }
+    Ok(())
+}
+
+/// Delivers the message to an SMTP relay that accepts unauthenticated mail.
+fn send_smtp(server: &str, options: &EmailOptions, message: &str) -> anyhow::Result<()> {
+    let stream = TcpStream::connect(server).with_context(|| format!("Could not connect to SMTP server {}", server))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    expect_reply(&mut reader, 220)?;
+    smtp_command(&mut writer, &mut reader, "HELO localhost", 250)?;
+    smtp_command(&mut writer, &mut reader, &format!("MAIL FROM:<{}>", options.from), 250)?;
+    for to in &options.to {
+        smtp_command(&mut writer, &mut reader, &format!("RCPT TO:<{}>", to), 250)?;
+    }
+    smtp_command(&mut writer, &mut reader, "DATA", 354)?;
+    writer.write_all(message.as_bytes())?;
+    smtp_command(&mut writer, &mut reader, ".", 250)?;
+    smtp_command(&mut writer, &mut reader, "QUIT", 221)?;
+    Ok(())
+}
+
+/// Sends a single SMTP command and checks the reply code.
+fn smtp_command(writer: &mut impl Write, reader: &mut impl BufRead, command: &str, expected: u16) -> anyhow::Result<()> {
+    writer.write_all(command.as_bytes())?;
+    writer.write_all(b"\r\n")?;
+    writer.flush()?;
+    expect_reply(reader, expected)
+}
+
+/// Reads a (possibly multi-line) SMTP reply and checks its code.
+fn expect_reply(reader: &mut impl BufRead, expected: u16) -> anyhow::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("SMTP server closed the connection");
+        }
+        let code: u16 = line.get(..3).and_then(|c| c.parse().ok()).ok_or_else(|| anyhow!("Malformed SMTP reply: {}", line.trim()))?;
+        // "250-..." continues, "250 ..." is the last line
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if code != expected {
+            bail!("Unexpected SMTP reply: {}", line.trim());
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> EmailOptions {
+        EmailOptions {
+            to: vec!["dba@example.com".to_string(), "ops@example.com".to_string()],
+            from: "digest@example.com".to_string(),
+            subject: "Slow Query Digest".to_string(),
+            smtp_server: None,
+            sendmail_path: "/usr/sbin/sendmail".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_build_message_parts() {
+        let msg = build_message(&options(), "summary\n.hidden", "<html></html>");
+        assert!(msg.contains("To: dba@example.com, ops@example.com\r\n"));
+        assert!(msg.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(msg.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(msg.contains("\r\n..hidden\r\n"));
+        assert!(msg.ends_with(&format!("--{}--\r\n", BOUNDARY)));
+    }
+
+    #[test]
+    fn test_expect_reply_multiline() {
+        let mut reply = &b"250-first\r\n250 last\r\n"[..];
+        assert!(expect_reply(&mut reply, 250).is_ok());
+
+        let mut reply = &b"550 rejected\r\n"[..];
+        assert!(expect_reply(&mut reply, 250).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::harness::{is_read_only, single_statement};
+use crate::report::ReportItem;
+use anyhow::Context;
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, OptsBuilder, Row, Value};
+use std::time::Duration;
+use tabled::builder::Builder;
+
+/// Output style of captured EXPLAIN plans.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExplainFormat {
+    /// The tabular `EXPLAIN` output.
+    #[default]
+    Traditional,
+    /// `EXPLAIN FORMAT=JSON`.
+    Json,
+}
+
+/// Runs `EXPLAIN` for the worst example of each item against `dsn` and stores
+/// the plan in `ReportItem::explain`.
+///
+/// Statements the server cannot explain get the error message as their plan,
+/// so one bad example does not hide the others. Examples holding more than one
+/// statement are not sent at all, and the `EXPLAIN` runs as a prepared
+/// statement, which the server never splits into several.
+pub fn explain_items(items: &mut [ReportItem], dsn: &str, format: ExplainFormat) -> anyhow::Result<()> {
+    let opts = Opts::from_url(dsn).context("Invalid EXPLAIN DSN")?;
+    let mut conn = Conn::new(opts).context("Could not connect to the EXPLAIN target")?;
+
+    for item in items.iter_mut().filter(|item| !item.worst_example_query.is_empty()) {
+        let plan = explain(&mut conn, &item.worst_example_query, format);
+        item.explain = Some(plan.unwrap_or_else(|e| format!("EXPLAIN failed: {:#}", e)));
+    }
+    Ok(())
+}
+
+fn explain(conn: &mut Conn, sql: &str, format: ExplainFormat) -> anyhow::Result<String> {
+    let (database, statement) = single_statement(sql).context("The example holds more than one statement")?;
+    if let Some(db) = database {
+        conn.select_db(db)?;
+    }
+    let prefix = match format {
+        ExplainFormat::Traditional => "EXPLAIN",
+        ExplainFormat::Json => "EXPLAIN FORMAT=JSON",
+    };
+    let rows: Vec<Row> = conn.exec(format!("{} {}", prefix, statement), ())?;
+    Ok(match format {
+        ExplainFormat::Traditional => format_rows(&rows),
+        ExplainFormat::Json => rows.first().and_then(|r| r.as_ref(0)).map(value_text).unwrap_or_default(),
+    })
+}
+
+/// Runs `EXPLAIN ANALYZE` (MySQL 8.0.18+) for the worst example of each item
+/// against `dsn` and stores the operator timings in `ReportItem::explain_analyze`.
+///
+/// `EXPLAIN ANALYZE` executes the statement, so only single read-only
+/// statements are analyzed (see [`is_read_only`]), as prepared statements
+/// inside a read-only transaction that is rolled back afterwards. Statements
+/// are aborted after `timeout` through `max_execution_time`.
+/// Returns the number of items skipped because they modify data or hold
+/// more than one statement.
+pub fn analyze_items(items: &mut [ReportItem], dsn: &str, timeout: Duration) -> anyhow::Result<usize> {
+    let opts = Opts::from_url(dsn).context("Invalid EXPLAIN DSN")?;
+    let opts = OptsBuilder::from_opts(opts).read_timeout(Some(timeout + Duration::from_secs(5)));
+    let mut conn = Conn::new(opts).context("Could not connect to the EXPLAIN target")?;
+    conn.query_drop(format!("SET SESSION max_execution_time = {}", timeout.as_millis()))?;
+    Ok(analyze_with(items, &mut conn))
+}
+
+/// The statements [`analyze_items`] sends: its own fixed ones, and the
+/// `EXPLAIN ANALYZE` of an example as a prepared statement.
+trait AnalyzeTarget {
+    /// Selects the default database.
+    fn select_db(&mut self, db: &str) -> anyhow::Result<()>;
+    /// Runs a fixed statement such as `ROLLBACK`.
+    fn run(&mut self, sql: &str) -> anyhow::Result<()>;
+    /// Runs a prepared statement and returns the first column of its first row.
+    fn first_value(&mut self, sql: &str) -> anyhow::Result<String>;
+}
+
+impl AnalyzeTarget for Conn {
+    fn select_db(&mut self, db: &str) -> anyhow::Result<()> {
+        Ok(Conn::select_db(self, db)?)
+    }
+
+    fn run(&mut self, sql: &str) -> anyhow::Result<()> {
+        Ok(self.query_drop(sql)?)
+    }
+
+    fn first_value(&mut self, sql: &str) -> anyhow::Result<String> {
+        let row: Option<Row> = self.exec_first(sql, ())?;
+        Ok(row.as_ref().and_then(|r| r.as_ref(0)).map(value_text).unwrap_or_default())
+    }
+}
+
+/// Analyzes the read-only examples of `items` on `target`, returning how many were skipped.
+fn analyze_with(items: &mut [ReportItem], target: &mut impl AnalyzeTarget) -> usize {
+    let mut skipped = 0;
+    for item in items {
+        let statement = single_statement(&item.worst_example_query).filter(|_| is_read_only(&item.worst_example_query));
+        let Some((database, statement)) = statement else {
+            skipped += 1;
+            continue;
+        };
+        let plan = analyze(target, database, statement);
+        item.explain_analyze = Some(plan.unwrap_or_else(|e| format!("EXPLAIN ANALYZE failed: {:#}", e)));
+    }
+    skipped
+}
+
+fn analyze(target: &mut impl AnalyzeTarget, database: Option<&str>, statement: &str) -> anyhow::Result<String> {
+    if let Some(db) = database {
+        target.select_db(db)?;
+    }
+    target.run("START TRANSACTION READ ONLY")?;
+    let result = target.first_value(&format!("EXPLAIN ANALYZE {}", statement));
+    target.run("ROLLBACK")?;
+    result
+}
+
+/// Formats result rows as a text table with the column names as header.
+pub(crate) fn format_rows(rows: &[Row]) -> String {
+    let Some(first) = rows.first() else {
+        return String::new();
+    };
+    let mut builder = Builder::default();
+    builder.push_record(first.columns_ref().iter().map(|c| c.name_str().into_owned()));
+    for row in rows {
+        builder.push_record((0..row.len()).map(|i| row.as_ref(i).map(value_text).unwrap_or_default()));
+    }
+    builder.build().to_string()
+}
+
+/// Renders a column value the way the `mysql` client shows it.
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::NULL => "NULL".to_string(),
+        Value::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        Value::Int(n) => n.to_string(),
+        Value::UInt(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        Value::Double(n) => n.to_string(),
+        other => other.as_sql(true).trim_matches('\'').to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the statements it is sent.
+    #[derive(Default)]
+    struct MockTarget(Vec<String>);
+
+    impl AnalyzeTarget for MockTarget {
+        fn select_db(&mut self, db: &str) -> anyhow::Result<()> {
+            self.0.push(format!("USE {}", db));
+            Ok(())
+        }
+
+        fn run(&mut self, sql: &str) -> anyhow::Result<()> {
+            self.0.push(sql.to_string());
+            Ok(())
+        }
+
+        fn first_value(&mut self, sql: &str) -> anyhow::Result<String> {
+            self.0.push(sql.to_string());
+            Ok("-> Table scan on t".to_string())
+        }
+    }
+
+    #[test]
+    fn test_analyze_refuses_writes_and_multiple_statements() {
+        let examples = ["use shop;\nSELECT * FROM t WHERE a = ';';", "DELETE FROM t WHERE id = 1;", "SELECT 1; DROP TABLE t;", "SELECT * FROM t /*!; DROP TABLE t */;"];
+        let log: String = examples.iter().enumerate()
+            .map(|(i, sql)| format!("# User@Host: app[app] @ localhost []\n# Query_time: {}\n{}\n", 10 - i, sql))
+            .collect();
+        let mut digest = crate::digest::Digest::new(crate::digest::DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let mut items = digest.finish();
+        assert_eq!(items.iter().map(|item| item.worst_example_query.as_str()).collect::<Vec<_>>(), examples);
+        let mut target = MockTarget::default();
+        assert_eq!(analyze_with(&mut items, &mut target), 3);
+        assert_eq!(target.0, ["USE shop", "START TRANSACTION READ ONLY", "EXPLAIN ANALYZE SELECT * FROM t WHERE a = ';'", "ROLLBACK"]);
+        assert_eq!(items[0].explain_analyze.as_deref(), Some("-> Table scan on t"));
+        assert!(items[1..].iter().all(|item| item.explain_analyze.is_none()));
+    }
+
+    #[test]
+    fn test_value_text() {
+        assert_eq!(value_text(&Value::NULL), "NULL");
+        assert_eq!(value_text(&Value::Bytes(b"ref".to_vec())), "ref");
+        assert_eq!(value_text(&Value::Int(-3)), "-3");
+        assert_eq!(value_text(&Value::Date(2024, 5, 1, 0, 0, 0, 0)), "2024-05-01");
+    }
+}
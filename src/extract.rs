@@ -0,0 +1,168 @@
+use crate::fingerprint::{fingerprint_with, FingerprintOptions};
+use crate::parser::{LogParser, Query};
+use crate::redact::{pseudonym, redact_sql};
+use crate::timezone::Timezone;
+use regex::Regex;
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::sync::OnceLock;
+
+static RE_USER_HOST: OnceLock<Regex> = OnceLock::new();
+
+/// Selects queries by query ID or by fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryMatcher {
+    /// A full or abbreviated (prefix) query ID, in lowercase.
+    Id(String),
+    /// The fingerprint of a sample statement.
+    Fingerprint(String),
+}
+
+impl QueryMatcher {
+    /// Treats up to 32 hexadecimal digits as a query ID, and anything else as
+    /// SQL whose fingerprint must match.
+    pub fn parse(pattern: &str, options: &FingerprintOptions) -> Self {
+        let pattern = pattern.trim();
+        if !pattern.is_empty() && pattern.len() <= 32 && pattern.chars().all(|c| c.is_ascii_hexdigit()) {
+            QueryMatcher::Id(pattern.to_lowercase())
+        } else {
+            QueryMatcher::Fingerprint(fingerprint_with(pattern, options))
+        }
+    }
+
+    /// Returns true if a query with this fingerprint is selected.
+    pub fn matches(&self, fingerprint: &str) -> bool {
+        match self {
+            QueryMatcher::Id(prefix) => crate::report::query_id(fingerprint).starts_with(prefix.as_str()),
+            QueryMatcher::Fingerprint(expected) => fingerprint == expected,
+        }
+    }
+}
+
+/// Copies the raw log blocks of the queries accepted by `predicate` to `writer`,
+/// returning how many were written.
+///
+/// Each block is terminated by a newline so the output stays a valid slow log.
+/// Timestamps without an offset are read as local time in `log_timezone`.
+pub fn copy_events<R: BufRead>(reader: R, log_timezone: Timezone, writer: &mut dyn Write, predicate: impl FnMut(&Query) -> bool) -> anyhow::Result<u64> {
+    write_events(reader, log_timezone, writer, predicate, |raw| Cow::Borrowed(raw))
+}
+
+/// Like [`copy_events`], but writes each block through [`sanitize_event`].
+pub fn copy_sanitized_events<R: BufRead>(reader: R, log_timezone: Timezone, writer: &mut dyn Write, predicate: impl FnMut(&Query) -> bool) -> anyhow::Result<u64> {
+    write_events(reader, log_timezone, writer, predicate, |raw| Cow::Owned(sanitize_event(raw)))
+}
+
+fn write_events<R: BufRead>(
+    reader: R,
+    log_timezone: Timezone,
+    writer: &mut dyn Write,
+    mut predicate: impl FnMut(&Query) -> bool,
+    rewrite: fn(&str) -> Cow<'_, str>,
+) -> anyhow::Result<u64> {
+    let mut parser = LogParser::new(reader).with_log_timezone(log_timezone);
+    let mut count = 0;
+    while let Some(event) = parser.next_event() {
+        let event = event?;
+        if predicate(&event.query) {
+            let block = rewrite(&event.raw);
+            writer.write_all(block.as_bytes())?;
+            if !block.ends_with('\n') {
+                writer.write_all(b"\n")?;
+            }
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Rewrites a raw log block so it can be shared: users and hosts in the
+/// `User@Host` header become pseudonyms and literals in the SQL become `?`.
+///
+/// Other headers, `use` and `SET timestamp=` lines are kept, so timings and
+/// structure are preserved.
+pub fn sanitize_event(raw: &str) -> String {
+    let re_user_host = RE_USER_HOST.get_or_init(|| Regex::new(r"^# User@Host: (.*?) @ (.*?)(\s+Id:\s*\d+)?$").unwrap());
+
+    let mut out = String::with_capacity(raw.len());
+    let mut lines = raw.split_inclusive('\n');
+    let mut sql = String::new();
+    for line in lines.by_ref() {
+        let trimmed = line.trim();
+        let preamble = trimmed.starts_with('#')
+            || trimmed.is_empty()
+            || trimmed.starts_with("SET timestamp=")
+            || trimmed.get(..4).is_some_and(|k| k.eq_ignore_ascii_case("use "));
+        if !preamble {
+            sql.push_str(line);
+            break;
+        }
+        match re_user_host.captures(line.trim_end()) {
+            Some(caps) => {
+                let user = pseudonym("user", &caps[1]);
+                let host = pseudonym("host", &caps[2]);
+                let id = caps.get(3).map_or("", |m| m.as_str());
+                out.push_str(&format!("# User@Host: {}[{}] @ {} []{}\n", user, user, host, id));
+            }
+            None => out.push_str(line),
+        }
+    }
+    sql.extend(lines);
+    out.push_str(&redact_sql(&sql));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "# User@Host: a[a] @ h []\n# Query_time: 1.0\nSELECT * FROM t WHERE id = 1;\n# User@Host: b[b] @ h []\n# Query_time: 2.0\nDELETE FROM t WHERE id = 2;\n# User@Host: c[c] @ h []\n# Query_time: 3.0\nSELECT * FROM t WHERE id = 3;\n";
+
+    #[test]
+    fn test_copy_events_terminates_last_block() {
+        let mut out = Vec::new();
+        let count = copy_events("# Query_time: 1.0\nSELECT 1;".as_bytes(), Timezone::default(), &mut out, |_| true).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(out, b"# Query_time: 1.0\nSELECT 1;\n");
+    }
+
+    #[test]
+    fn test_sanitize_event() {
+        let raw = "# Time: 2023-10-27T10:00:00Z\n# User@Host: app[app] @ web1 [10.0.0.1]  Id:    42\n# Query_time: 1.5  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10\nuse shop;\nSET timestamp=1698400800;\nSELECT * FROM users\nWHERE email = 'bob@example.com' AND id = 7;\n";
+        let sanitized = sanitize_event(raw);
+        let user = pseudonym("user", "app[app]");
+        let host = pseudonym("host", "web1 [10.0.0.1]");
+
+        assert_eq!(sanitized, format!("# Time: 2023-10-27T10:00:00Z\n# User@Host: {user}[{user}] @ {host} []  Id:    42\n# Query_time: 1.5  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10\nuse shop;\nSET timestamp=1698400800;\nSELECT * FROM users\nWHERE email = ? AND id = ?;\n"));
+
+        let query = LogParser::new(sanitized.as_bytes()).next().unwrap().unwrap();
+        assert_eq!((query.query_time, query.rows_examined), (1.5, 10));
+        assert_eq!(query.user_host, format!("{user}[{user}] @ {host} []"));
+    }
+
+    #[test]
+    fn test_matcher_parse() {
+        let options = FingerprintOptions::default();
+        assert_eq!(QueryMatcher::parse("ABC123", &options), QueryMatcher::Id("abc123".to_string()));
+        assert_eq!(QueryMatcher::parse("SELECT 1", &options), QueryMatcher::Fingerprint("select ?".to_string()));
+    }
+
+    #[test]
+    fn test_copy_events_by_fingerprint_and_id() {
+        let options = FingerprintOptions::default();
+        let matcher = QueryMatcher::parse("select * from t where id = 42;", &options);
+        let mut out = Vec::new();
+        let count = copy_events(LOG.as_bytes(), Timezone::default(), &mut out, |q| matcher.matches(&fingerprint_with(&q.sql_text, &options))).unwrap();
+        assert_eq!(count, 2);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("# User@Host: a[a]"));
+        assert!(out.contains("id = 3;"));
+        assert!(!out.contains("DELETE"));
+
+        let id = crate::report::query_id(&fingerprint_with("DELETE FROM t WHERE id = 2;", &options));
+        let matcher = QueryMatcher::parse(&id[..8], &options);
+        let mut out = Vec::new();
+        copy_events(LOG.as_bytes(), Timezone::default(), &mut out, |q| matcher.matches(&fingerprint_with(&q.sql_text, &options))).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "# User@Host: b[b] @ h []\n# Query_time: 2.0\nDELETE FROM t WHERE id = 2;\n");
+    }
+}
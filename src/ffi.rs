@@ -0,0 +1,223 @@
+//! C ABI for embedding the parser and digest in non-Rust programs.
+//!
+//! See `include/rs_slowquery_digest.h` for the C declarations. Strings returned
+//! by `sqd_fingerprint` and `sqd_digest_json` are owned by the caller and must be
+//! released with `sqd_string_free`.
+//!
+//! Panics are caught at every entry point, as unwinding into C is undefined
+//! behaviour; the function then returns NULL, or -1 from `sqd_parser_next`.
+
+use crate::digest::{Digest, DigestConfig};
+use crate::fingerprint::fingerprint;
+use crate::parser::{LogParser, Query};
+use std::ffi::{c_char, CStr, CString};
+use std::io::Cursor;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// A parsed event. String pointers stay valid until the next call on the same parser.
+#[repr(C)]
+pub struct SqdEvent {
+    pub query_time: f64,
+    pub lock_time: f64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    /// Microseconds since the Unix epoch, or -1 if the event has no timestamp.
+    pub timestamp_us: i64,
+    pub user_host: *const c_char,
+    pub sql_text: *const c_char,
+    pub fingerprint: *const c_char,
+}
+
+/// Opaque parser handle.
+pub struct SqdParser {
+    parser: LogParser<Cursor<Vec<u8>>>,
+    // Backing storage for the strings of the most recent event
+    current: Vec<CString>,
+}
+
+/// Creates a parser over a copy of `buf`. Returns NULL if `buf` is NULL.
+///
+/// # Safety
+///
+/// `buf` must point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_parser_new(buf: *const u8, len: usize) -> *mut SqdParser {
+    if buf.is_null() {
+        return ptr::null_mut();
+    }
+    guard(ptr::null_mut(), || {
+        let data = std::slice::from_raw_parts(buf, len).to_vec();
+        Box::into_raw(Box::new(SqdParser {
+            parser: LogParser::new(Cursor::new(data)),
+            current: Vec::new(),
+        }))
+    })
+}
+
+/// Fills `event` with the next parsed event. Returns 1 on success, 0 at end of
+/// input and -1 if parsing panicked, after which the parser must only be freed.
+///
+/// # Safety
+///
+/// `parser` must come from `sqd_parser_new` and `event` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_parser_next(parser: *mut SqdParser, event: *mut SqdEvent) -> i32 {
+    let (Some(parser), Some(event)) = (parser.as_mut(), event.as_mut()) else {
+        return 0;
+    };
+
+    guard(-1, || {
+        let query: Query = loop {
+            match parser.parser.next() {
+                Some(Ok(query)) => break query,
+                Some(Err(_)) => continue,
+                None => return 0,
+            }
+        };
+
+        parser.current = vec![
+            to_cstring(&query.user_host),
+            to_cstring(&query.sql_text),
+            to_cstring(&fingerprint(&query.sql_text)),
+        ];
+        *event = SqdEvent {
+            query_time: query.query_time,
+            lock_time: query.lock_time,
+            rows_sent: query.rows_sent,
+            rows_examined: query.rows_examined,
+            timestamp_us: query.timestamp.map(|ts| ts.timestamp_micros()).unwrap_or(-1),
+            user_host: parser.current[0].as_ptr(),
+            sql_text: parser.current[1].as_ptr(),
+            fingerprint: parser.current[2].as_ptr(),
+        };
+        1
+    })
+}
+
+/// Releases a parser.
+///
+/// # Safety
+///
+/// `parser` must come from `sqd_parser_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_parser_free(parser: *mut SqdParser) {
+    if !parser.is_null() {
+        guard((), || drop(Box::from_raw(parser)));
+    }
+}
+
+/// Returns the fingerprint of a NUL-terminated SQL string, or NULL on invalid input or a panic.
+///
+/// # Safety
+///
+/// `sql` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_fingerprint(sql: *const c_char) -> *mut c_char {
+    if sql.is_null() {
+        return ptr::null_mut();
+    }
+    guard(ptr::null_mut(), || match CStr::from_ptr(sql).to_str() {
+        Ok(sql) => to_cstring(&fingerprint(sql)).into_raw(),
+        Err(_) => ptr::null_mut(),
+    })
+}
+
+/// Digests a slow log buffer and returns the ranked report items as a JSON array.
+/// A `limit` of 0 includes every fingerprint, DDL statements included. Returns
+/// NULL on error or a panic.
+///
+/// # Safety
+///
+/// `buf` must point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_digest_json(buf: *const u8, len: usize, limit: usize) -> *mut c_char {
+    if buf.is_null() {
+        return ptr::null_mut();
+    }
+    guard(ptr::null_mut(), || {
+        let data = std::slice::from_raw_parts(buf, len);
+        let Ok(config) = DigestConfig::builder().limit(limit).include_ddl(true).build() else {
+            return ptr::null_mut();
+        };
+        let mut digest = Digest::new(config);
+        digest.add_reader(data);
+        match serde_json::to_string(&digest.finish()) {
+            Ok(json) => to_cstring(&json).into_raw(),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Releases a string returned by this library.
+///
+/// # Safety
+///
+/// `s` must come from `sqd_fingerprint` or `sqd_digest_json` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sqd_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        guard((), || drop(CString::from_raw(s)));
+    }
+}
+
+/// Runs the body of an entry point, returning `on_panic` if it panics.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+/// Converts to a C string, dropping interior NUL bytes.
+fn to_cstring(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).expect("NUL bytes removed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &[u8] = b"# Time: 2023-10-27T10:00:00Z
+# User@Host: app[app] @ localhost []
+# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 1;
+# User@Host: app[app] @ localhost []
+# Query_time: 0.5  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 2;
+";
+
+    #[test]
+    fn test_parser_iteration() {
+        unsafe {
+            let parser = sqd_parser_new(LOG.as_ptr(), LOG.len());
+            let mut event: SqdEvent = std::mem::zeroed();
+
+            assert_eq!(sqd_parser_next(parser, &mut event), 1);
+            assert_eq!(event.query_time, 2.0);
+            assert_eq!(event.timestamp_us, 1698400800000000);
+            assert_eq!(CStr::from_ptr(event.fingerprint).to_str().unwrap(), "select * from t where id = ?;");
+
+            assert_eq!(sqd_parser_next(parser, &mut event), 1);
+            assert_eq!(event.timestamp_us, -1);
+            assert_eq!(sqd_parser_next(parser, &mut event), 0);
+            sqd_parser_free(parser);
+        }
+    }
+
+    #[test]
+    fn test_panics_do_not_unwind() {
+        assert_eq!(guard(-1, || -> i32 { panic!("boom") }), -1);
+        assert!(guard(ptr::null_mut(), || -> *mut c_char { panic!("boom") }).is_null());
+        assert_eq!(guard(-1, || 1), 1);
+    }
+
+    #[test]
+    fn test_digest_json() {
+        unsafe {
+            let log = [LOG, b"# User@Host: admin[admin] @ localhost []\n# Query_time: 0.1\nDROP TABLE old_t;\n"].concat();
+            let json = sqd_digest_json(log.as_ptr(), log.len(), 0);
+            let items: serde_json::Value = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(items[0]["count"], 2);
+            assert_eq!(items[1]["normalized_query"], "drop table old_t;");
+            sqd_string_free(json);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::sync::OnceLock;
 
 static RE_NUMBER: OnceLock<Regex> = OnceLock::new();
@@ -6,44 +7,168 @@ static RE_STRING: OnceLock<Regex> = OnceLock::new();
 static RE_WHITESPACE: OnceLock<Regex> = OnceLock::new();
 static RE_COMMENT: OnceLock<Regex> = OnceLock::new();
 static RE_USE: OnceLock<Regex> = OnceLock::new();
+static RE_MYSQL_LITERAL: OnceLock<Regex> = OnceLock::new();
+static RE_POSTGRES_LITERAL: OnceLock<Regex> = OnceLock::new();
+
+/// SQL dialect whose quoting, escaping, comment and placeholder syntax
+/// fingerprints follow.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// Single- and double-quoted strings with backslash escapes, backquoted
+    /// identifiers, and `#`, `-- ` and `/* */` comments.
+    #[default]
+    Mysql,
+    /// The MySQL rules, which MariaDB shares.
+    Mariadb,
+    /// The MySQL rules, which TiDB shares.
+    Tidb,
+    /// Single-quoted strings without backslash escapes unless written `E'...'`,
+    /// dollar-quoted strings, double-quoted identifiers, `$1` placeholders,
+    /// and `--` and `/* */` comments.
+    Postgres,
+}
+
+impl Dialect {
+    /// Matches the comments (group `comment`), string literals (`string`),
+    /// the opening tags of dollar-quoted strings (`dollar`), quoted
+    /// identifiers (`ident`) and numbered placeholders (`param`) of the
+    /// dialect.
+    fn literal_regex(self) -> &'static Regex {
+        match self {
+            Dialect::Mysql | Dialect::Mariadb | Dialect::Tidb => RE_MYSQL_LITERAL.get_or_init(|| {
+                Regex::new(r#"(?s)(?P<comment>/\*.*?\*/|--(?:[ \t][^\n]*)?(?m:$)|#[^\n]*)|(?P<string>'(?:[^'\\]|\\.|'')*'|"(?:[^"\\]|\\.|"")*")|(?P<ident>`(?:[^`]|``)*`)"#).unwrap()
+            }),
+            // Block comments are not matched nested
+            Dialect::Postgres => RE_POSTGRES_LITERAL.get_or_init(|| {
+                Regex::new(r#"(?s)(?P<comment>/\*.*?\*/|--[^\n]*)|(?P<string>\b[Ee]'(?:[^'\\]|\\.|'')*'|'(?:[^']|'')*')|(?P<dollar>\$(?:[A-Za-z_][A-Za-z0-9_]*)?\$)|(?P<ident>"(?:[^"]|"")*")|(?P<param>\$\d+)"#).unwrap()
+            }),
+        }
+    }
+}
+
+/// Options controlling SQL normalization.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FingerprintOptions {
+    /// Remove `USE <db>;` statements.
+    pub strip_use: bool,
+    /// Convert the fingerprint to lowercase.
+    pub lowercase: bool,
+    /// Keep the SQL text as logged instead of normalizing it, only trimmed
+    /// (and without `USE` statements if `strip_use` is set).
+    #[serde(default)]
+    pub raw: bool,
+    /// Dialect of the SQL text.
+    #[serde(default)]
+    pub dialect: Dialect,
+}
+
+impl Default for FingerprintOptions {
+    fn default() -> Self {
+        Self {
+            strip_use: true,
+            lowercase: true,
+            raw: false,
+            dialect: Dialect::default(),
+        }
+    }
+}
 
 /// Generates a fingerprint for a SQL query by normalizing it.
 ///
 /// Normalization includes:
 /// - Removing `USE` statements
 /// - Removing comments
-/// - Replacing strings and numbers with `?`
+/// - Replacing strings, numbers and placeholders with `?`
 /// - Collapsing whitespace
 /// - Converting to lowercase
 pub fn fingerprint(sql: &str) -> String {
-    let re_number = RE_NUMBER.get_or_init(|| Regex::new(r"\b\d+\b").unwrap());
-    let re_string = RE_STRING.get_or_init(|| Regex::new(r"'(?:[^']|'')*'").unwrap()); // Simple string regex
+    fingerprint_with(sql, &FingerprintOptions::default())
+}
+
+/// Matches numeric literals.
+pub(crate) fn number_regex() -> &'static Regex {
+    RE_NUMBER.get_or_init(|| Regex::new(r"\b\d+\b").unwrap())
+}
+
+/// Matches single-quoted string literals.
+pub(crate) fn string_regex() -> &'static Regex {
+    RE_STRING.get_or_init(|| Regex::new(r"'(?:[^']|'')*'").unwrap()) // Simple string regex
+}
+
+/// Matches `/* ... */` and `-- ...` comments.
+pub(crate) fn comment_regex() -> &'static Regex {
+    RE_COMMENT.get_or_init(|| Regex::new(r"(?s:/\*.*?\*/)|--[^\n]*").unwrap())
+}
+
+/// Generates a fingerprint for a SQL query using the given options.
+pub fn fingerprint_with(sql: &str, options: &FingerprintOptions) -> String {
+    let re_number = number_regex();
     let re_whitespace = RE_WHITESPACE.get_or_init(|| Regex::new(r"\s+").unwrap());
-    let re_comment = RE_COMMENT.get_or_init(|| Regex::new(r"(?s:/\*.*?\*/)|--[^\n]*").unwrap());
     let re_use = RE_USE.get_or_init(|| Regex::new(r"(?i)use\s+\S+;").unwrap());
 
     // 0. Remove 'use <db>;' statements
-    let no_use = re_use.replace_all(sql, "");
-
-    // 1. Remove comments
-    let no_comments = re_comment.replace_all(&no_use, "");
+    let no_use = if options.strip_use { re_use.replace_all(sql, "") } else { sql.into() };
+    if options.raw {
+        return no_use.trim().to_string();
+    }
 
-    // 2. Replace strings with ?
-    let no_strings = re_string.replace_all(&no_comments, "?");
+    // 1. Remove comments, and replace strings and placeholders with ?
+    let no_strings = replace_literals(&no_use, options.dialect);
 
-    // 3. Replace numbers with ?
+    // 2. Replace numbers with ?
     let no_numbers = re_number.replace_all(&no_strings, "?");
 
-    // 4. Collapse whitespace
+    // 3. Collapse whitespace
     let normalized = re_whitespace.replace_all(&no_numbers, " ").trim().to_string();
 
-    normalized.to_lowercase()
+    if options.lowercase {
+        normalized.to_lowercase()
+    } else {
+        normalized
+    }
+}
+
+/// Removes the comments and replaces the strings and placeholders of `sql`
+/// with `?`, scanning left to right so that each hides what the others would
+/// match inside it.
+fn replace_literals(sql: &str, dialect: Dialect) -> String {
+    let re_literal = dialect.literal_regex();
+    let mut out = String::with_capacity(sql.len());
+    let mut pos = 0;
+    while let Some(caps) = re_literal.captures_at(sql, pos) {
+        let found = caps.get(0).unwrap();
+        out.push_str(&sql[pos..found.start()]);
+        pos = found.end();
+        if caps.name("comment").is_some() {
+            continue;
+        }
+        if let Some(ident) = caps.name("ident") {
+            out.push_str(ident.as_str());
+            continue;
+        }
+        if let Some(tag) = caps.name("dollar") {
+            // A dollar-quoted string ends at the same tag; unterminated, it runs to the end
+            pos = sql[pos..].find(tag.as_str()).map_or(sql.len(), |end| pos + end + tag.len());
+        }
+        out.push('?');
+    }
+    out.push_str(&sql[pos..]);
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_fingerprint_raw() {
+        let options = FingerprintOptions { raw: true, ..Default::default() };
+        assert_eq!(fingerprint_with("use shop;\nSELECT * FROM t WHERE id = ?  ", &options), "SELECT * FROM t WHERE id = ?");
+        assert_ne!(fingerprint_with("SELECT 1", &options), fingerprint_with("SELECT 2", &options));
+    }
+
     #[test]
     fn test_fingerprint_basic() {
         let sql = "SELECT * FROM users WHERE id = 1";
@@ -86,6 +211,24 @@ mod tests {
         assert_eq!(fingerprint(sql), "select * from users");
     }
 
+    #[test]
+    fn test_fingerprint_with_options() {
+        let options = FingerprintOptions { strip_use: false, lowercase: false, raw: false, dialect: Dialect::Mysql };
+        assert_eq!(fingerprint_with("USE mydb; SELECT * FROM t WHERE id = 1", &options), "USE mydb; SELECT * FROM t WHERE id = ?");
+    }
+
+    #[test]
+    fn test_fingerprint_dialects() {
+        let sql = r#"SELECT `a#b`, "it\"s" FROM t WHERE s = 'it\'s -- not a comment' AND n = 1 # comment"#;
+        assert_eq!(fingerprint(sql), "select `a#b`, ? from t where s = ? and n = ?");
+        assert_eq!(fingerprint("SELECT 5--1"), "select ?--?");
+
+        let postgres = FingerprintOptions { dialect: Dialect::Postgres, ..Default::default() };
+        let sql = r#"SELECT "Name", $$it's$$ FROM t WHERE path = 'C:\' AND note = E'it\'s' AND id = $1 AND n = $2::int -- comment"#;
+        assert_eq!(fingerprint_with(sql, &postgres), r#"select "name", ? from t where path = ? and note = ? and id = ? and n = ?::int"#);
+        assert_eq!(fingerprint_with("SELECT $body$ a $$ b $body$, 1", &postgres), "select ?, ?");
+    }
+
     #[test]
     fn test_fingerprint_multiline_sql() {
         let sql = "SELECT * FROM users\n WHERE\n name = 'Alice'\n AND age = 17";
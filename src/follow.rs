@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A reader that waits for more data at end of file, like `tail -F`.
+///
+/// Truncation (e.g. `copytruncate` rotation) restarts reading from the
+/// beginning, and on Unix a file replaced by rename rotation is reopened once
+/// the old file has been read to its end. Reads return end of file only after
+/// the stop flag is set.
+pub struct FollowReader {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    poll_interval: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl FollowReader {
+    /// Opens `path` for following. When `from_start` is false, reading starts at
+    /// the current end of the file so only new entries are seen.
+    pub fn open(path: &Path, from_start: bool, stop: Arc<AtomicBool>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let position = if from_start { 0 } else { file.seek(SeekFrom::End(0))? };
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            position,
+            poll_interval: Duration::from_millis(500),
+            stop,
+        })
+    }
+
+    /// Sets how long to sleep between checks for new data.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Detects truncation or replacement of the followed file.
+    fn check_rotation(&mut self) -> io::Result<()> {
+        let Ok(on_disk) = std::fs::metadata(&self.path) else {
+            // The file is momentarily missing during rotation; try again later
+            return Ok(());
+        };
+
+        if !same_file(&self.file.metadata()?, &on_disk) {
+            self.file = File::open(&self.path)?;
+            self.position = 0;
+        } else if on_disk.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 {
+                self.position += n as u64;
+                return Ok(n);
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            self.check_rotation()?;
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &std::fs::Metadata, _b: &std::fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_follow_reads_appended_data_and_truncation() {
+        let path = std::env::temp_dir().join(format!("sqd-follow-{}.log", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut reader = FollowReader::open(&path, true, stop.clone()).unwrap().with_poll_interval(Duration::from_millis(10));
+
+        let mut buf = [0u8; 64];
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"first\n");
+
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"second\n").unwrap();
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"second\n");
+
+        std::fs::write(&path, "new\n").unwrap();
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"new\n");
+
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use crate::audit::build_query;
+use crate::parser::{is_noise, parse_time_header, Query, QueryReader, RejectedBlock};
+use crate::timezone::Timezone;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::OnceLock;
+
+static RE_ENTRY: OnceLock<Regex> = OnceLock::new();
+
+/// Connections followed at once; the users of further ones are not known.
+pub const MAX_CONNECTIONS: usize = 100_000;
+
+/// Parses the MySQL and MariaDB general query log: one entry per command,
+/// `<time>\t<id> <command>\t<argument>`, such as
+/// `2024-05-01T10:00:00.123456Z\t   12 Query\tSELECT 1`.
+///
+/// `Query` and `Execute` entries become queries; `Connect`, `Change user`,
+/// `Init DB` and `Quit` entries tell the user and database of each connection.
+/// Lines that do not start an entry continue the one before them, as
+/// multi-line statements are logged as they were sent. Older servers log the
+/// time as `YYMMDD H:MM:SS` and leave it out of entries logged in the same
+/// second as the one before.
+///
+/// The general log has no execution times or row counts, so these are 0;
+/// the queries are only good for counts and throughput.
+pub struct GeneralLogParser<R> {
+    reader: R,
+    line: String,
+    /// The entry being read.
+    entry: Option<Entry>,
+    connections: HashMap<u64, Connection>,
+    /// Time of the last entry that had one.
+    last_time: Option<DateTime<Utc>>,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    noise_lines: u64,
+    log_timezone: Timezone,
+    lines_read: u64,
+    omitted: u64,
+    rejected: Option<Vec<RejectedBlock>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    time: Option<DateTime<Utc>>,
+    thread_id: u64,
+    command: String,
+    argument: String,
+}
+
+/// The account and database of a connection.
+#[derive(Debug, Default)]
+struct Connection {
+    user_host: String,
+    db: Option<String>,
+}
+
+impl<R: BufRead> GeneralLogParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            entry: None,
+            connections: HashMap::new(),
+            last_time: None,
+            bytes_read: 0,
+            skipped_blocks: 0,
+            noise_lines: 0,
+            log_timezone: Timezone::default(),
+            lines_read: 0,
+            omitted: 0,
+            rejected: None,
+        }
+    }
+
+    /// Sets the timezone of log times without an offset (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Reads up to the start of the next entry and returns the completed one.
+    fn next_entry(&mut self) -> Option<Result<Entry>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return self.entry.take().map(Ok),
+                Ok(n) => {
+                    self.bytes_read += n as u64;
+                    self.lines_read += 1;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+            let line = std::mem::take(&mut self.line);
+            let completed = self.add_line(line.trim_end_matches(['\n', '\r']));
+            self.line = line;
+            if let Some(entry) = completed {
+                return Some(Ok(entry));
+            }
+        }
+    }
+
+    /// Starts or continues an entry with a line, returning the entry it completes.
+    fn add_line(&mut self, line: &str) -> Option<Entry> {
+        let re = RE_ENTRY.get_or_init(|| Regex::new(r"^(\d{4}-\d{2}-\d{2}T\S+|\d{6} +\d{1,2}:\d{2}:\d{2})?\t+ *(\d+) ([A-Z][A-Za-z ]*?)(?:\t(.*))?$").unwrap());
+        // Written when the server starts or the log is flushed
+        if is_noise(line) {
+            self.noise_lines += 1;
+            return self.entry.take();
+        }
+        let Some(caps) = re.captures(line) else {
+            match &mut self.entry {
+                Some(entry) => {
+                    entry.argument.push('\n');
+                    entry.argument.push_str(line);
+                }
+                None if line.trim().is_empty() => {}
+                None => self.reject(line, "Not a general log entry"),
+            }
+            return None;
+        };
+        let time = match caps.get(1) {
+            Some(time) => match parse_time_header(time.as_str(), self.log_timezone) {
+                Some(time) => Some(time),
+                None => {
+                    self.reject(line, "Invalid time");
+                    return None;
+                }
+            },
+            None => self.last_time,
+        };
+        self.last_time = time;
+        let entry = Entry {
+            time,
+            thread_id: caps[2].parse().unwrap_or_default(),
+            command: caps[3].to_string(),
+            argument: caps.get(4).map_or_else(String::new, |argument| argument.as_str().to_string()),
+        };
+        self.entry.replace(entry)
+    }
+
+    fn reject(&mut self, line: &str, reason: &str) {
+        self.skipped_blocks += 1;
+        tracing::debug!("Skipping general log line {}: {}", self.lines_read, reason);
+        if let Some(rejected) = &mut self.rejected {
+            rejected.push(RejectedBlock { line: self.lines_read, reason: reason.to_string(), raw: line.to_string() });
+        }
+    }
+
+    /// Returns the query of a `Query` or `Execute` entry, and follows the connection for others.
+    fn handle(&mut self, entry: Entry) -> Option<Query> {
+        match entry.command.as_str() {
+            "Query" | "Execute" => {
+                let connection = self.connections.get(&entry.thread_id);
+                let user_host = connection.map(|connection| connection.user_host.clone()).unwrap_or_default();
+                let db = connection.and_then(|connection| connection.db.as_deref());
+                build_query(entry.argument, db, user_host, entry.time, Some(entry.thread_id), &HashMap::new())
+            }
+            "Connect" | "Change user" => {
+                // `app@10.0.0.5 on shop using TCP/IP`; failed logins log `Access denied ...` instead
+                let (user, rest) = entry.argument.split_once('@')?;
+                if user.contains(char::is_whitespace) {
+                    return None;
+                }
+                let mut words = rest.split(' ');
+                let host = words.next().unwrap_or_default();
+                let db = words.skip_while(|word| *word != "on").nth(1).filter(|db| !db.is_empty() && *db != "using");
+                let connection = Connection { user_host: format!("{}[{}] @ {} []", user, user, host), db: db.map(str::to_string) };
+                if self.connections.len() >= MAX_CONNECTIONS && !self.connections.contains_key(&entry.thread_id) {
+                    if self.omitted == 0 {
+                        tracing::warn!("More than {} open connections; the users of later ones are not known", MAX_CONNECTIONS);
+                    }
+                    self.omitted += 1;
+                    return None;
+                }
+                self.connections.insert(entry.thread_id, connection);
+                None
+            }
+            "Init DB" => {
+                let db = entry.argument.trim().to_string();
+                self.connections.entry(entry.thread_id).or_default().db = Some(db).filter(|db| !db.is_empty());
+                None
+            }
+            "Quit" => {
+                self.connections.remove(&entry.thread_id);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for GeneralLogParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.next_entry()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(query) = self.handle(entry) {
+                return Some(Ok(query));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> QueryReader for GeneralLogParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    fn noise_lines(&self) -> u64 {
+        self.noise_lines
+    }
+
+    fn keep_rejected(&mut self) {
+        self.rejected.get_or_insert_with(Vec::new);
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.rejected.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let log = "/usr/sbin/mysqld, Version: 8.0.36 (MySQL Community Server - GPL). started with:
+Tcp port: 3306  Unix socket: /var/run/mysqld/mysqld.sock
+Time                 Id Command    Argument
+2024-05-01T10:00:00.000100Z\t   12 Connect\tapp@10.0.0.5 on shop using TCP/IP
+2024-05-01T10:00:00.000200Z\t   12 Query\tSELECT *
+FROM orders
+WHERE id = 1
+2024-05-01T10:00:00.000300Z\t   13 Connect\tAccess denied for user 'app'@'10.0.0.6' (using password: YES)
+2024-05-01T10:00:01.000000Z\t   12 Init DB\tbilling
+2024-05-01T10:00:01.500000Z\t   12 Query\tSELECT 1
+2024-05-01T10:00:02.000000Z\t   12 Quit\t
+";
+        let mut parser = GeneralLogParser::new(log.as_bytes());
+        let queries: Vec<Query> = parser.by_ref().map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].sql_text, "use shop;\nSELECT *\nFROM orders\nWHERE id = 1");
+        assert_eq!((queries[0].user_host.as_str(), queries[0].thread_id, queries[0].query_time), ("app[app] @ 10.0.0.5 []", Some(12), 0.0));
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T10:00:00.000200Z".parse().unwrap()));
+        assert_eq!(queries[1].database(), Some("billing"));
+        assert_eq!((parser.skipped_blocks(), parser.noise_lines()), (0, 3));
+        assert_eq!(parser.bytes_read(), log.len() as u64);
+
+        // MySQL 5.6 and MariaDB leave out repeated times
+        let log = "240501  9:00:00\t    7 Connect\troot@localhost as anonymous on \n\t\t    7 Query\tSELECT 2\n240501  9:00:01\t    7 Query\tSELECT 3\n";
+        let queries: Vec<Query> = GeneralLogParser::new(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 2);
+        assert_eq!((queries[0].sql_text.as_str(), queries[0].user_host.as_str()), ("SELECT 2", "root[root] @ localhost []"));
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T09:00:00Z".parse().unwrap()));
+        assert_eq!(queries[1].timestamp, Some("2024-05-01T09:00:01Z".parse().unwrap()));
+    }
+}
@@ -0,0 +1,135 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// A rewrite of client hosts: hosts matching `pattern` are replaced with
+/// `replacement`, which may refer to capture groups (`$1`, `${name}`).
+///
+/// Parsed from `<regex>=<replacement>`, split at the first `=`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HostMapping {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl std::str::FromStr for HostMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = s.split_once('=').ok_or_else(|| format!("Invalid host mapping {:?}: expected <regex>=<replacement>", s))?;
+        let pattern = Regex::new(pattern).map_err(|e| format!("Invalid host mapping {:?}: {}", s, e))?;
+        Ok(Self { pattern, replacement: replacement.to_string() })
+    }
+}
+
+impl TryFrom<String> for HostMapping {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// How the host part of `User@Host` is rewritten before queries are grouped,
+/// so that ephemeral clients (container IPs, random ports) collapse together.
+#[derive(Debug, Clone, Default)]
+pub struct HostNormalizer {
+    /// Remove a trailing `:<port>`.
+    pub strip_port: bool,
+    /// Replace IPv4 addresses with their /24 subnet and IPv6 addresses with their /64.
+    pub subnet: bool,
+    /// Rewrites applied after the above; the first matching one wins.
+    pub mappings: Vec<HostMapping>,
+}
+
+impl HostNormalizer {
+    /// Returns true if any rewrite is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.strip_port || self.subnet || !self.mappings.is_empty()
+    }
+
+    /// Rewrites the host name and the bracketed IP of a `User@Host` value,
+    /// such as `app[app] @ web-1 [10.0.0.5]`.
+    pub fn normalize(&self, user_host: &str) -> String {
+        let Some((account, location)) = user_host.split_once(" @ ") else {
+            return user_host.to_string();
+        };
+        match location.strip_suffix(']').and_then(|rest| rest.rsplit_once('[')) {
+            Some((host, ip)) => format!("{} @ {} [{}]", account, self.host(host.trim()), self.host(ip.trim())),
+            None => format!("{} @ {}", account, self.host(location.trim())),
+        }
+    }
+
+    /// Rewrites a single host name or address.
+    pub fn host(&self, host: &str) -> String {
+        if host.is_empty() {
+            return String::new();
+        }
+        let mut host = host.to_string();
+        if self.strip_port {
+            host = strip_port(&host).to_string();
+        }
+        if self.subnet {
+            if let Some(subnet) = subnet(&host) {
+                host = subnet;
+            }
+        }
+        match self.mappings.iter().find(|mapping| mapping.pattern.is_match(&host)) {
+            Some(mapping) => mapping.pattern.replace(&host, mapping.replacement.as_str()).into_owned(),
+            None => host,
+        }
+    }
+}
+
+/// Removes the port from `host:port` or `[ipv6]:port`, leaving bare IPv6 addresses alone.
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split_once(']').map_or(host, |(address, _)| address);
+    }
+    match host.split_once(':') {
+        Some((name, port)) if !port.contains(':') && !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+/// Returns the subnet of an IP address: `10.1.2.0/24` or `2001:db8:0:1::/64`.
+fn subnet(host: &str) -> Option<String> {
+    match host.parse::<IpAddr>().ok()? {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(format!("{}.{}.{}.0/24", a, b, c))
+        }
+        IpAddr::V6(ip) => {
+            let prefix = Ipv6Addr::from(u128::from(ip) & !((1u128 << 64) - 1));
+            Some(format!("{}/64", prefix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = HostNormalizer { strip_port: true, subnet: true, mappings: vec![] };
+        assert_eq!(normalizer.normalize("app[app] @  [10.1.2.37]"), "app[app] @  [10.1.2.0/24]");
+        assert_eq!(normalizer.normalize("app[app] @ 10.1.2.37:51234 []"), "app[app] @ 10.1.2.0/24 []");
+        assert_eq!(normalizer.normalize("app[app] @ [2001:db8::1:2]:3306"), "app[app] @ 2001:db8::/64");
+        assert_eq!(normalizer.normalize("app[app] @ localhost []"), "app[app] @ localhost []");
+        assert_eq!(normalizer.normalize("app"), "app");
+    }
+
+    #[test]
+    fn test_mappings() {
+        let normalizer = HostNormalizer {
+            mappings: vec!["^(\\w+)-[0-9a-f]{5,10}-\\w{5}$=$1-*".parse().unwrap(), "^10\\..*=internal".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(normalizer.normalize("app[app] @ api-7d9f8b6c4-x2k9p [10.4.0.12]"), "app[app] @ api-* [internal]");
+        assert_eq!(normalizer.host("db-0"), "db-0");
+        assert!("no-equals-sign".parse::<HostMapping>().is_err());
+        assert!("(=x".parse::<HostMapping>().is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{bail, Context};
+use std::fs::File;
+#[cfg(feature = "cli")]
+use std::io::{BufRead, BufReader};
+use std::io::Read;
+use std::path::Path;
+
+/// Number of leading bytes inspected to tell text logs from binary files.
+const SNIFF_LEN: usize = 8192;
+
+/// Compression formats of rotated logs, which are read transparently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Tells the compression of a file from the magic bytes at its start, or
+    /// else from its extension (`.gz`, `.zst`, `.bz2`).
+    pub fn detect(path: &Path, head: &[u8]) -> Option<Compression> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            return Some(Compression::Gzip);
+        }
+        if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Some(Compression::Zstd);
+        }
+        if head.starts_with(b"BZh") && head.get(3).is_some_and(|level| (b'1'..=b'9').contains(level)) {
+            return Some(Compression::Bzip2);
+        }
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+}
+
+/// Opens a log file for reading, decompressing it if it is compressed (see [`Compression::detect`]).
+#[cfg(feature = "cli")]
+pub fn open(path: &Path) -> anyhow::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(path, reader.fill_buf()?);
+    Ok(match compression {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(ZstdDecoder::new(reader).with_context(|| format!("Could not read {:?} as zstd", path))?)),
+        Some(Compression::Bzip2) => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+    })
+}
+
+/// Decodes every zstd frame of a stream, as `zstd` writes one per input file
+/// and concatenated files are still valid.
+#[cfg(feature = "cli")]
+struct ZstdDecoder<R: BufRead> {
+    /// The decoder of the current frame; only `None` while the next one starts.
+    frames: Option<ruzstd::decoding::StreamingDecoder<R, ruzstd::decoding::FrameDecoder>>,
+}
+
+#[cfg(feature = "cli")]
+impl<R: BufRead> ZstdDecoder<R> {
+    fn new(reader: R) -> anyhow::Result<Self> {
+        Ok(Self { frames: Some(ruzstd::decoding::StreamingDecoder::new(reader)?) })
+    }
+}
+
+#[cfg(feature = "cli")]
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let Some(frames) = &mut self.frames else { return Ok(0) };
+            let n = frames.read(buf)?;
+            if n > 0 || buf.is_empty() || frames.get_mut().fill_buf()?.is_empty() {
+                return Ok(n);
+            }
+            let (source, decoder) = self.frames.take().unwrap().into_parts();
+            self.frames = Some(ruzstd::decoding::StreamingDecoder::new_with_decoder(source, decoder).map_err(std::io::Error::other)?);
+        }
+    }
+}
+
+/// Checks up front that `path` can be read as a slow log: it must be a regular
+/// file (not a directory) whose beginning looks like text, unless it is compressed
+/// or a pcap capture.
+pub fn validate(path: &Path) -> anyhow::Result<()> {
+    let metadata = std::fs::metadata(path).with_context(|| format!("Could not open file {:?}", path))?;
+    if metadata.is_dir() {
+        bail!("{:?} is a directory, not a log file", path);
+    }
+
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)
+        .and_then(|file| file.take(SNIFF_LEN as u64).read_to_end(&mut head))
+        .with_context(|| format!("Could not read file {:?}", path))?;
+    if Compression::detect(path, &head).is_none() && !crate::tcpdump::is_pcap(&head) && looks_binary(&head) {
+        bail!("{:?} looks like a binary file, not a slow query log", path);
+    }
+    Ok(())
+}
+
+/// Returns true if the bytes contain NUL characters or more than one control
+/// character in ten.
+///
+/// Invalid UTF-8 alone does not count, as logs may hold Latin-1 literals; the
+/// parser skips the lines it cannot decode.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    if bytes.contains(&0) {
+        return true;
+    }
+    let control = bytes.iter().filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c)) || b == 0x7f).count();
+    control * 10 > bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b"# Time: 2023-10-27T10:00:00Z\nSELECT 1;\n"));
+        assert!(!looks_binary("SELECT '日本'".as_bytes()));
+        assert!(!looks_binary(&"SELECT '日'".as_bytes()[..9]));
+        assert!(looks_binary(b"\x1f\x8b\x08\x00\x00\x00"));
+        assert!(looks_binary(b"\x1b\x02\x03\x04 \x05\x06\x07\x08"));
+        assert!(!looks_binary(b"SELECT * FROM t WHERE name = 'caf\xe9';\n"));
+    }
+
+    #[test]
+    fn test_validate_accepts_non_utf8_text() {
+        let path = std::env::temp_dir().join(format!("sqd-latin1-{}.log", std::process::id()));
+        let log = b"# User@Host: app[app] @ localhost []\n# Query_time: 1.0\nSELECT * FROM t WHERE name = 'caf\xe9';\n# User@Host: app[app] @ localhost []\n# Query_time: 2.0\nSELECT 1;\n";
+        std::fs::write(&path, log).unwrap();
+        validate(&path).unwrap();
+        let queries: Vec<_> = crate::parser::LogParser::new(&log[..]).flatten().collect();
+        assert_eq!(queries.iter().map(|q| q.sql_text.as_str()).collect::<Vec<_>>(), ["SELECT 1;"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn test_open_compressed() {
+        use std::io::Write;
+
+        let (first, second) = ("# Query_time: 1.0\nSELECT 1;\n", "# Query_time: 2.0\nSELECT 2;\n");
+        let gzip = |text: &str| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let bzip2 = |text: &str| {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstd = |text: &str| ruzstd::encoding::compress_to_vec(text.as_bytes(), ruzstd::encoding::CompressionLevel::Fastest);
+
+        // Rotated logs appended to one another compress to several members or frames
+        let dir = std::env::temp_dir().join(format!("sqd-compressed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, compress) in [("slow.log.gz", &gzip as &dyn Fn(&str) -> Vec<u8>), ("slow.log.zst", &zstd), ("slow.log.bz2", &bzip2), ("slow-log", &gzip)] {
+            let path = dir.join(name);
+            std::fs::write(&path, [compress(first), compress(second)].concat()).unwrap();
+            validate(&path).unwrap();
+            let mut text = String::new();
+            open(&path).unwrap().read_to_string(&mut text).unwrap();
+            assert_eq!(text, format!("{}{}", first, second), "{}", name);
+        }
+        std::fs::write(dir.join("plain.gz"), first).unwrap();
+        assert!(open(&dir.join("plain.gz")).unwrap().read_to_string(&mut String::new()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_rejects_directories() {
+        let err = validate(&std::env::temp_dir()).unwrap_err();
+        assert!(err.to_string().contains("is a directory"));
+        assert!(validate(Path::new("/nonexistent/slow.log")).is_err());
+    }
+}
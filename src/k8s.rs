@@ -0,0 +1,250 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where a pod's service account credentials are mounted.
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Longest wait between reconnection attempts while following.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The container whose log is read, and how to reach the API server.
+#[derive(Debug, Clone)]
+pub struct PodLogOptions {
+    /// API server URL, e.g. the in-cluster service or `http://127.0.0.1:8001` for `kubectl proxy`.
+    pub api: String,
+    pub namespace: String,
+    pub pod: String,
+    /// Container name, needed when the pod has several.
+    pub container: Option<String>,
+    /// File the bearer token is read from, re-read on every connection since
+    /// projected service account tokens are rotated.
+    pub token_file: Option<PathBuf>,
+    /// PEM file of the CA that signed the API server certificate.
+    pub ca_file: Option<PathBuf>,
+}
+
+impl PodLogOptions {
+    /// Creates options for `pod`, filling in what is not given from the pod's
+    /// own service account when running inside the cluster.
+    ///
+    /// The namespace defaults to the service account's, or `default`.
+    pub fn new(pod: &str, namespace: Option<&str>, container: Option<&str>, api: Option<&str>) -> anyhow::Result<Self> {
+        let service_account = Path::new(SERVICE_ACCOUNT_DIR);
+        let api = match api {
+            Some(api) => api.trim_end_matches('/').to_string(),
+            None => match (std::env::var("KUBERNETES_SERVICE_HOST"), std::env::var("KUBERNETES_SERVICE_PORT")) {
+                (Ok(host), Ok(port)) if host.contains(':') => format!("https://[{}]:{}", host, port),
+                (Ok(host), Ok(port)) => format!("https://{}:{}", host, port),
+                _ => anyhow::bail!("Not running in a Kubernetes cluster; give the API server with --k8s-api (e.g., \"http://127.0.0.1:8001\" with kubectl proxy)"),
+            },
+        };
+        let namespace = match namespace {
+            Some(namespace) => namespace.to_string(),
+            None => std::fs::read_to_string(service_account.join("namespace"))
+                .map(|namespace| namespace.trim().to_string())
+                .unwrap_or_else(|_| "default".to_string()),
+        };
+        let existing = |name: &str| Some(service_account.join(name)).filter(|path| path.exists());
+        Ok(Self {
+            api,
+            namespace,
+            pod: pod.to_string(),
+            container: container.map(str::to_string),
+            token_file: existing("token"),
+            ca_file: existing("ca.crt"),
+        })
+    }
+
+    /// Returns a name for the input, such as `pod/mysql/mysql-0/mysqld`.
+    pub fn name(&self) -> String {
+        match &self.container {
+            Some(container) => format!("pod/{}/{}/{}", self.namespace, self.pod, container),
+            None => format!("pod/{}/{}", self.namespace, self.pod),
+        }
+    }
+
+    fn agent(&self) -> anyhow::Result<ureq::Agent> {
+        let mut builder = ureq::AgentBuilder::new().timeout_connect(Duration::from_secs(10));
+        if let Some(path) = self.ca_file.as_ref().filter(|_| self.api.starts_with("https://")) {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path).with_context(|| format!("Could not read CA file {:?}", path))? {
+                roots.add(cert.with_context(|| format!("Invalid CA file {:?}", path))?)?;
+            }
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = rustls::ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            builder = builder.tls_config(Arc::new(config));
+        }
+        Ok(builder.build())
+    }
+}
+
+/// Reads the log of a pod's container through the Kubernetes API, like `kubectl logs`.
+///
+/// When following, a dropped connection, container restart or log rotation is
+/// handled by reconnecting (with backoff, until the pod is back) and resuming
+/// after the last line read, using the timestamps the API prefixes lines with.
+pub struct PodLogReader {
+    options: PodLogOptions,
+    agent: ureq::Agent,
+    follow: bool,
+    /// Whether to start with the lines already logged, rather than only new ones.
+    from_start: bool,
+    stream: Option<Box<dyn BufRead + Send>>,
+    /// Time of the last line returned; lines up to it are skipped after reconnecting.
+    last_time: Option<DateTime<Utc>>,
+    resumed: bool,
+    line: String,
+    position: usize,
+}
+
+impl PodLogReader {
+    /// Creates a reader of the current log, or a follower of new lines too.
+    ///
+    /// Without `follow`, the log is requested right away so that errors (such as
+    /// an unknown pod) are returned here.
+    pub fn open(options: PodLogOptions, follow: bool, from_start: bool) -> anyhow::Result<Self> {
+        let agent = options.agent()?;
+        let mut reader = Self { options, agent, follow, from_start, stream: None, last_time: None, resumed: false, line: String::new(), position: 0 };
+        if !follow {
+            reader.stream = Some(reader.connect()?);
+        }
+        Ok(reader)
+    }
+
+    fn connect(&self) -> anyhow::Result<Box<dyn BufRead + Send>> {
+        let options = &self.options;
+        let url = format!("{}/api/v1/namespaces/{}/pods/{}/log", options.api, options.namespace, options.pod);
+        let mut request = self.agent.get(&url).query("timestamps", "true");
+        if self.follow {
+            request = request.query("follow", "true");
+        }
+        if let Some(container) = &options.container {
+            request = request.query("container", container);
+        }
+        match self.last_time {
+            // sinceTime has second precision, so the lines already read in that second are sent again
+            Some(time) => request = request.query("sinceTime", &time.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+            None if !self.from_start => request = request.query("tailLines", "0"),
+            None => {}
+        }
+        if let Some(path) = &options.token_file {
+            let token = std::fs::read_to_string(path).with_context(|| format!("Could not read token file {:?}", path))?;
+            request = request.set("Authorization", &format!("Bearer {}", token.trim()));
+        }
+        match request.call() {
+            Ok(response) => Ok(Box::new(BufReader::new(response.into_reader()))),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let message = serde_json::from_str::<serde_json::Value>(&body).ok()
+                    .and_then(|status| status["message"].as_str().map(str::to_string))
+                    .unwrap_or(body);
+                anyhow::bail!("Kubernetes API returned status {} for {}: {}", status, options.name(), message.trim())
+            }
+            Err(e) => Err(e).with_context(|| format!("Request for the log of {} failed", options.name())),
+        }
+    }
+
+    /// Reads the next line of the log into `self.line`, without its timestamp.
+    /// Returns false at the end of the log.
+    fn next_line(&mut self) -> anyhow::Result<bool> {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            if self.stream.is_none() {
+                if !self.follow {
+                    return Ok(false);
+                }
+                match self.connect() {
+                    Ok(stream) => self.stream = Some(stream),
+                    Err(e) => {
+                        tracing::warn!("{:#}; retrying in {:?}", e, delay);
+                        std::thread::sleep(delay);
+                        delay = (delay * 2).min(MAX_RETRY_DELAY);
+                        continue;
+                    }
+                }
+            }
+            let Some(stream) = &mut self.stream else { continue };
+            let mut bytes = Vec::new();
+            match stream.read_until(b'\n', &mut bytes) {
+                Ok(n) if n > 0 => {
+                    let line = String::from_utf8_lossy(&bytes);
+                    let (time, text) = split_timestamp(&line);
+                    if self.resumed && time.is_some() && time <= self.last_time {
+                        continue;
+                    }
+                    self.resumed = false;
+                    if time.is_some() {
+                        self.last_time = time;
+                    }
+                    self.line = text.to_string();
+                    self.position = 0;
+                    return Ok(true);
+                }
+                result => {
+                    if !self.follow {
+                        self.stream = None;
+                        return result.map(|_| false).context("Reading the pod log failed");
+                    }
+                    if let Err(e) = result {
+                        tracing::warn!("Reading the log of {} failed: {}", self.options.name(), e);
+                    }
+                    // The stream also ends when the container stops; wait before asking again
+                    tracing::debug!("Reconnecting to the log of {} in {:?}", self.options.name(), delay);
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    self.stream = None;
+                    self.resumed = self.last_time.is_some();
+                }
+            }
+        }
+    }
+}
+
+impl Read for PodLogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.line.len() && !self.next_line().map_err(io::Error::other)? {
+            return Ok(0);
+        }
+        let rest = &self.line.as_bytes()[self.position..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Splits a line of a log requested with `timestamps=true` into its timestamp and text.
+fn split_timestamp(line: &str) -> (Option<DateTime<Utc>>, &str) {
+    line.split_once(' ')
+        .and_then(|(time, text)| Some((DateTime::parse_from_rfc3339(time).ok()?.with_timezone(&Utc), text)))
+        .map_or((None, line), |(time, text)| (Some(time), text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_timestamp() {
+        let (time, text) = split_timestamp("2024-05-01T10:00:00.123456789Z # Query_time: 1.5  Lock_time: 0.0\n");
+        assert_eq!(time, Some("2024-05-01T10:00:00.123456789Z".parse().unwrap()));
+        assert_eq!(text, "# Query_time: 1.5  Lock_time: 0.0\n");
+        assert_eq!(split_timestamp("SELECT 1;\n"), (None, "SELECT 1;\n"));
+    }
+
+    #[test]
+    fn test_options() {
+        let options = PodLogOptions::new("mysql-0", Some("db"), Some("mysqld"), Some("http://127.0.0.1:8001/")).unwrap();
+        assert_eq!(options.api, "http://127.0.0.1:8001");
+        assert_eq!(options.name(), "pod/db/mysql-0/mysqld");
+    }
+}
@@ -0,0 +1,112 @@
+//! Slow query log digestion for MySQL/MariaDB.
+//!
+//! The pipeline is split into reusable stages:
+//!
+//! - [`parser`] turns a slow log stream into [`parser::Query`] events; [`audit`] does the same for audit plugin logs, [`general`] for the general query log, [`cloudwatch`] for slow logs exported from CloudWatch Logs, [`tcpdump`] for captured MySQL protocol traffic. TiDB slow logs are read by [`parser`] too.
+//! - [`fingerprint`] normalizes SQL text so similar queries group together; [`classify`] tells the verb and tables of a statement.
+//! - [`aggregator`] folds queries into per-fingerprint [`aggregator::QueryStats`].
+//! - [`report`] ranks the statistics and, with the `render` feature, renders them as text, HTML or PDF.
+//! - [`digest`] ties the stages together behind a configurable [`digest::Digest`].
+//! - [`sink`] lets callers observe queries and finalized statistics as they are produced.
+//!
+//! The command line tool and the modules only it uses (configuration, servers,
+//! database access) need the default `cli` feature.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//! use rs_slowquery_digest::digest::{Digest, DigestConfig};
+//!
+//! let config = DigestConfig::builder().limit(10).min_query_time(0.5).build()?;
+//! let mut digest = Digest::new(config);
+//! digest.add_reader(BufReader::new(File::open("slow.log")?));
+//! let items = digest.finish();
+//! for item in &items {
+//!     println!("{} {:.3}s {}", item.query_id, item.total_time, item.normalized_query);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod parser;
+pub mod general;
+pub mod audit;
+pub mod singlestore;
+pub mod postgres;
+pub mod cloudwatch;
+pub mod tcpdump;
+pub mod fingerprint;
+pub mod classify;
+pub mod connections;
+pub mod lock_cluster;
+pub mod aggregator;
+pub mod annotation;
+pub mod report;
+pub mod digest;
+pub mod sink;
+#[cfg(feature = "cli")]
+pub mod config;
+pub mod daemon;
+pub mod snapshot;
+pub mod spill;
+#[cfg(feature = "render")]
+pub mod diff;
+#[cfg(feature = "render")]
+pub mod trend;
+pub mod extract;
+pub mod follow;
+pub mod queue;
+#[cfg(feature = "cli")]
+pub mod check;
+#[cfg(feature = "cli")]
+pub mod triage;
+#[cfg(feature = "cli")]
+pub mod profile;
+pub mod timezone;
+pub mod redact;
+pub mod host;
+pub mod input;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod explain;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod harness;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod review;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod serve;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod tui;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod top;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod watch;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod email;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod notify;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod alert;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod elastic;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod clickhouse;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod mysql_export;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod perf_schema;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod proxysql;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod raw_events;
+#[cfg(all(feature = "cli", not(target_arch = "wasm32")))]
+pub mod k8s;
+#[cfg(feature = "render")]
+pub mod pdf;
+pub mod replay;
+#[cfg(all(feature = "render", not(target_arch = "wasm32")))]
+mod pager;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "ffi")]
+pub mod ffi;
@@ -0,0 +1,257 @@
+use crate::classify;
+use crate::parser::Query;
+use crate::report::query_id;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Events of each kind kept for clustering; later ones are ignored with a warning.
+pub const MAX_LOCK_EVENTS: usize = 100_000;
+
+/// Blocking candidates listed per cluster.
+const MAX_BLOCKERS: usize = 5;
+
+/// When events count as waiting on locks, and how many must overlap to form a cluster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockClusterOptions {
+    /// Lock time, in seconds, at or above which an event is waiting. Data
+    /// changes running at least this long are blocking candidates.
+    pub min_lock_time: f64,
+    /// Overlapping waits needed to report a cluster.
+    pub min_events: usize,
+}
+
+impl Default for LockClusterOptions {
+    fn default() -> Self {
+        Self { min_lock_time: 1.0, min_events: 3 }
+    }
+}
+
+/// A pile-up of overlapping lock waits.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LockCluster {
+    /// When the first wait began and the last one ended.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub events: u64,
+    pub total_lock_time: f64,
+    pub max_lock_time: f64,
+    /// The waiting fingerprints, most lock time first.
+    pub fingerprints: Vec<ClusterFingerprint>,
+    /// Data changes that were already running when the waits began, longest running first.
+    pub blockers: Vec<BlockerCandidate>,
+}
+
+/// The waits of one fingerprint in a cluster.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClusterFingerprint {
+    pub query_id: String,
+    pub fingerprint: String,
+    pub events: u64,
+    pub lock_time: f64,
+}
+
+/// A long-running data change active when a cluster's waits began.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockerCandidate {
+    pub query_id: String,
+    pub fingerprint: String,
+    pub start: DateTime<Utc>,
+    pub query_time: f64,
+    pub user_host: String,
+    pub thread_id: Option<u64>,
+}
+
+/// A lock wait: from when the statement started until it got its locks.
+#[derive(Debug, Clone)]
+struct Wait {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    lock_time: f64,
+    key: String,
+}
+
+/// A data change and when it ran.
+#[derive(Debug, Clone)]
+struct Change {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    query_time: f64,
+    key: String,
+    user_host: String,
+    thread_id: Option<u64>,
+}
+
+/// Collects lock waits and long-running data changes and groups the waits
+/// into clusters.
+///
+/// Logged times are taken as when a statement finished, as in the slow log,
+/// so a statement ran from its time minus its query time, and waited on
+/// locks for its lock time from then. Events without a time are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct LockClusters {
+    options: LockClusterOptions,
+    waits: Vec<Wait>,
+    changes: Vec<Change>,
+    omitted: u64,
+}
+
+impl LockClusters {
+    pub fn new(options: LockClusterOptions) -> Self {
+        Self { options, ..Default::default() }
+    }
+
+    /// Records a query grouped under `key` if it waited on locks or is a long-running data change.
+    pub fn add(&mut self, key: &str, query: &Query) {
+        let Some(end) = query.timestamp else { return };
+        let waited = query.lock_time >= self.options.min_lock_time;
+        let long = query.query_time >= self.options.min_lock_time;
+        if !waited && !long {
+            return;
+        }
+        let start = end - seconds(query.query_time);
+        if waited {
+            if self.waits.len() < MAX_LOCK_EVENTS {
+                self.waits.push(Wait { start, end: start + seconds(query.lock_time), lock_time: query.lock_time, key: key.to_string() });
+            } else {
+                self.omit();
+            }
+        }
+        if long && matches!(classify::verb(&query.sql_text).as_str(), "INSERT" | "UPDATE" | "DELETE" | "REPLACE") {
+            if self.changes.len() < MAX_LOCK_EVENTS {
+                self.changes.push(Change { start, end, query_time: query.query_time, key: key.to_string(), user_host: query.user_host.clone(), thread_id: query.thread_id });
+            } else {
+                self.omit();
+            }
+        }
+    }
+
+    fn omit(&mut self) {
+        if self.omitted == 0 {
+            tracing::warn!("More than {} lock waits or long data changes; later ones are left out of lock clusters", MAX_LOCK_EVENTS);
+        }
+        self.omitted += 1;
+    }
+
+    /// Returns the clusters of at least `min_events` overlapping waits, most
+    /// lock time first, up to `limit` (0 for all).
+    pub fn clusters(&self, limit: usize) -> Vec<LockCluster> {
+        let mut waits: Vec<&Wait> = self.waits.iter().collect();
+        waits.sort_by_key(|wait| wait.start);
+
+        let mut groups: Vec<Vec<&Wait>> = Vec::new();
+        let mut group_end = DateTime::<Utc>::MIN_UTC;
+        for wait in waits {
+            match groups.last_mut() {
+                Some(group) if wait.start <= group_end => group.push(wait),
+                _ => groups.push(vec![wait]),
+            }
+            group_end = group_end.max(wait.end);
+        }
+
+        let mut clusters: Vec<LockCluster> = groups.into_iter().filter(|group| group.len() >= self.options.min_events.max(1)).map(|group| self.cluster(&group)).collect();
+        clusters.sort_by(|a, b| b.total_lock_time.total_cmp(&a.total_lock_time).then_with(|| a.start.cmp(&b.start)));
+        if limit > 0 {
+            clusters.truncate(limit);
+        }
+        clusters
+    }
+
+    fn cluster(&self, waits: &[&Wait]) -> LockCluster {
+        let start = waits.iter().map(|wait| wait.start).min().unwrap_or_default();
+        let end = waits.iter().map(|wait| wait.end).max().unwrap_or_default();
+
+        let mut by_key: HashMap<&str, (u64, f64)> = HashMap::new();
+        for wait in waits {
+            let (events, lock_time) = by_key.entry(&wait.key).or_default();
+            *events += 1;
+            *lock_time += wait.lock_time;
+        }
+        let mut fingerprints: Vec<ClusterFingerprint> = by_key
+            .into_iter()
+            .map(|(key, (events, lock_time))| ClusterFingerprint { query_id: query_id(key), fingerprint: key.to_string(), events, lock_time })
+            .collect();
+        fingerprints.sort_by(|a, b| b.lock_time.total_cmp(&a.lock_time).then_with(|| a.fingerprint.cmp(&b.fingerprint)));
+
+        // Changes already running when the first wait began, and still running after it
+        let mut blockers: Vec<&Change> = self.changes.iter().filter(|change| change.start <= start && change.end > start).collect();
+        blockers.sort_by_key(|change| change.start);
+        let blockers = blockers
+            .into_iter()
+            .take(MAX_BLOCKERS)
+            .map(|change| BlockerCandidate {
+                query_id: query_id(&change.key),
+                fingerprint: change.key.clone(),
+                start: change.start,
+                query_time: change.query_time,
+                user_host: change.user_host.clone(),
+                thread_id: change.thread_id,
+            })
+            .collect();
+
+        LockCluster {
+            start,
+            end,
+            events: waits.len() as u64,
+            total_lock_time: waits.iter().map(|wait| wait.lock_time).sum(),
+            max_lock_time: waits.iter().map(|wait| wait.lock_time).fold(0.0, f64::max),
+            fingerprints,
+            blockers,
+        }
+    }
+}
+
+fn seconds(seconds: f64) -> Duration {
+    Duration::microseconds((seconds * 1e6) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(time: &str, query_time: f64, lock_time: f64, sql: &str) -> Query {
+        Query {
+            query_time,
+            lock_time,
+            rows_sent: 0,
+            rows_examined: 0,
+            timestamp: Some(time.parse().unwrap()),
+            user_host: "app[app] @ localhost []".to_string(),
+            sql_text: sql.to_string(),
+            thread_id: Some(7),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_clusters() {
+        let mut locks = LockClusters::new(LockClusterOptions::default());
+        // A long UPDATE from 10:00:00 to 10:00:30 holds locks that three statements wait on
+        locks.add("update orders set state = ?", &query("2024-05-01T10:00:30Z", 30.0, 0.0, "UPDATE orders SET state = 'paid'"));
+        locks.add("select * from orders where id = ? for update", &query("2024-05-01T10:00:20Z", 15.0, 14.5, "SELECT * FROM orders WHERE id = 1 FOR UPDATE"));
+        locks.add("select * from orders where id = ? for update", &query("2024-05-01T10:00:25Z", 12.0, 11.0, "SELECT * FROM orders WHERE id = 2 FOR UPDATE"));
+        locks.add("insert into order_log values(?)", &query("2024-05-01T10:00:29Z", 8.0, 7.9, "INSERT INTO order_log VALUES (1)"));
+        // Two waits later that overlap each other only, and events that never waited
+        locks.add("select * from carts where id = ? for update", &query("2024-05-01T11:00:05Z", 5.0, 4.0, "SELECT 1"));
+        locks.add("select * from carts where id = ? for update", &query("2024-05-01T11:00:06Z", 5.0, 4.0, "SELECT 1"));
+        locks.add("select ?", &query("2024-05-01T10:00:10Z", 0.5, 0.0, "SELECT 1"));
+
+        let clusters = locks.clusters(0);
+        assert_eq!(clusters.len(), 1);
+        let cluster = &clusters[0];
+        assert_eq!((cluster.start, cluster.end), ("2024-05-01T10:00:05Z".parse().unwrap(), "2024-05-01T10:00:28.900Z".parse().unwrap()));
+        assert_eq!(cluster.events, 3);
+        assert!((cluster.total_lock_time - 33.4).abs() < 1e-9);
+        assert_eq!(cluster.max_lock_time, 14.5);
+        assert_eq!(cluster.fingerprints.len(), 2);
+        assert_eq!((cluster.fingerprints[0].events, cluster.fingerprints[0].lock_time), (2, 25.5));
+        assert_eq!(cluster.blockers.len(), 1);
+        assert_eq!(cluster.blockers[0].fingerprint, "update orders set state = ?");
+        assert_eq!(cluster.blockers[0].start, "2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        let mut locks = LockClusters::new(LockClusterOptions { min_lock_time: 1.0, min_events: 2 });
+        locks.add("a", &query("2024-05-01T11:00:05Z", 5.0, 4.0, "SELECT 1"));
+        locks.add("a", &query("2024-05-01T11:00:06Z", 5.0, 4.0, "SELECT 1"));
+        assert_eq!(locks.clusters(0)[0].events, 2);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::report::{truncate_chars, ReportItem};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+/// Default message template used when none is given.
+pub const DEFAULT_TEMPLATE: &str = "*Slow Query Digest*: {total_count} queries, {total_time} total\n{top}\n{report}";
+
+/// Settings for posting a run summary to a webhook.
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    pub url: String,
+    /// Number of fingerprints listed in the `{top}` placeholder.
+    pub top: usize,
+    pub template: String,
+    /// Path or URL of the full report, substituted for `{report}`.
+    pub report_location: Option<String>,
+}
+
+/// Posts a run summary to a Slack incoming webhook or any URL accepting JSON.
+///
+/// The payload is `{"text": "<message>"}`, which Slack renders directly and
+/// generic receivers can read from the `text` field.
+pub fn send_webhook(items: &[ReportItem], total_count: u64, total_time: f64, options: &WebhookOptions) -> anyhow::Result<()> {
+    let message = render_message(items, total_count, total_time, options);
+    post_json(&options.url, serde_json::json!({ "text": message }))
+}
+
+/// Posts a JSON payload to a webhook.
+pub fn post_json(url: &str, payload: serde_json::Value) -> anyhow::Result<()> {
+    ureq::post(url)
+        .send_json(payload)
+        .with_context(|| format!("Webhook POST to {} failed", url))?;
+    Ok(())
+}
+
+/// Settings for pushing the JSON report to an endpoint after a run.
+#[derive(Debug, Clone)]
+pub struct JsonPostOptions {
+    pub url: String,
+    /// Send only the headline metrics of each query instead of the full items.
+    pub summary: bool,
+    /// Extra request headers, such as `Authorization`.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Parses a `Name: value` request header.
+pub fn parse_header(s: &str) -> anyhow::Result<(String, String)> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => anyhow::bail!("Invalid header '{}': expected \"Name: value\"", s),
+    }
+}
+
+/// Builds the pushed document: run totals and the reported items, either in
+/// full (as with `--format json`) or trimmed to their headline metrics.
+pub fn json_report(items: &[ReportItem], total_count: u64, total_time: f64, summary: bool, generated_at: DateTime<Utc>) -> anyhow::Result<Value> {
+    let items = if summary {
+        items.iter().map(|item| json!({
+            "rank": item.rank,
+            "query_id": item.query_id,
+            "count": item.count,
+            "total_time": item.total_time,
+            "mean_time": item.mean_time,
+            "p95": item.p95,
+            "fingerprint": item.normalized_query,
+        })).collect()
+    } else {
+        serde_json::to_value(items)?
+    };
+    Ok(json!({
+        "generated_at": generated_at,
+        "total_count": total_count,
+        "total_time": total_time,
+        "items": items,
+    }))
+}
+
+/// POSTs the JSON report with the configured headers.
+pub fn post_report(items: &[ReportItem], total_count: u64, total_time: f64, options: &JsonPostOptions) -> anyhow::Result<()> {
+    let payload = json_report(items, total_count, total_time, options.summary, Utc::now())?;
+    let mut request = ureq::post(&options.url);
+    for (name, value) in &options.headers {
+        request = request.set(name, value);
+    }
+    match request.send_json(payload) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, response)) => {
+            anyhow::bail!("JSON report POST to {} returned status {}: {}", options.url, status, response.into_string().unwrap_or_default().trim())
+        }
+        Err(e) => Err(e).with_context(|| format!("JSON report POST to {} failed", options.url)),
+    }
+}
+
+/// Expands the message template.
+///
+/// Supported placeholders: `{total_count}`, `{total_time}`, `{top}`, `{report}`.
+fn render_message(items: &[ReportItem], total_count: u64, total_time: f64, options: &WebhookOptions) -> String {
+    let top: Vec<String> = items.iter().take(options.top).map(|item| {
+        let query = truncate_chars(&item.normalized_query, 80);
+        format!("{}. `{}` count={} total={:.3}s p95={:.3}s `{}`", item.rank, item.query_id, item.count, item.total_time, item.p95, query)
+    }).collect();
+
+    options.template
+        .replace("\\n", "\n")
+        .replace("{total_count}", &total_count.to_string())
+        .replace("{total_time}", &format!("{:.3}s", total_time))
+        .replace("{top}", &top.join("\n"))
+        .replace("{report}", options.report_location.as_deref().unwrap_or(""))
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(rank: usize) -> ReportItem {
+        ReportItem {
+            rank,
+            query_id: format!("id{}", rank),
+            count: 2,
+            total_time: 1.5,
+            mean_time: 0.75,
+            p95: 1.0,
+            p99: 1.0,
+            percentiles: vec![(0.95, 1.0), (0.99, 1.0)],
+            total_lock_time: 0.0,
+            mean_lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            ratio: 0.0,
+            score: 0.0,
+            badges: Vec::new(),
+            sources: Default::default(),
+            time_range: "N/A".to_string(),
+            example_query: String::new(),
+            worst_example_query: String::new(),
+            normalized_query: "select ?".to_string(),
+            users: Vec::new(),
+            databases: Vec::new(),
+            explain: None,
+            explain_analyze: None,
+            first_seen: None,
+            last_seen: None,
+            review: None,
+            triage: None,
+            server: None,
+            query_cache: None,
+            errors: None,
+            metrics: Default::default(),
+            plan_flags: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_render_message_placeholders() {
+        let options = WebhookOptions {
+            url: String::new(),
+            top: 1,
+            template: "{total_count} / {total_time}\\n{top}\\n{report}".to_string(),
+            report_location: Some("report.html".to_string()),
+        };
+        let msg = render_message(&[item(1), item(2)], 10, 3.0, &options);
+        assert_eq!(msg, "10 / 3.000s\n1. `id1` count=2 total=1.500s p95=1.000s `select ?`\nreport.html");
+
+        let long = ReportItem { normalized_query: format!("select {} from t", "名前, ".repeat(30)), ..item(1) };
+        let msg = render_message(&[long], 1, 1.0, &WebhookOptions { template: "{top}".to_string(), ..options });
+        assert!(msg.ends_with(&format!("`select {}名前...`", "名前, ".repeat(17))), "{}", msg);
+    }
+
+    #[test]
+    fn test_json_report() {
+        let generated_at: DateTime<Utc> = "2024-05-01T00:00:00Z".parse().unwrap();
+        let full = json_report(&[item(1), item(2)], 10, 3.0, false, generated_at).unwrap();
+        assert_eq!(full["generated_at"], "2024-05-01T00:00:00Z");
+        assert_eq!(full["total_count"], 10);
+        assert_eq!(full["items"][1]["query_id"], "id2");
+        assert!(full["items"][0].get("example_query").is_some());
+
+        let summary = json_report(&[item(1)], 10, 3.0, true, generated_at).unwrap();
+        assert_eq!(summary["items"][0], json!({ "rank": 1, "query_id": "id1", "count": 2, "total_time": 1.5, "mean_time": 0.75, "p95": 1.0, "fingerprint": "select ?" }));
+
+        assert_eq!(parse_header("Authorization: Bearer abc:def").unwrap(), ("Authorization".to_string(), "Bearer abc:def".to_string()));
+        assert!(parse_header("Authorization").is_err());
+        assert!(parse_header(": x").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::IsTerminal;
+use std::process::{Child, Command, Stdio};
+
+/// A running pager process whose stdin receives the report.
+pub struct Pager {
+    child: Child,
+}
+
+impl Pager {
+    /// Starts `$PAGER` (or `less`) if stdout is a terminal.
+    ///
+    /// Like git, `LESS=FRX` is set when not already defined so short output is
+    /// printed directly instead of opening the pager. Returns `None` when stdout
+    /// is not a terminal, paging is disabled with `PAGER=cat`/empty, or the
+    /// pager cannot be started.
+    pub fn spawn() -> Option<Self> {
+        if !std::io::stdout().is_terminal() {
+            return None;
+        }
+
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+        let pager = pager.trim();
+        if pager.is_empty() || pager == "cat" {
+            return None;
+        }
+
+        let mut command = if cfg!(windows) {
+            let mut c = Command::new("cmd");
+            c.arg("/C").arg(pager);
+            c
+        } else {
+            let mut c = Command::new("sh");
+            c.arg("-c").arg(pager);
+            c
+        };
+        if std::env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+
+        command.stdin(Stdio::piped()).spawn().ok().map(|child| Self { child })
+    }
+
+    /// Returns the writer connected to the pager's stdin.
+    pub fn writer(&mut self) -> Box<dyn std::io::Write + '_> {
+        Box::new(self.child.stdin.as_mut().expect("pager stdin is piped"))
+    }
+
+    /// Closes the pager's input and waits for the user to quit it.
+    pub fn wait(mut self) -> anyhow::Result<()> {
+        drop(self.child.stdin.take());
+        self.child.wait()?;
+        Ok(())
+    }
+}
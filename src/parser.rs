@@ -1,11 +1,14 @@
 use anyhow::{Result};
-use chrono::{DateTime, Utc};
+use crate::fingerprint::Dialect;
+use crate::timezone::Timezone;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::io::BufRead;
 use std::sync::OnceLock;
 
 /// Represents a parsed slow query.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Query {
     pub query_time: f64,
     pub lock_time: f64,
@@ -14,17 +17,307 @@ pub struct Query {
     pub timestamp: Option<DateTime<Utc>>,
     pub user_host: String,
     pub sql_text: String,
+    /// Connection the query ran on, from `Id:` on the `User@Host` line or a `Thread_id` header.
+    #[serde(default)]
+    pub thread_id: Option<u64>,
+    /// Default database of the connection, from MariaDB's `Schema` header, or
+    /// the last `use <db>;` of the log for slow logs without one.
+    #[serde(default)]
+    pub schema: Option<String>,
+    /// Whether the result came from the query cache, from MariaDB's `QC_hit` header.
+    #[serde(default)]
+    pub qc_hit: Option<bool>,
+    /// Error number the statement ended with, from MySQL's `Errno` or Percona's `Last_errno` header.
+    #[serde(default)]
+    pub errno: Option<u32>,
+    /// Why the statement was killed (0 if it was not), from the `Killed` header.
+    #[serde(default)]
+    pub killed: Option<u32>,
+    /// Further numeric headers, by name, such as Percona's `Bytes_sent` or
+    /// `InnoDB_IO_r_ops` (see [`EXTENDED_METRICS`]) and TiDB's `Process_time`
+    /// (see [`TIDB_METRICS`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<(String, f64)>,
+    /// `Yes`/`No` query plan headers, by name, such as Percona's `Full_scan`
+    /// or `Filesort` (see [`PLAN_FLAGS`]) and TiDB's `Succ` (see [`TIDB_FLAGS`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plan_flags: Vec<(String, bool)>,
+    /// Command of a `# administrator command:` entry, such as `Ping` or `Quit`,
+    /// whose SQL text is the synthetic `ADMIN <COMMAND>` (see [`admin_sql`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_command: Option<String>,
 }
 
-static RE_HEADER_USER: OnceLock<Regex> = OnceLock::new();
-static RE_HEADER_TIME: OnceLock<Regex> = OnceLock::new();
-static RE_HEADER_METRICS: OnceLock<Regex> = OnceLock::new();
+impl Query {
+    /// Returns the database selected by a leading `USE <db>;` statement, or
+    /// else the logged schema, if any.
+    pub fn database(&self) -> Option<&str> {
+        self.use_database().or_else(|| self.schema.as_deref().filter(|schema| !schema.is_empty()))
+    }
+
+    /// Returns the database selected by a leading `USE <db>;` statement, if any.
+    pub fn use_database(&self) -> Option<&str> {
+        let re = RE_USE_DB.get_or_init(|| Regex::new(r"(?i)^\s*use\s+`?([^`;\s]+)`?\s*;").unwrap());
+        re.captures(&self.sql_text).and_then(|c| c.get(1)).map(|m| m.as_str())
+    }
+
+    /// Returns the account name of the `User@Host` value: the user before the
+    /// brackets, or the bracketed name when that is empty (e.g. `[SQL_SLAVE]`).
+    pub fn user(&self) -> &str {
+        let account = self.user_host.split_once(" @").map_or(self.user_host.as_str(), |(account, _)| account).trim();
+        match account.split_once('[') {
+            Some(("", bracketed)) => bracketed.trim_end_matches(']').trim(),
+            Some((user, _)) => user.trim(),
+            None => account,
+        }
+    }
+
+    /// Returns the value of a numeric header kept in [`Query::metrics`], if logged.
+    pub fn metric(&self, name: &str) -> Option<f64> {
+        self.metrics.iter().find(|(metric, _)| metric == name).map(|(_, value)| *value)
+    }
+
+    /// Returns the rows the statement changed, from Percona's and MariaDB's `Rows_affected` header.
+    pub fn rows_affected(&self) -> Option<u64> {
+        self.metric("Rows_affected").map(|rows| rows as u64)
+    }
+
+    /// Returns the bytes sent to the client, from the `Bytes_sent` header.
+    pub fn bytes_sent(&self) -> Option<u64> {
+        self.metric("Bytes_sent").map(|bytes| bytes as u64)
+    }
+
+    /// Returns true if the statement is DDL (`ALTER`, `CREATE`, `DROP`, `TRUNCATE`
+    /// or `RENAME`), ignoring leading `USE`/`SET` statements and comments.
+    /// Temporary tables are not schema changes.
+    pub fn is_schema_change(&self) -> bool {
+        let re = RE_DDL.get_or_init(|| {
+            Regex::new(r"(?is)^\s*(?:(?:use|set)\s[^;]*;\s*|/\*.*?\*/\s*|(?:--|#)[^\n]*\n\s*)*(?:alter|create|drop|truncate|rename)\s+(temporary\b)?").unwrap()
+        });
+        re.captures(&self.sql_text).is_some_and(|c| c.get(1).is_none())
+    }
+
+    /// Returns `BEGIN`, `COMMIT`, `ROLLBACK` or `SAVEPOINT` if the statement is
+    /// only that transaction-control statement (`START TRANSACTION` counts as
+    /// `BEGIN`, `RELEASE SAVEPOINT` as `SAVEPOINT`).
+    pub fn transaction_control(&self) -> Option<&'static str> {
+        let re = RE_TXN.get_or_init(|| {
+            Regex::new(r"(?is)^\s*(?:use\s[^;]*;\s*|/\*.*?\*/\s*)*(begin|start\s+transaction|commit|rollback|savepoint|release\s+savepoint)\b[^;]*;?\s*$").unwrap()
+        });
+        let keyword = re.captures(&self.sql_text)?.get(1)?.as_str().to_ascii_lowercase();
+        Some(match keyword.split_whitespace().next()? {
+            "begin" | "start" => "BEGIN",
+            "commit" => "COMMIT",
+            "rollback" => "ROLLBACK",
+            _ => "SAVEPOINT",
+        })
+    }
+}
+
+/// Numeric headers of Percona Server's extended slow log (`log_slow_verbosity`)
+/// and MySQL's `log_slow_extra` kept in [`Query::metrics`]. Names ending in
+/// `_wait` are in seconds.
+pub const EXTENDED_METRICS: &[&str] = &[
+    "Rows_affected",
+    "Rows_read",
+    "Bytes_received",
+    "Bytes_sent",
+    "Read_first",
+    "Read_last",
+    "Read_key",
+    "Read_next",
+    "Read_prev",
+    "Read_rnd",
+    "Read_rnd_next",
+    "Sort_merge_passes",
+    "Sort_range_count",
+    "Sort_rows",
+    "Sort_scan_count",
+    "Created_tmp_disk_tables",
+    "Created_tmp_tables",
+    "Tmp_tables",
+    "Tmp_disk_tables",
+    "Tmp_table_sizes",
+    "Merge_passes",
+    "InnoDB_IO_r_ops",
+    "InnoDB_IO_r_bytes",
+    "InnoDB_IO_r_wait",
+    "InnoDB_rec_lock_wait",
+    "InnoDB_queue_wait",
+    "InnoDB_pages_distinct",
+];
+
+/// `Yes`/`No` query plan headers of Percona Server's extended slow log kept in [`Query::plan_flags`].
+pub const PLAN_FLAGS: &[&str] = &["Full_scan", "Full_join", "Tmp_table", "Tmp_table_on_disk", "Filesort", "Filesort_on_disk"];
+
+/// Numeric headers of the TiDB slow log kept in [`Query::metrics`]. Names
+/// ending in `_time`, `_avg` or `_total` and `Wait_TS` are in seconds.
+pub const TIDB_METRICS: &[&str] = &[
+    "Parse_time",
+    "Compile_time",
+    "Rewrite_time",
+    "Optimize_time",
+    "Wait_TS",
+    "Process_time",
+    "Wait_time",
+    "Backoff_time",
+    "Exec_retry_time",
+    "Request_count",
+    "Total_keys",
+    "Process_keys",
+    "Prewrite_time",
+    "Commit_time",
+    "Get_commit_ts_time",
+    "Local_latch_wait_time",
+    "Write_keys",
+    "Write_size",
+    "Prewrite_region",
+    "Txn_retry",
+    "Num_cop_tasks",
+    "Cop_proc_avg",
+    "Cop_wait_avg",
+    "Mem_max",
+    "Disk_max",
+    "KV_total",
+    "PD_total",
+    "Backoff_total",
+];
+
+/// `true`/`false` headers of the TiDB slow log kept in [`Query::plan_flags`].
+pub const TIDB_FLAGS: &[&str] = &["Succ", "Prepared", "Plan_from_cache", "Plan_from_binding", "Is_internal"];
+
+/// Returns whether the metric of the given name is a duration in seconds.
+pub fn is_time_metric(name: &str) -> bool {
+    name.ends_with("_wait") || (TIDB_METRICS.contains(&name) && (name.ends_with("_time") || name.ends_with("_avg") || name.ends_with("_total") || name == "Wait_TS"))
+}
+
+static RE_USE_DB: OnceLock<Regex> = OnceLock::new();
+static RE_DDL: OnceLock<Regex> = OnceLock::new();
+static RE_TXN: OnceLock<Regex> = OnceLock::new();
+static RE_NOISE: OnceLock<Regex> = OnceLock::new();
+
+/// Returns the SQL text standing in for an administrator command, which
+/// groups its executions under one class: `ADMIN PING` for `Ping`,
+/// `ADMIN BINLOG DUMP` for `Binlog Dump`.
+pub fn admin_sql(command: &str) -> String {
+    format!("ADMIN {}", command.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase())
+}
+
+/// Returns whether a line is one of those the server writes at the top of its
+/// logs when it starts or the log is flushed, rather than part of an event:
+/// the `mysqld, Version: ... started with:` banner, the `Tcp port:` line and
+/// the `Time Id Command Argument` column header.
+pub fn is_noise(line: &str) -> bool {
+    let line = line.trim_end();
+    // Most lines are SQL or headers; skip the regex unless a banner could match
+    if !(line.ends_with("started with:") || line.starts_with("Tcp port:") || line.starts_with("TCP Port:") || line.starts_with("Time")) {
+        return false;
+    }
+    let re = RE_NOISE.get_or_init(|| Regex::new(r"^(?:\S.*, Version: .*started with:|Tcp port: .*|TCP Port: .*, Named Pipe: .*|Time\s+Id\s+Command\s+Argument)$").unwrap());
+    re.is_match(line)
+}
+
+/// Splits a `# Key: value  Key: value` header line into its key/value pairs.
+///
+/// Keys are the words ending in `:`; a key directly followed by another key has
+/// an empty value.
+pub fn header_pairs(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut tokens = line.trim_start_matches('#').split_ascii_whitespace().peekable();
+    std::iter::from_fn(move || loop {
+        let token = tokens.next()?;
+        if let Some(key) = token.strip_suffix(':') {
+            let value = match tokens.peek() {
+                Some(next) if !next.ends_with(':') => tokens.next().unwrap_or_default(),
+                _ => "",
+            };
+            return Some((key, value));
+        }
+    })
+}
+
+/// Returns the account of a `# User@Host:` header, without the trailing
+/// `Id: <connection id>` MySQL appends, and that connection ID.
+fn parse_user_host(value: &str) -> (&str, Option<u64>) {
+    let value = value.trim();
+    if let Some(pos) = value.rfind("Id:") {
+        let (head, id) = (&value[..pos], value[pos + 3..].trim_start());
+        if head.ends_with(char::is_whitespace) && !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) {
+            return (head.trim_end(), id.parse().ok());
+        }
+    }
+    (value, None)
+}
+
+/// Parses the value of a `# Time:` header: ISO 8601 (MySQL 5.7 and later),
+/// the same without an offset, or the `YYMMDD H:MM:SS` of older servers. Times
+/// without an offset are in `log_timezone`.
+pub(crate) fn parse_time_header(value: &str, log_timezone: Timezone) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(naive) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(log_timezone.to_utc(&naive));
+    }
+    // Older servers pad single-digit hours with a space: `# Time: 231027  9:05:03`
+    let (date, time) = value.split_once(char::is_whitespace)?;
+    let time = time.trim_start();
+    let time = if time.find(':') == Some(1) { format!("0{}", time) } else { time.to_string() };
+    NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%y%m%d %H:%M:%S%.f").ok().map(|naive| log_timezone.to_utc(&naive))
+}
+
+/// Parses the seconds since the epoch of a `SET timestamp=` line, such as
+/// `1698400800` or `1698400800.123456`.
+fn parse_epoch(value: &str) -> Option<DateTime<Utc>> {
+    let seconds: f64 = value.trim().parse().ok()?;
+    DateTime::from_timestamp_micros((seconds * 1e6).round() as i64)
+}
+
+/// Parses the `Yes`/`No` values of MariaDB and Percona headers.
+fn parse_yes_no(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// A parsed query and the raw log block (headers and SQL) it came from.
+#[derive(Debug, Clone)]
+pub struct RawEvent {
+    pub raw: String,
+    pub query: Query,
+}
+
+/// A log block or record the parser could not turn into a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedBlock {
+    /// Line of the input the block starts on, counting from 1.
+    pub line: u64,
+    /// Why the block was skipped.
+    pub reason: String,
+    /// The text of the block, with invalid UTF-8 replaced.
+    pub raw: String,
+}
 
 /// Parses a slow query log stream.
 pub struct LogParser<R> {
     reader: R,
     current_block: String,
     read_buffer: String,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    noise_lines: u64,
+    log_timezone: Timezone,
+    lines_read: u64,
+    /// Line the block being read starts on, and the one of the block last returned.
+    current_line: u64,
+    block_line: u64,
+    rejected: Option<Vec<RejectedBlock>>,
+    /// Database of the last `use <db>;` in the log, and whether the server
+    /// restarted after the block last returned, which forgets it.
+    current_db: Option<String>,
+    restarted: bool,
 }
 
 impl<R: BufRead> LogParser<R> {
@@ -34,9 +327,40 @@ impl<R: BufRead> LogParser<R> {
             reader,
             current_block: String::new(),
             read_buffer: String::new(),
+            bytes_read: 0,
+            skipped_blocks: 0,
+            noise_lines: 0,
+            log_timezone: Timezone::default(),
+            lines_read: 0,
+            current_line: 1,
+            block_line: 1,
+            rejected: None,
+            current_db: None,
+            restarted: false,
         }
     }
 
+    /// Sets the timezone that timestamps without an offset are logged in (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Returns the number of bytes read from the input so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the number of log blocks skipped so far because they held no SQL.
+    pub fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    /// Returns the number of server banner and header lines skipped so far (see [`is_noise`]).
+    pub fn noise_lines(&self) -> u64 {
+        self.noise_lines
+    }
+
     /// Parses a single block of log lines into a `Query`.
     fn parse_block(&self, block: &str) -> Option<Query> {
         if block.is_empty() {
@@ -48,56 +372,126 @@ impl<R: BufRead> LogParser<R> {
         let mut rows_sent = 0;
         let mut rows_examined = 0;
         let mut user_host = String::new();
+        let mut thread_id = None;
+        let mut schema = None;
+        let mut qc_hit = None;
+        let mut errno = None;
+        let mut killed = None;
+        let mut end = None;
+        let mut metrics = Vec::new();
+        let mut plan_flags = Vec::new();
+        let mut admin_command = None;
+        let mut admin_line = String::new();
         let mut sql_lines = Vec::new();
         let mut timestamp = None;
-
-        let re_header_user = RE_HEADER_USER.get_or_init(|| Regex::new(r"^# User@Host: (.*)").unwrap());
-        let re_header_time = RE_HEADER_TIME.get_or_init(|| Regex::new(r"^# Time: (.*)").unwrap());
-        let re_header_metrics = RE_HEADER_METRICS.get_or_init(|| Regex::new(r"Query_time: \s*([\d\.]+) \s*Lock_time: \s*([\d\.]+) \s*Rows_sent: \s*(\d+) \s*Rows_examined: \s*(\d+)").unwrap());
+        let mut set_timestamp = None;
+        let mut has_metrics = false;
 
         for line in block.lines() {
             let trimmed = line.trim();
-            if let Some(caps) = re_header_user.captures(trimmed) {
-                user_host = caps[1].trim().to_string();
-            } else if let Some(caps) = re_header_time.captures(trimmed) {
-                let time_str = &caps[1];
-                // Try parsing ISO 8601
-                if let Ok(dt) = DateTime::parse_from_rfc3339(time_str) {
-                    timestamp = Some(dt.with_timezone(&Utc));
+            if let Some(value) = trimmed.strip_prefix("# User@Host:") {
+                let (user, id) = parse_user_host(value);
+                user_host = user.to_string();
+                thread_id = thread_id.or(id);
+            } else if let Some(time_str) = trimmed.strip_prefix("# Time:") {
+                timestamp = parse_time_header(time_str, self.log_timezone).or(timestamp);
+            } else if let Some(command) = trimmed.strip_prefix("# administrator command:") {
+                // Logged instead of SQL for protocol commands such as COM_PING
+                let command = command.trim().trim_end_matches(';').trim();
+                if !command.is_empty() {
+                    admin_line = admin_sql(command);
+                    admin_command = Some(command.to_string());
+                }
+            } else if trimmed.starts_with('#') {
+                for (key, value) in header_pairs(trimmed) {
+                    match key {
+                        "Query_time" => match value.parse() {
+                            Ok(seconds) => {
+                                query_time = seconds;
+                                has_metrics = true;
+                            }
+                            Err(_) => tracing::debug!("Invalid Query_time '{}'", value),
+                        },
+                        "Lock_time" => lock_time = value.parse().unwrap_or(0.0),
+                        "Rows_sent" => rows_sent = value.parse().unwrap_or(0),
+                        "Rows_examined" => rows_examined = value.parse().unwrap_or(0),
+                        "Thread_id" => thread_id = value.parse().ok().or(thread_id),
+                        "Schema" if !value.is_empty() => schema = Some(value.to_string()),
+                        // TiDB's names for the connection, database and rows returned
+                        "Conn_ID" => thread_id = value.parse().ok().or(thread_id),
+                        "DB" if !value.is_empty() => schema = Some(value.to_string()),
+                        "Result_rows" => rows_sent = value.parse().unwrap_or(0),
+                        // MariaDB writes `QC_hit`, Percona Server `QC_Hit`
+                        "QC_hit" | "QC_Hit" => qc_hit = parse_yes_no(value),
+                        "Errno" | "Last_errno" => errno = value.parse().ok(),
+                        "Killed" => killed = value.parse().ok(),
+                        // When `log_slow_extra` is on, MySQL writes when the statement ended
+                        "End" => end = DateTime::parse_from_rfc3339(value).ok().map(|end| end.with_timezone(&Utc)),
+                        key if EXTENDED_METRICS.contains(&key) || TIDB_METRICS.contains(&key) => match value.parse() {
+                            Ok(value) => metrics.push((key.to_string(), value)),
+                            Err(_) => tracing::debug!("Invalid {} '{}'", key, value),
+                        },
+                        key if PLAN_FLAGS.contains(&key) || TIDB_FLAGS.contains(&key) => {
+                            if let Some(set) = parse_yes_no(value) {
+                                plan_flags.push((key.to_string(), set));
+                            }
+                        }
+                        // Ignore other headers
+                        _ => {}
+                    }
                 }
-            } else if let Some(caps) = re_header_metrics.captures(trimmed) {
-                query_time = caps[1].parse().unwrap_or(0.0);
-                lock_time = caps[2].parse().unwrap_or(0.0);
-                rows_sent = caps[3].parse().unwrap_or(0);
-                rows_examined = caps[4].parse().unwrap_or(0);
-            } else if trimmed.starts_with("#") {
-                // Ignore other headers
-            } else if trimmed.starts_with("SET timestamp=") {
-                // Ignore for now
+            } else if let Some(value) = trimmed.strip_prefix("SET timestamp=") {
+                set_timestamp = parse_epoch(value.trim_end_matches(';'));
             } else {
                 sql_lines.push(trimmed);
             }
         }
 
+        if !has_metrics {
+            if block.contains("Query_time:") {
+                tracing::debug!("Could not parse the Query_time header; counting the query as 0s");
+            } else {
+                tracing::trace!("Log block has no Query_time header; counting the query as 0s");
+            }
+        }
+
+        if !admin_line.is_empty() {
+            sql_lines.push(&admin_line);
+        }
         let sql_text = sql_lines.join("\n").trim().to_string();
         if sql_text.is_empty() {
             return None;
         }
 
+        // Many servers only write `# Time:` for the first query of each second,
+        // but every query sets the session timestamp to when it started
+        let ended = set_timestamp.map(|start: DateTime<Utc>| start + chrono::Duration::microseconds((query_time * 1e6) as i64));
+
         Some(Query {
             query_time,
             lock_time,
             rows_sent,
             rows_examined,
-            timestamp,
+            timestamp: timestamp.or(end).or(ended),
             user_host,
             sql_text,
+            thread_id,
+            schema,
+            qc_hit,
+            errno,
+            killed,
+            metrics,
+            plan_flags,
+            admin_command,
         })
     }
     /// Checks if a block contains any SQL statements.
     fn has_sql(&self, block: &str) -> bool {
         for line in block.lines() {
             let trimmed = line.trim();
+            if trimmed.starts_with("# administrator command:") {
+                return true;
+            }
             if trimmed.starts_with("#") || trimmed.starts_with("SET timestamp=") {
                 continue;
             }
@@ -109,48 +503,100 @@ impl<R: BufRead> LogParser<R> {
     }
 }
 
-impl<R: BufRead> Iterator for LogParser<R> {
-    type Item = Result<Query>;
+impl<R: BufRead> LogParser<R> {
+    /// Returns the next query together with the raw log lines it was parsed from.
+    pub fn next_event(&mut self) -> Option<Result<RawEvent>> {
+        loop {
+            let raw = match self.next_block()? {
+                Ok(raw) => raw,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(mut query) = self.parse_block(&raw) {
+                self.track_database(&mut query);
+                return Some(Ok(RawEvent { raw, query }));
+            }
+            // If the block yielded no query (e.g. just headers?), continue with the next one
+            self.skipped_blocks += 1;
+            tracing::trace!("Skipping log block without SQL: {}", raw.lines().next().unwrap_or_default());
+            if let Some(rejected) = &mut self.rejected {
+                rejected.push(RejectedBlock { line: self.block_line, reason: "no SQL statement".to_string(), raw });
+            }
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Gives statements logged without a database the one of the last `use`.
+    ///
+    /// MySQL writes `use <db>;` before a statement only when its database
+    /// differs from the one last written to the log, so the statements after
+    /// it ran in that database too. A `Schema` header wins over it.
+    fn track_database(&mut self, query: &mut Query) {
+        if let Some(db) = query.use_database() {
+            self.current_db = Some(db.to_string());
+        } else if query.schema.is_none() {
+            query.schema = self.current_db.clone();
+        }
+    }
+
+    /// Reads lines up to the start of the next block and returns the completed block.
+    fn next_block(&mut self) -> Option<Result<String>> {
+        if std::mem::take(&mut self.restarted) {
+            self.current_db = None;
+        }
         loop {
-            self.read_buffer.clear();
-            match self.reader.read_line(&mut self.read_buffer) {
+            let mut line = std::mem::take(&mut self.read_buffer).into_bytes();
+            line.clear();
+            match self.reader.read_until(b'\n', &mut line) {
                 Ok(0) => {
                     // EOF
-                    if !self.current_block.is_empty() {
-                        let q = self.parse_block(&self.current_block);
-                        self.current_block.clear();
-                        if let Some(query) = q {
-                            return Some(Ok(query));
-                        }
+                    if self.current_block.is_empty() {
+                        return None;
                     }
-                    return None;
+                    self.block_line = self.current_line;
+                    return Some(Ok(std::mem::take(&mut self.current_block)));
                 }
-                Ok(_) => {
-                    // Continue processing
+                Ok(n) => {
+                    self.bytes_read += n as u64;
+                    self.lines_read += 1;
                 }
                 Err(e) => return Some(Err(anyhow::anyhow!(e))),
             }
+            match String::from_utf8(line) {
+                Ok(line) => self.read_buffer = line,
+                Err(e) => {
+                    if let Some(rejected) = &mut self.rejected {
+                        let raw = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                        rejected.push(RejectedBlock { line: self.lines_read, reason: "invalid UTF-8".to_string(), raw });
+                    }
+                    return Some(Err(anyhow::anyhow!("Line {} is not valid UTF-8", self.lines_read)));
+                }
+            }
+            if self.current_block.is_empty() {
+                self.current_line = self.lines_read;
+            }
 
             let trimmed = self.read_buffer.trim();
-            
+            if is_noise(trimmed) {
+                // Server restarts write a banner between events, and start the log without a database
+                self.noise_lines += 1;
+                tracing::trace!("Skipping line {}: {}", self.lines_read, trimmed);
+                if self.has_sql(&self.current_block) {
+                    self.restarted = true;
+                    self.block_line = self.current_line;
+                    return Some(Ok(std::mem::take(&mut self.current_block)));
+                }
+                self.current_db = None;
+                continue;
+            }
+
             // Heuristic: A new block often starts with # User@Host or # Time
             let is_header = trimmed.starts_with("# User@Host:") || trimmed.starts_with("# Time:");
             
             if is_header && self.has_sql(&self.current_block) {
                 // We found a start of a NEW block, and we have data in current_block.
-                // Process current_block as a query.
-                let q = self.parse_block(&self.current_block);
-                
-                // Clear and start new block with this line
-                self.current_block.clear();
-                self.current_block.push_str(&self.read_buffer);
-                
-                if let Some(query) = q {
-                    return Some(Ok(query));
-                }
-                // If previous block yielded no query (e.g. just headers?), continue loop
+                // Start a new block with this line and hand back the finished one.
+                let block = std::mem::replace(&mut self.current_block, self.read_buffer.clone());
+                self.block_line = std::mem::replace(&mut self.current_line, self.lines_read);
+                return Some(Ok(block));
             } else {
                 self.current_block.push_str(&self.read_buffer);
             }
@@ -158,11 +604,114 @@ impl<R: BufRead> Iterator for LogParser<R> {
     }
 }
 
+impl<R: BufRead> Iterator for LogParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().map(|event| event.map(|e| e.query))
+    }
+}
+
 /// Convenience function to create a `LogParser`.
 pub fn parse_log<R: BufRead>(reader: R) -> LogParser<R> {
     LogParser::new(reader)
 }
 
+/// Log formats queries can be read from.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// MySQL/MariaDB slow query log.
+    #[default]
+    Slow,
+    /// Percona or MySQL Enterprise audit log, as JSON or XML.
+    Audit,
+    /// SingleStore (MemSQL) `Query_completion` trace events, as JSON or `\G` text.
+    Singlestore,
+    /// PostgreSQL stderr log with `log_min_duration_statement` durations.
+    #[cfg_attr(feature = "cli", value(alias = "postgres"))]
+    #[serde(alias = "postgres")]
+    Postgresql,
+    /// Slow log events exported from CloudWatch Logs (RDS, Aurora), as JSON.
+    Cloudwatch,
+    /// MySQL or MariaDB general query log, without execution times.
+    General,
+    /// MySQL protocol traffic captured by tcpdump, as a pcap file or `-x` hex dump text.
+    Tcpdump,
+    /// TiDB slow query log.
+    Tidb,
+}
+
+impl InputFormat {
+    /// Returns the SQL dialect of the statements logged in this format.
+    pub fn dialect(self) -> Dialect {
+        match self {
+            InputFormat::Postgresql => Dialect::Postgres,
+            InputFormat::Tidb => Dialect::Tidb,
+            InputFormat::Slow | InputFormat::General | InputFormat::Audit | InputFormat::Singlestore | InputFormat::Cloudwatch | InputFormat::Tcpdump => Dialect::Mysql,
+        }
+    }
+}
+
+/// A stream of queries parsed from a log, with progress counters.
+pub trait QueryReader: Iterator<Item = Result<Query>> {
+    /// Returns the number of bytes read from the input so far.
+    fn bytes_read(&self) -> u64;
+    /// Returns the number of log records skipped so far because they could not be parsed.
+    fn skipped_blocks(&self) -> u64;
+
+    /// Returns the number of lines skipped so far because they are not part of
+    /// any event, such as the banner the server writes when it starts.
+    fn noise_lines(&self) -> u64 {
+        0
+    }
+
+    /// Keeps the blocks skipped from now on for [`QueryReader::take_rejected`].
+    fn keep_rejected(&mut self) {}
+
+    /// Takes the blocks kept since the last call.
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        Vec::new()
+    }
+}
+
+impl<R: BufRead> QueryReader for LogParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    fn noise_lines(&self) -> u64 {
+        self.noise_lines
+    }
+
+    fn keep_rejected(&mut self) {
+        self.rejected.get_or_insert_with(Vec::new);
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.rejected.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+/// Creates a parser for a log in the given format.
+pub fn parse_input<'a, R: BufRead + 'a>(reader: R, format: InputFormat, log_timezone: Timezone) -> Box<dyn QueryReader + 'a> {
+    match format {
+        // The slow log parser reads TiDB's headers as well
+        InputFormat::Slow | InputFormat::Tidb => Box::new(LogParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::General => Box::new(crate::general::GeneralLogParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::Audit => Box::new(crate::audit::AuditLogParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::Singlestore => Box::new(crate::singlestore::SingleStoreParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::Postgresql => Box::new(crate::postgres::PostgresLogParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::Cloudwatch => Box::new(crate::cloudwatch::CloudWatchParser::new(reader).with_log_timezone(log_timezone)),
+        InputFormat::Tcpdump => Box::new(crate::tcpdump::TcpdumpParser::new(reader).with_log_timezone(log_timezone)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +756,291 @@ WHERE id = 1;"#;
         assert_eq!(query.sql_text, "SELECT 1;");
         assert_eq!(query.query_time, 0.0);
     }
+
+    #[test]
+    fn test_query_serde_roundtrip() {
+        let block = r#"# Time: 2023-10-27T10:00:00.123456Z
+# Query_time: 0.5  Lock_time: 0.1 Rows_sent: 1  Rows_examined: 2
+SELECT 1;"#;
+        let parser = LogParser::new(&[][..]);
+        let query = parser.parse_block(block).unwrap();
+
+        let json = serde_json::to_string(&query).unwrap();
+        let decoded: Query = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, query);
+    }
+
+    #[test]
+    fn test_naive_time_uses_log_timezone() {
+        let block = "# Time: 2023-10-27T10:00:00.5\n# Query_time: 0.5\nSELECT 1;";
+        let query = LogParser::new(&[][..]).parse_block(block).unwrap();
+        assert_eq!(query.timestamp.unwrap().to_rfc3339(), "2023-10-27T10:00:00.500+00:00");
+
+        let parser = LogParser::new(&[][..]).with_log_timezone("Asia/Tokyo".parse().unwrap());
+        let query = parser.parse_block(block).unwrap();
+        assert_eq!(query.timestamp.unwrap().to_rfc3339(), "2023-10-27T01:00:00.500+00:00");
+
+        // Timestamps with an offset are not affected
+        let query = parser.parse_block("# Time: 2023-10-27T10:00:00Z\n# Query_time: 0.5\nSELECT 1;").unwrap();
+        assert_eq!(query.timestamp.unwrap().to_rfc3339(), "2023-10-27T10:00:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_block_strips_connection_id() {
+        let parser = LogParser::new(&[][..]);
+        let query = parser.parse_block("# User@Host: app[app] @ web1 [10.0.0.1]  Id:    42\n# Query_time: 0.5\nSELECT 1;").unwrap();
+        assert_eq!(query.user_host, "app[app] @ web1 [10.0.0.1]");
+    }
+
+    #[test]
+    fn test_header_pairs() {
+        let pairs: Vec<_> = header_pairs("# Query_time: 0.5  Lock_time: 0.1 Rows_sent: 1  Rows_examined: 2").collect();
+        assert_eq!(pairs, [("Query_time", "0.5"), ("Lock_time", "0.1"), ("Rows_sent", "1"), ("Rows_examined", "2")]);
+        let pairs: Vec<_> = header_pairs("# Schema:  Last_errno: 0").collect();
+        assert_eq!(pairs, [("Schema", ""), ("Last_errno", "0")]);
+    }
+
+    #[test]
+    fn test_parse_user_host() {
+        assert_eq!(parse_user_host(" app[app] @ web1 [10.0.0.1]  Id:    42"), ("app[app] @ web1 [10.0.0.1]", Some(42)));
+        assert_eq!(parse_user_host(" root[root] @ localhost []"), ("root[root] @ localhost []", None));
+        assert_eq!(parse_user_host(" userId:[x] @ h []"), ("userId:[x] @ h []", None));
+    }
+
+    #[test]
+    fn test_query_database() {
+        let parser = LogParser::new(&[][..]);
+        let query = parser.parse_block("# Query_time: 0.5\nuse `shop`;\nSELECT 1;").unwrap();
+        assert_eq!(query.database(), Some("shop"));
+
+        let query = parser.parse_block("# Query_time: 0.5\nSELECT 1;").unwrap();
+        assert_eq!(query.database(), None);
+
+        // MySQL only logs `use` when the database changes, until the server restarts
+        let log = ["SELECT 0;", "use shop;\nSELECT 1;", "SELECT 2;", "# Schema: billing\nSELECT 3;", "use `crm`;\nSELECT 4;", "SELECT 5;\n/usr/sbin/mysqld, Version: 8.0.36 (MySQL Community Server - GPL). started with:", "SELECT 6;"]
+            .map(|sql| format!("# User@Host: app[app] @ localhost []\n# Query_time: 0.5\n{}\n", sql))
+            .concat();
+        let databases: Vec<_> = LogParser::new(log.as_bytes()).map(|query| query.unwrap().database().map(str::to_string)).collect();
+        let expected = [None, Some("shop"), Some("shop"), Some("billing"), Some("crm"), Some("crm"), None];
+        assert_eq!(databases, expected.map(|db| db.map(str::to_string)));
+    }
+
+    fn databases(entries: &[(&str, &str)]) -> Vec<Option<String>> {
+        let log = entries.iter().map(|(headers, sql)| format!("# User@Host: app[app] @ localhost []\n{}# Query_time: 0.5\n{}\n", headers, sql)).collect::<String>();
+        LogParser::new(log.as_bytes()).map(|query| query.unwrap().database().map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_use_carries_over_to_later_entries() {
+        let entries = [("", "SELECT 0;"), ("", "use shop;\nSELECT 1;"), ("", "SELECT 2;"), ("", "SELECT 3;")];
+        assert_eq!(databases(&entries), [None, Some("shop".to_string()), Some("shop".to_string()), Some("shop".to_string())]);
+
+        let log = "# User@Host: app[app] @ localhost []\n# Query_time: 0.5\nuse shop;\nSELECT 1;\n# User@Host: app[app] @ localhost []\n# Query_time: 0.5\nSELECT 2;\n";
+        let query = LogParser::new(log.as_bytes()).nth(1).unwrap().unwrap();
+        assert_eq!(query.schema.as_deref(), Some("shop"));
+        assert_eq!(query.sql_text, "SELECT 2;");
+    }
+
+    #[test]
+    fn test_schema_header_overrides_carried_use() {
+        let entries = [
+            ("", "use shop;\nSELECT 1;"),
+            ("# Thread_id: 42  Schema: billing  QC_hit: No\n", "SELECT 2;"),
+            ("# Thread_id: 42  Schema:   QC_hit: No\n", "SELECT 3;"),
+            ("", "SELECT 4;"),
+        ];
+        // An empty Schema header means no database was logged, so the carried one applies
+        let expected = [Some("shop"), Some("billing"), Some("shop"), Some("shop")];
+        assert_eq!(databases(&entries), expected.map(|db| db.map(str::to_string)));
+    }
+
+    #[test]
+    fn test_is_schema_change() {
+        let query = |sql: &str| Query { query_time: 1.0, lock_time: 0.0, rows_sent: 0, rows_examined: 0, timestamp: None, user_host: String::new(), sql_text: sql.to_string(), ..Default::default() };
+        assert!(query("use shop;\nSET timestamp=1700000000;\nALTER TABLE orders ADD INDEX (user_id);").is_schema_change());
+        assert!(query("/* migration 42 */ create index i on t (a);").is_schema_change());
+        assert!(query("TRUNCATE t;").is_schema_change());
+        assert!(!query("CREATE TEMPORARY TABLE tmp (id int);").is_schema_change());
+        assert!(!query("SELECT 'drop table t';").is_schema_change());
+        assert!(!query("UPDATE created SET a = 1;").is_schema_change());
+    }
+
+    #[test]
+    fn test_transaction_control() {
+        let query = |sql: &str| Query { query_time: 1.0, lock_time: 0.0, rows_sent: 0, rows_examined: 0, timestamp: None, user_host: String::new(), sql_text: sql.to_string(), ..Default::default() };
+        assert_eq!(query("COMMIT;").transaction_control(), Some("COMMIT"));
+        assert_eq!(query("use shop;\nstart transaction read write;").transaction_control(), Some("BEGIN"));
+        assert_eq!(query("ROLLBACK TO SAVEPOINT s1").transaction_control(), Some("ROLLBACK"));
+        assert_eq!(query("release savepoint s1;").transaction_control(), Some("SAVEPOINT"));
+        assert_eq!(query("SELECT commit FROM t;").transaction_control(), None);
+        assert_eq!(query("committed_at = 1;").transaction_control(), None);
+
+        let parser = LogParser::new(&[][..]);
+        let parsed = parser.parse_block("# User@Host: a[a] @ h []  Id: 7\n# Query_time: 0.5\nCOMMIT;").unwrap();
+        assert_eq!(parsed.thread_id, Some(7));
+    }
+
+    #[test]
+    fn test_mariadb_headers() {
+        let parser = LogParser::new(&[][..]);
+        let parsed = parser
+            .parse_block("# User@Host: app[app] @ localhost []\n# Thread_id: 42  Schema: shop  QC_hit: Yes\n# Query_time: 0.5  Lock_time: 0.0  Rows_sent: 1  Rows_examined: 1\nSELECT 1;")
+            .unwrap();
+        assert_eq!((parsed.thread_id, parsed.schema.as_deref(), parsed.qc_hit), (Some(42), Some("shop"), Some(true)));
+        assert_eq!(parsed.database(), Some("shop"));
+
+        // A USE in the statement wins over the schema, and an empty schema is none
+        let parsed = parser.parse_block("# User@Host: app[app] @ localhost []\n# Thread_id: 42  Schema:   QC_hit: No\n# Query_time: 0.5\nuse other;\nSELECT 1;").unwrap();
+        assert_eq!((parsed.schema.as_deref(), parsed.qc_hit, parsed.database()), (None, Some(false), Some("other")));
+    }
+
+    #[test]
+    fn test_log_slow_extra() {
+        let parser = LogParser::new(&[][..]);
+        let block = "# User@Host: app[app] @ localhost []  Id: 8
+# Query_time: 0.000231  Lock_time: 0.000106 Rows_sent: 1  Rows_examined: 1 Thread_id: 10 Errno: 1317 Killed: 0 Bytes_received: 0 Bytes_sent: 56 Read_first: 0 Read_last: 0 Read_key: 1 Read_next: 0 Read_prev: 0 Read_rnd: 0 Read_rnd_next: 5 Sort_merge_passes: 0 Sort_range_count: 0 Sort_rows: 0 Sort_scan_count: 0 Created_tmp_disk_tables: 0 Created_tmp_tables: 0 Start: 2019-03-25T12:36:04.016104Z End: 2019-03-25T12:36:04.016335Z
+SELECT * FROM t WHERE id = 1;";
+        let parsed = parser.parse_block(block).unwrap();
+        assert_eq!((parsed.thread_id, parsed.errno, parsed.killed), (Some(10), Some(1317), Some(0)));
+        assert_eq!(parsed.timestamp, Some("2019-03-25T12:36:04.016335Z".parse().unwrap()));
+        assert_eq!(parsed.metrics.len(), 15);
+        assert!(parsed.metrics.contains(&("Read_rnd_next".to_string(), 5.0)));
+        assert!(parsed.metrics.contains(&("Bytes_sent".to_string(), 56.0)));
+        assert_eq!((parsed.bytes_sent(), parsed.rows_affected()), (Some(56), None));
+    }
+
+    #[test]
+    fn test_tidb() {
+        let log = "# Time: 2019-08-14T09:26:59.487776265+08:00
+# Txn_start_ts: 410450924122144769
+# User@Host: root[root] @ localhost [127.0.0.1]
+# Conn_ID: 3086
+# Query_time: 1.527627037
+# Parse_time: 0.000054933
+# Compile_time: 0.000129729
+# Process_time: 0.07 Request_count: 1 Total_keys: 131073 Process_keys: 131072 Prewrite_time: 0.335415029
+# DB: test
+# Is_internal: false
+# Digest: 50a2e32d2abbd6c1764b1b7f2058d428ef2712b029282b776beb9506a365c0f1
+# Stats: t:pseudo
+# Cop_proc_avg: 0.07 Cop_proc_p90: 0.07 Cop_proc_max: 0.07 Cop_proc_addr: 172.16.5.87:20171
+# Mem_max: 525211
+# Prepared: false
+# Plan_from_cache: false
+# Result_rows: 12
+# Succ: true
+# Plan: tidb_decode_plan('ZJAwCTMyXzcJMAkyMAlkYXRhOlRhYmxlU2Nhbl82CjE=')
+use test;
+insert into t select * from t;
+# Time: 2019-08-14T09:27:00.000000001+08:00
+# User@Host: root[root] @ localhost [127.0.0.1]
+# Conn_ID: 3087
+# Query_time: 0.5
+# Succ: false
+select * from t where id = 1;
+";
+        let queries: Vec<Query> = parse_input(log.as_bytes(), InputFormat::Tidb, Timezone::default()).map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 2);
+        let query = &queries[0];
+        assert_eq!((query.query_time, query.rows_sent, query.thread_id), (1.527627037, 12, Some(3086)));
+        assert_eq!((query.user_host.as_str(), query.schema.as_deref()), ("root[root] @ localhost [127.0.0.1]", Some("test")));
+        assert_eq!(query.sql_text, "use test;\ninsert into t select * from t;");
+        assert_eq!(query.timestamp, Some("2019-08-14T01:26:59.487776265Z".parse().unwrap()));
+        assert!(query.metrics.contains(&("Process_time".to_string(), 0.07)));
+        assert!(query.metrics.contains(&("Total_keys".to_string(), 131073.0)));
+        assert!(!query.metrics.iter().any(|(name, _)| name == "Cop_proc_p90"));
+        assert!(query.plan_flags.contains(&("Succ".to_string(), true)));
+        assert!(query.plan_flags.contains(&("Plan_from_cache".to_string(), false)));
+        assert_eq!(queries[1].plan_flags, vec![("Succ".to_string(), false)]);
+        assert_eq!(InputFormat::Tidb.dialect(), Dialect::Tidb);
+
+        assert!(is_time_metric("Process_time") && is_time_metric("Wait_TS") && is_time_metric("InnoDB_IO_r_wait"));
+        assert!(!is_time_metric("Total_keys") && !is_time_metric("Rows_affected"));
+    }
+
+    #[test]
+    fn test_legacy_time_header() {
+        let utc = Timezone::default();
+        assert_eq!(parse_time_header(" 231027 10:00:00", utc), Some("2023-10-27T10:00:00Z".parse().unwrap()));
+        assert_eq!(parse_time_header("231027  9:05:03", utc), Some("2023-10-27T09:05:03Z".parse().unwrap()));
+        assert_eq!(parse_time_header("231027  9:05:03", "+02:00".parse().unwrap()), Some("2023-10-27T07:05:03Z".parse().unwrap()));
+        assert_eq!(parse_time_header("2023-10-27T10:00:00.5+01:00", utc), Some("2023-10-27T09:00:00.5Z".parse().unwrap()));
+        assert_eq!(parse_time_header("yesterday", utc), None);
+
+        let log = "# Time: 231027  9:05:03\n# User@Host: a[a] @ h []\n# Query_time: 1.0\nSELECT 1;\n";
+        let query = LogParser::new(log.as_bytes()).with_log_timezone("+02:00".parse().unwrap()).next().unwrap().unwrap();
+        assert_eq!(query.timestamp, Some("2023-10-27T07:05:03Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_set_timestamp_fallback() {
+        let log = "# Time: 2023-10-27T10:00:00Z
+# User@Host: a[a] @ h []
+# Query_time: 1.0
+SET timestamp=1698400799;
+SELECT 1;
+# User@Host: a[a] @ h []
+# Query_time: 0.5
+SET timestamp=1698400801.250000;
+SELECT 2;
+";
+        let queries: Vec<Query> = LogParser::new(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(queries[0].timestamp, Some("2023-10-27T10:00:00Z".parse().unwrap()));
+        // Started at 10:00:01.25 and ran for half a second
+        assert_eq!(queries[1].timestamp, Some("2023-10-27T10:00:01.750Z".parse().unwrap()));
+        assert_eq!(queries[1].sql_text, "SELECT 2;");
+    }
+
+    #[test]
+    fn test_counts_skipped_blocks() {
+        let log = "# Time: 2023-10-27T10:00:00Z\n# Query_time: 1.0\n\n# User@Host: a[a] @ h []\n# Query_time: 2.0\nSELECT 2;\n# Time: 2023-10-27T10:00:05Z\n";
+        let mut parser = LogParser::new(log.as_bytes());
+        assert_eq!(parser.by_ref().count(), 1);
+        assert_eq!(parser.skipped_blocks(), 1);
+    }
+
+    #[test]
+    fn test_skips_noise() {
+        let log = "/usr/sbin/mysqld, Version: 8.0.36 (MySQL Community Server - GPL). started with:
+Tcp port: 3306  Unix socket: /var/run/mysqld/mysqld.sock
+Time                 Id Command    Argument
+# Time: 2023-10-27T10:00:00Z
+# User@Host: a[a] @ h []
+# Query_time: 1.0
+SELECT 1;
+/usr/sbin/mariadbd, Version: 10.11.6-MariaDB-log (MariaDB Server). started with:
+Tcp port: 3306  Unix socket: /run/mysqld/mysqld.sock
+Time		    Id Command	Argument
+# Time: 2023-10-27T10:00:05Z
+# User@Host: a[a] @ h []
+# Query_time: 2.0
+SELECT 2;
+";
+        let mut parser = LogParser::new(log.as_bytes());
+        let queries: Vec<Query> = parser.by_ref().map(Result::unwrap).collect();
+        assert_eq!(queries.iter().map(|query| query.sql_text.as_str()).collect::<Vec<_>>(), ["SELECT 1;", "SELECT 2;"]);
+        assert_eq!((parser.noise_lines(), parser.skipped_blocks()), (6, 0));
+
+        assert!(is_noise("C:\\Program Files\\MySQL\\bin\\mysqld.exe, Version: 8.0.36 (MySQL Community Server - GPL). started with:"));
+        assert!(is_noise("TCP Port: 3306, Named Pipe: MySQL"));
+        assert!(!is_noise("SELECT 'Tcp port: 3306'"));
+        assert!(is_noise("Time\t\t    Id Command    Argument\r"));
+        assert!(!is_noise("Time taken: 3s"));
+    }
+
+    #[test]
+    fn test_next_event_keeps_raw_block() {
+        let log = "# Time: 2023-10-27T10:00:00Z\n# User@Host: a[a] @ h []\n# Query_time: 1.0\nSELECT 1;\n# User@Host: b[b] @ h []\n# Query_time: 2.0\nSELECT 2;\n";
+        let mut parser = LogParser::new(log.as_bytes());
+
+        let first = parser.next_event().unwrap().unwrap();
+        assert_eq!(first.raw, "# Time: 2023-10-27T10:00:00Z\n# User@Host: a[a] @ h []\n# Query_time: 1.0\nSELECT 1;\n");
+        assert_eq!(first.query.sql_text, "SELECT 1;");
+
+        let second = parser.next_event().unwrap().unwrap();
+        assert_eq!(second.raw, "# User@Host: b[b] @ h []\n# Query_time: 2.0\nSELECT 2;\n");
+        assert!(parser.next_event().is_none());
+        assert_eq!(parser.bytes_read(), log.len() as u64);
+        assert_eq!(parser.skipped_blocks(), 0);
+    }
 }
@@ -0,0 +1,120 @@
+use std::io::Write;
+
+/// A4 page size in points.
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 36.0;
+const FONT_SIZE: f64 = 8.0;
+const LINE_HEIGHT: f64 = 10.0;
+/// Courier glyphs are 0.6 em wide.
+const CHAR_WIDTH: f64 = FONT_SIZE * 0.6;
+
+/// Writes plain text as a paginated PDF document using the built-in Courier font.
+///
+/// Lines longer than the printable width are wrapped, and characters outside
+/// printable ASCII are replaced with `?` since no font is embedded.
+pub fn write_text_pdf(text: &str, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let max_chars = ((PAGE_WIDTH - 2.0 * MARGIN) / CHAR_WIDTH) as usize;
+    let lines_per_page = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize;
+
+    let lines = wrap_lines(text, max_chars);
+    let pages: Vec<&[String]> = if lines.is_empty() {
+        vec![&[]]
+    } else {
+        lines.chunks(lines_per_page).collect()
+    };
+
+    // Object layout: 1 catalog, 2 pages, 3 font, then a (page, content) pair per page
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", 4 + i * 2)).collect();
+
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).into_bytes());
+    objects.push(b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>".to_vec());
+
+    for (i, page) in pages.iter().enumerate() {
+        let content_id = 5 + i * 2;
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH, PAGE_HEIGHT, content_id
+        ).into_bytes());
+
+        let mut stream = format!("BT\n/F1 {} Tf\n{} TL\n{} {} Td\n", FONT_SIZE, LINE_HEIGHT, MARGIN, PAGE_HEIGHT - MARGIN - FONT_SIZE);
+        for line in page.iter() {
+            stream.push_str(&format!("({}) Tj T*\n", escape_pdf_string(line)));
+        }
+        stream.push_str("ET\n");
+
+        let mut obj = format!("<< /Length {} >>\nstream\n", stream.len()).into_bytes();
+        obj.extend_from_slice(stream.as_bytes());
+        obj.extend_from_slice(b"endstream");
+        objects.push(obj);
+    }
+
+    let mut out: Vec<u8> = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, obj) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        out.extend_from_slice(obj);
+        out.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref_offset = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref_offset).as_bytes());
+
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+/// Splits text into lines no longer than `max_chars`, sanitizing non-ASCII characters.
+fn wrap_lines(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let chars: Vec<char> = line
+            .chars()
+            .map(|c| if c == '\t' { ' ' } else if c.is_ascii() && !c.is_ascii_control() { c } else { '?' })
+            .collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+            continue;
+        }
+        for chunk in chars.chunks(max_chars) {
+            lines.push(chunk.iter().collect());
+        }
+    }
+    lines
+}
+
+/// Escapes characters with special meaning inside PDF literal strings.
+fn escape_pdf_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_lines() {
+        let lines = wrap_lines("abcdef\n\nxy\u{00e9}", 4);
+        assert_eq!(lines, vec!["abcd", "ef", "", "xy?"]);
+    }
+
+    #[test]
+    fn test_write_text_pdf_paginates() {
+        let text = (0..200).map(|i| format!("line ({})", i)).collect::<Vec<_>>().join("\n");
+        let mut buf = Vec::new();
+        write_text_pdf(&text, &mut buf).unwrap();
+        let pdf = String::from_utf8_lossy(&buf);
+
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("/Count 3"));
+        assert!(pdf.contains("(line \\(199\\)) Tj"));
+    }
+}
@@ -0,0 +1,434 @@
+use crate::aggregator::QueryStats;
+use crate::audit::build_query;
+use crate::digest::DigestConfig;
+use crate::fingerprint::fingerprint;
+use crate::parser::Query;
+use crate::report::{ReportItem, ServerStats};
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, Row};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+const DIGEST_QUERY: &str = "SELECT SCHEMA_NAME, DIGEST, DIGEST_TEXT, COUNT_STAR, SUM_TIMER_WAIT, MIN_TIMER_WAIT, MAX_TIMER_WAIT, SUM_LOCK_TIME, SUM_ROWS_SENT, SUM_ROWS_EXAMINED,
+    UNIX_TIMESTAMP(FIRST_SEEN), UNIX_TIMESTAMP(LAST_SEEN)
+FROM performance_schema.events_statements_summary_by_digest
+WHERE DIGEST_TEXT IS NOT NULL";
+
+const HISTORY_QUERY: &str = "SELECT h.THREAD_ID, h.EVENT_ID, h.SQL_TEXT, h.CURRENT_SCHEMA, h.TIMER_END, h.TIMER_WAIT, h.LOCK_TIME, h.ROWS_SENT, h.ROWS_EXAMINED, h.MYSQL_ERRNO,
+    t.PROCESSLIST_ID, t.PROCESSLIST_USER, t.PROCESSLIST_HOST
+FROM performance_schema.events_statements_history_long h
+LEFT JOIN performance_schema.threads t ON t.THREAD_ID = h.THREAD_ID
+WHERE h.SQL_TEXT IS NOT NULL AND h.END_EVENT_ID IS NOT NULL
+ORDER BY h.TIMER_END";
+
+/// performance_schema timers count picoseconds.
+const PICOSECONDS: f64 = 1e12;
+
+/// One row of `events_statements_summary_by_digest`, with times in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DigestRow {
+    pub schema: Option<String>,
+    pub digest: String,
+    pub digest_text: String,
+    pub count: u64,
+    pub total_time: f64,
+    pub min_time: f64,
+    pub max_time: f64,
+    pub lock_time: f64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Normalizes SQL text or a performance_schema `DIGEST_TEXT` so that the
+/// statements performance_schema groups under one digest get the same key.
+///
+/// On top of [`fingerprint`], identifier quotes are removed, value lists
+/// (`IN (?, ?)`, `IN (...)`, multi-row `VALUES`) collapse to `(?)`, and
+/// whitespace next to punctuation is dropped.
+pub fn digest_key(sql: &str) -> String {
+    static RE_LIST: OnceLock<Regex> = OnceLock::new();
+    static RE_ROWS: OnceLock<Regex> = OnceLock::new();
+    static RE_PUNCTUATION: OnceLock<Regex> = OnceLock::new();
+    let re_list = RE_LIST.get_or_init(|| Regex::new(r"\(\s*(?:\.\.\.|\?(?:\s*,\s*\?)*)\s*\)").unwrap());
+    let re_rows = RE_ROWS.get_or_init(|| Regex::new(r"\(\?\)(?:\s*,\s*\(\?\))+").unwrap());
+    let re_punctuation = RE_PUNCTUATION.get_or_init(|| Regex::new(r"\s*([^\w\s?])\s*").unwrap());
+
+    let key = fingerprint(sql).replace('`', "");
+    let key = re_list.replace_all(&key, "(?)");
+    let key = re_rows.replace_all(&key, "(?)");
+    let key = re_punctuation.replace_all(&key, "$1");
+    key.trim_end_matches(';').trim().to_string()
+}
+
+/// Reads the statement digests of the server at `dsn`.
+pub fn fetch(dsn: &str) -> anyhow::Result<Vec<DigestRow>> {
+    let opts = Opts::from_url(dsn).context("Invalid performance_schema DSN")?;
+    let mut conn = Conn::new(opts).context("Could not connect to the performance_schema server")?;
+    let rows = conn
+        .query_map(DIGEST_QUERY, |row: Row| DigestRow {
+            schema: column(&row, 0),
+            digest: column(&row, 1).unwrap_or_default(),
+            digest_text: column(&row, 2).unwrap_or_default(),
+            count: column(&row, 3).unwrap_or_default(),
+            total_time: picoseconds(column(&row, 4)),
+            min_time: picoseconds(column(&row, 5)),
+            max_time: picoseconds(column(&row, 6)),
+            lock_time: picoseconds(column(&row, 7)),
+            rows_sent: column(&row, 8).unwrap_or_default(),
+            rows_examined: column(&row, 9).unwrap_or_default(),
+            first_seen: column(&row, 10).and_then(epoch),
+            last_seen: column(&row, 11).and_then(epoch),
+        })
+        .context("Could not read events_statements_summary_by_digest")?;
+    Ok(rows)
+}
+
+/// Returns a column of a row, or `None` if it is NULL or of another type.
+fn column<T: mysql::prelude::FromValue>(row: &Row, index: usize) -> Option<T> {
+    row.get_opt::<Option<T>, _>(index).and_then(Result::ok).flatten()
+}
+
+fn picoseconds(timer: Option<u64>) -> f64 {
+    timer.unwrap_or_default() as f64 / PICOSECONDS
+}
+
+fn epoch(seconds: f64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_micros((seconds * 1e6).round() as i64)
+}
+
+/// A server whose statements are digested alongside or instead of logs, see `--source`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// The totals of `events_statements_summary_by_digest`, given as `dsn=<URL>`.
+    Digests(String),
+    /// The statements of `events_statements_history_long`, polled while the
+    /// digest runs, given as `history=<URL>`.
+    History(String),
+    /// The totals of ProxySQL's `stats_mysql_query_digest`, given as
+    /// `proxysql=<URL>` for the admin interface or `proxysql=<FILE>` for an export.
+    Proxysql(String),
+}
+
+impl Source {
+    /// Returns the name the source's queries are attributed to in the report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Digests(_) | Source::History(_) => "performance_schema",
+            Source::Proxysql(_) => "proxysql",
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("dsn", url)) if !url.is_empty() => Ok(Source::Digests(url.to_string())),
+            Some(("history", url)) if !url.is_empty() => Ok(Source::History(url.to_string())),
+            Some(("proxysql", location)) if !location.is_empty() => Ok(Source::Proxysql(location.to_string())),
+            _ => Err(format!("Invalid source {:?}: expected dsn=<URL>, history=<URL> or proxysql=<URL or FILE>", s)),
+        }
+    }
+}
+
+/// Converts digest rows into statistics, keyed the way `config` keys the
+/// queries of a log, so they are ranked and reported like a digested log.
+///
+/// The rows hold totals only, so no percentiles or users are known; the
+/// digest text stands in for the example queries.
+pub fn digest_stats(rows: Vec<DigestRow>, config: &DigestConfig) -> HashMap<String, QueryStats> {
+    let mut stats = HashMap::new();
+    for row in rows {
+        let Some(query) = build_query(row.digest_text, row.schema.as_deref(), String::new(), row.last_seen, None, &HashMap::new()) else { continue };
+        let example: Arc<str> = Arc::from(query.sql_text.as_str());
+        let entry = QueryStats {
+            count: row.count,
+            total_time: row.total_time,
+            min_time: row.min_time,
+            max_time: row.max_time,
+            total_lock_time: row.lock_time,
+            total_rows_sent: row.rows_sent,
+            total_rows_examined: row.rows_examined,
+            example_query: example.clone(),
+            worst_example_query: example,
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+            databases: row.schema.map(|schema| BTreeSet::from([Arc::from(schema)])).unwrap_or_default(),
+            ..Default::default()
+        };
+        crate::aggregator::merge_entry(&mut stats, config.key_for(&query), entry);
+    }
+    stats
+}
+
+/// One statement of `events_statements_history_long`, with the account of its connection if still open.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryRow {
+    pub thread_id: u64,
+    pub event_id: u64,
+    pub sql_text: String,
+    pub schema: Option<String>,
+    /// Picoseconds from the start of the server's timers to the end of the statement.
+    pub timer_end: u64,
+    pub timer_wait: u64,
+    pub lock_time: u64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    pub errno: u32,
+    pub connection_id: Option<u64>,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl HistoryRow {
+    /// Converts the row into a query, given when the server's timers started.
+    pub fn into_query(self, timers_started: DateTime<Utc>) -> Option<Query> {
+        let user = self.user.unwrap_or_default();
+        let user_host = format!("{}[{}] @ {} []", user, user, self.host.unwrap_or_default());
+        let timestamp = timers_started + Duration::microseconds((self.timer_end / 1_000_000) as i64);
+        let statistics = HashMap::from([
+            ("QUERY_TIME".to_string(), (self.timer_wait as f64 / PICOSECONDS).to_string()),
+            ("LOCK_TIME".to_string(), (self.lock_time as f64 / PICOSECONDS).to_string()),
+            ("ROWS_SENT".to_string(), self.rows_sent.to_string()),
+            ("ROWS_EXAMINED".to_string(), self.rows_examined.to_string()),
+        ]);
+        let mut query = build_query(self.sql_text, self.schema.as_deref(), user_host, Some(timestamp), self.connection_id, &statistics)?;
+        query.errno = (self.errno != 0).then_some(self.errno);
+        Some(query)
+    }
+}
+
+/// Reads the statements that ended since the last poll from `events_statements_history_long`.
+///
+/// The table keeps the last `performance_schema_events_statements_history_long_size`
+/// statements of the server, so statements that come and go between two polls
+/// are missed. The `events_statements_history_long` consumer must be enabled.
+pub struct HistoryPoller {
+    conn: Conn,
+    /// The poller's own thread, whose statements are left out.
+    own_thread: Option<u64>,
+    timers_started: DateTime<Utc>,
+    /// Last event read per thread.
+    last_events: HashMap<u64, u64>,
+}
+
+impl HistoryPoller {
+    pub fn connect(dsn: &str) -> anyhow::Result<Self> {
+        let opts = Opts::from_url(dsn).context("Invalid performance_schema DSN")?;
+        let mut conn = Conn::new(opts).context("Could not connect to the performance_schema server")?;
+        let own_thread = conn.query_first("SELECT THREAD_ID FROM performance_schema.threads WHERE PROCESSLIST_ID = CONNECTION_ID()")?;
+        let uptime: Option<f64> = conn.query_first("SELECT VARIABLE_VALUE FROM performance_schema.global_status WHERE VARIABLE_NAME = 'Uptime'")?;
+        // Timers start with the server; the uptime is only precise to the second
+        let timers_started = Utc::now() - Duration::seconds(uptime.unwrap_or_default() as i64);
+        Ok(Self { conn, own_thread, timers_started, last_events: HashMap::new() })
+    }
+
+    /// Returns the statements that ended since the last poll, oldest first.
+    pub fn poll(&mut self) -> anyhow::Result<Vec<Query>> {
+        let rows = self.conn
+            .query_map(HISTORY_QUERY, |row: Row| HistoryRow {
+                thread_id: column(&row, 0).unwrap_or_default(),
+                event_id: column(&row, 1).unwrap_or_default(),
+                sql_text: column(&row, 2).unwrap_or_default(),
+                schema: column(&row, 3),
+                timer_end: column(&row, 4).unwrap_or_default(),
+                timer_wait: column(&row, 5).unwrap_or_default(),
+                lock_time: column(&row, 6).unwrap_or_default(),
+                rows_sent: column(&row, 7).unwrap_or_default(),
+                rows_examined: column(&row, 8).unwrap_or_default(),
+                errno: column(&row, 9).unwrap_or_default(),
+                connection_id: column(&row, 10),
+                user: column(&row, 11),
+                host: column(&row, 12),
+            })
+            .context("Could not read events_statements_history_long")?;
+        let mut queries = Vec::new();
+        for row in rows {
+            if Some(row.thread_id) == self.own_thread {
+                continue;
+            }
+            let last = self.last_events.entry(row.thread_id).or_default();
+            if row.event_id <= *last {
+                continue;
+            }
+            *last = row.event_id;
+            queries.extend(row.into_query(self.timers_started));
+        }
+        Ok(queries)
+    }
+}
+
+/// Sets `ReportItem::server` for each item whose example matches digest rows.
+///
+/// Rows of all schemas the item ran in are summed (all schemas when the log
+/// did not record one). Digest texts truncated by `performance_schema_max_digest_length`
+/// cannot match. Returns the number of items annotated.
+pub fn annotate(items: &mut [ReportItem], rows: &[DigestRow]) -> usize {
+    let mut by_key: HashMap<String, Vec<&DigestRow>> = HashMap::new();
+    for row in rows {
+        by_key.entry(digest_key(&row.digest_text)).or_default().push(row);
+    }
+
+    let mut annotated = 0;
+    for item in items {
+        let Some(candidates) = by_key.get(&digest_key(&item.example_query)) else { continue };
+        let matching: Vec<&DigestRow> = candidates.iter().copied()
+            .filter(|row| item.databases.is_empty() || row.schema.as_ref().is_some_and(|schema| item.databases.iter().any(|db| db.eq_ignore_ascii_case(schema))))
+            .collect();
+        if matching.is_empty() {
+            continue;
+        }
+        let count: u64 = matching.iter().map(|row| row.count).sum();
+        let total_time: f64 = matching.iter().map(|row| row.total_time).sum();
+        let mean_time = if count > 0 { total_time / count as f64 } else { 0.0 };
+        let mut digests: Vec<String> = matching.iter().map(|row| row.digest.clone()).collect();
+        digests.sort();
+        digests.dedup();
+        item.server = Some(ServerStats {
+            digests,
+            count,
+            total_time,
+            mean_time,
+            lock_time: matching.iter().map(|row| row.lock_time).sum(),
+            rows_sent: matching.iter().map(|row| row.rows_sent).sum(),
+            rows_examined: matching.iter().map(|row| row.rows_examined).sum(),
+            coverage: if count > 0 { item.count as f64 / count as f64 } else { 0.0 },
+            representative: is_representative(item.mean_time, mean_time),
+        });
+        annotated += 1;
+    }
+    annotated
+}
+
+/// Returns true if the logged mean time is within a factor of two of the server's mean.
+fn is_representative(log_mean: f64, server_mean: f64) -> bool {
+    if server_mean <= 0.0 {
+        return log_mean <= 0.0;
+    }
+    (0.5..=2.0).contains(&(log_mean / server_mean))
+}
+
+/// Fetches the statement digests of the server at `dsn` and annotates the items with them.
+pub fn correlate(items: &mut [ReportItem], dsn: &str) -> anyhow::Result<usize> {
+    let rows = fetch(dsn)?;
+    Ok(annotate(items, &rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::{Digest, DigestConfig};
+
+    const LOG: &str = "# Time: 2024-05-01T10:00:00.000000Z
+# User@Host: app[app] @ localhost []
+# Query_time: 1.000000  Lock_time: 0.000000 Rows_sent: 0  Rows_examined: 0
+INSERT INTO audit (a, b) VALUES (1, 'x'), (2, 'y');
+# Time: 2024-05-01T10:00:01.000000Z
+# User@Host: app[app] @ localhost []
+# Query_time: 2.000000  Lock_time: 0.000000 Rows_sent: 1  Rows_examined: 10
+use shop;
+SELECT * FROM orders WHERE id IN (1, 2, 3);
+";
+
+    fn row(schema: &str, digest: &str, digest_text: &str, count: u64, total_time: f64) -> DigestRow {
+        DigestRow {
+            schema: Some(schema.to_string()),
+            digest: digest.to_string(),
+            digest_text: digest_text.to_string(),
+            count,
+            total_time,
+            rows_sent: count,
+            rows_examined: 10 * count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_digest_key_matches_digest_text() {
+        assert_eq!(digest_key("SELECT * FROM orders WHERE id IN (1, 2, 3);"), digest_key("SELECT * FROM `orders` WHERE `id` IN (...)"));
+        assert_eq!(digest_key("INSERT INTO t (a, b) VALUES (1, 'x'), (2, 'y')"), digest_key("INSERT INTO `t` ( `a` , `b` ) VALUES (...) /* , ... */"));
+        assert_eq!(digest_key("select count(*) from t where a=1"), digest_key("SELECT COUNT ( * ) FROM `t` WHERE `a` = ?"));
+        assert_ne!(digest_key("SELECT * FROM t WHERE a = 1"), digest_key("SELECT * FROM `t` WHERE `b` = ?"));
+    }
+
+    #[test]
+    fn test_annotate() {
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.add_reader(LOG.as_bytes());
+        let mut items = digest.finish();
+        let rows = vec![
+            row("shop", "aaa", "SELECT * FROM `orders` WHERE `id` IN (...)", 400, 40.0),
+            row("other", "aaa", "SELECT * FROM `orders` WHERE `id` IN (...)", 100, 10.0),
+            row("logs", "bbb", "INSERT INTO `audit` ( `a` , `b` ) VALUES (...) /* , ... */", 2, 1.8),
+        ];
+
+        assert_eq!(annotate(&mut items, &rows), 2);
+        let select = items.iter().find(|item| item.normalized_query.starts_with("select")).unwrap();
+        let server = select.server.as_ref().unwrap();
+        assert_eq!(server.count, 400);
+        assert_eq!(server.mean_time, 0.1);
+        assert_eq!(server.coverage, 1.0 / 400.0);
+        assert!(!server.representative);
+
+        // The INSERT ran without USE, so rows of every schema match
+        let insert = items.iter().find(|item| item.normalized_query.starts_with("insert")).unwrap();
+        let server = insert.server.as_ref().unwrap();
+        assert_eq!(server.digests, vec!["bbb".to_string()]);
+        assert_eq!(server.coverage, 0.5);
+        assert!(server.representative);
+    }
+
+    #[test]
+    fn test_digest_stats() {
+        assert_eq!("dsn=mysql://root@db:3306".parse(), Ok(Source::Digests("mysql://root@db:3306".to_string())));
+        assert_eq!("history=mysql://root@db".parse(), Ok(Source::History("mysql://root@db".to_string())));
+        assert_eq!("proxysql=digest.csv".parse(), Ok(Source::Proxysql("digest.csv".to_string())));
+        assert!("mysql://root@db".parse::<Source>().is_err());
+
+        let rows = vec![
+            DigestRow { min_time: 0.05, max_time: 0.5, ..row("shop", "aaa", "SELECT * FROM `orders` WHERE `id` = ?", 400, 40.0) },
+            DigestRow { min_time: 0.01, max_time: 0.2, ..row("other", "bbb", "SELECT * FROM `orders` WHERE `id` = ?", 100, 10.0) },
+            row("logs", "ccc", "INSERT INTO `audit` ( `a` ) VALUES (...)", 2, 1.8),
+        ];
+        let mut digest = Digest::new(DigestConfig::default());
+        for (key, stats) in digest_stats(rows, digest.config()) {
+            digest.add_stats(key, stats);
+        }
+        let items = digest.finish();
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].count, items[0].total_time, items[0].mean_time), (500, 50.0, 0.1));
+        assert_eq!(items[0].databases, vec!["other".to_string(), "shop".to_string()]);
+        assert_eq!(items[0].example_query, "use shop;\nSELECT * FROM `orders` WHERE `id` = ?");
+    }
+
+    #[test]
+    fn test_history_row() {
+        let row = HistoryRow {
+            thread_id: 48,
+            event_id: 7,
+            sql_text: "SELECT * FROM orders WHERE id = 1".to_string(),
+            schema: Some("shop".to_string()),
+            timer_end: 90_500_000_000_000,
+            timer_wait: 1_500_000_000_000,
+            rows_sent: 1,
+            rows_examined: 100,
+            errno: 0,
+            connection_id: Some(12),
+            user: Some("app".to_string()),
+            host: Some("10.0.0.5".to_string()),
+            ..Default::default()
+        };
+        let query = row.into_query("2024-05-01T10:00:00Z".parse().unwrap()).unwrap();
+        assert_eq!(query.sql_text, "use shop;\nSELECT * FROM orders WHERE id = 1");
+        assert_eq!((query.query_time, query.rows_examined, query.thread_id, query.errno), (1.5, 100, Some(12), None));
+        assert_eq!(query.user_host, "app[app] @ 10.0.0.5 []");
+        assert_eq!(query.timestamp, Some("2024-05-01T10:01:30.500Z".parse().unwrap()));
+    }
+}
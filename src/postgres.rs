@@ -0,0 +1,210 @@
+use crate::audit::build_query;
+use crate::parser::{Query, QueryReader, RejectedBlock};
+use crate::timezone::Timezone;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::OnceLock;
+
+static RE_DURATION: OnceLock<Regex> = OnceLock::new();
+static RE_TIME: OnceLock<Regex> = OnceLock::new();
+static RE_PREFIX_FIELD: OnceLock<Regex> = OnceLock::new();
+static RE_USER_AT_DB: OnceLock<Regex> = OnceLock::new();
+static RE_PID: OnceLock<Regex> = OnceLock::new();
+
+/// Parses the statements PostgreSQL logs to stderr with
+/// `log_min_duration_statement`: `LOG:  duration: 12.345 ms  statement: ...`
+/// entries, and `execute <name>: ...` for the extended query protocol.
+///
+/// Lines that start with whitespace continue the entry before them, as
+/// PostgreSQL indents the further lines of multi-line statements with a tab.
+/// Other entries (`DETAIL: parameters: ...`, errors, `parse` and `bind`
+/// durations, checkpoints) are ignored.
+///
+/// From the `log_line_prefix`, the parser reads the time at its start (`%t`
+/// or `%m`), the process ID in brackets (`[%p]`, used as the connection),
+/// `user=%u`, `db=%d` and `client=%h`/`host=%h` pairs, or a `%u@%d` pair.
+/// Times in UTC or with a numeric offset are converted; other zone names are
+/// taken to be the log timezone.
+pub struct PostgresLogParser<R> {
+    reader: R,
+    line: String,
+    /// The entry being read.
+    entry: String,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    log_timezone: Timezone,
+    lines_read: u64,
+    /// Line the entry being read starts on.
+    entry_line: u64,
+    rejected: Option<Vec<RejectedBlock>>,
+}
+
+impl<R: BufRead> PostgresLogParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            entry: String::new(),
+            bytes_read: 0,
+            skipped_blocks: 0,
+            log_timezone: Timezone::default(),
+            lines_read: 0,
+            entry_line: 0,
+            rejected: None,
+        }
+    }
+
+    /// Sets the timezone of log times whose zone is not UTC or a numeric offset (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Reads up to the start of the next entry and returns the line and text of the completed one.
+    fn next_entry(&mut self) -> Option<Result<(u64, String)>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) if self.entry.is_empty() => return None,
+                Ok(0) => return Some(Ok((self.entry_line, std::mem::take(&mut self.entry)))),
+                Ok(n) => {
+                    self.bytes_read += n as u64;
+                    self.lines_read += 1;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+            let continues = self.line.starts_with(char::is_whitespace);
+            if continues && !self.entry.is_empty() {
+                self.entry.push_str(&self.line);
+                continue;
+            }
+            let line = self.lines_read;
+            let completed = std::mem::replace(&mut self.entry, if continues { String::new() } else { std::mem::take(&mut self.line) });
+            let completed_line = std::mem::replace(&mut self.entry_line, line);
+            if !completed.is_empty() {
+                return Some(Ok((completed_line, completed)));
+            }
+        }
+    }
+
+    /// Returns the query of a duration entry, `Ok(None)` for other entries.
+    fn parse_entry(&self, entry: &str) -> Result<Option<Query>> {
+        let re = RE_DURATION.get_or_init(|| Regex::new(r"(?s)^(.*?)\b[A-Z]+:\s+duration: ([0-9.]+) ms\s+(statement|execute [^:]*): (.*)$").unwrap());
+        let Some(caps) = re.captures(entry) else { return Ok(None) };
+        let prefix = &caps[1];
+        let duration: f64 = caps[2].parse().map_err(|_| anyhow::anyhow!("Invalid duration {:?}", &caps[2]))?;
+        // Further lines of the statement are indented with a tab
+        let sql = caps[4].lines().map(|line| line.strip_prefix('\t').unwrap_or(line)).collect::<Vec<_>>().join("\n");
+
+        let fields: HashMap<&str, &str> = RE_PREFIX_FIELD
+            .get_or_init(|| Regex::new(r"\b(user|db|client|host)=([^\s,\]]+)").unwrap())
+            .captures_iter(prefix)
+            .map(|caps| (caps.get(1).unwrap().as_str(), caps.get(2).unwrap().as_str()))
+            .collect();
+        let user_at_db = RE_USER_AT_DB.get_or_init(|| Regex::new(r"(?:^|\s)([A-Za-z_][\w$-]*)@([A-Za-z_][\w$-]*)(?:\s|$)").unwrap()).captures(prefix);
+        let user = fields.get("user").copied().or_else(|| user_at_db.as_ref().map(|caps| caps.get(1).unwrap().as_str())).unwrap_or_default();
+        let db = fields.get("db").copied().or_else(|| user_at_db.as_ref().map(|caps| caps.get(2).unwrap().as_str()));
+        let host = fields.get("client").or_else(|| fields.get("host")).copied().unwrap_or_default();
+        let thread_id = RE_PID.get_or_init(|| Regex::new(r"\[(\d+)\]").unwrap()).captures(prefix).and_then(|caps| caps[1].parse().ok());
+
+        let statistics = HashMap::from([("QUERY_TIME".to_string(), (duration / 1000.0).to_string())]);
+        let user_host = format!("{}[{}] @ {} []", user, user, host);
+        Ok(build_query(sql, db, user_host, parse_prefix_time(prefix, self.log_timezone), thread_id, &statistics))
+    }
+}
+
+/// Parses the `%t` or `%m` time at the start of a log line prefix, such as
+/// `2024-05-01 10:00:00.123 UTC` or `2024-05-01 19:00:00 +09`.
+fn parse_prefix_time(prefix: &str, log_timezone: Timezone) -> Option<DateTime<Utc>> {
+    let re = RE_TIME.get_or_init(|| Regex::new(r"^\s*(\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:\.\d+)?)(?: ([A-Za-z]+|[+-]\d{2}(?::?\d{2})?))?").unwrap());
+    let caps = re.captures(prefix)?;
+    let naive = NaiveDateTime::parse_from_str(&caps[1], "%Y-%m-%d %H:%M:%S%.f").ok()?;
+    match caps.get(2).map(|zone| zone.as_str()) {
+        Some("UTC" | "GMT" | "Z") => Some(naive.and_utc()),
+        Some(offset) if offset.starts_with(['+', '-']) => {
+            let digits: String = offset[1..].chars().filter(char::is_ascii_digit).collect();
+            let minutes = digits[..2].parse::<i32>().ok()? * 60 + digits.get(2..4).map_or(Ok(0), str::parse::<i32>).ok()?;
+            let offset = FixedOffset::east_opt(if offset.starts_with('-') { -minutes * 60 } else { minutes * 60 })?;
+            offset.from_local_datetime(&naive).single().map(|time| time.with_timezone(&Utc))
+        }
+        _ => Some(log_timezone.to_utc(&naive)),
+    }
+}
+
+impl<R: BufRead> Iterator for PostgresLogParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, entry) = match self.next_entry()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.parse_entry(&entry) {
+                Ok(Some(query)) => return Some(Ok(query)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.skipped_blocks += 1;
+                    tracing::debug!("Skipping invalid PostgreSQL log entry: {:#}", e);
+                    if let Some(rejected) = &mut self.rejected {
+                        rejected.push(RejectedBlock { line, reason: format!("{:#}", e), raw: entry });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> QueryReader for PostgresLogParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    fn keep_rejected(&mut self) {
+        self.rejected.get_or_insert_with(Vec::new);
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.rejected.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let log = "2024-05-01 10:00:00 UTC [4242]: [1-1] user=app,db=shop,app=psql,client=10.0.0.5 LOG:  duration: 1523.456 ms  statement: SELECT *
+\tFROM orders
+\tWHERE id = 1;
+2024-05-01 10:00:01.250 UTC [4242]: [2-1] user=app,db=shop,app=psql,client=10.0.0.5 LOG:  duration: 0.512 ms  parse S_1: SELECT 1
+2024-05-01 10:00:01.500 UTC [4242]: [3-1] user=app,db=shop,app=psql,client=10.0.0.5 LOG:  duration: 2.500 ms  execute S_1: SELECT * FROM carts WHERE id = $1
+2024-05-01 10:00:01.500 UTC [4242]: [4-1] user=app,db=shop,app=psql,client=10.0.0.5 DETAIL:  parameters: $1 = '7'
+2024-05-01 10:00:02 UTC [17]: [1-1] LOG:  checkpoint starting: time
+2024-05-01 19:00:03.000 +09 [99] report@analytics LOG:  duration: 10.000 ms  statement: SELECT count(*) FROM events
+";
+        let queries: Vec<Query> = PostgresLogParser::new(log.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 3);
+
+        let query = &queries[0];
+        assert!((query.query_time - 1.523456).abs() < 1e-9);
+        assert_eq!(query.sql_text, "use shop;\nSELECT *\nFROM orders\nWHERE id = 1;");
+        assert_eq!((query.user_host.as_str(), query.user(), query.database()), ("app[app] @ 10.0.0.5 []", "app", Some("shop")));
+        assert_eq!(query.timestamp, Some("2024-05-01T10:00:00Z".parse().unwrap()));
+        assert_eq!(query.thread_id, Some(4242));
+
+        assert_eq!(queries[1].sql_text, "use shop;\nSELECT * FROM carts WHERE id = $1");
+        assert_eq!(queries[1].timestamp, Some("2024-05-01T10:00:01.500Z".parse().unwrap()));
+
+        assert_eq!((queries[2].user(), queries[2].database(), queries[2].thread_id), ("report", Some("analytics"), Some(99)));
+        assert_eq!(queries[2].timestamp, Some("2024-05-01T10:00:03Z".parse().unwrap()));
+    }
+}
@@ -0,0 +1,179 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, Ordering};
+use std::time::Duration;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+/// Bytes allocated minus bytes freed since counting started; negative when
+/// memory allocated before that is freed.
+static HEAP: AtomicIsize = AtomicIsize::new(0);
+static PEAK_HEAP: AtomicIsize = AtomicIsize::new(0);
+
+/// The system allocator, counting allocations once [`start_counting`] is called.
+///
+/// Install it in a binary with `#[global_allocator]`; until counting starts,
+/// it costs one relaxed load per allocation.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(&self, allocated: usize, freed: usize) {
+        if allocated > 0 {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED.fetch_add(allocated as u64, Ordering::Relaxed);
+        }
+        let heap = HEAP.fetch_add(allocated as isize - freed as isize, Ordering::Relaxed) + allocated as isize - freed as isize;
+        PEAK_HEAP.fetch_max(heap, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() && COUNTING.load(Ordering::Relaxed) {
+            self.count(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() && COUNTING.load(Ordering::Relaxed) {
+            self.count(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        if COUNTING.load(Ordering::Relaxed) {
+            self.count(0, layout.size());
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() && COUNTING.load(Ordering::Relaxed) {
+            self.count(new_size, layout.size());
+        }
+        new_ptr
+    }
+}
+
+/// Starts counting the allocations of a [`CountingAllocator`].
+pub fn start_counting() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+/// Allocations counted by a [`CountingAllocator`] since [`start_counting`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// Allocations and reallocations.
+    pub allocations: u64,
+    /// Bytes requested by them.
+    pub allocated: u64,
+    /// Most bytes held at once, over what was held when counting started.
+    pub peak_heap: u64,
+}
+
+/// Returns the allocations counted so far, or `None` if counting was never
+/// started (or the allocator is not installed, so nothing was counted).
+pub fn allocation_stats() -> Option<AllocationStats> {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    (COUNTING.load(Ordering::Relaxed) && allocations > 0).then(|| AllocationStats {
+        allocations,
+        allocated: ALLOCATED.load(Ordering::Relaxed),
+        peak_heap: PEAK_HEAP.load(Ordering::Relaxed).max(0) as u64,
+    })
+}
+
+/// Returns the peak resident set size of the process in bytes, where the
+/// platform reports it (Linux).
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+/// The resource usage of one run, as printed by `--self-stats`.
+#[derive(Debug, Clone, Default)]
+pub struct SelfStats {
+    pub wall_time: Duration,
+    /// Time per stage, in pipeline order. Whatever the wall time is not
+    /// accounted for by a stage is shown as "Other".
+    pub stages: Vec<(&'static str, Duration)>,
+    /// Queries parsed.
+    pub events: u64,
+    /// Bytes of input read.
+    pub bytes: u64,
+    pub peak_rss: Option<u64>,
+    pub allocations: Option<AllocationStats>,
+}
+
+impl SelfStats {
+    pub fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        let seconds = self.wall_time.as_secs_f64().max(f64::EPSILON);
+        let share = |time: Duration| time.as_secs_f64() / seconds * 100.0;
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+        writeln!(writer, "Self stats:")?;
+        writeln!(writer, "  {:<14}{:.3}s", "Wall time", self.wall_time.as_secs_f64())?;
+        let staged: Duration = self.stages.iter().map(|(_, time)| *time).sum();
+        let other = ("Other", self.wall_time.saturating_sub(staged));
+        for (stage, time) in self.stages.iter().chain(std::iter::once(&other)) {
+            writeln!(writer, "  {:<14}{:.3}s ({:.1}%)", stage, time.as_secs_f64(), share(*time))?;
+        }
+        writeln!(writer, "  {:<14}{} ({:.0}/s)", "Events", self.events, self.events as f64 / seconds)?;
+        writeln!(writer, "  {:<14}{:.1} MiB ({:.1} MiB/s)", "Input", mib(self.bytes), mib(self.bytes) / seconds)?;
+        match self.peak_rss {
+            Some(rss) => writeln!(writer, "  {:<14}{:.1} MiB", "Peak RSS", mib(rss))?,
+            None => writeln!(writer, "  {:<14}unavailable on this platform", "Peak RSS")?,
+        }
+        if let Some(allocations) = &self.allocations {
+            writeln!(
+                writer,
+                "  {:<14}{} ({:.1} MiB allocated, {:.1} MiB peak heap)",
+                "Allocations",
+                allocations.allocations,
+                mib(allocations.allocated),
+                mib(allocations.peak_heap)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let stats = SelfStats {
+            wall_time: Duration::from_secs(2),
+            stages: vec![("Read", Duration::from_millis(500)), ("Parse", Duration::from_millis(1000))],
+            events: 1000,
+            bytes: 4 * 1024 * 1024,
+            peak_rss: Some(64 * 1024 * 1024),
+            allocations: Some(AllocationStats { allocations: 5000, allocated: 10 * 1024 * 1024, peak_heap: 3 * 1024 * 1024 }),
+        };
+        let mut out = Vec::new();
+        stats.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Self stats:
+  Wall time     2.000s
+  Read          0.500s (25.0%)
+  Parse         1.000s (50.0%)
+  Other         0.500s (25.0%)
+  Events        1000 (500/s)
+  Input         4.0 MiB (2.0 MiB/s)
+  Peak RSS      64.0 MiB
+  Allocations   5000 (10.0 MiB allocated, 3.0 MiB peak heap)
+"
+        );
+        #[cfg(target_os = "linux")]
+        assert!(peak_rss().unwrap() > 0);
+    }
+}
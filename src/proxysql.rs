@@ -0,0 +1,223 @@
+use crate::aggregator::QueryStats;
+use crate::audit::build_query;
+use crate::digest::DigestConfig;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use mysql::prelude::Queryable;
+use mysql::{Conn, Opts, Row};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use std::sync::Arc;
+
+const DIGEST_QUERY: &str = "SELECT * FROM stats_mysql_query_digest";
+
+/// ProxySQL times count microseconds.
+const MICROSECONDS: f64 = 1e6;
+
+/// One row of ProxySQL's `stats_mysql_query_digest`, with times in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProxyDigestRow {
+    pub hostgroup: Option<u32>,
+    pub schema: Option<String>,
+    pub username: Option<String>,
+    pub client_address: Option<String>,
+    pub digest: String,
+    pub digest_text: String,
+    pub count: u64,
+    pub total_time: f64,
+    pub min_time: f64,
+    pub max_time: f64,
+    /// `sum_rows_sent`, which ProxySQL 1.x does not have.
+    pub rows_sent: u64,
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl ProxyDigestRow {
+    /// Builds a row from its columns, by lowercase name. `digest_text` and
+    /// `count_star` are required; other missing columns are left empty.
+    fn from_columns(columns: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let text = |name: &str| columns.get(name).map(String::as_str).filter(|value| !value.is_empty() && *value != "NULL");
+        let number = |name: &str| text(name).and_then(|value| value.parse::<u64>().ok());
+        let (Some(digest_text), Some(count)) = (text("digest_text"), number("count_star")) else {
+            bail!("Not a stats_mysql_query_digest row: digest_text and count_star are required");
+        };
+        Ok(Self {
+            hostgroup: number("hostgroup").map(|hostgroup| hostgroup as u32),
+            schema: text("schemaname").map(str::to_string),
+            username: text("username").map(str::to_string),
+            client_address: text("client_address").map(str::to_string),
+            digest: text("digest").unwrap_or_default().to_string(),
+            digest_text: digest_text.to_string(),
+            count,
+            total_time: number("sum_time").unwrap_or_default() as f64 / MICROSECONDS,
+            min_time: number("min_time").unwrap_or_default() as f64 / MICROSECONDS,
+            max_time: number("max_time").unwrap_or_default() as f64 / MICROSECONDS,
+            rows_sent: number("sum_rows_sent").unwrap_or_default(),
+            first_seen: number("first_seen").and_then(|seconds| DateTime::from_timestamp(seconds as i64, 0)),
+            last_seen: number("last_seen").and_then(|seconds| DateTime::from_timestamp(seconds as i64, 0)),
+        })
+    }
+}
+
+/// Reads an export of `stats_mysql_query_digest` with a header row: CSV, or
+/// the tab-separated output of `mysql -B`, told by the header.
+pub fn read_export<R: Read>(mut reader: R) -> anyhow::Result<Vec<ProxyDigestRow>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).context("Could not read the ProxySQL export")?;
+    let tabs = text.lines().next().is_some_and(|header| header.contains('\t'));
+    let mut builder = csv::ReaderBuilder::new();
+    if tabs {
+        // `mysql -B` escapes tabs, newlines and backslashes instead of quoting
+        builder.delimiter(b'\t').quoting(false);
+    }
+    let mut csv = builder.trim(csv::Trim::Headers).from_reader(text.as_bytes());
+    let headers: Vec<String> = csv.headers()?.iter().map(|name| name.to_ascii_lowercase()).collect();
+    let mut rows = Vec::new();
+    for (i, record) in csv.records().enumerate() {
+        let record = record.with_context(|| format!("Invalid row {} of the ProxySQL export", i + 2))?;
+        let columns = headers.iter().cloned()
+            .zip(record.iter().map(|value| if tabs { unescape(value) } else { value.to_string() }))
+            .collect();
+        rows.push(ProxyDigestRow::from_columns(&columns).with_context(|| format!("Row {} of the ProxySQL export", i + 2))?);
+    }
+    Ok(rows)
+}
+
+/// Undoes the escaping of `mysql -B` output.
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('0') => out.push('\0'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Reads `stats_mysql_query_digest` from the ProxySQL admin interface at `dsn`.
+pub fn fetch(dsn: &str) -> anyhow::Result<Vec<ProxyDigestRow>> {
+    let opts = Opts::from_url(dsn).context("Invalid ProxySQL admin DSN")?;
+    let mut conn = Conn::new(opts).context("Could not connect to the ProxySQL admin interface")?;
+    // The admin interface returns every column as text, and 1.x has fewer of them
+    let rows: Vec<Row> = conn.query(DIGEST_QUERY).context("Could not read stats_mysql_query_digest")?;
+    rows.into_iter()
+        .map(|row| {
+            let columns = row.columns_ref().iter()
+                .enumerate()
+                .filter_map(|(i, column)| Some((column.name_str().to_ascii_lowercase(), row.get_opt::<Option<String>, _>(i)?.ok().flatten()?)))
+                .collect();
+            ProxyDigestRow::from_columns(&columns)
+        })
+        .collect()
+}
+
+/// Reads the digests of `source`: a `mysql://` URL of the admin interface, or an export file.
+pub fn read(source: &str) -> anyhow::Result<Vec<ProxyDigestRow>> {
+    if source.starts_with("mysql://") {
+        return fetch(source);
+    }
+    let file = std::fs::File::open(source).with_context(|| format!("Could not open ProxySQL export {:?}", source))?;
+    read_export(file)
+}
+
+/// Converts digest rows into statistics, keyed the way `config` keys the
+/// queries of a log, so that they can be merged with a digested log.
+///
+/// ProxySQL keeps one row per hostgroup, user, schema and digest; these are
+/// summed. The rows hold totals only, so no percentiles, lock time or rows
+/// examined are known; the digest text stands in for the example queries.
+pub fn digest_stats(rows: Vec<ProxyDigestRow>, config: &DigestConfig) -> HashMap<String, QueryStats> {
+    let mut stats = HashMap::new();
+    for row in rows {
+        let user_host = row.username.as_deref().map(|user| format!("{}[{}] @ {} []", user, user, row.client_address.as_deref().unwrap_or_default()));
+        // Slow logs end statements with `;`, which fingerprints keep
+        let mut sql = row.digest_text;
+        if !sql.trim_end().ends_with(';') {
+            sql.push(';');
+        }
+        let Some(query) = build_query(sql, row.schema.as_deref(), user_host.clone().unwrap_or_default(), row.last_seen, None, &HashMap::new()) else { continue };
+        let example: Arc<str> = Arc::from(query.sql_text.as_str());
+        let entry = QueryStats {
+            count: row.count,
+            total_time: row.total_time,
+            min_time: row.min_time,
+            max_time: row.max_time,
+            total_rows_sent: row.rows_sent,
+            example_query: example.clone(),
+            worst_example_query: example,
+            first_seen: row.first_seen,
+            last_seen: row.last_seen,
+            users: user_host.map(|user_host| BTreeSet::from([Arc::from(user_host)])).unwrap_or_default(),
+            databases: row.schema.map(|schema| BTreeSet::from([Arc::from(schema)])).unwrap_or_default(),
+            ..Default::default()
+        };
+        crate::aggregator::merge_entry(&mut stats, config.key_for(&query), entry);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::Digest;
+
+    #[test]
+    fn test_read_export() {
+        let csv = r#"hostgroup,schemaname,username,client_address,digest,digest_text,count_star,first_seen,last_seen,sum_time,min_time,max_time,sum_rows_affected,sum_rows_sent
+1,shop,app,,0x3FB8C2A1,"SELECT * FROM orders WHERE id IN (?,?,?)",400,1714557600,1714561200,40000000,50000,500000,0,400
+2,shop,app,,0x3FB8C2A1,"SELECT * FROM orders WHERE id IN (?,?,?)",100,1714557000,1714560000,10000000,10000,200000,0,100
+1,shop,report,,0x1D2E3F40,"SELECT ""a,b"" FROM t",2,1714557600,1714557600,1800000,900000,900000,0,2
+"#;
+        let rows = read_export(csv.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!((rows[0].hostgroup, rows[0].count, rows[0].total_time, rows[0].max_time), (Some(1), 400, 40.0, 0.5));
+        assert_eq!(rows[0].client_address, None);
+        assert_eq!(rows[0].first_seen, Some("2024-05-01T10:00:00Z".parse().unwrap()));
+        assert_eq!(rows[2].digest_text, r#"SELECT "a,b" FROM t"#);
+
+        // `mysql -B -e 'SELECT * FROM stats_mysql_query_digest'` of ProxySQL 1.4
+        let tsv = "hostgroup\tschemaname\tusername\tdigest\tcount_star\tfirst_seen\tlast_seen\tsum_time\tmin_time\tmax_time\tdigest_text\n0\tshop\tapp\t0x1\t3\t1714557600\t1714557601\t3000\t1000\t1000\tSELECT '\\t\\\\'\n";
+        let rows = read_export(tsv.as_bytes()).unwrap();
+        assert_eq!((rows[0].digest_text.as_str(), rows[0].count, rows[0].rows_sent), ("SELECT '\t\\'", 3, 0));
+
+        assert!(read_export("a,b\n1,2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_digest_stats() {
+        let row = |hostgroup, user: &str, count, total_time| ProxyDigestRow {
+            hostgroup: Some(hostgroup),
+            schema: Some("shop".to_string()),
+            username: Some(user.to_string()),
+            digest_text: "SELECT * FROM orders WHERE id = ?".to_string(),
+            count,
+            total_time,
+            min_time: 0.01,
+            max_time: 0.5,
+            rows_sent: count,
+            ..Default::default()
+        };
+        let mut digest = Digest::new(DigestConfig::default());
+        digest.set_source("slow.log");
+        digest.add_reader("# User@Host: app[app] @ 10.0.0.5 []\n# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10\nuse shop;\nSELECT * FROM orders WHERE id = 7;\n".as_bytes());
+        digest.set_source("proxysql");
+        for (key, stats) in digest_stats(vec![row(1, "app", 400, 40.0), row(2, "batch", 100, 10.0)], digest.config()) {
+            digest.add_stats(key, stats);
+        }
+        let items = digest.finish();
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].count, items[0].total_time, items[0].databases.len()), (501, 52.0, 1));
+        assert_eq!(items[0].users.len(), 3);
+        assert_eq!(items[0].sources.len(), 2);
+    }
+}
@@ -0,0 +1,120 @@
+//! Python bindings, exposed as the `rs_slowquery_digest` extension module.
+//!
+//! Build with `maturin build --features python`. Results are returned as plain
+//! lists and dicts so they can be passed straight to `pandas.DataFrame`.
+
+use crate::digest::{Digest, DigestConfig, GroupBy};
+use crate::parser;
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::fs::File;
+use std::io::BufReader;
+
+/// Returns the normalized fingerprint of a SQL statement.
+#[pyfunction]
+fn fingerprint(sql: &str) -> String {
+    crate::fingerprint::fingerprint(sql)
+}
+
+/// Parses a slow log file into a list of query dicts.
+#[pyfunction]
+fn parse<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyAny>> {
+    let file = File::open(path).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))?;
+    let queries: Vec<parser::Query> = parser::parse_log(BufReader::new(file)).flatten().collect();
+    to_py(py, &queries)
+}
+
+/// Digests slow log files and returns the ranked report items as a list of dicts.
+///
+/// Supported options: `limit`, `percentiles`, `min_query_time`, `user`,
+/// `group_by` ("fingerprint" or "user"), `timezone` and `include_ddl`
+/// (default `True`, as only the items are returned).
+#[pyfunction]
+#[pyo3(signature = (paths, options = None))]
+fn digest<'py>(py: Python<'py>, paths: Vec<String>, options: Option<&Bound<'py, PyDict>>) -> PyResult<Bound<'py, PyAny>> {
+    let config = build_config(options)?;
+    let mut digest = Digest::new(config);
+    for path in &paths {
+        let file = File::open(path).map_err(|e| PyIOError::new_err(format!("{}: {}", path, e)))?;
+        digest.add_reader(BufReader::new(file));
+    }
+    to_py(py, &digest.finish())
+}
+
+/// Translates the Python options dict into a `DigestConfig`.
+fn build_config(options: Option<&Bound<'_, PyDict>>) -> PyResult<DigestConfig> {
+    let mut builder = DigestConfig::builder().include_ddl(true);
+    if let Some(options) = options {
+        if let Some(limit) = options.get_item("limit")? {
+            builder = builder.limit(limit.extract()?);
+        }
+        if let Some(percentiles) = options.get_item("percentiles")? {
+            builder = builder.percentiles(percentiles.extract()?);
+        }
+        if let Some(seconds) = options.get_item("min_query_time")? {
+            builder = builder.min_query_time(seconds.extract()?);
+        }
+        if let Some(user) = options.get_item("user")? {
+            builder = builder.user_pattern(user.extract::<String>()?);
+        }
+        if let Some(include) = options.get_item("include_ddl")? {
+            builder = builder.include_ddl(include.extract()?);
+        }
+        if let Some(timezone) = options.get_item("timezone")? {
+            builder = builder.timezone(timezone.extract::<String>()?);
+        }
+        if let Some(group_by) = options.get_item("group_by")? {
+            let group_by = match group_by.extract::<String>()?.as_str() {
+                "fingerprint" => GroupBy::Fingerprint,
+                "user" => GroupBy::User,
+                other => return Err(PyValueError::new_err(format!("Unknown group_by '{}'", other))),
+            };
+            builder = builder.group_by(group_by);
+        }
+    }
+    builder.build().map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Converts any serializable value into native Python objects via its JSON form.
+fn to_py<'py, T: serde::Serialize>(py: Python<'py>, value: &T) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_value(value).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    json_to_py(py, &json)
+}
+
+fn json_to_py<'py>(py: Python<'py>, value: &serde_json::Value) -> PyResult<Bound<'py, PyAny>> {
+    use serde_json::Value;
+
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any(),
+        Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => i.into_pyobject(py)?.into_any(),
+            (None, Some(u)) => u.into_pyobject(py)?.into_any(),
+            _ => n.as_f64().unwrap_or(f64::NAN).into_pyobject(py)?.into_any(),
+        },
+        Value::String(s) => s.into_pyobject(py)?.into_any(),
+        Value::Array(items) => {
+            let list = PyList::empty(py);
+            for item in items {
+                list.append(json_to_py(py, item)?)?;
+            }
+            list.into_any()
+        }
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, item) in map {
+                dict.set_item(key, json_to_py(py, item)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+#[pymodule]
+fn rs_slowquery_digest(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(fingerprint, m)?)?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(digest, m)?)?;
+    Ok(())
+}
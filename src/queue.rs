@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+/// Events queued between the threads reading followed inputs and the digest, by default.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// What a reader does with an event when the queue is full.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueFull {
+    /// Wait for room, so the reader falls behind the input but nothing is lost.
+    #[default]
+    Block,
+    /// Discard the event and count it.
+    Drop,
+}
+
+/// Counters of a queue, shared by its ends.
+#[derive(Debug, Default)]
+struct Counters {
+    queued: AtomicU64,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    peak_depth: AtomicU64,
+    warned: AtomicBool,
+}
+
+/// Counts of events through a queue since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueueMetrics {
+    pub capacity: usize,
+    /// Events accepted into the queue.
+    pub queued: u64,
+    /// Events discarded because the queue was full.
+    pub dropped: u64,
+    /// Events waiting in the queue.
+    pub depth: u64,
+    /// Most events seen waiting at once.
+    pub peak_depth: u64,
+}
+
+/// Reads the metrics of a queue while its ends are in use elsewhere.
+#[derive(Debug, Clone)]
+pub struct QueueMonitor {
+    capacity: usize,
+    counters: Arc<Counters>,
+}
+
+impl QueueMonitor {
+    pub fn metrics(&self) -> QueueMetrics {
+        let counters = &self.counters;
+        let received = counters.received.load(Ordering::Relaxed);
+        let queued = counters.queued.load(Ordering::Relaxed);
+        QueueMetrics {
+            capacity: self.capacity,
+            queued,
+            dropped: counters.dropped.load(Ordering::Relaxed),
+            depth: queued.saturating_sub(received),
+            peak_depth: counters.peak_depth.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The sending end of a bounded queue; clones send into the same queue.
+#[derive(Debug, Clone)]
+pub struct QueueSender<T> {
+    sender: SyncSender<T>,
+    policy: QueueFull,
+    counters: Arc<Counters>,
+}
+
+/// The receiving end of a bounded queue.
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    receiver: Receiver<T>,
+    counters: Arc<Counters>,
+}
+
+/// Creates a queue holding at most `capacity` events (at least one), and a
+/// monitor of its metrics.
+///
+/// Readers of followed inputs produce events as fast as the input grows; the
+/// bound keeps a burst that the digest cannot keep up with from using
+/// unbounded memory, either by slowing the readers down or by dropping events,
+/// depending on `policy`.
+pub fn bounded<T>(capacity: usize, policy: QueueFull) -> (QueueSender<T>, QueueReceiver<T>, QueueMonitor) {
+    let capacity = capacity.max(1);
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    let counters = Arc::new(Counters::default());
+    let monitor = QueueMonitor { capacity, counters: counters.clone() };
+    (QueueSender { sender, policy, counters: counters.clone() }, QueueReceiver { receiver, counters }, monitor)
+}
+
+impl<T> QueueSender<T> {
+    /// Queues an event, waiting for room or dropping it according to the
+    /// policy. Returns false once the receiver is gone.
+    pub fn send(&self, event: T) -> bool {
+        let result = match self.policy {
+            QueueFull::Block => self.sender.send(event).map_err(|_| false),
+            QueueFull::Drop => self.sender.try_send(event).map_err(|e| matches!(e, TrySendError::Full(_))),
+        };
+        match result {
+            Ok(()) => {
+                let queued = self.counters.queued.fetch_add(1, Ordering::Relaxed) + 1;
+                let depth = queued.saturating_sub(self.counters.received.load(Ordering::Relaxed));
+                self.counters.peak_depth.fetch_max(depth, Ordering::Relaxed);
+                true
+            }
+            Err(full) => {
+                if full {
+                    self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                    if !self.counters.warned.swap(true, Ordering::Relaxed) {
+                        tracing::warn!("Event queue is full, dropping events until the digest catches up");
+                    }
+                }
+                full
+            }
+        }
+    }
+}
+
+impl<T> QueueReceiver<T> {
+    /// Waits for an event, then moves it and up to `max - 1` more that are
+    /// already queued into `batch`. Returns false once every sender is gone
+    /// and the queue is empty.
+    pub fn recv_batch(&self, batch: &mut Vec<T>, max: usize) -> bool {
+        let Ok(event) = self.receiver.recv() else { return false };
+        let len = batch.len();
+        batch.push(event);
+        batch.extend(self.receiver.try_iter().take(max.saturating_sub(1)));
+        self.counters.received.fetch_add((batch.len() - len) as u64, Ordering::Relaxed);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let (sender, receiver, monitor) = bounded(2, QueueFull::Drop);
+        assert!((0..5).all(|i| sender.send(i)));
+        assert_eq!(monitor.metrics(), QueueMetrics { capacity: 2, queued: 2, dropped: 3, depth: 2, peak_depth: 2 });
+        let mut batch = Vec::new();
+        assert!(receiver.recv_batch(&mut batch, 10));
+        assert_eq!(batch, vec![0, 1]);
+        assert_eq!(monitor.metrics().depth, 0);
+
+        let (sender, receiver, monitor) = bounded(1, QueueFull::Block);
+        let producer = std::thread::spawn(move || (0..100).all(|i| sender.send(i)));
+        let mut received = Vec::new();
+        while receiver.recv_batch(&mut received, 10) {}
+        assert!(producer.join().unwrap());
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        let metrics = monitor.metrics();
+        assert_eq!((metrics.queued, metrics.dropped, metrics.depth), (100, 0, 0));
+    }
+}
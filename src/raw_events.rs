@@ -0,0 +1,200 @@
+use crate::parser::Query;
+use crate::redact;
+use crate::report::{query_id, ReportItem};
+use crate::sink::QuerySink;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Events inserted per transaction.
+const BATCH: usize = 10_000;
+
+/// Tables of the event database, created if missing.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+  id     INTEGER PRIMARY KEY,
+  run_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fingerprints (
+  run_id      INTEGER NOT NULL REFERENCES runs (id),
+  query_id    TEXT NOT NULL,
+  rank        INTEGER NOT NULL,
+  count       INTEGER NOT NULL,
+  total_time  REAL NOT NULL,
+  mean_time   REAL NOT NULL,
+  p95         REAL NOT NULL,
+  fingerprint TEXT NOT NULL,
+  PRIMARY KEY (run_id, query_id)
+);
+CREATE TABLE IF NOT EXISTS events (
+  run_id        INTEGER NOT NULL REFERENCES runs (id),
+  time          TEXT,
+  query_id      TEXT NOT NULL,
+  user          TEXT NOT NULL,
+  user_host     TEXT NOT NULL,
+  db            TEXT,
+  thread_id     INTEGER,
+  query_time    REAL NOT NULL,
+  lock_time     REAL NOT NULL,
+  rows_sent     INTEGER NOT NULL,
+  rows_examined INTEGER NOT NULL,
+  sql           TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_query_id ON events (query_id, time);
+";
+
+const INSERT_EVENT: &str = "INSERT INTO events (run_id, time, query_id, user, user_host, db, thread_id, query_time, lock_time, rows_sent, rows_examined, sql)
+VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+const INSERT_FINGERPRINT: &str = "INSERT INTO fingerprints (run_id, query_id, rank, count, total_time, mean_time, p95, fingerprint)
+VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// Stores every event the digest aggregates in a SQLite database, together
+/// with the reported fingerprints, so that a report row can be drilled down
+/// to its executions with plain SQL (`WHERE query_id = ...`).
+///
+/// Each run adds a row to `runs`, and its events and fingerprints refer to it.
+/// Events are inserted as they arrive and committed every 10,000; the
+/// fingerprints are only known once the report is built, and are written by
+/// [`finish`](RawEventStore::finish), which also commits the rest.
+#[derive(Clone)]
+pub struct RawEventStore(Arc<Mutex<State>>);
+
+struct State {
+    conn: Connection,
+    run_id: i64,
+    /// Events inserted in the open transaction.
+    pending: usize,
+    events: u64,
+    redact: bool,
+    redact_users: bool,
+    /// The first insert that failed; nothing is written after it.
+    error: Option<rusqlite::Error>,
+}
+
+impl RawEventStore {
+    /// Opens (or creates) the database at `path` and starts a run.
+    ///
+    /// With `redact`, SQL literals are replaced with `?`; with `redact_users`,
+    /// users and hosts are replaced with pseudonyms, as in the report.
+    pub fn open(path: &Path, run_at: DateTime<Utc>, redact: bool, redact_users: bool) -> anyhow::Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("Could not open event database {:?}", path))?;
+        conn.execute_batch(SCHEMA).context("Could not create the event tables")?;
+        conn.execute("INSERT INTO runs (run_at) VALUES (?)", [datetime(run_at)])?;
+        let run_id = conn.last_insert_rowid();
+        conn.execute_batch("BEGIN")?;
+        Ok(Self(Arc::new(Mutex::new(State { conn, run_id, pending: 0, events: 0, redact, redact_users, error: None }))))
+    }
+
+    /// Commits the events and stores the reported items. Returns the number of
+    /// events stored, or the error that stopped storing them.
+    pub fn finish(&self, items: &[ReportItem]) -> anyhow::Result<u64> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            let _ = state.conn.execute_batch("ROLLBACK");
+            return Err(e).context("Could not store the events");
+        }
+        let conn = &state.conn;
+        {
+            let mut insert = conn.prepare_cached(INSERT_FINGERPRINT)?;
+            for item in items {
+                insert.execute(params![state.run_id, item.query_id, item.rank as i64, item.count as i64, item.total_time, item.mean_time, item.p95, item.normalized_query])?;
+            }
+        }
+        conn.execute_batch("COMMIT").context("Could not store the events")?;
+        Ok(state.events)
+    }
+}
+
+impl State {
+    fn insert(&mut self, key: &str, query: &Query) -> rusqlite::Result<()> {
+        if self.pending >= BATCH {
+            self.conn.execute_batch("COMMIT; BEGIN")?;
+            self.pending = 0;
+        }
+        let sql = if self.redact { redact::redact_sql(&query.sql_text) } else { query.sql_text.clone() };
+        let (user, user_host) = match self.redact_users {
+            true => (redact::mask_user_host(query.user()), redact::mask_user_host(&query.user_host)),
+            false => (query.user().to_string(), query.user_host.clone()),
+        };
+        self.conn.prepare_cached(INSERT_EVENT)?.execute(params![
+            self.run_id,
+            query.timestamp.map(datetime),
+            query_id(key),
+            user,
+            user_host,
+            query.database(),
+            query.thread_id.map(|id| id as i64),
+            query.query_time,
+            query.lock_time,
+            query.rows_sent as i64,
+            query.rows_examined as i64,
+            sql,
+        ])?;
+        self.pending += 1;
+        self.events += 1;
+        Ok(())
+    }
+}
+
+impl QuerySink for RawEventStore {
+    fn on_query(&mut self, key: &str, query: &Query) {
+        let mut state = self.0.lock().unwrap();
+        if state.error.is_some() {
+            return;
+        }
+        if let Err(e) = state.insert(key, query) {
+            tracing::warn!("Could not store an event, no longer storing them: {}", e);
+            state.error = Some(e);
+        }
+    }
+}
+
+/// Formats a time the way SQLite's date and time functions read it.
+fn datetime(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S%.6f").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::{Digest, DigestConfig};
+
+    #[test]
+    fn test_store_events() {
+        let path = std::env::temp_dir().join(format!("sqd-raw-events-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = "# Time: 2024-05-01T10:00:00Z
+# User@Host: app[app] @ localhost []  Id: 7
+# Query_time: 2.0  Lock_time: 0.1 Rows_sent: 1  Rows_examined: 10
+use shop;
+SELECT * FROM orders WHERE id = 1;
+# Time: 2024-05-01T10:00:01Z
+# User@Host: app[app] @ localhost []  Id: 7
+# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10
+SELECT * FROM orders WHERE id = 'secret';
+";
+        for _ in 0..2 {
+            let store = RawEventStore::open(&path, Utc::now(), true, false).unwrap();
+            let mut digest = Digest::new(DigestConfig::default()).with_sink(Box::new(store.clone()));
+            digest.add_reader(log.as_bytes());
+            let items = digest.finish();
+            assert_eq!(store.finish(&items).unwrap(), 2);
+        }
+
+        let conn = Connection::open(&path).unwrap();
+        let (runs, events): (i64, i64) = conn.query_row("SELECT COUNT(DISTINCT run_id), COUNT(*) FROM events", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!((runs, events), (2, 4));
+        let (time, user, db, thread_id, sql): (String, String, Option<String>, i64, String) = conn
+            .query_row("SELECT e.time, e.user, e.db, e.thread_id, e.sql FROM events e JOIN fingerprints f USING (run_id, query_id) WHERE f.rank = 1 ORDER BY e.time DESC LIMIT 1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            })
+            .unwrap();
+        assert_eq!((time.as_str(), user.as_str(), db, thread_id), ("2024-05-01 10:00:01.000000", "app", Some("shop".to_string()), 7));
+        assert_eq!(sql, "SELECT * FROM orders WHERE id = ?;");
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+use crate::fingerprint::{comment_regex, number_regex, string_regex};
+use crate::report::{RenderOptions, ReportItem};
+use regex::Regex;
+use std::sync::OnceLock;
+
+static RE_DOUBLE_QUOTED: OnceLock<Regex> = OnceLock::new();
+static RE_HEX: OnceLock<Regex> = OnceLock::new();
+
+/// Replaces every literal in a statement with `?` and drops comments, keeping
+/// identifiers, keywords and layout.
+///
+/// Uses the fingerprint's literal detection, plus double-quoted strings and hex
+/// literals, which fingerprints leave alone.
+pub fn redact_sql(sql: &str) -> String {
+    let re_double_quoted = RE_DOUBLE_QUOTED.get_or_init(|| Regex::new(r#""(?:[^"\\]|\\.|"")*""#).unwrap());
+    let re_hex = RE_HEX.get_or_init(|| Regex::new(r"(?i)\b0x[0-9a-f]+\b").unwrap());
+
+    let sql = comment_regex().replace_all(sql, "");
+    let sql = string_regex().replace_all(&sql, "?");
+    let sql = re_double_quoted.replace_all(&sql, "?");
+    let sql = re_hex.replace_all(&sql, "?");
+    number_regex().replace_all(&sql, "?").into_owned()
+}
+
+/// Replaces quoted strings only, for text such as query plans where numbers are
+/// row estimates rather than data.
+pub fn redact_strings(text: &str) -> String {
+    string_regex().replace_all(text, "?").into_owned()
+}
+
+/// Replaces the user and host of a `User@Host` value with stable pseudonyms, so
+/// different accounts stay distinguishable without being named.
+pub fn mask_user_host(user_host: &str) -> String {
+    match user_host.split_once(" @ ") {
+        Some((user, host)) => format!("{} @ {}", pseudonym("user", user.trim()), pseudonym("host", host.trim())),
+        None if user_host.is_empty() => String::new(),
+        None => pseudonym("user", user_host),
+    }
+}
+
+/// Returns a stable pseudonym such as `user-1a2b3c4d` for a value.
+pub(crate) fn pseudonym(prefix: &str, value: &str) -> String {
+    format!("{}-{}", prefix, &format!("{:x}", md5::compute(value))[..8])
+}
+
+/// Redacts the example queries (and strings in query plans) of report items.
+pub fn redact_items(items: &mut [ReportItem]) {
+    for item in items {
+        item.example_query = redact_sql(&item.example_query);
+        item.worst_example_query = redact_sql(&item.worst_example_query);
+        for plan in [&mut item.explain, &mut item.explain_analyze].into_iter().flatten() {
+            *plan = redact_strings(plan);
+        }
+    }
+}
+
+/// Masks the `User@Host` values of report items, including the grouping key
+/// when the items are grouped by user.
+pub fn mask_item_users(items: &mut [ReportItem], grouped_by_user: bool) {
+    for item in items {
+        for user in &mut item.users {
+            *user = mask_user_host(user);
+        }
+        if grouped_by_user {
+            item.normalized_query = mask_user_host(&item.normalized_query);
+        }
+    }
+}
+
+/// Redacts the statements of the Schema Changes section.
+pub fn redact_schema_changes(options: &mut RenderOptions) {
+    for change in &mut options.schema_changes {
+        change.statement = redact_sql(&change.statement);
+    }
+}
+
+/// Masks the `User@Host` values the report sections outside the items show.
+pub fn mask_section_users(options: &mut RenderOptions) {
+    for change in &mut options.schema_changes {
+        change.user_host = mask_user_host(&change.user_host);
+    }
+    for connections in &mut options.connections {
+        connections.user_host = mask_user_host(&connections.user_host);
+    }
+    for sample in options.samples.values_mut().flatten() {
+        sample.user_host = mask_user_host(&sample.user_host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_sql() {
+        let sql = "SELECT id FROM users /* token=abc */ WHERE email = 'bob@example.com'\n  AND note = \"it's\" AND flags = 0xFF AND t1.age > 42";
+        assert_eq!(redact_sql(sql), "SELECT id FROM users  WHERE email = ?\n  AND note = ? AND flags = ? AND t1.age > ?");
+    }
+
+    #[test]
+    fn test_mask_user_host() {
+        let masked = mask_user_host("app[app] @ web1 [10.0.0.1]");
+        assert!(masked.starts_with("user-") && masked.contains(" @ host-"), "{}", masked);
+        assert!(!masked.contains("app") && !masked.contains("10.0.0.1"));
+        assert_eq!(masked, mask_user_host("app[app] @ web1 [10.0.0.1]"));
+        assert_ne!(masked, mask_user_host("app[app] @ web2 [10.0.0.2]"));
+    }
+
+    #[test]
+    fn test_redact_schema_changes() {
+        let mut options = RenderOptions::default();
+        options.schema_changes.push(crate::report::SchemaChange {
+            timestamp: None,
+            query_time: 1.0,
+            user_host: "admin[admin] @ bastion [10.0.0.9]".to_string(),
+            statement: "ALTER TABLE t COMMENT 'token=s3cr3t'".to_string(),
+        });
+        redact_schema_changes(&mut options);
+        mask_section_users(&mut options);
+        let change = &options.schema_changes[0];
+        assert_eq!(change.statement, "ALTER TABLE t COMMENT ?");
+        assert_eq!(change.user_host, mask_user_host("admin[admin] @ bastion [10.0.0.9]"));
+    }
+
+    #[test]
+    fn test_mask_section_users() {
+        let mut options = RenderOptions::default();
+        options.connections.push(crate::connections::ConnectionStats { user_host: "app[app] @ web1 [10.0.0.1]".to_string(), ..Default::default() });
+        let sample = crate::report::ExecutionSample { timestamp: None, query_time: 1.0, lock_time: 0.0, rows_sent: 0, rows_examined: 0, user_host: "app[app] @ web1 [10.0.0.1]".to_string() };
+        options.samples.insert("q1".to_string(), vec![sample]);
+        mask_section_users(&mut options);
+        assert_eq!(options.connections[0].user_host, mask_user_host("app[app] @ web1 [10.0.0.1]"));
+        assert_eq!(options.samples["q1"][0].user_host, mask_user_host("app[app] @ web1 [10.0.0.1]"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::parser::Query;
+use crate::report::ReportItem;
+use crate::sink::QuerySink;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Which logged statements of each fingerprint go into a replay file.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayExamples {
+    /// The slowest occurrence only.
+    #[default]
+    Worst,
+    /// Every logged occurrence, in log order.
+    All,
+}
+
+/// A sink that keeps the SQL text of every query, grouped by key, for
+/// `--replay-examples all`.
+///
+/// Every occurrence is kept, not just the slowest one the statistics hold, so
+/// memory grows with the log. The statement lists live behind a shared handle:
+/// register a clone with the [`Digest`](crate::digest::Digest) and call
+/// [`take`](Self::take) on the original once the logs are read.
+#[derive(Clone, Default)]
+pub struct ExampleCollector(Arc<Mutex<HashMap<String, Vec<String>>>>);
+
+impl ExampleCollector {
+    /// Takes the collected statements out of the collector.
+    pub fn take(&self) -> HashMap<String, Vec<String>> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl QuerySink for ExampleCollector {
+    fn on_query(&mut self, key: &str, query: &Query) {
+        self.0.lock().unwrap().entry(key.to_string()).or_default().push(query.sql_text.clone());
+    }
+}
+
+/// Writes the example statements of `items` as a runnable SQL script.
+///
+/// Each fingerprint is introduced by comments carrying its query ID and
+/// statistics. With `examples`, every collected statement of the fingerprint
+/// is written; otherwise its worst example.
+pub fn write_replay_sql(items: &[ReportItem], examples: Option<&HashMap<String, Vec<String>>>, writer: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(writer, "-- Generated by rs-slowquery-digest")?;
+    for item in items {
+        writeln!(writer)?;
+        writeln!(writer, "-- Rank {}: query ID {}", item.rank, item.query_id)?;
+        writeln!(
+            writer,
+            "-- Count: {}, Total: {:.3}s, Mean: {:.3}s, P95: {:.3}s, Rows sent/examined: {}/{}",
+            item.count, item.total_time, item.mean_time, item.p95, item.rows_sent, item.rows_examined
+        )?;
+        for line in item.normalized_query.lines() {
+            writeln!(writer, "-- Fingerprint: {}", line)?;
+        }
+
+        let statements = match examples.and_then(|e| e.get(&item.normalized_query)) {
+            Some(all) => all.iter().map(String::as_str).collect(),
+            None => vec![item.worst_example_query.as_str()],
+        };
+        for sql in statements {
+            let sql = sql.trim();
+            if sql.is_empty() {
+                continue;
+            }
+            if sql.ends_with(';') {
+                writeln!(writer, "{}", sql)?;
+            } else {
+                writeln!(writer, "{};", sql)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::{Digest, DigestConfig};
+
+    const LOG: &str = "# User@Host: a[a] @ h []
+# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 1
+# User@Host: a[a] @ h []
+# Query_time: 3.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM t WHERE id = 2
+";
+
+    #[test]
+    fn test_write_replay_sql() {
+        let collector = ExampleCollector::default();
+        let mut digest = Digest::new(DigestConfig::default()).with_sink(Box::new(collector.clone()));
+        digest.add_reader(LOG.as_bytes());
+        let items = digest.finish();
+
+        let mut out = Vec::new();
+        write_replay_sql(&items, None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("-- Rank 1: query ID {}\n", items[0].query_id)));
+        assert!(out.contains("-- Count: 2, Total: 4.000s, Mean: 2.000s"));
+        assert!(out.ends_with("-- Fingerprint: select * from t where id = ?\nSELECT * FROM t WHERE id = 2;\n"));
+
+        let examples = collector.take();
+        let mut out = Vec::new();
+        write_replay_sql(&items, Some(&examples), &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().ends_with("SELECT * FROM t WHERE id = 1;\nSELECT * FROM t WHERE id = 2;\n"));
+    }
+}
@@ -1,21 +1,327 @@
-use crate::aggregator::QueryStats;
-use crate::OutputFormat;
-use std::collections::HashMap;
+use crate::aggregator::{FlagStats, MetricStats, QueryStats, SourceStats};
+use crate::annotation::Annotation;
+#[cfg(feature = "render")]
+use crate::classify;
+use crate::digest::DigestConfig;
+use crate::connections::ConnectionStats;
+use crate::lock_cluster::LockCluster;
+#[cfg(feature = "render")]
+use crate::digest::ReadStats;
+#[cfg(feature = "render")]
+#[cfg(not(target_arch = "wasm32"))]
+use crate::pager::Pager;
+use crate::timezone::Timezone;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "render")]
 use std::path::PathBuf;
-use std::io::Write;
+#[cfg(feature = "render")]
+use std::io::{IsTerminal, Write};
+#[cfg(feature = "render")]
 use tabled::{Table, Tabled};
 
+/// Supported output formats for the report.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Table,
+    Html,
+    Pdf,
+    Json,
+}
+
+/// The key the report is ranked by.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortBy {
+    /// Total execution time.
+    #[default]
+    TotalTime,
+    /// Impact score, see [`ScoreWeights`].
+    Score,
+    /// Number of executions, for logs without execution times such as the general log.
+    Count,
+    /// Total rows changed, from the `Rows_affected` header, to find write-heavy queries.
+    RowsAffected,
+    /// Total bytes sent to clients, from the `Bytes_sent` header, to find network-heavy queries.
+    BytesSent,
+}
+
+impl SortBy {
+    /// Returns the value of `stats` this key ranks by, for the keys that are not the score.
+    pub fn value(self, stats: &QueryStats) -> f64 {
+        match self {
+            SortBy::TotalTime | SortBy::Score => stats.total_time,
+            SortBy::Count => stats.count as f64,
+            SortBy::RowsAffected => stats.rows_affected().map_or(0.0, |rows| rows.total),
+            SortBy::BytesSent => stats.bytes_sent().map_or(0.0, |bytes| bytes.total),
+        }
+    }
+}
+
+/// Metrics shown with the row counts instead of with the other extended metrics, with their labels.
+pub const ROW_METRICS: &[(&str, &str)] = &[("Rows_affected", "Affected"), ("Bytes_sent", "Bytes Sent")];
+
+/// Unit durations are displayed in.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    /// Seconds.
+    #[default]
+    S,
+    /// Milliseconds.
+    Ms,
+    /// Microseconds.
+    Us,
+    /// Seconds, milliseconds or microseconds, whichever suits each value.
+    Auto,
+}
+
+impl TimeUnit {
+    /// Formats a duration given in seconds.
+    pub fn format(self, seconds: f64) -> String {
+        let unit = match self {
+            TimeUnit::Auto if seconds >= 1.0 => TimeUnit::S,
+            TimeUnit::Auto if seconds >= 0.001 => TimeUnit::Ms,
+            TimeUnit::Auto => TimeUnit::Us,
+            unit => unit,
+        };
+        match unit {
+            TimeUnit::Ms => format!("{:.3}ms", seconds * 1e3),
+            TimeUnit::Us => format!("{:.0}us", seconds * 1e6),
+            _ => format!("{:.3}s", seconds),
+        }
+    }
+}
+
+/// Weights of the metrics combined into a query's impact score.
+///
+/// Every metric is divided by its largest value across all queries, so the
+/// score is `100 * sum(weight * metric / max) / sum(weight)`, from 0 to 100.
+/// Parsed from strings like `"time=0.5,count=0.2,rows=0.2,lock=0.1"`, where
+/// metrics left out get weight 0.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ScoreWeights {
+    pub total_time: f64,
+    pub count: f64,
+    pub rows_examined: f64,
+    pub lock_time: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self { total_time: 0.5, count: 0.2, rows_examined: 0.2, lock_time: 0.1 }
+    }
+}
+
+impl ScoreWeights {
+    const NAMES: [&'static str; 4] = ["time", "count", "rows", "lock"];
+
+    fn weights(&self) -> [f64; 4] {
+        [self.total_time, self.count, self.rows_examined, self.lock_time]
+    }
+
+    fn metrics(stats: &QueryStats) -> [f64; 4] {
+        [stats.total_time, stats.count as f64, stats.total_rows_examined as f64, stats.total_lock_time]
+    }
+
+    /// Returns the score of `stats`, given the largest value of every metric.
+    fn score(&self, stats: &QueryStats, maxima: &[f64; 4]) -> f64 {
+        let weights = self.weights();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let weighted: f64 = Self::metrics(stats).iter().zip(maxima).zip(weights)
+            .map(|((value, max), weight)| if *max > 0.0 { weight * value / max } else { 0.0 })
+            .sum();
+        100.0 * weighted / total
+    }
+
+    /// Describes how the score is computed, for the report.
+    pub fn formula(&self) -> String {
+        let terms: Vec<String> = Self::NAMES.iter().zip(self.weights())
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(name, weight)| format!("{}*{}", weight, name))
+            .collect();
+        let total = (self.weights().iter().sum::<f64>() * 1e6).round() / 1e6;
+        format!("Score = 100 * ({}) / {}, each of total time, count, rows examined and lock time divided by its maximum over all queries", terms.join(" + "), total)
+    }
+}
+
+impl std::str::FromStr for ScoreWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = [0.0; 4];
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').ok_or_else(|| format!("Invalid score weight {:?}: expected <metric>=<weight>", pair))?;
+            let index = Self::NAMES.iter().position(|n| *n == name.trim())
+                .ok_or_else(|| format!("Unknown score metric {:?}: expected one of {}", name.trim(), Self::NAMES.join(", ")))?;
+            let weight: f64 = value.trim().parse().map_err(|_| format!("Invalid weight {:?} for {}", value.trim(), name.trim()))?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("Invalid weight {} for {}: must be non-negative", weight, name.trim()));
+            }
+            weights[index] = weight;
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err("At least one score weight must be positive".to_string());
+        }
+        let [total_time, count, rows_examined, lock_time] = weights;
+        Ok(Self { total_time, count, rows_examined, lock_time })
+    }
+}
+
+impl TryFrom<String> for ScoreWeights {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A condition that marks a report row as likely pathological.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Badge {
+    /// Many rows examined per row sent.
+    HighRatio,
+    /// Many rows examined without sending any.
+    NoRowsSent,
+    /// Lock time makes up a large share of the query time.
+    LockHeavy,
+    /// Waiting for InnoDB page reads makes up a large share of the query time.
+    IoBound,
+    /// Waiting for InnoDB row locks makes up a large share of the query time.
+    RowLockWait,
+    /// Many executions ended with an error.
+    Failing,
+}
+
+impl Badge {
+    /// Returns the short label shown in reports.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Badge::HighRatio => "high-ratio",
+            Badge::NoRowsSent => "no-rows-sent",
+            Badge::LockHeavy => "lock-heavy",
+            Badge::IoBound => "io-bound",
+            Badge::RowLockWait => "row-lock-wait",
+            Badge::Failing => "failing",
+        }
+    }
+}
+
+/// Thresholds at which report rows get [`Badge`]s. A threshold of 0 disables its badge.
+///
+/// Parsed from strings like `"ratio=100,examined=1000,lock=0.5,io=0.5,rowlock=0.5,errors=0.05"`, where
+/// thresholds left out keep their defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct BadgeThresholds {
+    /// Rows examined per row sent above which [`Badge::HighRatio`] is set.
+    pub ratio: f64,
+    /// Rows examined at or above which [`Badge::NoRowsSent`] is set when no rows were sent.
+    pub examined_without_rows: u64,
+    /// Share of the query time spent waiting for locks above which [`Badge::LockHeavy`] is set.
+    pub lock_share: f64,
+    /// Share of the query time spent in `InnoDB_IO_r_wait` above which [`Badge::IoBound`] is set.
+    pub io_share: f64,
+    /// Share of the query time spent in `InnoDB_rec_lock_wait` above which [`Badge::RowLockWait`] is set.
+    pub row_lock_share: f64,
+    /// Share of the executions ending with an error above which [`Badge::Failing`] is set.
+    pub error_share: f64,
+}
+
+impl Default for BadgeThresholds {
+    fn default() -> Self {
+        Self { ratio: 100.0, examined_without_rows: 1000, lock_share: 0.5, io_share: 0.5, row_lock_share: 0.5, error_share: 0.05 }
+    }
+}
+
+impl BadgeThresholds {
+    /// Returns the badges earned by the statistics.
+    fn badges(&self, stats: &QueryStats) -> Vec<Badge> {
+        let mut badges = Vec::new();
+        if self.ratio > 0.0 && stats.total_rows_sent > 0 && stats.total_rows_examined as f64 / stats.total_rows_sent as f64 > self.ratio {
+            badges.push(Badge::HighRatio);
+        }
+        if self.examined_without_rows > 0 && stats.total_rows_sent == 0 && stats.total_rows_examined >= self.examined_without_rows {
+            badges.push(Badge::NoRowsSent);
+        }
+        if self.lock_share > 0.0 && stats.total_time > 0.0 && stats.total_lock_time / stats.total_time > self.lock_share {
+            badges.push(Badge::LockHeavy);
+        }
+        // Percona Server's InnoDB statistics, for the executions that logged them
+        let wait_share = |name: &str| match stats.metrics.get(name) {
+            Some(wait) if stats.total_time > 0.0 => wait.total / stats.total_time,
+            _ => 0.0,
+        };
+        if self.io_share > 0.0 && wait_share("InnoDB_IO_r_wait") > self.io_share {
+            badges.push(Badge::IoBound);
+        }
+        if self.row_lock_share > 0.0 && wait_share("InnoDB_rec_lock_wait") > self.row_lock_share {
+            badges.push(Badge::RowLockWait);
+        }
+        if self.error_share > 0.0 && stats.count > 0 && stats.errors as f64 / stats.count as f64 > self.error_share {
+            badges.push(Badge::Failing);
+        }
+        badges
+    }
+}
+
+impl std::str::FromStr for BadgeThresholds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut thresholds = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').ok_or_else(|| format!("Invalid badge threshold {:?}: expected <name>=<value>", pair))?;
+            let (name, value) = (name.trim(), value.trim());
+            let invalid = || format!("Invalid value {:?} for badge threshold {}", value, name);
+            match name {
+                "ratio" => thresholds.ratio = value.parse().ok().filter(|v: &f64| *v >= 0.0).ok_or_else(invalid)?,
+                "examined" => thresholds.examined_without_rows = value.parse().map_err(|_| invalid())?,
+                "lock" => thresholds.lock_share = value.parse().ok().filter(|v: &f64| (0.0..=1.0).contains(v)).ok_or_else(invalid)?,
+                "io" => thresholds.io_share = value.parse().ok().filter(|v: &f64| (0.0..=1.0).contains(v)).ok_or_else(invalid)?,
+                "rowlock" => thresholds.row_lock_share = value.parse().ok().filter(|v: &f64| (0.0..=1.0).contains(v)).ok_or_else(invalid)?,
+                "errors" => thresholds.error_share = value.parse().ok().filter(|v: &f64| (0.0..=1.0).contains(v)).ok_or_else(invalid)?,
+                _ => return Err(format!("Unknown badge threshold {:?}: expected one of ratio, examined, lock, io, rowlock, errors", name)),
+            }
+        }
+        Ok(thresholds)
+    }
+}
+
+impl TryFrom<String> for BadgeThresholds {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[cfg(feature = "render")]
 /// Represents a row in the summary table.
 #[derive(Tabled)]
 struct Row {
     #[tabled(rename = "Rank")]
-    rank: usize,
+    rank: String,
     #[tabled(rename = "Count")]
     count: u64,
     #[tabled(rename = "Total Time")]
     total_time: String,
     #[tabled(rename = "Mean Time")]
     mean_time: String,
+    #[tabled(rename = "Score")]
+    score: String,
+    #[tabled(rename = "Flags")]
+    flags: String,
     #[tabled(rename = "Query ID")]
     query_id: String,
     #[tabled(rename = "Query")]
@@ -23,83 +329,568 @@ struct Row {
 }
 
 /// Represents a single item in the report, corresponding to a unique query fingerprint.
-#[derive(Debug)]
-struct ReportItem {
-    rank: usize,
-    query_id: String,
-    count: u64,
-    total_time: f64,
-    mean_time: f64,
-    p95: f64,
-    p99: f64,
-    total_lock_time: f64,
-    mean_lock_time: f64,
-    rows_sent: u64,
-    rows_examined: u64,
-    ratio: f64,
-    time_range: String,
-    example_query: String,
-    worst_example_query: String,
-    normalized_query: String,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportItem {
+    pub rank: usize,
+    pub query_id: String,
+    pub count: u64,
+    pub total_time: f64,
+    pub mean_time: f64,
+    pub p95: f64,
+    pub p99: f64,
+    /// Configured percentiles as `(fraction, value)` pairs.
+    pub percentiles: Vec<(f64, f64)>,
+    pub total_lock_time: f64,
+    pub mean_lock_time: f64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    pub ratio: f64,
+    /// Impact score from 0 to 100, see [`ScoreWeights`].
+    #[serde(default)]
+    pub score: f64,
+    /// Conditions that mark the query as likely pathological.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub badges: Vec<Badge>,
+    pub time_range: String,
+    pub example_query: String,
+    pub worst_example_query: String,
+    pub normalized_query: String,
+    /// Distinct `User@Host` values that ran the query.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Distinct databases the query ran in.
+    #[serde(default)]
+    pub databases: Vec<String>,
+    /// Count and total time per input, when several inputs were digested.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, SourceStats>,
+    /// Execution plan of the worst example, if captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain: Option<String>,
+    /// `EXPLAIN ANALYZE` output of the worst example, if captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explain_analyze: Option<String>,
+    #[serde(default)]
+    pub first_seen: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Sign-off recorded in the review table, if the query has been reviewed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<Review>,
+    /// Owner, ticket and status from a triage file, if the query is listed in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triage: Option<Triage>,
+    /// Totals performance_schema recorded for the same statements, if correlated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<ServerStats>,
+    /// Query cache hits, if the log reported them (MariaDB's `QC_hit`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_cache: Option<QueryCacheHits>,
+    /// Failed and killed executions, if any were logged (`Errno` and `Killed`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errors: Option<ErrorStats>,
+    /// Totals of further numeric headers the log reported, such as Percona's `Bytes_sent`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, MetricStats>,
+    /// Executions with each query plan header set, such as Percona's `Full_scan`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plan_flags: BTreeMap<String, FlagStats>,
+}
+
+impl ReportItem {
+    /// Returns the statement shown in summaries: the example, or the normalized
+    /// query if no example was stored (see `ExampleThresholds`).
+    pub fn display_query(&self) -> &str {
+        if self.example_query.is_empty() { &self.normalized_query } else { &self.example_query }
+    }
+}
+
+/// A DDL statement, listed in its own section instead of being ranked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaChange {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub query_time: f64,
+    pub user_host: String,
+    pub statement: String,
+}
+
+/// How much of the logged workload alternative `long_query_time` settings would capture.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LongQueryTimeWhatIf {
+    /// Count and total time of every digested event.
+    pub total_count: u64,
+    pub total_time: f64,
+    /// Query time of the fastest event, a lower bound of the threshold the log was written with.
+    pub fastest: Option<f64>,
+    /// Events at or above each threshold, in ascending order of threshold.
+    pub thresholds: Vec<ThresholdStats>,
+}
+
+/// Events a `long_query_time` of `threshold` seconds would have logged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdStats {
+    pub threshold: f64,
+    pub count: u64,
+    pub total_time: f64,
+}
+
+impl LongQueryTimeWhatIf {
+    /// Creates empty totals for the given thresholds, in seconds.
+    pub fn new(thresholds: &[f64]) -> Self {
+        let mut thresholds: Vec<ThresholdStats> = thresholds.iter().map(|&threshold| ThresholdStats { threshold, ..Default::default() }).collect();
+        thresholds.sort_by(|a, b| a.threshold.total_cmp(&b.threshold));
+        thresholds.dedup_by(|a, b| a.threshold == b.threshold);
+        Self { thresholds, ..Default::default() }
+    }
+
+    /// Counts one event taking `query_time` seconds.
+    pub fn add(&mut self, query_time: f64) {
+        self.total_count += 1;
+        self.total_time += query_time;
+        self.fastest = Some(self.fastest.map_or(query_time, |fastest| fastest.min(query_time)));
+        // MySQL logs statements taking strictly longer than long_query_time
+        for stats in self.thresholds.iter_mut().take_while(|stats| query_time > stats.threshold) {
+            stats.count += 1;
+            stats.total_time += query_time;
+        }
+    }
+}
+
+/// Bucket widths of the HTML timeline, in seconds, from which the narrowest
+/// that fits the logged period is picked.
+const TIMELINE_WIDTHS: &[i64] = &[60, 300, 600, 900, 1800, 3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400, 7 * 86400];
+
+/// Count and total time of the digested queries per minute of log time,
+/// drawn as the HTML timeline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timeline {
+    minutes: BTreeMap<i64, (u64, f64)>,
+}
+
+impl Timeline {
+    /// Counts one query logged at `time` that took `query_time` seconds.
+    pub fn add(&mut self, time: DateTime<Utc>, query_time: f64) {
+        let minute = time.timestamp().div_euclid(60) * 60;
+        let (count, total_time) = self.minutes.entry(minute).or_default();
+        *count += 1;
+        *total_time += query_time;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.minutes.is_empty()
+    }
+
+    /// Returns the bucket width in seconds and the consecutive buckets, as
+    /// (start, count, total time), using the narrowest width that needs at
+    /// most `max_buckets`. Buckets without queries are included.
+    pub fn buckets(&self, max_buckets: usize) -> (i64, Vec<(i64, u64, f64)>) {
+        let (Some((&first, _)), Some((&last, _))) = (self.minutes.first_key_value(), self.minutes.last_key_value()) else {
+            return (60, Vec::new());
+        };
+        let end = last + 60;
+        let width = TIMELINE_WIDTHS.iter().copied().find(|width| ((end - first) as f64 / *width as f64).ceil() as usize <= max_buckets).unwrap_or(*TIMELINE_WIDTHS.last().unwrap());
+        let start = first - first.rem_euclid(width);
+        let mut buckets: Vec<(i64, u64, f64)> = (start..end).step_by(width as usize).map(|bucket| (bucket, 0, 0.0)).collect();
+        for (minute, (count, total_time)) in &self.minutes {
+            let bucket = &mut buckets[((minute - start) / width) as usize];
+            bucket.1 += count;
+            bucket.2 += total_time;
+        }
+        (width, buckets)
+    }
+
+    /// Returns the start and query count of the minute with the most queries.
+    pub fn busiest_minute(&self) -> Option<(DateTime<Utc>, u64)> {
+        let (&minute, &(count, _)) = self.minutes.iter().max_by_key(|(minute, (count, _))| (*count, std::cmp::Reverse(**minute)))?;
+        Some((DateTime::from_timestamp(minute, 0)?, count))
+    }
+}
+
+/// Queries digested and the time they span, listed in the throughput section.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Throughput {
+    pub queries: u64,
+    /// Times of the first and last query with a logged time.
+    pub first: Option<DateTime<Utc>>,
+    pub last: Option<DateTime<Utc>>,
+    /// Start and query count of the busiest minute.
+    pub busiest_minute: Option<(DateTime<Utc>, u64)>,
+}
+
+impl Throughput {
+    /// Counts one query, logged at `time` if known.
+    pub fn add(&mut self, time: Option<DateTime<Utc>>) {
+        self.queries += 1;
+        if let Some(time) = time {
+            self.first = Some(self.first.map_or(time, |first| first.min(time)));
+            self.last = Some(self.last.map_or(time, |last| last.max(time)));
+        }
+    }
+
+    /// Returns the seconds from the first to the last query, unless under a second.
+    pub fn seconds(&self) -> Option<f64> {
+        let seconds = (self.last? - self.first?).num_microseconds()? as f64 / 1e6;
+        (seconds >= 1.0).then_some(seconds)
+    }
+
+    /// Returns the mean queries per second of `count` queries over the logged span.
+    pub fn qps(&self, count: u64) -> Option<f64> {
+        self.seconds().map(|seconds| count as f64 / seconds)
+    }
+}
+
+/// Totals of one kind of transaction-control statement, listed in their own section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TxnControlStats {
+    /// `BEGIN`, `COMMIT`, `ROLLBACK` or `SAVEPOINT`.
+    pub statement: String,
+    pub count: u64,
+    pub total_time: f64,
+    /// Part of `total_time` added to the statements of the transactions.
+    pub attributed_time: f64,
+}
+
+/// Time a fingerprint spent on reads that examined many rows and returned
+/// none, listed in their own section.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ZeroRowWork {
+    pub query_id: String,
+    pub fingerprint: String,
+    /// Executions that sent no rows while examining at least the threshold.
+    pub count: u64,
+    pub total_time: f64,
+    pub rows_examined: u64,
+    /// Total time of all executions of the fingerprint, unless its statistics
+    /// were dropped or spilled to disk to stay within the memory budget.
+    pub fingerprint_time: Option<f64>,
+}
+
+/// One execution of a fingerprint, as sampled for the CSV download of the HTML report.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionSample {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub query_time: f64,
+    pub lock_time: f64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    pub user_host: String,
+}
+
+/// A reviewer's sign-off on a query, as stored in a pt-style review table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Review {
+    pub reviewed_by: String,
+    pub reviewed_on: Option<String>,
+    pub comments: Option<String>,
+}
+
+/// Triage state of a query tracked outside the report, e.g. in a spreadsheet.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Triage {
+    pub owner: Option<String>,
+    /// Ticket number or link.
+    pub ticket: Option<String>,
+    pub status: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl Triage {
+    /// Trims the fields and drops the empty ones.
+    pub fn normalized(self) -> Self {
+        let field = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Self { owner: field(self.owner), ticket: field(self.ticket), status: field(self.status), notes: field(self.notes) }
+    }
+}
+
+/// How often executions of a query were served from the query cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCacheHits {
+    pub hits: u64,
+    /// Executions whose log said whether they hit the cache.
+    pub reported: u64,
+}
+
+impl QueryCacheHits {
+    /// Returns the share of reported executions that hit the cache, in percent.
+    pub fn rate(&self) -> f64 {
+        if self.reported > 0 { 100.0 * self.hits as f64 / self.reported as f64 } else { 0.0 }
+    }
+}
+
+/// How many executions of a query failed or were killed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorStats {
+    pub failed: u64,
+    pub killed: u64,
+    pub executions: u64,
+    /// Failed executions by error number.
+    #[serde(default)]
+    pub codes: BTreeMap<u32, u64>,
+}
+
+impl ErrorStats {
+    /// Returns the share of executions that failed, in percent.
+    pub fn rate(&self) -> f64 {
+        if self.executions > 0 { 100.0 * self.failed as f64 / self.executions as f64 } else { 0.0 }
+    }
+
+    /// Lists the error numbers, most frequent first, as `1317 (2), 1205 (1)`.
+    pub fn codes_summary(&self) -> String {
+        let mut codes: Vec<_> = self.codes.iter().collect();
+        codes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        codes.iter().map(|(errno, count)| format!("{} ({})", errno, count)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// Server-side totals of a query from performance_schema's statement digests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStats {
+    /// Digests of the matching `events_statements_summary_by_digest` rows.
+    pub digests: Vec<String>,
+    pub count: u64,
+    pub total_time: f64,
+    pub mean_time: f64,
+    pub lock_time: f64,
+    pub rows_sent: u64,
+    pub rows_examined: u64,
+    /// Logged executions as a share of the server's count.
+    pub coverage: f64,
+    /// Whether the logged executions are typical of all executions, that is,
+    /// their mean time is within a factor of two of the server's.
+    pub representative: bool,
+}
+
+#[cfg(feature = "render")]
+impl ServerStats {
+    /// Describes how the logged executions compare with all of them.
+    fn sample_summary(&self) -> &'static str {
+        if self.representative {
+            "representative"
+        } else {
+            "not representative (logged executions differ from the server mean)"
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+impl Triage {
+    /// Formats the status and owner as "<status>, owner <owner>".
+    fn summary(&self) -> String {
+        let owner = self.owner.as_ref().map(|owner| format!("owner {}", owner));
+        [self.status.clone(), owner].into_iter().flatten().collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[cfg(feature = "render")]
+/// Returns the rank of an item, followed by whether it has been reviewed and
+/// its triage status.
+fn rank_label(item: &ReportItem) -> String {
+    let review = item.review.as_ref().map(|_| "reviewed".to_string());
+    let status = item.triage.as_ref().and_then(|triage| triage.status.clone());
+    let notes: Vec<String> = [review, status].into_iter().flatten().collect();
+    if notes.is_empty() {
+        item.rank.to_string()
+    } else {
+        format!("{} ({})", item.rank, notes.join(", "))
+    }
 }
 
-/// Generates and prints the slow query report based on the provided statistics.
+#[cfg(feature = "render")]
+impl Review {
+    /// Formats the sign-off as "<who> on <when>".
+    fn summary(&self) -> String {
+        match &self.reviewed_on {
+            Some(on) => format!("{} on {}", self.reviewed_by, on),
+            None => self.reviewed_by.clone(),
+        }
+    }
+}
+
+/// How an HTML report left open in a browser keeps itself up to date.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtmlRefresh {
+    /// Reload the page every this many seconds, for report files rewritten on a schedule.
+    Reload(u64),
+    /// Poll a JSON URL every `seconds` and reload the page when its content changes.
+    Poll { url: String, seconds: u64 },
+}
+
+/// Options controlling how report items are rendered.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Maximum number of detailed sections to render. 0 means all items.
+    pub detail_limit: usize,
+    /// Weights of the impact score, described under the summary.
+    pub score_weights: ScoreWeights,
+    /// Highlight rows with badges in table output using ANSI colors.
+    pub color: bool,
+    /// DDL statements listed in the schema changes section.
+    pub schema_changes: Vec<SchemaChange>,
+    /// Transaction-control statements listed in their own section.
+    pub transaction_control: Vec<TxnControlStats>,
+    /// Names of the inputs, in the order read, when queries are broken down by input.
+    pub inputs: Vec<String>,
+    /// Keeps HTML reports current when left open in a browser.
+    pub refresh: Option<HtmlRefresh>,
+    /// Timezone used to display the times of schema changes.
+    pub timezone: Timezone,
+    /// Unit durations are displayed in.
+    pub time_unit: TimeUnit,
+    /// Capture at alternative `long_query_time` thresholds, listed in their own section.
+    pub long_query_time: Option<LongQueryTimeWhatIf>,
+    /// Fingerprints with reads that examined rows without returning any, most time first.
+    pub zero_rows: Vec<ZeroRowWork>,
+    /// Overlapping lock waits and their probable blockers, most lock time first.
+    pub lock_clusters: Vec<LockCluster>,
+    /// Connection use by `User@Host`, most connections first.
+    pub connections: Vec<ConnectionStats>,
+    /// Query counts over the logged time span, listed with each query's rate if set.
+    pub throughput: Option<Throughput>,
+    /// Sampled executions by query ID, offered as a CSV download in the HTML detailed sections.
+    pub samples: HashMap<String, Vec<ExecutionSample>>,
+    /// Queries per minute, drawn as the HTML timeline.
+    pub timeline: Timeline,
+    /// Deploys and other events marked on the timeline and listed under the time ranges.
+    pub annotations: Vec<Annotation>,
+}
+
+#[cfg(feature = "render")]
+/// Generates and prints the slow query report based on the provided report items.
 ///
 /// # Arguments
 ///
-/// * `stats` - A map of query fingerprints to their statistics.
-/// * `format` - The desired output format (Table or HTML).
+/// * `items` - The ranked report items, as returned by `prepare_report_items`.
+/// * `format` - The desired output format (Table, HTML or PDF).
+/// * `options` - Rendering options such as the detailed section limit.
 /// * `output_path` - Optional path to write the report to. If None, writes to stdout.
-/// * `timezone_str` - Timezone string for formatting timestamps.
-/// * `limit` - Maximum number of queries to include in the report.
-pub fn print_report(stats: HashMap<String, QueryStats>, format: OutputFormat, output_path: Option<&PathBuf>, timezone_str: &str, limit: usize) -> anyhow::Result<()> {
-    let items = prepare_report_items(stats, timezone_str, limit);
+/// * `use_pager` - Whether table output to a terminal should go through `$PAGER`.
+pub fn print_report(items: &[ReportItem], format: &OutputFormat, options: &RenderOptions, output_path: Option<&PathBuf>, use_pager: bool) -> anyhow::Result<()> {
+    if let Some(path) = output_path {
+        let mut writer = std::fs::File::create(path)?;
+        return write_report(items, format, options, &mut writer);
+    }
 
-    let mut writer: Box<dyn Write> = if let Some(path) = output_path {
-        Box::new(std::fs::File::create(path)?)
-    } else {
-        Box::new(std::io::stdout())
-    };
+    // Highlight flagged rows on terminals, unless disabled with NO_COLOR (https://no-color.org)
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    let options = &RenderOptions { color, ..options.clone() };
+    // Processes cannot be spawned in the browser
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(mut pager) = if use_pager && matches!(format, OutputFormat::Table) { Pager::spawn() } else { None } {
+        let result = write_report(items, format, options, &mut pager.writer());
+        pager.wait()?;
+        // The user quitting the pager early closes the pipe; that is not an error
+        return match result {
+            Err(e) if e.downcast_ref::<std::io::Error>().map(|e| e.kind()) == Some(std::io::ErrorKind::BrokenPipe) => Ok(()),
+            other => other,
+        };
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = use_pager;
+    write_report(items, format, options, &mut std::io::stdout())
+}
+
+#[cfg(feature = "render")]
+/// Renders the report in the given format to an arbitrary writer.
+pub fn write_report(items: &[ReportItem], format: &OutputFormat, options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let detail_items = limit_items(items, options.detail_limit);
 
     match format {
         OutputFormat::Table => {
             // Always print the summary table first
             let rows: Vec<Row> = items.iter().map(|item| {
-                let query_display = format_query(&item.example_query, &format);
+                let query_display = format_query(item.display_query(), format);
                 Row {
-                    rank: item.rank,
+                    rank: rank_label(item),
                     count: item.count,
-                    total_time: format!("{:.3}s", item.total_time),
-                    mean_time: format!("{:.3}s", item.mean_time),
+                    total_time: options.time_unit.format(item.total_time),
+                    mean_time: options.time_unit.format(item.mean_time),
+                    score: format!("{:.1}", item.score),
+                    flags: badge_labels(&item.badges),
                     query_id: item.query_id.clone(),
                     query: query_display,
                 }
             }).collect();
 
-            print_table(rows, &mut writer)?;
+            let flagged: Vec<bool> = items.iter().map(|item| !item.badges.is_empty()).collect();
+            print_table(rows, if options.color { &flagged } else { &[] }, writer)?;
+            writeln!(writer, "{}", options.score_weights.formula())?;
+            print_schema_changes(&options.schema_changes, options, writer)?;
+            print_transaction_control(&options.transaction_control, options.time_unit, writer)?;
+            print_zero_rows(&options.zero_rows, options.time_unit, writer)?;
+            print_lock_clusters(&options.lock_clusters, options, writer)?;
+            print_connections(&options.connections, options.time_unit, writer)?;
+            if let Some(throughput) = &options.throughput {
+                print_throughput(items, throughput, options, writer)?;
+            }
+            if let Some(what_if) = &options.long_query_time {
+                print_long_query_time(what_if, options.time_unit, writer)?;
+            }
 
-            print_detailed_sections(&items, &mut writer)?;
+            print_detailed_sections(detail_items, options, writer)?;
         }
         OutputFormat::Html => {
-            print_html(&items, &mut writer)?;
+            print_html(items, detail_items, options, writer)?;
+        }
+        OutputFormat::Pdf => {
+            let text = render_report(items, &OutputFormat::Table, options)?;
+            crate::pdf::write_text_pdf(&text, writer)?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, items)?;
+            writeln!(writer)?;
         }
     }
     Ok(())
 }
 
+#[cfg(feature = "render")]
+/// Renders the report in the given format into a string.
+pub fn render_report(items: &[ReportItem], format: &OutputFormat, options: &RenderOptions) -> anyhow::Result<String> {
+    let mut buf = Vec::new();
+    write_report(items, format, options, &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
 /// Prepares the list of `ReportItem`s from the raw statistics.
 ///
-/// Sorts the queries by total execution time and limits the result.
-fn prepare_report_items(stats: HashMap<String, QueryStats>, timezone_str: &str, limit: usize) -> Vec<ReportItem> {
-    let mut stats_vec: Vec<(String, QueryStats)> = stats.into_iter().collect();
-    
-    // Sort by total time desc
-    stats_vec.sort_by(|a, b| b.1.total_time.partial_cmp(&a.1.total_time).unwrap_or(std::cmp::Ordering::Equal));
+/// Scores every query, sorts them by `config.sort_by` and limits the result to
+/// `config.limit` (0 keeps all queries).
+pub fn prepare_report_items(stats: HashMap<String, QueryStats>, config: &DigestConfig) -> Vec<ReportItem> {
+    // Scores are relative to the largest value of each metric over all queries, not just the reported ones
+    let maxima = stats.values().fold([0.0; 4], |mut maxima, stat| {
+        for (max, value) in maxima.iter_mut().zip(ScoreWeights::metrics(stat)) {
+            *max = f64::max(*max, value);
+        }
+        maxima
+    });
+    let mut stats_vec: Vec<(String, String, f64, QueryStats)> = stats.into_iter().map(|(fp, stat)| {
+        let query_id = query_id(&fp);
+        let score = config.score_weights.score(&stat, &maxima);
+        (query_id, fp, score, stat)
+    }).collect();
 
-    stats_vec.into_iter().enumerate().take(limit).map(|(i, (fp, mut stat))| {
-        let digest = md5::compute(&fp);
-        let query_id = format!("{:x}", digest);
-        
+    // Sort descending, breaking ties by query ID so output is stable across runs
+    stats_vec.sort_by(|a, b| {
+        let order = match config.sort_by {
+            SortBy::Score => b.2.partial_cmp(&a.2),
+            SortBy::Count => Some(b.3.count.cmp(&a.3.count)),
+            sort_by => sort_by.value(&b.3).partial_cmp(&sort_by.value(&a.3)),
+        };
+        order.unwrap_or(std::cmp::Ordering::Equal).then_with(|| a.0.cmp(&b.0))
+    });
+
+    let limit = if config.limit == 0 { usize::MAX } else { config.limit };
+
+    let timezone = match config.timezone.parse::<Timezone>() {
+        Ok(timezone) => timezone,
+        Err(e) => {
+            tracing::warn!("{}, using UTC", e);
+            Timezone::default()
+        }
+    };
+
+    stats_vec.into_iter().enumerate().take(limit).map(|(i, (query_id, fp, score, mut stat))| {
         let mean = if stat.count > 0 { stat.total_time / stat.count as f64 } else { 0.0 };
         let mean_lock_time = if stat.count > 0 { stat.total_lock_time / stat.count as f64 } else { 0.0 };
         let ratio = if stat.total_rows_sent > 0 {
@@ -111,21 +902,15 @@ fn prepare_report_items(stats: HashMap<String, QueryStats>, timezone_str: &str,
         stat.all_query_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
         let p95 = percentile(&stat.all_query_times, 0.95);
         let p99 = percentile(&stat.all_query_times, 0.99);
-
-        let tz_offset = match timezone_str.parse::<chrono::FixedOffset>() {
-            Ok(offset) => offset,
-            Err(_) => {
-                eprintln!("Warning: Invalid timezone offset '{}', using UTC.", timezone_str);
-                chrono::FixedOffset::east_opt(0).unwrap()
-            }
-        };
+        let percentiles = config.percentiles.iter().map(|&p| (p, percentile(&stat.all_query_times, p))).collect();
 
         let time_range = if let (Some(first), Some(last)) = (stat.first_seen, stat.last_seen) {
-            format!("{} - {}", first.with_timezone(&tz_offset).format("%Y-%m-%d %H:%M:%S %z"), last.with_timezone(&tz_offset).format("%Y-%m-%d %H:%M:%S %z"))
+            format!("{} - {}", timezone.format(&first, "%Y-%m-%d %H:%M:%S %z"), timezone.format(&last, "%Y-%m-%d %H:%M:%S %z"))
         } else {
             "N/A".to_string()
         };
 
+        let badges = config.badge_thresholds.badges(&stat);
         ReportItem {
             rank: i + 1,
             query_id,
@@ -134,50 +919,159 @@ fn prepare_report_items(stats: HashMap<String, QueryStats>, timezone_str: &str,
             mean_time: mean,
             p95,
             p99,
+            percentiles,
             total_lock_time: stat.total_lock_time,
             mean_lock_time,
             rows_sent: stat.total_rows_sent,
             rows_examined: stat.total_rows_examined,
             ratio,
+            score,
+            badges,
             time_range,
-            example_query: stat.example_query,
-            worst_example_query: stat.worst_example_query,
+            example_query: stat.example_query.to_string(),
+            worst_example_query: stat.worst_example_query.to_string(),
             normalized_query: fp,
+            users: stat.users.iter().map(|user| user.to_string()).collect(),
+            databases: stat.databases.iter().map(|db| db.to_string()).collect(),
+            sources: stat.sources.into_iter().map(|(source, stats)| (source.to_string(), stats)).collect(),
+            explain: None,
+            explain_analyze: None,
+            first_seen: stat.first_seen,
+            last_seen: stat.last_seen,
+            review: None,
+            triage: None,
+            server: None,
+            query_cache: (stat.qc_reported > 0).then_some(QueryCacheHits { hits: stat.qc_hits, reported: stat.qc_reported }),
+            errors: (stat.errors > 0 || stat.kills > 0).then_some(ErrorStats { failed: stat.errors, killed: stat.kills, executions: stat.count, codes: stat.error_codes }),
+            metrics: stat.metrics,
+            plan_flags: stat.plan_flags,
         }
     }).collect()
 }
 
+#[cfg(feature = "render")]
 /// Prints the detailed sections of the report in text format.
-fn print_detailed_sections(items: &[ReportItem], writer: &mut dyn Write) -> anyhow::Result<()> {
+fn print_detailed_sections(items: &[ReportItem], options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let time = |seconds: f64| options.time_unit.format(seconds);
     writeln!(writer, "\nDetailed Report\n===============")?;
     
     for item in items {
         writeln!(writer, "\nQuery ID: {}", item.query_id)?;
         writeln!(writer, "Rank: {}", item.rank)?;
         writeln!(writer, "  Time Range: {}", item.time_range)?;
+        for annotation in item_annotations(item, options) {
+            writeln!(writer, "    {} {}", options.timezone.format(&annotation.time, "%Y-%m-%d %H:%M:%S %z"), annotation.label)?;
+        }
+        if !item.databases.is_empty() {
+            writeln!(writer, "  Databases: {}", item.databases.join(", "))?;
+        }
+        if !item.badges.is_empty() {
+            writeln!(writer, "  Flags: {}", badge_labels(&item.badges))?;
+        }
+        if let Some(review) = &item.review {
+            writeln!(writer, "  Reviewed: {}", review.summary())?;
+            if let Some(comments) = &review.comments {
+                writeln!(writer, "  Comments: {}", comments)?;
+            }
+        }
+        if let Some(triage) = &item.triage {
+            let summary = triage.summary();
+            if !summary.is_empty() {
+                writeln!(writer, "  Triage: {}", summary)?;
+            }
+            if let Some(ticket) = &triage.ticket {
+                writeln!(writer, "  Ticket: {}", ticket)?;
+            }
+            if let Some(notes) = &triage.notes {
+                writeln!(writer, "  Notes: {}", notes)?;
+            }
+        }
         writeln!(writer, "  Execution Stats:")?;
         writeln!(writer, "    Count: {}", item.count)?;
-        writeln!(writer, "    Total Time: {:.3}s", item.total_time)?;
-        writeln!(writer, "    Mean Time:  {:.3}s", item.mean_time)?;
-        writeln!(writer, "    P95:        {:.3}s", item.p95)?;
-        writeln!(writer, "    P99:        {:.3}s", item.p99)?;
-        writeln!(writer, "    Total Lock Time: {:.3}s", item.total_lock_time)?;
-        writeln!(writer, "    Mean Lock Time:  {:.3}s", item.mean_lock_time)?;
+        writeln!(writer, "    Total Time: {}", time(item.total_time))?;
+        writeln!(writer, "    Mean Time:  {}", time(item.mean_time))?;
+        writeln!(writer, "    Score:      {:.1}", item.score)?;
+        for (p, value) in &item.percentiles {
+            writeln!(writer, "    {:<12}{}", format!("{}:", percentile_label(*p)), time(*value))?;
+        }
+        writeln!(writer, "    Total Lock Time: {}", time(item.total_lock_time))?;
+        writeln!(writer, "    Mean Lock Time:  {}", time(item.mean_lock_time))?;
+        if let Some(cache) = &item.query_cache {
+            writeln!(writer, "    Query Cache Hits: {} of {} ({:.1}%)", cache.hits, cache.reported, cache.rate())?;
+        }
+        if let Some(errors) = &item.errors {
+            writeln!(writer, "    Errors:     {} of {} ({:.1}%), {} killed", errors.failed, errors.executions, errors.rate(), errors.killed)?;
+            if !errors.codes.is_empty() {
+                writeln!(writer, "    Error Codes: {}", errors.codes_summary())?;
+            }
+        }
         writeln!(writer, "  Row Stats:")?;
         writeln!(writer, "    Sent:       {}", item.rows_sent)?;
         writeln!(writer, "    Examined:   {}", item.rows_examined)?;
         writeln!(writer, "    Examined/Sent Ratio: {:.2}", item.ratio)?;
+        for (name, label) in ROW_METRICS {
+            if let Some(metric) = item.metrics.get(*name) {
+                writeln!(writer, "    {:<11} total {:.0}, mean {:.2}, max {:.0}", format!("{}:", label), metric.total, metric.mean(), metric.max)?;
+            }
+        }
+        let extended: Vec<(&String, &MetricStats)> = item.metrics.iter().filter(|(name, _)| !is_row_metric(name)).collect();
+        if !extended.is_empty() {
+            writeln!(writer, "  Extended Metrics:")?;
+            let width = extended.iter().map(|(name, _)| name.len() + 1).max().unwrap_or_default();
+            for (name, metric) in extended {
+                let value = |value: f64| metric_value(name, value, options);
+                writeln!(writer, "    {:<width$} total {}, mean {}, max {}", format!("{}:", name), value(metric.total), value(metric.mean()), value(metric.max))?;
+            }
+        }
+        if !item.plan_flags.is_empty() {
+            writeln!(writer, "  Query Plan:")?;
+            for (name, flag) in &item.plan_flags {
+                writeln!(writer, "    {}: {} of {} ({:.1}%)", name, flag.set, flag.count, flag.rate())?;
+            }
+        }
+        let breakdown = source_breakdown(item, &options.inputs);
+        if !breakdown.is_empty() {
+            writeln!(writer, "  By Input (seen in {} of {}):", breakdown.iter().filter(|(_, stats)| stats.is_some()).count(), breakdown.len())?;
+            for (source, stats) in &breakdown {
+                match stats {
+                    Some(stats) => writeln!(writer, "    {}: {} queries, {} ({:.0}%)", source, stats.count, time(stats.total_time), share(stats.total_time, item.total_time))?,
+                    None => writeln!(writer, "    {}: -", source)?,
+                }
+            }
+        }
         writeln!(writer, "  Normalized Query:")?;
         writeln!(writer, "    {}", item.normalized_query.trim())?;
         writeln!(writer, "  Worst Case Example:")?;
         writeln!(writer, "    {}", item.worst_example_query.trim())?;
+        if let Some(server) = &item.server {
+            writeln!(writer, "  Server Stats (performance_schema):")?;
+            writeln!(writer, "    Count: {} ({:.0}% logged)", server.count, server.coverage * 100.0)?;
+            writeln!(writer, "    Total Time: {}", time(server.total_time))?;
+            writeln!(writer, "    Mean Time:  {}", time(server.mean_time))?;
+            writeln!(writer, "    Rows Examined: {}", server.rows_examined)?;
+            writeln!(writer, "    Log Sample: {}", server.sample_summary())?;
+        }
+        if let Some(plan) = &item.explain {
+            writeln!(writer, "  EXPLAIN:")?;
+            for line in plan.lines() {
+                writeln!(writer, "    {}", line)?;
+            }
+        }
+        if let Some(plan) = &item.explain_analyze {
+            writeln!(writer, "  EXPLAIN ANALYZE:")?;
+            for line in plan.lines() {
+                writeln!(writer, "    {}", line)?;
+            }
+        }
         writeln!(writer, "--------------------------------------------------------------------------------")?;
     }
     Ok(())
 }
 
+#[cfg(feature = "render")]
 /// Prints the report in HTML format.
-fn print_html(items: &[ReportItem], writer: &mut dyn Write) -> anyhow::Result<()> {
+fn print_html(items: &[ReportItem], detail_items: &[ReportItem], options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let time = |seconds: f64| options.time_unit.format(seconds);
     writeln!(writer, "<!DOCTYPE html>")?;
     writeln!(writer, "<html>")?;
     writeln!(writer, "<head>")?;
@@ -191,6 +1085,14 @@ fn print_html(items: &[ReportItem], writer: &mut dyn Write) -> anyhow::Result<()
     writeln!(writer, ".query-sql {{ background-color: #f8f8f8; padding: 10px; overflow-x: auto; font-family: monospace; }}")?;
     writeln!(writer, ".query-id {{ font-family: monospace; }}")?;
     writeln!(writer, ".copy-btn {{ margin-bottom: 5px; padding: 5px 10px; cursor: pointer; }}")?;
+    writeln!(writer, "tr.flagged {{ background-color: #fdecea; }}")?;
+    writeln!(writer, ".badge {{ display: inline-block; background-color: #c62828; color: #fff; border-radius: 3px; padding: 1px 6px; margin: 1px; font-size: 0.85em; white-space: nowrap; }}")?;
+    writeln!(writer, ".flame {{ position: relative; margin-bottom: 20px; }}")?;
+    writeln!(writer, ".flame-cell {{ position: absolute; height: {}px; box-sizing: border-box; border: 1px solid #fff; padding: 0 4px; overflow: hidden; white-space: nowrap; text-overflow: ellipsis; font-size: 12px; line-height: {}px; color: #000; text-decoration: none; }}", FLAME_ROW_HEIGHT, FLAME_ROW_HEIGHT - 2)?;
+    writeln!(writer, ".timeline rect {{ fill: #5c85d6; }}")?;
+    writeln!(writer, ".timeline .annotation {{ stroke: #c62828; stroke-dasharray: 4 3; }}")?;
+    writeln!(writer, ".timeline text {{ font-size: 11px; }}")?;
+    writeln!(writer, "ul.annotations {{ margin-top: -8px; color: #c62828; }}")?;
     writeln!(writer, "</style>")?;
     writeln!(writer, "<script>")?;
     writeln!(writer, "function copyToClipboard(elementId) {{")?;
@@ -201,7 +1103,24 @@ fn print_html(items: &[ReportItem], writer: &mut dyn Write) -> anyhow::Result<()
     writeln!(writer, "    console.error('Async: Could not copy text: ', err);")?;
     writeln!(writer, "  }});")?;
     writeln!(writer, "}}")?;
+    match &options.refresh {
+        Some(HtmlRefresh::Poll { url, seconds }) => {
+            writeln!(writer, "(function() {{")?;
+            writeln!(writer, "  var last = null;")?;
+            writeln!(writer, "  setInterval(function() {{")?;
+            writeln!(writer, "    fetch({:?}, {{ cache: 'no-store' }}).then(function(r) {{ return r.text(); }}).then(function(body) {{", url)?;
+            writeln!(writer, "      if (last !== null && body !== last) location.reload();")?;
+            writeln!(writer, "      last = body;")?;
+            writeln!(writer, "    }}).catch(function() {{}});")?;
+            writeln!(writer, "  }}, {});", seconds * 1000)?;
+            writeln!(writer, "}})();")?;
+        }
+        Some(HtmlRefresh::Reload(_)) | None => {}
+    }
     writeln!(writer, "</script>")?;
+    if let Some(HtmlRefresh::Reload(seconds)) = &options.refresh {
+        writeln!(writer, "<meta http-equiv=\"refresh\" content=\"{}\">", seconds)?;
+    }
     writeln!(writer, "</head>")?;
     writeln!(writer, "<body>")?;
     
@@ -209,67 +1128,497 @@ fn print_html(items: &[ReportItem], writer: &mut dyn Write) -> anyhow::Result<()
     
     writeln!(writer, "<h2>Summary</h2>")?;
     writeln!(writer, "<table>")?;
-    writeln!(writer, "<thead><tr><th>Rank</th><th>Count</th><th>Total Time</th><th>Mean Time</th><th>Query ID</th><th>Query</th></tr></thead>")?;
+    writeln!(writer, "<thead><tr><th>Rank</th><th>Count</th><th>Total Time</th><th>Mean Time</th><th>Score</th><th>Flags</th><th>Query ID</th><th>Query</th></tr></thead>")?;
     writeln!(writer, "<tbody>")?;
     for item in items {
-        let mut query_display = format_query(&item.example_query, &OutputFormat::Html);
-        if query_display.len() > 100 {
-            query_display.truncate(97);
-            query_display.push_str("...");
-        }
-        writeln!(writer, "<tr>")?;
-        writeln!(writer, "<td>{}</td>", item.rank)?;
+        let query_display = truncate_chars(item.display_query(), 100);
+        writeln!(writer, "{}", if item.badges.is_empty() { "<tr>" } else { "<tr class=\"flagged\">" })?;
+        writeln!(writer, "<td>{}</td>", html_escape(&rank_label(item)))?;
         writeln!(writer, "<td>{}</td>", item.count)?;
-        writeln!(writer, "<td>{:.3}s</td>", item.total_time)?;
-        writeln!(writer, "<td>{:.3}s</td>", item.mean_time)?;
+        writeln!(writer, "<td>{}</td>", time(item.total_time))?;
+        writeln!(writer, "<td>{}</td>", time(item.mean_time))?;
+        writeln!(writer, "<td>{:.1}</td>", item.score)?;
+        writeln!(writer, "<td>{}</td>", badge_spans(&item.badges))?;
         writeln!(writer, "<td class=\"query-id\"><a href=\"#{}\">{}</a></td>", item.query_id, item.query_id)?;
         writeln!(writer, "<td>{}</td>", html_escape(&query_display))?;
         writeln!(writer, "</tr>")?;
     }
     writeln!(writer, "</tbody>")?;
     writeln!(writer, "</table>")?;
+    writeln!(writer, "<p>{}</p>", html_escape(&options.score_weights.formula()))?;
 
-    writeln!(writer, "<h2>Detailed Report</h2>")?;
-    for item in items {
-        writeln!(writer, "<div id=\"{}\" class=\"query-block\">", item.query_id)?;
-        writeln!(writer, "<h3>Rank {}: Query ID {}</h3>", item.rank, item.query_id)?;
-        writeln!(writer, "<p><strong>Time Range:</strong> {}</p>", item.time_range)?;
-        
-        writeln!(writer, "<h4>Execution Stats</h4>")?;
-        writeln!(writer, "<ul>")?;
-        writeln!(writer, "<li>Count: {}</li>", item.count)?;
-        writeln!(writer, "<li>Total Time: {:.3}s</li>", item.total_time)?;
-        writeln!(writer, "<li>Mean Time: {:.3}s</li>", item.mean_time)?;
-        writeln!(writer, "<li>P95: {:.3}s</li>", item.p95)?;
-        writeln!(writer, "<li>P99: {:.3}s</li>", item.p99)?;
-        writeln!(writer, "<li>Total Lock Time: {:.3}s</li>", item.total_lock_time)?;
-        writeln!(writer, "<li>Mean Lock Time: {:.3}s</li>", item.mean_lock_time)?;
-        writeln!(writer, "</ul>")?;
+    if !options.timeline.is_empty() {
+        write_timeline(writer, options)?;
+    }
 
-        writeln!(writer, "<h4>Row Stats</h4>")?;
-        writeln!(writer, "<ul>")?;
-        writeln!(writer, "<li>Sent: {}</li>", item.rows_sent)?;
-        writeln!(writer, "<li>Examined: {}</li>", item.rows_examined)?;
-        writeln!(writer, "<li>Examined/Sent Ratio: {:.2}</li>", item.ratio)?;
-        writeln!(writer, "</ul>")?;
+    let flame = FlameNode::build(items);
+    if flame.total_time > 0.0 {
+        writeln!(writer, "<h2>Time Breakdown</h2>")?;
+        writeln!(writer, "<p>Total time of the reported queries by statement type, table (the first one each query uses) and query. Hover over a box for its time.</p>")?;
+        writeln!(writer, "<div class=\"flame\" style=\"height: {}px\">", 4 * FLAME_ROW_HEIGHT)?;
+        flame.write_html(writer, 0, 0.0, flame.total_time, 0, options)?;
+        writeln!(writer, "</div>")?;
+    }
 
-        writeln!(writer, "<h4>Normalized Query</h4>")?;
-        writeln!(writer, "<button class=\"copy-btn\" onclick=\"copyToClipboard('norm-sql-{}')\">Copy SQL</button>", item.query_id)?;
-        writeln!(writer, "<div class=\"query-sql\"><pre id=\"norm-sql-{}\">{}</pre></div>", item.query_id, html_escape(item.normalized_query.trim()))?;
+    if !options.schema_changes.is_empty() {
+        writeln!(writer, "<h2>Schema Changes</h2>")?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>Time</th><th>Query Time</th><th>User@Host</th><th>Statement</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for change in sorted_schema_changes(&options.schema_changes) {
+            writeln!(writer, "<tr>")?;
+            writeln!(writer, "<td>{}</td>", schema_change_time(change, &options.timezone))?;
+            writeln!(writer, "<td>{}</td>", time(change.query_time))?;
+            writeln!(writer, "<td>{}</td>", html_escape(&change.user_host))?;
+            writeln!(writer, "<td class=\"query-id\">{}</td>", html_escape(change.statement.trim()))?;
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+    }
 
-        writeln!(writer, "<h4>Worst Case Example</h4>")?;
-        writeln!(writer, "<button class=\"copy-btn\" onclick=\"copyToClipboard('sql-{}')\">Copy SQL</button>", item.query_id)?;
-        writeln!(writer, "<div class=\"query-sql\"><pre id=\"sql-{}\">{}</pre></div>", item.query_id, html_escape(item.worst_example_query.trim()))?;
-        
-        writeln!(writer, "<p><a href=\"#top\">Back to Top</a></p>")?;
-        writeln!(writer, "</div>")?;
+    if let Some(what_if) = &options.long_query_time {
+        writeln!(writer, "<h2>long_query_time What-If</h2>")?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>long_query_time</th><th>Events</th><th>Events %</th><th>Total Time</th><th>Time %</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for stats in &what_if.thresholds {
+            writeln!(writer, "<tr>")?;
+            writeln!(writer, "<td>{}</td>", time(stats.threshold))?;
+            writeln!(writer, "<td>{}</td>", stats.count)?;
+            writeln!(writer, "<td>{:.1}%</td>", share(stats.count as f64, what_if.total_count as f64))?;
+            writeln!(writer, "<td>{}</td>", time(stats.total_time))?;
+            writeln!(writer, "<td>{:.1}%</td>", share(stats.total_time, what_if.total_time))?;
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+        writeln!(writer, "<p>{}</p>", html_escape(&what_if.note(options.time_unit)))?;
     }
 
-    writeln!(writer, "</body>")?;
+    if !options.transaction_control.is_empty() {
+        writeln!(writer, "<h2>Transaction Control</h2>")?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>Statement</th><th>Count</th><th>Total Time</th><th>Mean Time</th><th>Attributed</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for stats in &options.transaction_control {
+            writeln!(writer, "<tr>")?;
+            writeln!(writer, "<td>{}</td>", stats.statement)?;
+            writeln!(writer, "<td>{}</td>", stats.count)?;
+            writeln!(writer, "<td>{}</td>", time(stats.total_time))?;
+            writeln!(writer, "<td>{}</td>", time(stats.total_time / stats.count.max(1) as f64))?;
+            writeln!(writer, "<td>{}</td>", time(stats.attributed_time))?;
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+    }
+
+    if !options.zero_rows.is_empty() {
+        writeln!(writer, "<h2>Zero-Row Queries</h2>")?;
+        writeln!(writer, "<p>Reads that examined many rows but returned none, by the time spent on them.</p>")?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>Rank</th><th>Executions</th><th>Total Time</th><th>Of Query Time</th><th>Rows Examined</th><th>Query ID</th><th>Fingerprint</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for (i, work) in options.zero_rows.iter().enumerate() {
+            writeln!(writer, "<tr>")?;
+            writeln!(writer, "<td>{}</td>", i + 1)?;
+            writeln!(writer, "<td>{}</td>", work.count)?;
+            writeln!(writer, "<td>{}</td>", time(work.total_time))?;
+            writeln!(writer, "<td>{}</td>", zero_row_share(work))?;
+            writeln!(writer, "<td>{}</td>", work.rows_examined)?;
+            writeln!(writer, "<td class=\"query-id\"><a href=\"#{}\">{}</a></td>", work.query_id, work.query_id)?;
+            writeln!(writer, "<td>{}</td>", html_escape(&truncate_chars(&work.fingerprint, 100)))?;
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+    }
+
+    if !options.lock_clusters.is_empty() {
+        writeln!(writer, "<h2>Lock Contention</h2>")?;
+        writeln!(writer, "<p>Windows where lock waits piled up, with the data changes already running when they began.</p>")?;
+        for (i, cluster) in options.lock_clusters.iter().enumerate() {
+            writeln!(writer, "<h3>{}</h3>", html_escape(&lock_cluster_heading(i, cluster, options)))?;
+            writeln!(writer, "<table>")?;
+            writeln!(writer, "<thead><tr><th>Waits</th><th>Lock Time</th><th>Query ID</th><th>Fingerprint</th></tr></thead>")?;
+            writeln!(writer, "<tbody>")?;
+            for fingerprint in &cluster.fingerprints {
+                writeln!(writer, "<tr>")?;
+                writeln!(writer, "<td>{}</td>", fingerprint.events)?;
+                writeln!(writer, "<td>{}</td>", time(fingerprint.lock_time))?;
+                writeln!(writer, "<td class=\"query-id\"><a href=\"#{}\">{}</a></td>", fingerprint.query_id, fingerprint.query_id)?;
+                writeln!(writer, "<td>{}</td>", html_escape(&truncate_chars(&fingerprint.fingerprint, 100)))?;
+                writeln!(writer, "</tr>")?;
+            }
+            writeln!(writer, "</tbody>")?;
+            writeln!(writer, "</table>")?;
+            if cluster.blockers.is_empty() {
+                writeln!(writer, "<p>No long-running data change was logged as active when the waits began.</p>")?;
+            } else {
+                writeln!(writer, "<p><strong>Probable blockers:</strong></p>")?;
+                writeln!(writer, "<ul>")?;
+                for blocker in &cluster.blockers {
+                    writeln!(writer, "<li>{}</li>", html_escape(&blocker_label(blocker, options)))?;
+                }
+                writeln!(writer, "</ul>")?;
+            }
+        }
+    }
+
+    if !options.connections.is_empty() {
+        writeln!(writer, "<h2>Connections</h2>")?;
+        writeln!(writer, "<p>{}</p>", CONNECTIONS_NOTE)?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>User@Host</th><th>Connections</th><th>Statements</th><th>Per Connection</th><th>Single-Statement</th><th>Per Minute</th><th>Time per Connection</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for stats in &options.connections {
+            writeln!(writer, "<tr>")?;
+            writeln!(writer, "<td>{}</td>", html_escape(&stats.user_host))?;
+            writeln!(writer, "<td>{}</td>", stats.connections)?;
+            writeln!(writer, "<td>{}</td>", stats.statements)?;
+            writeln!(writer, "<td>{:.1}</td>", stats.statements_per_connection())?;
+            writeln!(writer, "<td>{:.0}%</td>", stats.single_statement_share())?;
+            writeln!(writer, "<td>{}</td>", connections_per_minute(stats))?;
+            writeln!(writer, "<td>{}</td>", time(stats.total_time / stats.connections.max(1) as f64))?;
+            writeln!(writer, "</tr>")?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+    }
+
+    if let Some(throughput) = &options.throughput {
+        writeln!(writer, "<h2>Throughput</h2>")?;
+        writeln!(writer, "<p>{}</p>", html_escape(&throughput_summary(throughput, options)))?;
+        writeln!(writer, "<table>")?;
+        writeln!(writer, "<thead><tr><th>Rank</th><th>Query ID</th><th>Count</th><th>Share</th><th>QPS</th></tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+        for item in items {
+            writeln!(
+                writer,
+                "<tr><td>{}</td><td><a href=\"#{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                item.rank,
+                item.query_id,
+                item.query_id,
+                item.count,
+                query_share(item.count, throughput),
+                qps(throughput.qps(item.count))
+            )?;
+        }
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+    }
+
+    writeln!(writer, "<h2>Detailed Report</h2>")?;
+    for item in detail_items {
+        writeln!(writer, "<div id=\"{}\" class=\"query-block\">", item.query_id)?;
+        writeln!(writer, "<h3>Rank {}: Query ID {}</h3>", item.rank, item.query_id)?;
+        writeln!(writer, "<p><strong>Time Range:</strong> {}</p>", item.time_range)?;
+        let annotations: Vec<String> = item_annotations(item, options)
+            .map(|annotation| format!("<li>{} {}</li>", options.timezone.format(&annotation.time, "%Y-%m-%d %H:%M:%S %z"), html_escape(&annotation.label)))
+            .collect();
+        if !annotations.is_empty() {
+            writeln!(writer, "<ul class=\"annotations\">{}</ul>", annotations.concat())?;
+        }
+        if !item.databases.is_empty() {
+            writeln!(writer, "<p><strong>Databases:</strong> {}</p>", html_escape(&item.databases.join(", ")))?;
+        }
+        if !item.badges.is_empty() {
+            writeln!(writer, "<p><strong>Flags:</strong> {}</p>", badge_spans(&item.badges))?;
+        }
+        if let Some(review) = &item.review {
+            writeln!(writer, "<p><strong>Reviewed:</strong> {}</p>", html_escape(&review.summary()))?;
+            if let Some(comments) = &review.comments {
+                writeln!(writer, "<p><strong>Comments:</strong> {}</p>", html_escape(comments))?;
+            }
+        }
+        if let Some(triage) = &item.triage {
+            let summary = triage.summary();
+            if !summary.is_empty() {
+                writeln!(writer, "<p><strong>Triage:</strong> {}</p>", html_escape(&summary))?;
+            }
+            match &triage.ticket {
+                Some(ticket) if ticket.starts_with("https://") || ticket.starts_with("http://") => {
+                    writeln!(writer, "<p><strong>Ticket:</strong> <a href=\"{}\">{}</a></p>", html_escape(ticket), html_escape(ticket))?
+                }
+                Some(ticket) => writeln!(writer, "<p><strong>Ticket:</strong> {}</p>", html_escape(ticket))?,
+                None => {}
+            }
+            if let Some(notes) = &triage.notes {
+                writeln!(writer, "<p><strong>Notes:</strong> {}</p>", html_escape(notes))?;
+            }
+        }
+        
+        writeln!(writer, "<h4>Execution Stats</h4>")?;
+        writeln!(writer, "<ul>")?;
+        writeln!(writer, "<li>Count: {}</li>", item.count)?;
+        writeln!(writer, "<li>Total Time: {}</li>", time(item.total_time))?;
+        writeln!(writer, "<li>Mean Time: {}</li>", time(item.mean_time))?;
+        writeln!(writer, "<li>Score: {:.1}</li>", item.score)?;
+        for (p, value) in &item.percentiles {
+            writeln!(writer, "<li>{}: {}</li>", percentile_label(*p), time(*value))?;
+        }
+        writeln!(writer, "<li>Total Lock Time: {}</li>", time(item.total_lock_time))?;
+        writeln!(writer, "<li>Mean Lock Time: {}</li>", time(item.mean_lock_time))?;
+        if let Some(cache) = &item.query_cache {
+            writeln!(writer, "<li>Query Cache Hits: {} of {} ({:.1}%)</li>", cache.hits, cache.reported, cache.rate())?;
+        }
+        if let Some(errors) = &item.errors {
+            writeln!(writer, "<li>Errors: {} of {} ({:.1}%), {} killed</li>", errors.failed, errors.executions, errors.rate(), errors.killed)?;
+            if !errors.codes.is_empty() {
+                writeln!(writer, "<li>Error Codes: {}</li>", errors.codes_summary())?;
+            }
+        }
+        writeln!(writer, "</ul>")?;
+
+        writeln!(writer, "<h4>Row Stats</h4>")?;
+        writeln!(writer, "<ul>")?;
+        writeln!(writer, "<li>Sent: {}</li>", item.rows_sent)?;
+        writeln!(writer, "<li>Examined: {}</li>", item.rows_examined)?;
+        writeln!(writer, "<li>Examined/Sent Ratio: {:.2}</li>", item.ratio)?;
+        for (name, label) in ROW_METRICS {
+            if let Some(metric) = item.metrics.get(*name) {
+                writeln!(writer, "<li>{}: total {:.0}, mean {:.2}, max {:.0}</li>", label, metric.total, metric.mean(), metric.max)?;
+            }
+        }
+        writeln!(writer, "</ul>")?;
+        let extended: Vec<(&String, &MetricStats)> = item.metrics.iter().filter(|(name, _)| !is_row_metric(name)).collect();
+        if !extended.is_empty() {
+            writeln!(writer, "<h4>Extended Metrics</h4>")?;
+            writeln!(writer, "<table>")?;
+            writeln!(writer, "<thead><tr><th>Metric</th><th>Total</th><th>Mean</th><th>Max</th></tr></thead>")?;
+            writeln!(writer, "<tbody>")?;
+            for (name, metric) in extended {
+                let value = |value: f64| metric_value(name, value, options);
+                writeln!(writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", html_escape(name), value(metric.total), value(metric.mean()), value(metric.max))?;
+            }
+            writeln!(writer, "</tbody>")?;
+            writeln!(writer, "</table>")?;
+        }
+        if !item.plan_flags.is_empty() {
+            writeln!(writer, "<h4>Query Plan</h4>")?;
+            writeln!(writer, "<ul>")?;
+            for (name, flag) in &item.plan_flags {
+                writeln!(writer, "<li>{}: {} of {} ({:.1}%)</li>", html_escape(name), flag.set, flag.count, flag.rate())?;
+            }
+            writeln!(writer, "</ul>")?;
+        }
+        if let Some(samples) = options.samples.get(&item.query_id).filter(|samples| !samples.is_empty()) {
+            let label = if samples.len() as u64 == item.count {
+                format!("Download all {} executions (CSV)", samples.len())
+            } else {
+                format!("Download {} sampled executions of {} (CSV)", samples.len(), item.count)
+            };
+            writeln!(
+                writer,
+                "<p><a class=\"download\" download=\"{}.csv\" href=\"data:text/csv;charset=utf-8,{}\">{}</a></p>",
+                item.query_id,
+                percent_encode(&samples_csv(samples, options)),
+                label
+            )?;
+        }
+
+        let breakdown = source_breakdown(item, &options.inputs);
+        if !breakdown.is_empty() {
+            writeln!(writer, "<h4>By Input (seen in {} of {})</h4>", breakdown.iter().filter(|(_, stats)| stats.is_some()).count(), breakdown.len())?;
+            writeln!(writer, "<table>")?;
+            writeln!(writer, "<thead><tr><th>Input</th><th>Count</th><th>Total Time</th><th>Share</th></tr></thead>")?;
+            writeln!(writer, "<tbody>")?;
+            for (source, stats) in &breakdown {
+                match stats {
+                    Some(stats) => writeln!(writer, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.0}%</td></tr>", html_escape(source), stats.count, time(stats.total_time), share(stats.total_time, item.total_time))?,
+                    None => writeln!(writer, "<tr><td>{}</td><td>0</td><td>-</td><td>-</td></tr>", html_escape(source))?,
+                }
+            }
+            writeln!(writer, "</tbody>")?;
+            writeln!(writer, "</table>")?;
+        }
+
+        writeln!(writer, "<h4>Normalized Query</h4>")?;
+        writeln!(writer, "<button class=\"copy-btn\" onclick=\"copyToClipboard('norm-sql-{}')\">Copy SQL</button>", item.query_id)?;
+        writeln!(writer, "<div class=\"query-sql\"><pre id=\"norm-sql-{}\">{}</pre></div>", item.query_id, html_escape(item.normalized_query.trim()))?;
+
+        writeln!(writer, "<h4>Worst Case Example</h4>")?;
+        writeln!(writer, "<button class=\"copy-btn\" onclick=\"copyToClipboard('sql-{}')\">Copy SQL</button>", item.query_id)?;
+        writeln!(writer, "<div class=\"query-sql\"><pre id=\"sql-{}\">{}</pre></div>", item.query_id, html_escape(item.worst_example_query.trim()))?;
+
+        if let Some(server) = &item.server {
+            writeln!(writer, "<h4>Server Stats (performance_schema)</h4>")?;
+            writeln!(writer, "<ul>")?;
+            writeln!(writer, "<li>Count: {} ({:.0}% logged)</li>", server.count, server.coverage * 100.0)?;
+            writeln!(writer, "<li>Total Time: {}</li>", time(server.total_time))?;
+            writeln!(writer, "<li>Mean Time: {}</li>", time(server.mean_time))?;
+            writeln!(writer, "<li>Rows Examined: {}</li>", server.rows_examined)?;
+            writeln!(writer, "<li>Log Sample: {}</li>", server.sample_summary())?;
+            writeln!(writer, "</ul>")?;
+        }
+
+        if let Some(plan) = &item.explain {
+            writeln!(writer, "<h4>EXPLAIN</h4>")?;
+            writeln!(writer, "<div class=\"query-sql\"><pre>{}</pre></div>", html_escape(plan))?;
+        }
+        if let Some(plan) = &item.explain_analyze {
+            writeln!(writer, "<h4>EXPLAIN ANALYZE</h4>")?;
+            writeln!(writer, "<div class=\"query-sql\"><pre>{}</pre></div>", html_escape(plan))?;
+        }
+        
+        writeln!(writer, "<p><a href=\"#top\">Back to Top</a></p>")?;
+        writeln!(writer, "</div>")?;
+    }
+
+    writeln!(writer, "</body>")?;
     writeln!(writer, "</html>")?;
     Ok(())
 }
 
+#[cfg(feature = "render")]
+/// Returns the annotations within the time range of an item.
+fn item_annotations<'a>(item: &ReportItem, options: &'a RenderOptions) -> impl Iterator<Item = &'a Annotation> {
+    let range = item.first_seen.zip(item.last_seen);
+    let annotations = if range.is_some() { &options.annotations[..] } else { &[] };
+    let (first, last) = range.unwrap_or_default();
+    crate::annotation::between(annotations, first, last)
+}
+
+#[cfg(feature = "render")]
+/// Size in pixels of the HTML timeline chart, and the most bars it is drawn with.
+const TIMELINE_WIDTH: f64 = 960.0;
+#[cfg(feature = "render")]
+const TIMELINE_HEIGHT: f64 = 160.0;
+#[cfg(feature = "render")]
+const TIMELINE_BARS: usize = 120;
+
+#[cfg(feature = "render")]
+/// Writes the timeline section: total query time per bucket as an SVG bar
+/// chart, with the annotations in the logged period marked on it.
+fn write_timeline(writer: &mut dyn Write, options: &RenderOptions) -> anyhow::Result<()> {
+    let (width, buckets) = options.timeline.buckets(TIMELINE_BARS);
+    let (Some(first), Some(last)) = (buckets.first(), buckets.last()) else { return Ok(()) };
+    let (start, end) = (first.0, last.0 + width);
+    let max = buckets.iter().map(|bucket| bucket.2).fold(0.0, f64::max);
+    let bar_width = TIMELINE_WIDTH / buckets.len() as f64;
+    let plot_height = TIMELINE_HEIGHT - 20.0;
+    let at = |seconds: i64| DateTime::from_timestamp(seconds, 0).map(|time| options.timezone.format(&time, "%Y-%m-%d %H:%M")).unwrap_or_default();
+
+    writeln!(writer, "<h2>Timeline</h2>")?;
+    writeln!(writer, "<p>Total query time per {}{}. Hover over a bar for its totals.</p>", format_bucket_width(width), if options.annotations.is_empty() { "" } else { ", with the annotations marked" })?;
+    writeln!(writer, "<svg class=\"timeline\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", TIMELINE_WIDTH, TIMELINE_HEIGHT, TIMELINE_WIDTH, TIMELINE_HEIGHT)?;
+    for (i, (bucket, count, total_time)) in buckets.iter().enumerate() {
+        let height = if max > 0.0 { total_time / max * (plot_height - 14.0) } else { 0.0 };
+        writeln!(writer, "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}: {} queries, {}</title></rect>",
+            i as f64 * bar_width, plot_height - height, (bar_width - 1.0).max(1.0), height, at(*bucket), count, options.time_unit.format(*total_time))?;
+    }
+    for annotation in options.annotations.iter().filter(|annotation| (start..=end).contains(&annotation.time.timestamp())) {
+        let x = (annotation.time.timestamp() - start) as f64 / (end - start) as f64 * TIMELINE_WIDTH;
+        let title = format!("{} {}", options.timezone.format(&annotation.time, "%Y-%m-%d %H:%M:%S %z"), annotation.label);
+        writeln!(writer, "<line class=\"annotation\" x1=\"{:.1}\" y1=\"0\" x2=\"{:.1}\" y2=\"{}\"><title>{}</title></line>", x, x, plot_height, html_escape(&title))?;
+        let anchor = if x > TIMELINE_WIDTH / 2.0 { "end" } else { "start" };
+        let offset = if anchor == "end" { -3.0 } else { 3.0 };
+        writeln!(writer, "<text x=\"{:.1}\" y=\"10\" text-anchor=\"{}\" fill=\"#c62828\">{}</text>", x + offset, anchor, html_escape(&annotation.label))?;
+    }
+    writeln!(writer, "<text x=\"0\" y=\"{}\">{}</text>", TIMELINE_HEIGHT - 4.0, at(start))?;
+    writeln!(writer, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>", TIMELINE_WIDTH, TIMELINE_HEIGHT - 4.0, at(end))?;
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+fn format_bucket_width(seconds: i64) -> String {
+    match seconds {
+        s if s % 86400 == 0 => format!("{} day{}", s / 86400, if s == 86400 { "" } else { "s" }),
+        s if s % 3600 == 0 => format!("{} hour{}", s / 3600, if s == 3600 { "" } else { "s" }),
+        s => format!("{} minute{}", s / 60, if s == 60 { "" } else { "s" }),
+    }
+}
+
+#[cfg(feature = "render")]
+/// Height in pixels of a level of the HTML time breakdown.
+const FLAME_ROW_HEIGHT: usize = 24;
+
+#[cfg(feature = "render")]
+/// A box of the HTML time breakdown: all queries, a statement verb, a table or a query.
+struct FlameNode {
+    label: String,
+    /// Query ID of the detailed section a query's box links to.
+    query_id: Option<String>,
+    total_time: f64,
+    children: Vec<FlameNode>,
+}
+
+#[cfg(feature = "render")]
+impl FlameNode {
+    /// Groups the items by verb, then by the first table they use.
+    fn build(items: &[ReportItem]) -> Self {
+        let mut verbs: BTreeMap<String, BTreeMap<String, Vec<&ReportItem>>> = BTreeMap::new();
+        for item in items {
+            // The example rather than the key, which is `User@Host` when grouping by user
+            let table = classify::tables(item.display_query()).into_iter().next().unwrap_or_else(|| "(no table)".to_string());
+            verbs.entry(classify::verb(item.display_query())).or_default().entry(table).or_default().push(item);
+        }
+        let verbs = verbs.into_iter().map(|(verb, tables)| {
+            let tables = tables.into_iter().map(|(table, items)| {
+                let queries = items.into_iter().map(|item| FlameNode {
+                    label: item.normalized_query.clone(),
+                    query_id: Some(item.query_id.clone()),
+                    total_time: item.total_time,
+                    children: Vec::new(),
+                });
+                Self::group(table, queries.collect())
+            });
+            Self::group(verb, tables.collect())
+        });
+        Self::group("All queries".to_string(), verbs.collect())
+    }
+
+    /// Creates a box holding `children`, largest first.
+    fn group(label: String, mut children: Vec<FlameNode>) -> Self {
+        children.sort_by(|a, b| b.total_time.total_cmp(&a.total_time));
+        let total_time = children.iter().map(|child| child.total_time).sum();
+        Self { label, query_id: None, total_time, children }
+    }
+
+    /// Writes this box and its descendants, `offset` seconds from the left edge.
+    /// Each verb gets a hue that its tables and queries share.
+    fn write_html(&self, writer: &mut dyn Write, depth: usize, offset: f64, total: f64, hue: u32, options: &RenderOptions) -> anyhow::Result<()> {
+        let width = share(self.total_time, total);
+        // Too narrow to see or hover
+        if width < 0.1 {
+            return Ok(());
+        }
+        let hue = if depth == 1 { self.label.bytes().fold(0u32, |hash, b| hash.wrapping_mul(31).wrapping_add(b as u32)) % 360 } else { hue };
+        let color = if depth == 0 { "hsl(0, 0%, 80%)".to_string() } else { format!("hsl({}, 65%, {}%)", hue, 55 + 10 * depth) };
+        let title = format!("{}\n{} ({:.1}%)", self.label.trim(), options.time_unit.format(self.total_time), width);
+        let (tag, href) = match &self.query_id {
+            Some(id) => ("a", format!(" href=\"#{}\"", id)),
+            None => ("div", String::new()),
+        };
+        writeln!(writer, "<{tag} class=\"flame-cell\"{href} style=\"left: {:.3}%; width: {:.3}%; top: {}px; background-color: {}\" title=\"{}\">{}</{tag}>",
+            share(offset, total), width, depth * FLAME_ROW_HEIGHT, color, html_escape(&title), html_escape(self.label.trim()))?;
+        let mut offset = offset;
+        for child in &self.children {
+            child.write_html(writer, depth + 1, offset, total, hue, options)?;
+            offset += child.total_time;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "render")]
+/// Returns the first `limit` items, or all of them when `limit` is 0.
+fn limit_items(items: &[ReportItem], limit: usize) -> &[ReportItem] {
+    if limit == 0 {
+        items
+    } else {
+        &items[..limit.min(items.len())]
+    }
+}
+
+/// Computes the query ID (MD5 hex digest) of a fingerprint.
+pub fn query_id(fingerprint: &str) -> String {
+    format!("{:x}", md5::compute(fingerprint))
+}
+
+#[cfg(feature = "render")]
 /// Escapes special characters for HTML output.
 fn html_escape(s: &str) -> String {
     s.replace("&", "&amp;")
@@ -279,8 +1628,8 @@ fn html_escape(s: &str) -> String {
      .replace("'", "&#39;")
 }
 
-/// Calculates the percentile value from a list of values.
-fn percentile(times: &[f64], p: f64) -> f64 {
+/// Calculates the percentile value from a list of values sorted in ascending order.
+pub fn percentile(times: &[f64], p: f64) -> f64 {
     if times.is_empty() {
         return 0.0;
     }
@@ -289,24 +1638,1082 @@ fn percentile(times: &[f64], p: f64) -> f64 {
     times[idx.min(times.len() - 1)]
 }
 
+/// Formats a percentile fraction as a label such as `P95` or `P99.9`.
+pub fn percentile_label(p: f64) -> String {
+    format!("P{}", (p * 1000.0).round() / 10.0)
+}
+
+#[cfg(feature = "render")]
 /// Formats a query string for display, truncating if necessary for table view.
 fn format_query(query: &str, format: &OutputFormat) -> String {
     match format {
-        OutputFormat::Table => {
-             let mut q = query.replace("\n", " ");
-             if q.len() > 50 {
-                 q.truncate(47);
-                 q.push_str("...");
-             }
-             q
-        },
+        OutputFormat::Table => truncate_chars(query, 50),
         _ => query.replace("\n", " "),
     }
 }
 
-/// Prints the summary table to the writer.
-fn print_table(rows: Vec<Row>, writer: &mut dyn Write) -> anyhow::Result<()> {
+#[cfg(feature = "render")]
+/// Prints the summary table to the writer, coloring the rows marked in `highlight` red.
+fn print_table(rows: Vec<Row>, highlight: &[bool], writer: &mut dyn Write) -> anyhow::Result<()> {
     let table = Table::new(rows).to_string();
-    writeln!(writer, "{}", table)?;
+    // Every row is a single line, so the lines starting with '|' are the header and then the rows in order
+    let mut row = 0;
+    for line in table.lines() {
+        let highlighted = line.starts_with('|') && row > 0 && highlight.get(row - 1) == Some(&true);
+        if line.starts_with('|') {
+            row += 1;
+        }
+        if highlighted {
+            writeln!(writer, "\x1b[1;31m{}\x1b[0m", line)?;
+        } else {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the schema changes table.
+#[derive(Tabled)]
+struct SchemaChangeRow {
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "Query Time")]
+    query_time: String,
+    #[tabled(rename = "User@Host")]
+    user_host: String,
+    #[tabled(rename = "Statement")]
+    statement: String,
+}
+
+#[cfg(feature = "render")]
+/// Prints the schema changes section, if there were any.
+fn print_schema_changes(changes: &[SchemaChange], options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "\nSchema Changes\n==============")?;
+    let rows: Vec<SchemaChangeRow> = sorted_schema_changes(changes).into_iter().map(|change| {
+        let mut statement = change.statement.trim().replace('\n', " ");
+        if statement.len() > 80 {
+            statement.truncate(statement.floor_char_boundary(77));
+            statement.push_str("...");
+        }
+        SchemaChangeRow {
+            time: schema_change_time(change, &options.timezone),
+            query_time: options.time_unit.format(change.query_time),
+            user_host: change.user_host.clone(),
+            statement,
+        }
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the transaction control table.
+#[derive(Tabled)]
+struct TxnControlRow {
+    #[tabled(rename = "Statement")]
+    statement: String,
+    #[tabled(rename = "Count")]
+    count: u64,
+    #[tabled(rename = "Total Time")]
+    total_time: String,
+    #[tabled(rename = "Mean Time")]
+    mean_time: String,
+    #[tabled(rename = "Attributed")]
+    attributed_time: String,
+}
+
+#[cfg(feature = "render")]
+/// Prints the transaction control section, if any such statements were set aside.
+fn print_transaction_control(stats: &[TxnControlStats], unit: TimeUnit, writer: &mut dyn Write) -> anyhow::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "\nTransaction Control\n===================")?;
+    let rows: Vec<TxnControlRow> = stats.iter().map(|stats| TxnControlRow {
+        statement: stats.statement.clone(),
+        count: stats.count,
+        total_time: unit.format(stats.total_time),
+        mean_time: unit.format(stats.total_time / stats.count.max(1) as f64),
+        attributed_time: unit.format(stats.attributed_time),
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the zero-row queries table.
+#[derive(Tabled)]
+struct ZeroRowRow {
+    #[tabled(rename = "Rank")]
+    rank: usize,
+    #[tabled(rename = "Executions")]
+    count: u64,
+    #[tabled(rename = "Total Time")]
+    total_time: String,
+    #[tabled(rename = "Of Query Time")]
+    share: String,
+    #[tabled(rename = "Rows Examined")]
+    rows_examined: u64,
+    #[tabled(rename = "Query ID")]
+    query_id: String,
+    #[tabled(rename = "Fingerprint")]
+    fingerprint: String,
+}
+
+#[cfg(feature = "render")]
+/// Prints the zero-row queries section, if any read examined rows without returning any.
+fn print_zero_rows(work: &[ZeroRowWork], unit: TimeUnit, writer: &mut dyn Write) -> anyhow::Result<()> {
+    if work.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "\nZero-Row Queries\n================")?;
+    let rows: Vec<ZeroRowRow> = work.iter().enumerate().map(|(i, work)| ZeroRowRow {
+        rank: i + 1,
+        count: work.count,
+        total_time: unit.format(work.total_time),
+        share: zero_row_share(work),
+        rows_examined: work.rows_examined,
+        query_id: work.query_id.clone(),
+        fingerprint: truncate_chars(&work.fingerprint, 60),
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    writeln!(writer, "Reads that examined many rows but returned none, by the time spent on them.")?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the fingerprints table of a lock cluster.
+#[derive(Tabled)]
+struct LockWaitRow {
+    #[tabled(rename = "Waits")]
+    events: u64,
+    #[tabled(rename = "Lock Time")]
+    lock_time: String,
+    #[tabled(rename = "Query ID")]
+    query_id: String,
+    #[tabled(rename = "Fingerprint")]
+    fingerprint: String,
+}
+
+#[cfg(feature = "render")]
+/// Prints the lock contention section, if any lock waits piled up.
+fn print_lock_clusters(clusters: &[LockCluster], options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    if clusters.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "\nLock Contention\n===============")?;
+    for (i, cluster) in clusters.iter().enumerate() {
+        writeln!(writer, "\n{}", lock_cluster_heading(i, cluster, options))?;
+        let rows: Vec<LockWaitRow> = cluster.fingerprints.iter().map(|fingerprint| LockWaitRow {
+            events: fingerprint.events,
+            lock_time: options.time_unit.format(fingerprint.lock_time),
+            query_id: fingerprint.query_id.clone(),
+            fingerprint: truncate_chars(&fingerprint.fingerprint, 60),
+        }).collect();
+        writeln!(writer, "{}", Table::new(rows))?;
+        if cluster.blockers.is_empty() {
+            writeln!(writer, "No long-running data change was logged as active when the waits began.")?;
+        } else {
+            writeln!(writer, "Probable blockers:")?;
+            for blocker in &cluster.blockers {
+                writeln!(writer, "  {}", blocker_label(blocker, options))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the connections table.
+#[derive(Tabled)]
+struct ConnectionRow {
+    #[tabled(rename = "User@Host")]
+    user_host: String,
+    #[tabled(rename = "Connections")]
+    connections: u64,
+    #[tabled(rename = "Statements")]
+    statements: u64,
+    #[tabled(rename = "Per Connection")]
+    per_connection: String,
+    #[tabled(rename = "Single-Statement")]
+    single_statement: String,
+    #[tabled(rename = "Per Minute")]
+    per_minute: String,
+    #[tabled(rename = "Time per Connection")]
+    time_per_connection: String,
+}
+
+#[cfg(feature = "render")]
+const CONNECTIONS_NOTE: &str = "Logged statements per connection. Many connections with a single slow statement each suggest missing or misconfigured connection pooling.";
+
+#[cfg(feature = "render")]
+/// Prints the connections section, if connection stats were collected.
+fn print_connections(connections: &[ConnectionStats], unit: TimeUnit, writer: &mut dyn Write) -> anyhow::Result<()> {
+    if connections.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "\nConnections\n===========")?;
+    let rows: Vec<ConnectionRow> = connections.iter().map(|stats| ConnectionRow {
+        user_host: stats.user_host.clone(),
+        connections: stats.connections,
+        statements: stats.statements,
+        per_connection: format!("{:.1}", stats.statements_per_connection()),
+        single_statement: format!("{:.0}%", stats.single_statement_share()),
+        per_minute: connections_per_minute(stats),
+        time_per_connection: unit.format(stats.total_time / stats.connections.max(1) as f64),
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    writeln!(writer, "{}", CONNECTIONS_NOTE)?;
     Ok(())
 }
+
+#[cfg(feature = "render")]
+/// Represents a row in the throughput table.
+#[derive(Tabled)]
+struct ThroughputRow {
+    #[tabled(rename = "Rank")]
+    rank: String,
+    #[tabled(rename = "Query ID")]
+    query_id: String,
+    #[tabled(rename = "Count")]
+    count: u64,
+    #[tabled(rename = "Share")]
+    share: String,
+    #[tabled(rename = "QPS")]
+    qps: String,
+}
+
+#[cfg(feature = "render")]
+/// Prints the throughput section: the query rate over the logged span and per query.
+fn print_throughput(items: &[ReportItem], throughput: &Throughput, options: &RenderOptions, writer: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(writer, "\nThroughput\n==========")?;
+    writeln!(writer, "{}", throughput_summary(throughput, options))?;
+    let rows: Vec<ThroughputRow> = items.iter().map(|item| ThroughputRow {
+        rank: rank_label(item),
+        query_id: item.query_id.clone(),
+        count: item.count,
+        share: query_share(item.count, throughput),
+        qps: qps(throughput.qps(item.count)),
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Describes the queries, the span they were logged over, their mean rate and the busiest minute.
+fn throughput_summary(throughput: &Throughput, options: &RenderOptions) -> String {
+    let format_time = |time: &DateTime<Utc>| options.timezone.format(time, "%Y-%m-%d %H:%M:%S %z");
+    let mut summary = match (throughput.first, throughput.last) {
+        (Some(first), Some(last)) => format!("{} queries from {} to {}", throughput.queries, format_time(&first), format_time(&last)),
+        _ => format!("{} queries, none with a logged time", throughput.queries),
+    };
+    if let Some(rate) = throughput.qps(throughput.queries) {
+        summary.push_str(&format!(", {:.2} QPS on average", rate));
+    }
+    if let Some((minute, count)) = &throughput.busiest_minute {
+        summary.push_str(&format!("; busiest minute {} with {} queries ({:.2} QPS)", format_time(minute), count, *count as f64 / 60.0));
+    }
+    summary.push('.');
+    summary
+}
+
+#[cfg(feature = "render")]
+/// Formats the share of all digested queries that `count` makes up.
+fn query_share(count: u64, throughput: &Throughput) -> String {
+    format!("{:.1}%", count as f64 / throughput.queries.max(1) as f64 * 100.0)
+}
+
+#[cfg(feature = "render")]
+/// Formats a query rate, or "-" when the log spans less than a second.
+fn qps(rate: Option<f64>) -> String {
+    rate.map_or_else(|| "-".to_string(), |rate| format!("{:.2}", rate))
+}
+
+#[cfg(feature = "render")]
+/// Formats the connections opened per minute, or "-" when the user was seen for less than a minute.
+fn connections_per_minute(stats: &ConnectionStats) -> String {
+    stats.per_minute.map_or_else(|| "-".to_string(), |rate| format!("{:.1}", rate))
+}
+
+#[cfg(feature = "render")]
+/// Describes the window and the waits of a lock cluster.
+fn lock_cluster_heading(i: usize, cluster: &LockCluster, options: &RenderOptions) -> String {
+    format!(
+        "Cluster {}: {} to {}, {} waits, {} lock time (max {})",
+        i + 1,
+        options.timezone.format(&cluster.start, "%Y-%m-%d %H:%M:%S %z"),
+        options.timezone.format(&cluster.end, "%Y-%m-%d %H:%M:%S %z"),
+        cluster.events,
+        options.time_unit.format(cluster.total_lock_time),
+        options.time_unit.format(cluster.max_lock_time)
+    )
+}
+
+#[cfg(feature = "render")]
+/// Describes a data change that may have held the locks waited on.
+fn blocker_label(blocker: &crate::lock_cluster::BlockerCandidate, options: &RenderOptions) -> String {
+    let thread = blocker.thread_id.map(|id| format!(", thread {}", id)).unwrap_or_default();
+    format!(
+        "{} started {} and ran {} ({}{}): {}",
+        blocker.query_id,
+        options.timezone.format(&blocker.start, "%Y-%m-%d %H:%M:%S %z"),
+        options.time_unit.format(blocker.query_time),
+        blocker.user_host,
+        thread,
+        truncate_chars(&blocker.fingerprint, 60)
+    )
+}
+
+#[cfg(feature = "render")]
+/// Writes sampled executions as CSV with a header row, oldest first. Times are
+/// RFC 3339 in the display timezone, durations in seconds.
+fn samples_csv(samples: &[ExecutionSample], options: &RenderOptions) -> String {
+    let mut samples: Vec<&ExecutionSample> = samples.iter().collect();
+    samples.sort_by_key(|sample| sample.timestamp);
+    let mut csv = String::from("timestamp,query_time,lock_time,rows_sent,rows_examined,user\r\n");
+    for sample in samples {
+        let timestamp = sample.timestamp.map(|t| options.timezone.format(&t, "%Y-%m-%dT%H:%M:%S%.6f%:z")).unwrap_or_default();
+        csv.push_str(&format!("{},{:.6},{:.6},{},{},{}\r\n", timestamp, sample.query_time, sample.lock_time, sample.rows_sent, sample.rows_examined, csv_field(&sample.user_host)));
+    }
+    csv
+}
+
+#[cfg(feature = "render")]
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "render")]
+/// Percent-encodes text for a data URI, leaving unreserved characters as they are.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(feature = "render")]
+/// Formats the share of a fingerprint's time spent on zero-row executions.
+fn zero_row_share(work: &ZeroRowWork) -> String {
+    work.fingerprint_time.map_or_else(|| "-".to_string(), |total| format!("{:.1}%", share(work.total_time, total)))
+}
+
+#[cfg(feature = "render")]
+/// Returns the first `max` characters of a single-line rendering of `text`.
+pub(crate) fn truncate_chars(text: &str, max: usize) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(max.saturating_sub(3)) {
+        Some((end, _)) if text.chars().count() > max => format!("{}...", &text[..end]),
+        _ => text,
+    }
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the long_query_time what-if table.
+#[derive(Tabled)]
+struct ThresholdRow {
+    #[tabled(rename = "long_query_time")]
+    threshold: String,
+    #[tabled(rename = "Events")]
+    count: u64,
+    #[tabled(rename = "Events %")]
+    count_share: String,
+    #[tabled(rename = "Total Time")]
+    total_time: String,
+    #[tabled(rename = "Time %")]
+    time_share: String,
+}
+
+#[cfg(feature = "render")]
+impl LongQueryTimeWhatIf {
+    /// Returns the note under the section on what the shares are relative to.
+    fn note(&self, unit: TimeUnit) -> String {
+        let mut note = format!("Shares are of all {} digested events ({}).", self.total_count, unit.format(self.total_time));
+        if let Some(fastest) = self.fastest {
+            note.push_str(&format!(
+                " The log only holds statements slower than the long_query_time it was written with (the fastest took {}), so lower thresholds are undercounted.",
+                unit.format(fastest)
+            ));
+        }
+        note
+    }
+}
+
+#[cfg(feature = "render")]
+/// Prints the long_query_time what-if section.
+fn print_long_query_time(what_if: &LongQueryTimeWhatIf, unit: TimeUnit, writer: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(writer, "
+long_query_time What-If
+=======================")?;
+    let rows: Vec<ThresholdRow> = what_if.thresholds.iter().map(|stats| ThresholdRow {
+        threshold: unit.format(stats.threshold),
+        count: stats.count,
+        count_share: format!("{:.1}%", share(stats.count as f64, what_if.total_count as f64)),
+        total_time: unit.format(stats.total_time),
+        time_share: format!("{:.1}%", share(stats.total_time, what_if.total_time)),
+    }).collect();
+    writeln!(writer, "{}", Table::new(rows))?;
+    writeln!(writer, "{}", what_if.note(unit))?;
+    Ok(())
+}
+
+#[cfg(feature = "render")]
+/// Returns the schema changes ordered by time, those without a timestamp last.
+fn sorted_schema_changes(changes: &[SchemaChange]) -> Vec<&SchemaChange> {
+    let mut sorted: Vec<&SchemaChange> = changes.iter().collect();
+    sorted.sort_by_key(|change| (change.timestamp.is_none(), change.timestamp));
+    sorted
+}
+
+#[cfg(feature = "render")]
+fn schema_change_time(change: &SchemaChange, timezone: &Timezone) -> String {
+    change.timestamp.map_or_else(|| "N/A".to_string(), |ts| timezone.format(&ts, "%Y-%m-%d %H:%M:%S %z"))
+}
+
+#[cfg(feature = "render")]
+/// Returns the per-input statistics of an item: every input in `inputs` (with
+/// `None` where the query did not appear), then any other recorded source.
+fn source_breakdown<'a>(item: &'a ReportItem, inputs: &'a [String]) -> Vec<(&'a str, Option<&'a SourceStats>)> {
+    if item.sources.is_empty() {
+        return Vec::new();
+    }
+    let mut breakdown: Vec<(&str, Option<&SourceStats>)> = inputs.iter().map(|input| (input.as_str(), item.sources.get(input))).collect();
+    breakdown.extend(item.sources.iter().filter(|(source, _)| !inputs.contains(source)).map(|(source, stats)| (source.as_str(), Some(stats))));
+    breakdown
+}
+
+#[cfg(feature = "render")]
+/// Formats a value of an extended metric: `_wait` metrics as times, others
+/// as numbers with at most two decimals.
+fn metric_value(name: &str, value: f64, options: &RenderOptions) -> String {
+    if crate::parser::is_time_metric(name) {
+        options.time_unit.format(value)
+    } else if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+#[cfg(feature = "render")]
+fn is_row_metric(name: &str) -> bool {
+    ROW_METRICS.iter().any(|(metric, _)| *metric == name)
+}
+
+#[cfg(feature = "render")]
+/// Returns `part` as a percentage of `total`.
+fn share(part: f64, total: f64) -> f64 {
+    if total > 0.0 { 100.0 * part / total } else { 0.0 }
+}
+
+#[cfg(feature = "render")]
+/// Renders the badges as HTML labels.
+fn badge_spans(badges: &[Badge]) -> String {
+    badges.iter().map(|badge| format!("<span class=\"badge\">{}</span>", badge.label())).collect()
+}
+
+#[cfg(feature = "render")]
+/// Joins the labels of the badges, separated by spaces.
+fn badge_labels(badges: &[Badge]) -> String {
+    badges.iter().map(Badge::label).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(feature = "render")]
+/// Represents a row in the parse statistics table.
+#[derive(Tabled)]
+struct ReadStatsRow {
+    #[tabled(rename = "Input")]
+    input: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Parsed")]
+    parsed: u64,
+    #[tabled(rename = "Skipped Blocks")]
+    skipped_blocks: u64,
+    #[tabled(rename = "Noise Lines")]
+    noise_lines: u64,
+    #[tabled(rename = "Unreadable")]
+    unreadable: u64,
+    #[tabled(rename = "Filtered")]
+    filtered: u64,
+    #[tabled(rename = "Aggregated")]
+    aggregated: u64,
+    #[tabled(rename = "Time")]
+    time: String,
+    #[tabled(rename = "Throughput")]
+    throughput: String,
+}
+
+#[cfg(feature = "render")]
+impl ReadStatsRow {
+    fn new(input: &str, stats: &ReadStats) -> Self {
+        let seconds = stats.elapsed.as_secs_f64();
+        let (mb, events) = if seconds > 0.0 {
+            (stats.bytes as f64 / 1_048_576.0 / seconds, stats.parsed as f64 / seconds)
+        } else {
+            (0.0, 0.0)
+        };
+        Self {
+            input: input.to_string(),
+            size: format!("{:.1} MiB", stats.bytes as f64 / 1_048_576.0),
+            parsed: stats.parsed,
+            skipped_blocks: stats.skipped_blocks,
+            noise_lines: stats.noise_lines,
+            unreadable: stats.unreadable,
+            filtered: stats.filtered.values().sum(),
+            aggregated: stats.aggregated,
+            time: format!("{:.3}s", seconds),
+            throughput: format!("{:.1} MiB/s, {:.0} q/s", mb, events),
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+/// Writes a table of what happened to each input, with a total row, followed
+/// by the number of queries each filter condition rejected.
+pub fn write_read_stats(inputs: &[(String, ReadStats)], writer: &mut dyn Write) -> anyhow::Result<()> {
+    let mut total = ReadStats::default();
+    let mut rows: Vec<ReadStatsRow> = inputs.iter().map(|(name, stats)| {
+        total.merge(stats);
+        ReadStatsRow::new(name, stats)
+    }).collect();
+    if inputs.len() > 1 {
+        rows.push(ReadStatsRow::new("Total", &total));
+    }
+    writeln!(writer, "{}", Table::new(rows))?;
+    for (reason, count) in &total.filtered {
+        writeln!(writer, "Filtered {} queries: {}", count, reason)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_with_total(total_time: f64) -> QueryStats {
+        QueryStats {
+            count: 1,
+            total_time,
+            min_time: total_time,
+            max_time: total_time,
+            all_query_times: vec![total_time],
+            ..Default::default()
+        }
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_write_read_stats() {
+        let mut stats = ReadStats { bytes: 2048, parsed: 10, aggregated: 7, elapsed: std::time::Duration::from_millis(500), ..Default::default() };
+        stats.filtered.insert("below the minimum query time", 3);
+        let mut out = Vec::new();
+        write_read_stats(&[("a.log".to_string(), stats.clone()), ("b.log".to_string(), stats)], &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.contains("| a.log "));
+        assert!(text.contains("| Total | 0.0 MiB | 20     | 0              | 0           | 0          | 6        | 14         | 1.000s | 0.0 MiB/s, 20 q/s |"), "{}", text);
+        assert!(text.ends_with("Filtered 6 queries: below the minimum query time\n"));
+    }
+
+    #[test]
+    fn test_prepare_report_items_tie_break_by_query_id() {
+        let fingerprints = ["select ?", "select * from a", "select * from b", "update t set x = ?"];
+
+        for _ in 0..5 {
+            let mut stats = HashMap::new();
+            for fp in fingerprints {
+                stats.insert(fp.to_string(), stats_with_total(1.0));
+            }
+            stats.insert("select * from slow".to_string(), stats_with_total(2.0));
+
+            let items = prepare_report_items(stats, &DigestConfig { limit: 10, ..Default::default() });
+            assert_eq!(items[0].normalized_query, "select * from slow");
+
+            let ids: Vec<&str> = items[1..].iter().map(|item| item.query_id.as_str()).collect();
+            let mut sorted = ids.clone();
+            sorted.sort();
+            assert_eq!(ids, sorted);
+        }
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_explain_and_review_are_rendered() {
+        let mut stats = HashMap::new();
+        stats.insert("select * from t where a = ?".to_string(), stats_with_total(1.0));
+        let mut items = prepare_report_items(stats, &DigestConfig::default());
+        items[0].explain = Some("type: ALL <full scan>".to_string());
+        items[0].review = Some(Review { reviewed_by: "dba".to_string(), reviewed_on: Some("2024-05-01 10:00:00".to_string()), comments: Some("needs index".to_string()) });
+        items[0].explain_analyze = Some("-> Table scan on t (actual time=0.1..2.5 rows=10 loops=1)".to_string());
+        let options = RenderOptions { detail_limit: 0, ..Default::default() };
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("  EXPLAIN:\n    type: ALL <full scan>\n"));
+        assert!(text.contains("| 1 (reviewed) |"));
+        assert!(text.contains("  Reviewed: dba on 2024-05-01 10:00:00\n  Comments: needs index\n"));
+        assert!(text.contains("  EXPLAIN ANALYZE:\n    -> Table scan on t (actual time=0.1..2.5 rows=10 loops=1)\n"));
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h4>EXPLAIN</h4>\n<div class=\"query-sql\"><pre>type: ALL &lt;full scan&gt;</pre></div>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_triage_is_rendered() {
+        let stats = HashMap::from([("select * from t where a = ?".to_string(), stats_with_total(2.0)), ("select 1".to_string(), stats_with_total(1.0))]);
+        let mut items = prepare_report_items(stats, &DigestConfig::default());
+        items[0].review = Some(Review { reviewed_by: "dba".to_string(), reviewed_on: None, comments: None });
+        items[0].triage = Some(Triage { owner: Some("alice".to_string()), ticket: Some("https://tracker.example.com/DBA-12".to_string()), status: Some("in progress".to_string()), notes: Some("needs <index>".to_string()) });
+        items[1].triage = Some(Triage { notes: Some("batch job".to_string()), ..Default::default() });
+        let options = RenderOptions { detail_limit: 0, ..Default::default() };
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("| 1 (reviewed, in progress) |"));
+        assert!(text.contains("| 2     "));
+        assert!(text.contains("  Triage: in progress, owner alice\n  Ticket: https://tracker.example.com/DBA-12\n  Notes: needs <index>\n"));
+        assert!(text.contains("Rank: 2\n  Time Range: N/A\n  Notes: batch job\n"), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<td>1 (reviewed, in progress)</td>"));
+        assert!(html.contains("<p><strong>Ticket:</strong> <a href=\"https://tracker.example.com/DBA-12\">https://tracker.example.com/DBA-12</a></p>"));
+        assert!(html.contains("<p><strong>Notes:</strong> needs &lt;index&gt;</p>"));
+    }
+
+    #[test]
+    fn test_sort_by_score() {
+        let mut stats = HashMap::new();
+        stats.insert("select slow".to_string(), stats_with_total(10.0));
+        stats.insert("select often".to_string(), QueryStats { count: 1000, total_rows_examined: 50_000, ..stats_with_total(4.0) });
+        let config = DigestConfig::builder().sort_by(SortBy::Score).limit(1).build().unwrap();
+        let items = prepare_report_items(stats, &config);
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].normalized_query, "select often");
+        // 100 * (0.5 * 4/10 + 0.2 * 1 + 0.2 * 1) / 1.0
+        assert!((items[0].score - 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_time_units() {
+        assert_eq!(TimeUnit::S.format(0.000_25), "0.000s");
+        assert_eq!(TimeUnit::Ms.format(0.000_25), "0.250ms");
+        assert_eq!(TimeUnit::Us.format(0.000_25), "250us");
+        assert_eq!(TimeUnit::Auto.format(2.5), "2.500s");
+        assert_eq!(TimeUnit::Auto.format(0.0125), "12.500ms");
+        assert_eq!(TimeUnit::Auto.format(0.000_012), "12us");
+    }
+
+    #[test]
+    fn test_long_query_time_what_if() {
+        let mut what_if = LongQueryTimeWhatIf::new(&[2.0, 0.5, 1.0, 0.5]);
+        for query_time in [0.2, 0.5, 0.7, 1.5, 3.0] {
+            what_if.add(query_time);
+        }
+        let captured: Vec<(f64, u64, f64)> = what_if.thresholds.iter().map(|t| (t.threshold, t.count, t.total_time)).collect();
+        // Statements are logged when strictly slower than long_query_time
+        assert_eq!(captured, vec![(0.5, 3, 5.2), (1.0, 2, 4.5), (2.0, 1, 3.0)]);
+        assert_eq!((what_if.total_count, what_if.fastest), (5, Some(0.2)));
+    }
+
+    #[test]
+    fn test_parse_score_weights() {
+        let weights: ScoreWeights = "time=1, lock=0.5".parse().unwrap();
+        assert_eq!(weights, ScoreWeights { total_time: 1.0, count: 0.0, rows_examined: 0.0, lock_time: 0.5 });
+        assert!(weights.formula().starts_with("Score = 100 * (1*time + 0.5*lock) / 1.5,"));
+        assert!("time=-1".parse::<ScoreWeights>().is_err());
+        assert!("cpu=1".parse::<ScoreWeights>().is_err());
+        assert!("time=0".parse::<ScoreWeights>().is_err());
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_badges_highlight_rows() {
+        let mut stats = HashMap::new();
+        stats.insert("select scan".to_string(), QueryStats { total_rows_sent: 0, total_rows_examined: 5000, ..stats_with_total(2.0) });
+        stats.insert("update locked".to_string(), QueryStats { total_rows_sent: 1, total_rows_examined: 500, total_lock_time: 0.9, ..stats_with_total(1.0) });
+        stats.insert("select fine".to_string(), QueryStats { total_rows_sent: 10, total_rows_examined: 10, ..stats_with_total(0.5) });
+        let items = prepare_report_items(stats, &DigestConfig::default());
+
+        assert_eq!(items[0].badges, vec![Badge::NoRowsSent]);
+        assert_eq!(items[1].badges, vec![Badge::HighRatio, Badge::LockHeavy]);
+        assert!(items[2].badges.is_empty());
+
+        let options = RenderOptions { color: true, ..Default::default() };
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        let highlighted: Vec<&str> = text.lines().filter(|line| line.starts_with("\x1b[1;31m|")).collect();
+        assert_eq!(highlighted.len(), 2);
+        assert!(highlighted[1].contains("high-ratio lock-heavy"));
+        assert!(text.contains("  Flags: no-rows-sent\n"));
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert_eq!(html.matches("<tr class=\"flagged\">").count(), 2);
+
+        let thresholds: BadgeThresholds = "ratio=0, lock=0.95".parse().unwrap();
+        assert_eq!(thresholds, BadgeThresholds { ratio: 0.0, lock_share: 0.95, ..Default::default() });
+        assert!("lock=2".parse::<BadgeThresholds>().is_err());
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_html_refresh() {
+        let items = prepare_report_items(HashMap::from([("select 1".to_string(), stats_with_total(1.0))]), &DigestConfig::default());
+        let html = |refresh| render_report(&items, &OutputFormat::Html, &RenderOptions { refresh, ..Default::default() }).unwrap();
+
+        assert!(!html(None).contains("http-equiv"));
+        assert!(html(Some(HtmlRefresh::Reload(900))).contains("<meta http-equiv=\"refresh\" content=\"900\">\n</head>"));
+        let polling = html(Some(HtmlRefresh::Poll { url: "/api/summary".to_string(), seconds: 5 }));
+        assert!(polling.contains("fetch(\"/api/summary\""));
+        assert!(polling.contains("}, 5000);"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_html_time_breakdown() {
+        let log = "# User@Host: app[app] @ localhost []
+# Query_time: 3.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders o JOIN customers c ON c.id = o.customer_id WHERE o.id = 1;
+# User@Host: app[app] @ localhost []
+# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 1
+UPDATE orders SET state = 'paid' WHERE id = 1;
+";
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let items = digest.finish();
+        let html = render_report(&items, &OutputFormat::Html, &RenderOptions::default()).unwrap();
+
+        assert!(html.contains("<h2>Time Breakdown</h2>"));
+        assert!(html.contains("style=\"left: 0.000%; width: 75.000%; top: 24px; background-color: hsl("));
+        assert!(html.contains(">SELECT</div>") && html.contains(">UPDATE</div>") && html.contains(">orders</div>"));
+        assert!(html.contains(&format!("<a class=\"flame-cell\" href=\"#{}\" style=\"left: 75.000%; width: 25.000%; top: 72px;", items[1].query_id)));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_timeline_annotations() {
+        let log = "# Time: 2024-05-01T10:00:10Z
+# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 1;
+# Time: 2024-05-01T10:30:00Z
+# Query_time: 4.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 2;
+# Time: 2024-05-01T12:59:00Z
+# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 3;
+";
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let mut options = digest.render_options();
+        let items = digest.finish();
+
+        let (width, buckets) = options.timeline.buckets(120);
+        assert_eq!(width, 300);
+        assert_eq!(buckets.len(), 36);
+        assert_eq!((buckets[0].0, buckets[0].1, buckets[0].2), ("2024-05-01T10:00:00Z".parse::<DateTime<Utc>>().unwrap().timestamp(), 1, 2.0));
+        assert_eq!(buckets[6].2, 4.0);
+        assert_eq!(buckets.iter().map(|bucket| bucket.1).sum::<u64>(), 3);
+
+        options.annotations = crate::annotation::parse("2024-05-01T10:20:00Z deploy api v1.4.2\n2024-05-02T00:00:00Z later").unwrap();
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h2>Timeline</h2>\n<p>Total query time per 5 minutes, with the annotations marked."));
+        assert_eq!(html.matches("<line class=\"annotation\"").count(), 1);
+        assert!(html.contains("<title>2024-05-01 10:20:00 +0000 deploy api v1.4.2</title>"));
+        assert!(html.contains("<ul class=\"annotations\"><li>2024-05-01 10:20:00 +0000 deploy api v1.4.2</li></ul>"));
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains(&format!("  Time Range: {}\n    2024-05-01 10:20:00 +0000 deploy api v1.4.2\n", items[0].time_range)));
+        assert!(!text.contains("later"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_zero_row_queries() {
+        let log = "# User@Host: app[app] @ localhost []
+# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 50000
+SELECT * FROM orders WHERE email = 'a@example.com';
+# User@Host: app[app] @ localhost []
+# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 50000
+SELECT * FROM orders WHERE email = 'b@example.com';
+# User@Host: app[app] @ localhost []
+# Query_time: 0.5  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 10
+SELECT * FROM orders WHERE email = 'c@example.com';
+# User@Host: app[app] @ localhost []
+# Query_time: 3.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 90000
+UPDATE orders SET state = 'void' WHERE created < '2020-01-01';
+# User@Host: app[app] @ localhost []
+# Query_time: 4.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 2000
+SELECT id FROM carts WHERE token = 'x';
+";
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+
+        // The UPDATE sends no rows by nature, and the cheap miss is below the threshold
+        assert_eq!(options.zero_rows.len(), 2);
+        assert_eq!((options.zero_rows[0].fingerprint.as_str(), options.zero_rows[0].fingerprint_time), ("select id from carts where token = ?;", Some(4.0)));
+        assert_eq!((options.zero_rows[1].count, options.zero_rows[1].total_time, options.zero_rows[1].rows_examined), (1, 2.0, 50000));
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("\nZero-Row Queries\n================\n"));
+        assert!(text.contains(&format!("| 2    | 1          | 2.000s     | 57.1%         | 50000         | {} | select * from orders where email = ?; |", options.zero_rows[1].query_id)), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h2>Zero-Row Queries</h2>"));
+        assert!(html.contains("<td>100.0%</td>\n<td>2000</td>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_lock_clusters_are_rendered() {
+        let log = "# Time: 2024-05-01T10:00:30Z
+# User@Host: app[app] @ localhost []  Id: 41
+# Query_time: 30.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 90000
+UPDATE orders SET state = 'void' WHERE created < '2020-01-01';
+# Time: 2024-05-01T10:00:20Z
+# User@Host: app[app] @ localhost []  Id: 42
+# Query_time: 15.0  Lock_time: 14.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 1 FOR UPDATE;
+# Time: 2024-05-01T10:00:22Z
+# User@Host: app[app] @ localhost []  Id: 43
+# Query_time: 12.0  Lock_time: 11.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 2 FOR UPDATE;
+# Time: 2024-05-01T10:00:25Z
+# User@Host: app[app] @ localhost []  Id: 44
+# Query_time: 10.0  Lock_time: 9.0 Rows_sent: 1  Rows_examined: 1
+SELECT * FROM orders WHERE id = 3 FOR UPDATE;
+";
+        let config = DigestConfig::builder().lock_clusters(crate::lock_cluster::LockClusterOptions::default()).build().unwrap();
+        let mut digest = crate::digest::Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        assert_eq!(options.lock_clusters.len(), 1);
+        assert_eq!(options.lock_clusters[0].blockers[0].thread_id, Some(41));
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("\nLock Contention\n===============\n\nCluster 1: 2024-05-01 10:00:05 +0000 to 2024-05-01 10:00:24 +0000, 3 waits, 34.000s lock time (max 14.000s)\n"), "{}", text);
+        assert!(text.contains("started 2024-05-01 10:00:00 +0000 and ran 30.000s (app[app] @ localhost [], thread 41): update orders set state = ?"), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h2>Lock Contention</h2>"));
+        assert!(html.contains("<p><strong>Probable blockers:</strong></p>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_execution_samples_csv() {
+        let mut log = String::new();
+        for i in 0..5 {
+            log.push_str(&format!("# Time: 2024-05-01T10:00:0{}Z\n# User@Host: app[app] @ web,1 []\n# Query_time: {}.5  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10\nSELECT * FROM t WHERE id = {};\n", i, i, i));
+        }
+        let config = DigestConfig::builder().execution_samples(3).build().unwrap();
+        let mut digest = crate::digest::Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        let samples = &options.samples[&items[0].query_id];
+        assert_eq!(samples.len(), 3);
+
+        let csv = samples_csv(samples, &options);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "timestamp,query_time,lock_time,rows_sent,rows_examined,user");
+        assert!(lines[1].ends_with(",0.000000,1,10,\"app[app] @ web,1 []\""), "{}", csv);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains(&format!("<a class=\"download\" download=\"{}.csv\" href=\"data:text/csv;charset=utf-8,timestamp%2Cquery_time%2C", items[0].query_id)));
+        assert!(html.contains(">Download 3 sampled executions of 5 (CSV)</a>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_multibyte_queries_are_truncated() {
+        let log = "# User@Host: app[app] @ localhost []\n# Query_time: 1.0\nSELECT 顧客名, 住所, 電話番号, 注文番号, 金額 FROM 顧客テーブル WHERE id = 1;\n";
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("| SELECT 顧客名, 住所, 電話番号, 注文番号, 金額 FROM 顧客テーブル WHER... |"), "{}", text);
+        assert_eq!(format_query(&"顧客".repeat(30), &OutputFormat::Table), format!("{}...", "顧客".repeat(23) + "顧"));
+        render_report(&items, &OutputFormat::Html, &options).unwrap();
+    }
+
+    #[test]
+    fn test_innodb_badges() {
+        let log = "# User@Host: app[app] @ localhost []  Id: 3
+# Schema: shop  Last_errno: 0  Killed: 0
+# Query_time: 2.000000  Lock_time: 0.000100  Rows_sent: 10  Rows_examined: 10  Rows_affected: 0
+# Bytes_sent: 512  Tmp_tables: 0  Tmp_disk_tables: 0  Tmp_table_sizes: 0
+# InnoDB_trx_id: 1A5F
+# QC_Hit: No  Full_scan: No  Full_join: No  Tmp_table: No  Tmp_table_on_disk: No
+# Filesort: No  Filesort_on_disk: No  Merge_passes: 0
+#   InnoDB_IO_r_ops: 120  InnoDB_IO_r_bytes: 1966080  InnoDB_IO_r_wait: 1.600000
+#   InnoDB_rec_lock_wait: 0.000000  InnoDB_queue_wait: 0.000000
+#   InnoDB_pages_distinct: 130
+SELECT * FROM orders WHERE customer_id = 1;
+# User@Host: app[app] @ localhost []  Id: 4
+# Query_time: 1.000000  Lock_time: 0.000100  Rows_sent: 0  Rows_examined: 1  Rows_affected: 1
+#   InnoDB_IO_r_ops: 0  InnoDB_IO_r_bytes: 0  InnoDB_IO_r_wait: 0.000000
+#   InnoDB_rec_lock_wait: 0.900000  InnoDB_queue_wait: 0.050000
+#   InnoDB_pages_distinct: 2
+UPDATE stock SET qty = qty - 1 WHERE id = 7;
+# User@Host: app[app] @ localhost []  Id: 5
+# Query_time: 0.500000  Lock_time: 0.000000  Rows_sent: 1  Rows_examined: 0
+# No InnoDB statistics available for this query
+SELECT 1;
+";
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let items = digest.finish();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].badges, vec![Badge::IoBound]);
+        assert_eq!(items[0].metrics["InnoDB_IO_r_bytes"], MetricStats { count: 1, total: 1966080.0, max: 1966080.0 });
+        assert_eq!(items[1].badges, vec![Badge::RowLockWait]);
+        assert_eq!(items[1].metrics["InnoDB_queue_wait"].total, 0.05);
+        assert!(items[2].badges.is_empty() && items[2].metrics.is_empty());
+
+        let thresholds: BadgeThresholds = "io=0, rowlock=0.95".parse().unwrap();
+        assert_eq!((thresholds.io_share, thresholds.row_lock_share), (0.0, 0.95));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_error_rates() {
+        let mut log = String::new();
+        for (errno, killed) in [(0, 0), (0, 0), (1317, 1), (1205, 0), (1205, 0)] {
+            log.push_str(&format!(
+                "# User@Host: app[app] @ localhost []\n# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 0 Thread_id: 10 Errno: {} Killed: {}\nUPDATE stock SET qty = qty - 1 WHERE id = 7;\n",
+                errno, killed
+            ));
+        }
+        log.push_str("# User@Host: app[app] @ localhost []\n# Query_time: 1.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 1 Thread_id: 10 Errno: 0 Killed: 0\nSELECT 1;\n");
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        let errors = items[0].errors.as_ref().unwrap();
+        assert_eq!((errors.failed, errors.killed, errors.executions, errors.rate()), (3, 1, 5, 60.0));
+        assert_eq!(errors.codes_summary(), "1205 (2), 1317 (1)");
+        assert_eq!(items[0].badges, vec![Badge::Failing]);
+        assert!(items[1].errors.is_none() && items[1].badges.is_empty());
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("    Errors:     3 of 5 (60.0%), 1 killed\n    Error Codes: 1205 (2), 1317 (1)\n"), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<li>Errors: 3 of 5 (60.0%), 1 killed</li>"));
+
+        let thresholds: BadgeThresholds = "errors=0.75".parse().unwrap();
+        assert_eq!(thresholds.error_share, 0.75);
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_extended_metrics_are_rendered() {
+        let mut log = String::new();
+        for (i, filesort) in ["Yes", "No"].into_iter().enumerate() {
+            log.push_str(&format!(
+                "# User@Host: app[app] @ localhost []\n# Query_time: 1.0  Lock_time: 0.0  Rows_sent: 1  Rows_examined: 10  Rows_affected: 0\n# Bytes_sent: {}  Tmp_tables: 1\n# QC_Hit: No  Full_scan: Yes  Filesort: {}\n#   InnoDB_IO_r_ops: 2  InnoDB_IO_r_wait: 0.250000\nSELECT * FROM t ORDER BY b LIMIT {};\n",
+                100 * (i + 1),
+                filesort,
+                i
+            ));
+        }
+        let mut digest = crate::digest::Digest::new(DigestConfig::default());
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        let item = &items[0];
+        assert_eq!(item.metrics["Bytes_sent"], MetricStats { count: 2, total: 300.0, max: 200.0 });
+        assert_eq!(item.plan_flags["Filesort"], FlagStats { count: 2, set: 1 });
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("    Affected:   total 0, mean 0.00, max 0\n    Bytes Sent: total 300, mean 150.00, max 200\n  Extended Metrics:\n    InnoDB_IO_r_ops:  total 4, mean 2, max 2\n"), "{}", text);
+        assert!(text.contains("    InnoDB_IO_r_wait: total 0.500s, mean 0.250s, max 0.250s\n"), "{}", text);
+        assert!(text.contains("  Query Plan:\n    Filesort: 1 of 2 (50.0%)\n    Full_scan: 2 of 2 (100.0%)\n"), "{}", text);
+        assert!(text.contains("    Query Cache Hits: 0 of 2 (0.0%)\n"), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<tr><td>Tmp_tables</td><td>2</td><td>1</td><td>1</td></tr>"));
+        assert!(html.contains("<li>Bytes Sent: total 300, mean 150.00, max 200</li>"));
+        assert!(!html.contains("<td>Bytes_sent</td>"));
+
+        let config = DigestConfig::builder().sort_by(SortBy::BytesSent).build().unwrap();
+        let mut digest = crate::digest::Digest::new(config);
+        digest.add_reader(format!("{}# User@Host: app[app] @ localhost []\n# Query_time: 9.0\n# Rows_affected: 5  Bytes_sent: 11\nUPDATE t SET a = 1;\n", log).as_bytes());
+        let items = digest.finish();
+        assert!(items[0].normalized_query.starts_with("select"));
+        assert_eq!(items[1].metrics["Rows_affected"], MetricStats { count: 1, total: 5.0, max: 5.0 });
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_throughput_is_rendered() {
+        let mut log = String::new();
+        for second in 0..10 {
+            log.push_str(&format!("2024-05-01T10:00:{:02}.000000Z\t   12 Query\tSELECT * FROM t WHERE id = {}\n", second, second));
+        }
+        log.push_str("2024-05-01T10:00:10.000000Z\t   12 Query\tUPDATE t SET a = 1\n");
+        let config = DigestConfig::builder().input_format(crate::parser::InputFormat::General).sort_by(SortBy::Count).throughput(true).build().unwrap();
+        let mut digest = crate::digest::Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        assert_eq!((items[0].count, items[1].count), (10, 1));
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("\nThroughput\n==========\n11 queries from 2024-05-01 10:00:00 +0000 to 2024-05-01 10:00:10 +0000, 1.10 QPS on average; busiest minute 2024-05-01 10:00:00 +0000 with 11 queries (0.18 QPS).\n"), "{}", text);
+        assert!(text.contains(&format!("| 1    | {} | 10    | 90.9% | 1.00 |", items[0].query_id)), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h2>Throughput</h2>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_connections_are_rendered() {
+        let mut log = String::new();
+        for i in 0..3 {
+            log.push_str(&format!("# Time: 2024-05-01T10:0{}:00Z\n# User@Host: php[php] @ web1 []  Id: {}\n# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10\nSELECT * FROM t WHERE id = {};\n", i, 10 + i, i));
+        }
+        let config = DigestConfig::builder().connection_stats(true).build().unwrap();
+        let mut digest = crate::digest::Digest::new(config);
+        digest.add_reader(log.as_bytes());
+        let options = digest.render_options();
+        let items = digest.finish();
+        assert_eq!(options.connections.len(), 1);
+
+        let text = render_report(&items, &OutputFormat::Table, &options).unwrap();
+        assert!(text.contains("\nConnections\n===========\n"));
+        assert!(text.contains("| php[php] @ web1 [] | 3           | 3          | 1.0            | 100%             | 1.5        | 2.000s              |"), "{}", text);
+        let html = render_report(&items, &OutputFormat::Html, &options).unwrap();
+        assert!(html.contains("<h2>Connections</h2>"));
+    }
+
+    #[cfg(feature = "render")]
+    #[test]
+    fn test_limits_zero_means_all() {
+        let mut stats = HashMap::new();
+        for i in 0..30 {
+            stats.insert(format!("select {}", i), stats_with_total(i as f64));
+        }
+        let items = prepare_report_items(stats, &DigestConfig { limit: 0, ..Default::default() });
+        assert_eq!(items.len(), 30);
+
+        assert_eq!(limit_items(&items, 0).len(), 30);
+        assert_eq!(limit_items(&items, 5).len(), 5);
+        assert_eq!(limit_items(&items, 50).len(), 30);
+    }
+
+    #[test]
+    fn test_percentile_label() {
+        assert_eq!(percentile_label(0.95), "P95");
+        assert_eq!(percentile_label(0.999), "P99.9");
+        assert_eq!(percentile_label(0.5), "P50");
+    }
+}
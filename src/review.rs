@@ -0,0 +1,258 @@
+use crate::report::{Review, ReportItem};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use mysql::prelude::Queryable;
+use mysql::{Conn, OptsBuilder};
+
+/// A Percona Toolkit style DSN such as `h=host,P=3306,u=user,p=pass,D=percona,t=query_review`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PtDsn {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub socket: Option<String>,
+    pub database: Option<String>,
+    pub table: Option<String>,
+}
+
+impl PtDsn {
+    /// Parses comma-separated `key=value` pairs (keys `h`, `P`, `u`, `p`, `S`, `D`, `t`).
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut dsn = PtDsn::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let Some((key, value)) = part.split_once('=') else {
+                bail!("Invalid DSN part '{}': expected key=value", part);
+            };
+            let value = value.to_string();
+            match key {
+                "h" => dsn.host = Some(value),
+                "P" => dsn.port = Some(value.parse().with_context(|| format!("Invalid port '{}'", value))?),
+                "u" => dsn.user = Some(value),
+                "p" => dsn.password = Some(value),
+                "S" => dsn.socket = Some(value),
+                "D" => dsn.database = Some(value),
+                "t" => dsn.table = Some(value),
+                _ => bail!("Unknown DSN key '{}'", key),
+            }
+        }
+        Ok(dsn)
+    }
+
+    /// Opens a connection to the server the DSN points to.
+    pub fn connect(&self) -> anyhow::Result<Conn> {
+        let opts = OptsBuilder::new()
+            .ip_or_hostname(Some(self.host.clone().unwrap_or_else(|| "localhost".to_string())))
+            .tcp_port(self.port.unwrap_or(3306))
+            .user(self.user.clone())
+            .pass(self.password.clone())
+            .socket(self.socket.clone());
+        Conn::new(opts).context("Could not connect to the review server")
+    }
+
+    /// Returns the quoted `database`.`table` name, defaulting to `percona`.`query_review`.
+    fn table_name(&self, default_table: &str) -> String {
+        format!(
+            "{}.{}",
+            quote_identifier(self.database.as_deref().unwrap_or("percona")),
+            quote_identifier(self.table.as_deref().unwrap_or(default_table))
+        )
+    }
+}
+
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+/// Returns the checksum pt-query-digest keys its tables on: the low 64 bits of
+/// the MD5 a query ID is the hex form of.
+pub fn pt_checksum(query_id: &str) -> u64 {
+    u64::from_str_radix(&query_id[query_id.len().saturating_sub(16)..], 16).unwrap_or(0)
+}
+
+pub(crate) fn format_datetime(ts: Option<DateTime<Utc>>) -> Option<String> {
+    ts.map(|ts| ts.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Records every item in the review table and fills in `ReportItem::review`
+/// for the ones a reviewer has signed off.
+///
+/// New fingerprints are inserted with their worst example as sample; known ones
+/// get their `first_seen`/`last_seen` widened. The table is created if missing.
+pub fn sync(items: &mut [ReportItem], dsn: &PtDsn) -> anyhow::Result<()> {
+    let mut conn = dsn.connect()?;
+    let table = dsn.table_name("query_review");
+
+    conn.query_drop(format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+  checksum    BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+  fingerprint TEXT NOT NULL,
+  sample      TEXT NOT NULL,
+  first_seen  DATETIME,
+  last_seen   DATETIME,
+  reviewed_by VARCHAR(20),
+  reviewed_on DATETIME,
+  comments    TEXT
+)",
+        table
+    ))
+    .with_context(|| format!("Could not create review table {}", table))?;
+
+    let upsert = format!(
+        "INSERT INTO {} (checksum, fingerprint, sample, first_seen, last_seen) VALUES (?, ?, ?, ?, ?)
+ON DUPLICATE KEY UPDATE
+  first_seen = IF(first_seen IS NULL, VALUES(first_seen), LEAST(first_seen, COALESCE(VALUES(first_seen), first_seen))),
+  last_seen = IF(last_seen IS NULL, VALUES(last_seen), GREATEST(last_seen, COALESCE(VALUES(last_seen), last_seen)))",
+        table
+    );
+    let select = format!(
+        "SELECT reviewed_by, CAST(reviewed_on AS CHAR), comments FROM {} WHERE checksum = ? AND reviewed_by IS NOT NULL AND reviewed_by <> ''",
+        table
+    );
+
+    for item in items {
+        let checksum = pt_checksum(&item.query_id);
+        conn.exec_drop(&upsert, (
+            checksum,
+            &item.normalized_query,
+            &item.worst_example_query,
+            format_datetime(item.first_seen),
+            format_datetime(item.last_seen),
+        ))?;
+
+        let reviewed: Option<(String, Option<String>, Option<String>)> = conn.exec_first(&select, (checksum,))?;
+        item.review = reviewed.map(|(reviewed_by, reviewed_on, comments)| Review { reviewed_by, reviewed_on, comments });
+    }
+    Ok(())
+}
+
+/// Appends one row per item to the history table, keyed by checksum and
+/// `run_at`, so query behavior can be charted across runs.
+///
+/// The table (default `percona`.`query_history`) is created if missing. Its
+/// columns follow pt-query-digest's `query_history` naming, but it is not that
+/// table: pt keys rows on `ts_min`/`ts_max` and keeps the min, max, median and
+/// standard deviation of every attribute, while this one keeps a row per run,
+/// so logs without timestamps can be charted too. Returns the number of rows
+/// written.
+pub fn append_history(items: &[ReportItem], dsn: &PtDsn, run_at: DateTime<Utc>) -> anyhow::Result<usize> {
+    let mut conn = dsn.connect()?;
+    let table = dsn.table_name("query_history");
+
+    conn.query_drop(history_schema(&table)).with_context(|| format!("Could not create history table {}", table))?;
+    let run_at = format_datetime(Some(run_at));
+    conn.exec_batch(history_insert(&table), items.iter().map(|item| history_row(item, &run_at)))
+        .with_context(|| format!("Could not write to history table {}", table))?;
+    Ok(items.len())
+}
+
+/// Columns of the history table, in insert order.
+const HISTORY_COLUMNS: [&str; 12] = [
+    "checksum", "run_at", "sample", "ts_min", "ts_max", "ts_cnt", "Query_time_sum", "Query_time_mean", "Query_time_pct_95", "Lock_time_sum", "Rows_sent_sum", "Rows_examined_sum",
+];
+
+fn history_schema(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+  checksum           BIGINT UNSIGNED NOT NULL,
+  run_at             DATETIME NOT NULL,
+  sample             TEXT NOT NULL,
+  ts_min             DATETIME,
+  ts_max             DATETIME,
+  ts_cnt             BIGINT UNSIGNED NOT NULL,
+  Query_time_sum     DOUBLE NOT NULL,
+  Query_time_mean    DOUBLE NOT NULL,
+  Query_time_pct_95  DOUBLE NOT NULL,
+  Lock_time_sum      DOUBLE NOT NULL,
+  Rows_sent_sum      BIGINT UNSIGNED NOT NULL,
+  Rows_examined_sum  BIGINT UNSIGNED NOT NULL,
+  PRIMARY KEY (checksum, run_at)
+)",
+        table
+    )
+}
+
+fn history_insert(table: &str) -> String {
+    format!("REPLACE INTO {} ({}) VALUES ({})", table, HISTORY_COLUMNS.join(", "), vec!["?"; HISTORY_COLUMNS.len()].join(", "))
+}
+
+fn history_row(item: &ReportItem, run_at: &Option<String>) -> Vec<mysql::Value> {
+    vec![
+        pt_checksum(&item.query_id).into(),
+        run_at.clone().into(),
+        item.worst_example_query.clone().into(),
+        format_datetime(item.first_seen).into(),
+        format_datetime(item.last_seen).into(),
+        item.count.into(),
+        item.total_time.into(),
+        item.mean_time.into(),
+        item.p95.into(),
+        item.total_lock_time.into(),
+        item.rows_sent.into(),
+        item.rows_examined.into(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pt_dsn() {
+        let dsn = PtDsn::parse("h=db1,P=3307,u=dba,p=s3cret,D=percona,t=query_review").unwrap();
+        assert_eq!(dsn.host.as_deref(), Some("db1"));
+        assert_eq!(dsn.port, Some(3307));
+        assert_eq!(dsn.table_name("query_review"), "`percona`.`query_review`");
+
+        assert_eq!(PtDsn::parse("D=x").unwrap().table_name("query_history"), "`x`.`query_history`");
+        assert!(PtDsn::parse("h=db1,X=1").is_err());
+        assert!(PtDsn::parse("h").is_err());
+        assert!(PtDsn::parse("P=abc").is_err());
+    }
+
+    #[test]
+    fn test_pt_checksum() {
+        // pt-query-digest stores CONV(RIGHT(MD5(fingerprint), 16), 16, 10)
+        let query_id = crate::report::query_id("select * from t where id = ?");
+        assert_eq!(query_id, "86a4fd498c70095e35fc3906162972da");
+        assert_eq!(pt_checksum(&query_id), 3890046876445668058);
+        assert_eq!(pt_checksum("0123456789abcdef00000000000000ff"), 255);
+        assert_eq!(pt_checksum("0123456789abcdefffffffffffffffff"), u64::MAX);
+    }
+
+    #[test]
+    fn test_history_statements() {
+        let table = PtDsn::parse("D=metrics,t=slow`history").unwrap().table_name("query_history");
+        assert_eq!(table, "`metrics`.`slow``history`");
+
+        let schema = history_schema(&table);
+        assert!(schema.starts_with("CREATE TABLE IF NOT EXISTS `metrics`.`slow``history` ("));
+        let defined: Vec<&str> = schema.lines().skip(1).filter_map(|line| line.split_whitespace().next()).filter(|column| *column != "PRIMARY" && *column != ")").collect();
+        assert_eq!(defined, HISTORY_COLUMNS);
+        assert!(schema.contains("PRIMARY KEY (checksum, run_at)"));
+
+        assert_eq!(
+            history_insert("`percona`.`query_history`"),
+            "REPLACE INTO `percona`.`query_history` (checksum, run_at, sample, ts_min, ts_max, ts_cnt, Query_time_sum, Query_time_mean, Query_time_pct_95, Lock_time_sum, Rows_sent_sum, Rows_examined_sum) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        );
+    }
+
+    #[test]
+    fn test_history_row() {
+        let mut digest = crate::digest::Digest::new(crate::digest::DigestConfig::default());
+        digest.add_reader(&b"# Time: 2024-05-01T10:00:00Z\n# User@Host: app[app] @ localhost []\n# Query_time: 2.0  Lock_time: 0.5 Rows_sent: 1  Rows_examined: 10\nSELECT * FROM t WHERE id = 1;\n"[..]);
+        let item = &digest.finish()[0];
+        let row = history_row(item, &Some("2024-05-02 00:00:00".to_string()));
+        assert_eq!(row.len(), HISTORY_COLUMNS.len());
+        assert_eq!(row[0], pt_checksum(&item.query_id).into());
+        assert_eq!(row[1], "2024-05-02 00:00:00".into());
+        assert_eq!(row[2], "SELECT * FROM t WHERE id = 1;".into());
+        assert_eq!(row[3], "2024-05-01 10:00:00".into());
+        assert_eq!(row[5..], [1u64.into(), 2.0.into(), 2.0.into(), 2.0.into(), 0.5.into(), 1u64.into(), 10u64.into()]);
+    }
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("we`ird"), "`we``ird`");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::digest::Digest;
+use crate::queue::QueueMonitor;
+use crate::report::{self, HtmlRefresh, OutputFormat, ReportItem};
+use anyhow::anyhow;
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const INDEX_HTML: &str = include_str!("serve_index.html");
+/// How often `/report.html` checks the totals for new data.
+const REFRESH_SECONDS: u64 = 5;
+
+/// Serves the in-memory digest over HTTP until the process exits.
+///
+/// Endpoints:
+/// - `GET /` - interactive web UI
+/// - `GET /report.html?limit=<n>` - the HTML report of the top `n` (default 20)
+///   queries, reloading itself when the totals change
+/// - `GET /api/summary` - overall totals, and the metrics of the queue of
+///   followed inputs, if any
+/// - `GET /api/queries?q=<text>&limit=<n>` - ranked report items, optionally
+///   filtered by a case-insensitive substring of the fingerprint
+/// - `GET /api/queries/<query_id>` - a single report item
+pub fn serve(addr: &str, digest: Arc<Mutex<Digest>>, queue: Option<QueueMonitor>) -> anyhow::Result<()> {
+    let server = Server::http(addr).map_err(|e| anyhow!("Could not listen on {}: {}", addr, e))?;
+    tracing::info!("Serving on http://{}/", server.server_addr());
+
+    for request in server.incoming_requests() {
+        let response = handle(&request, &digest, queue.as_ref());
+        if let Err(e) = request.respond(response) {
+            tracing::warn!("Could not send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn handle(request: &Request, digest: &Mutex<Digest>, queue: Option<&QueueMonitor>) -> Response<std::io::Cursor<Vec<u8>>> {
+    if request.method() != &Method::Get {
+        return text_response(405, "Method Not Allowed");
+    }
+
+    let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+    match path {
+        "/" | "/index.html" => with_content_type(Response::from_string(INDEX_HTML), "text/html; charset=utf-8"),
+        "/report.html" => {
+            let limit: usize = query_param(query, "limit").and_then(|l| l.parse().ok()).unwrap_or(20);
+            let (mut items, mut options) = {
+                let digest = digest.lock().unwrap();
+                (digest.report(), digest.render_options())
+            };
+            if limit > 0 {
+                items.truncate(limit);
+            }
+            options.detail_limit = limit;
+            options.refresh = Some(HtmlRefresh::Poll { url: "/api/summary".to_string(), seconds: REFRESH_SECONDS });
+            match report::render_report(&items, &OutputFormat::Html, &options) {
+                Ok(html) => with_content_type(Response::from_string(html), "text/html; charset=utf-8"),
+                Err(e) => text_response(500, &e.to_string()),
+            }
+        }
+        "/api/summary" => {
+            let digest = digest.lock().unwrap();
+            let (total_count, total_time) = digest.totals();
+            let mut summary = serde_json::json!({
+                "total_count": total_count,
+                "total_time": total_time,
+                "fingerprints": digest.stats().len(),
+            });
+            if let Some(queue) = queue {
+                summary["queue"] = serde_json::json!(queue.metrics());
+            }
+            json_response(&summary)
+        }
+        "/api/queries" => {
+            let items = digest.lock().unwrap().report();
+            let needle = query_param(query, "q").unwrap_or_default().to_lowercase();
+            let limit: usize = query_param(query, "limit").and_then(|l| l.parse().ok()).unwrap_or(0);
+
+            let matching: Vec<&ReportItem> = items
+                .iter()
+                .filter(|item| needle.is_empty() || item.normalized_query.to_lowercase().contains(&needle) || item.query_id.starts_with(&needle))
+                .take(if limit == 0 { usize::MAX } else { limit })
+                .collect();
+            json_response(&matching)
+        }
+        _ => match path.strip_prefix("/api/queries/") {
+            Some(id) => {
+                let items = digest.lock().unwrap().report();
+                match items.iter().find(|item| item.query_id == id) {
+                    Some(item) => json_response(item),
+                    None => text_response(404, "Not Found"),
+                }
+            }
+            None => text_response(404, "Not Found"),
+        },
+    }
+}
+
+/// Extracts and percent-decodes a query string parameter.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| percent_decode(value))
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok());
+                match hex {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn json_response<T: serde::Serialize + ?Sized>(value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    match serde_json::to_vec(value) {
+        Ok(body) => with_content_type(Response::from_data(body), "application/json"),
+        Err(_) => text_response(500, "Internal Server Error"),
+    }
+}
+
+fn text_response(status: u16, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    with_content_type(Response::from_string(body), "text/plain; charset=utf-8").with_status_code(status)
+}
+
+fn with_content_type(response: Response<std::io::Cursor<Vec<u8>>>, content_type: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    response.with_header(Header::from_bytes("Content-Type", content_type).expect("valid header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param("q=select%20from+t&limit=5", "q").as_deref(), Some("select from t"));
+        assert_eq!(query_param("q=select&limit=5", "limit").as_deref(), Some("5"));
+        assert_eq!(query_param("q=100%", "q").as_deref(), Some("100%"));
+        assert_eq!(query_param("q=x", "missing"), None);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::audit::{build_query, parse_time};
+use crate::parser::{Query, QueryReader, RejectedBlock};
+use crate::timezone::Timezone;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+/// Parses the query history of SingleStore (formerly MemSQL): the
+/// `Query_completion` events of `information_schema.MV_TRACE_EVENTS`.
+///
+/// Two layouts are read, told apart per record:
+///
+/// * JSON, one event per line, either the `DETAILS` object itself or a row
+///   with `TYPE`, `TIME` and `DETAILS` (as an object or a JSON string).
+/// * Text, one `Field: value` line per field, as printed by the SingleStore
+///   client with `\G`; records are separated by blank lines or
+///   `*** N. row ***` lines, and a line without a field name continues the
+///   previous value.
+///
+/// Field names are case-insensitive. The query time is read from
+/// `duration_ms` (or `elapsed_time_ms`), the statement from `query_text`, the
+/// account from `user_name` and `host`, and the database from `database`.
+pub struct SingleStoreParser<R> {
+    reader: R,
+    line: String,
+    /// Lines of the text record being read.
+    record: String,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    log_timezone: Timezone,
+    lines_read: u64,
+    /// Line the record being read starts on.
+    record_line: u64,
+    rejected: Option<Vec<RejectedBlock>>,
+}
+
+impl<R: BufRead> SingleStoreParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            record: String::new(),
+            bytes_read: 0,
+            skipped_blocks: 0,
+            log_timezone: Timezone::default(),
+            lines_read: 0,
+            record_line: 0,
+            rejected: None,
+        }
+    }
+
+    /// Sets the timezone that event times are logged in (UTC by default).
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Reads up to the end of the next record and returns its line and text.
+    fn next_record(&mut self) -> Option<Result<(u64, String)>> {
+        loop {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) if self.record.is_empty() => return None,
+                Ok(0) => return Some(Ok((self.record_line, std::mem::take(&mut self.record)))),
+                Ok(n) => {
+                    self.bytes_read += n as u64;
+                    self.lines_read += 1;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+            let trimmed = self.line.trim();
+            if self.record.is_empty() && trimmed.starts_with('{') {
+                return Some(Ok((self.lines_read, trimmed.to_string())));
+            }
+            if trimmed.is_empty() || is_row_separator(trimmed) {
+                if !self.record.is_empty() {
+                    return Some(Ok((self.record_line, std::mem::take(&mut self.record))));
+                }
+                continue;
+            }
+            if self.record.is_empty() {
+                self.record_line = self.lines_read;
+            }
+            self.record.push_str(&self.line);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for SingleStoreParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, record) = match self.next_record()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            let fields = if record.starts_with('{') { json_fields(&record) } else { Ok(text_fields(&record)) };
+            match fields.and_then(|fields| event_query(&fields, self.log_timezone)) {
+                Ok(Some(query)) => return Some(Ok(query)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.skipped_blocks += 1;
+                    tracing::debug!("Skipping invalid SingleStore event: {:#}", e);
+                    if let Some(rejected) = &mut self.rejected {
+                        rejected.push(RejectedBlock { line, reason: format!("{:#}", e), raw: record });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> QueryReader for SingleStoreParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+
+    fn keep_rejected(&mut self) {
+        self.rejected.get_or_insert_with(Vec::new);
+    }
+
+    fn take_rejected(&mut self) -> Vec<RejectedBlock> {
+        self.rejected.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+/// Returns true for the `*************************** 1. row ***` lines of `\G` output.
+fn is_row_separator(line: &str) -> bool {
+    line.starts_with("***") && line.ends_with("***")
+}
+
+/// Returns the fields of a JSON event, with the `DETAILS` of a trace event row
+/// merged in, keyed by lowercase name.
+fn json_fields(record: &str) -> Result<HashMap<String, String>> {
+    let value: Value = serde_json::from_str(record)?;
+    let Value::Object(object) = value else {
+        return Err(anyhow!("Expected a JSON object"));
+    };
+    let mut fields = HashMap::new();
+    for (key, value) in object {
+        let key = key.to_ascii_lowercase();
+        match value {
+            Value::Object(details) if key == "details" => {
+                fields.extend(details.into_iter().map(|(key, value)| (key.to_ascii_lowercase(), json_text(value))));
+            }
+            value => {
+                fields.insert(key, json_text(value));
+            }
+        }
+    }
+    if let Some(details) = fields.remove("details") {
+        let details = json_fields(&details).map_err(|e| anyhow!("Invalid DETAILS: {}", e))?;
+        fields.extend(details);
+    }
+    Ok(fields)
+}
+
+fn json_text(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Returns the fields of a text record. `DETAILS` holding JSON is merged in.
+fn text_fields(record: &str) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut last: Option<String> = None;
+    for line in record.lines() {
+        let field = line.split_once(':').filter(|(name, _)| {
+            let name = name.trim();
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        });
+        match (field, &last) {
+            (Some((name, value)), _) => {
+                let name = name.trim().to_ascii_lowercase();
+                fields.insert(name.clone(), value.trim().to_string());
+                last = Some(name);
+            }
+            (None, Some(name)) => {
+                let value = fields.entry(name.clone()).or_default();
+                value.push('\n');
+                value.push_str(line.trim_end());
+            }
+            (None, None) => {}
+        }
+    }
+    if let Some(details) = fields.get("details").filter(|details| details.starts_with('{')).cloned() {
+        if let Ok(details) = json_fields(&details) {
+            fields.remove("details");
+            fields.extend(details);
+        }
+    }
+    fields
+}
+
+/// Builds the query of an event, or returns `None` for trace events other than
+/// `Query_completion`.
+fn event_query(fields: &HashMap<String, String>, log_timezone: Timezone) -> Result<Option<Query>> {
+    let field = |names: &[&str]| names.iter().find_map(|name| fields.get(*name).filter(|v| !v.is_empty()));
+    if field(&["type"]).is_some_and(|kind| !kind.eq_ignore_ascii_case("query_completion")) {
+        return Ok(None);
+    }
+    let sql = field(&["query_text", "query"]).ok_or_else(|| anyhow!("No query_text"))?;
+
+    let mut statistics = HashMap::new();
+    let seconds = match (field(&["duration_ms", "elapsed_time_ms"]), field(&["query_time", "duration"])) {
+        (Some(ms), _) => ms.parse::<f64>().map(|ms| ms / 1000.0).map_err(|_| anyhow!("Invalid duration_ms {:?}", ms))?,
+        (None, Some(seconds)) => seconds.parse().map_err(|_| anyhow!("Invalid query time {:?}", seconds))?,
+        (None, None) => 0.0,
+    };
+    statistics.insert("QUERY_TIME".to_string(), seconds.to_string());
+    for (key, names) in [("ROWS_SENT", &["rows_sent", "result_rows"][..]), ("ROWS_EXAMINED", &["rows_examined"][..])] {
+        if let Some(value) = field(names) {
+            statistics.insert(key.to_string(), value.clone());
+        }
+    }
+
+    let user = field(&["user_name", "user"]).map_or("", String::as_str);
+    let host = field(&["host"]).map_or("", String::as_str);
+    let timestamp = field(&["start_time", "time", "end_time"]).and_then(|ts| parse_time(ts, log_timezone));
+    let thread_id = field(&["connection_id"]).and_then(|id| id.parse().ok());
+    Ok(build_query(sql.clone(), field(&["database", "db"]).map(String::as_str), format!("{}[{}] @ {} []", user, user, host), timestamp, thread_id, &statistics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_events() {
+        let log = r#"{"ID":41,"TYPE":"Query_completion","TIME":"2024-05-01 10:00:00.250","DETAILS":"{\"connection_id\":\"12\",\"database\":\"analytics\",\"duration_ms\":2500,\"query_text\":\"SELECT COUNT(*) FROM events WHERE day = '2024-05-01'\",\"start_time\":\"2024-05-01 09:59:57.750\",\"user_name\":\"looker\"}"}
+{"ID":42,"TYPE":"Memory_usage","TIME":"2024-05-01 10:00:01","DETAILS":"{}"}
+{"activity_name":"Select_t","duration_ms":120,"query_text":"select 1","user_name":"app","host":"10.0.0.5"}
+{"TYPE":"Query_completion","DETAILS":"not json"}
+"#;
+        let mut parser = SingleStoreParser::new(log.as_bytes());
+        parser.keep_rejected();
+        let queries: Vec<Query> = parser.by_ref().flatten().collect();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].sql_text, "use analytics;\nSELECT COUNT(*) FROM events WHERE day = '2024-05-01'");
+        assert_eq!(queries[0].query_time, 2.5);
+        assert_eq!(queries[0].user_host, "looker[looker] @  []");
+        assert_eq!(queries[0].thread_id, Some(12));
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T09:59:57.750Z".parse().unwrap()));
+        assert_eq!(queries[1].user_host, "app[app] @ 10.0.0.5 []");
+        assert_eq!(queries[1].query_time, 0.12);
+        assert_eq!(parser.skipped_blocks(), 1);
+        assert_eq!(parser.take_rejected()[0].line, 4);
+        assert_eq!(parser.bytes_read(), log.len() as u64);
+    }
+
+    #[test]
+    fn test_vertical_text() {
+        let log = "*************************** 1. row ***************************
+     ID: 7
+   TYPE: Query_completion
+   TIME: 2024-05-01 10:00:00
+DETAILS: {\"database\":\"shop\",\"duration_ms\":\"800\",\"query_text\":\"SELECT 1\",\"user_name\":\"app\"}
+*************************** 2. row ***************************
+CONNECTION_ID: 9
+USER_NAME: etl
+START_TIME: 2024-05-01 10:00:05
+DURATION_MS: 1500
+QUERY_TEXT: UPDATE t
+  SET a = 1
+  WHERE id = 2
+
+USER_NAME: nobody
+";
+        let mut parser = SingleStoreParser::new(log.as_bytes());
+        let queries: Vec<Query> = parser.by_ref().flatten().collect();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].sql_text, "use shop;\nSELECT 1");
+        assert_eq!(queries[0].query_time, 0.8);
+        assert_eq!(queries[0].timestamp, Some("2024-05-01T10:00:00Z".parse().unwrap()));
+        assert_eq!(queries[1].sql_text, "UPDATE t\n  SET a = 1\n  WHERE id = 2");
+        assert_eq!(queries[1].thread_id, Some(9));
+        assert_eq!(queries[1].query_time, 1.5);
+        assert_eq!(parser.skipped_blocks(), 1);
+    }
+}
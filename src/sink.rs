@@ -0,0 +1,107 @@
+use crate::aggregator::QueryStats;
+use crate::parser::{Query, RejectedBlock};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Receives events from the digest pipeline as they happen.
+///
+/// All methods default to doing nothing, so implementors only override the
+/// events they care about. `key` is the aggregation key (the fingerprint by default).
+pub trait QuerySink {
+    /// Called for every parsed query that passes the filter.
+    fn on_query(&mut self, _key: &str, _query: &Query) {}
+
+    /// Called once per key with the finalized statistics.
+    fn on_stats(&mut self, _key: &str, _stats: &QueryStats) {}
+
+    /// Called for every log block the parser skipped.
+    fn on_rejected(&mut self, _block: &RejectedBlock) {}
+}
+
+/// Adapts a closure into a sink that only observes queries.
+pub struct QueryFn<F>(pub F);
+
+impl<F: FnMut(&str, &Query)> QuerySink for QueryFn<F> {
+    fn on_query(&mut self, key: &str, query: &Query) {
+        (self.0)(key, query)
+    }
+}
+
+/// Collects every query with its aggregation key.
+///
+/// Each event is a full clone of the parsed [`Query`], kept in log order for
+/// exporters that ship individual executions (Elasticsearch, ClickHouse), so
+/// memory grows with the log. The list sits behind a shared handle; a clone
+/// given to [`Digest::with_sink`](crate::digest::Digest::with_sink) fills the
+/// list that [`take`](Self::take) drains.
+#[derive(Clone, Default)]
+pub struct EventCollector(Arc<Mutex<Vec<(String, Query)>>>);
+
+impl EventCollector {
+    /// Takes the collected events out of the collector, in the order they were logged.
+    pub fn take(&self) -> Vec<(String, Query)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl QuerySink for EventCollector {
+    fn on_query(&mut self, key: &str, query: &Query) {
+        self.0.lock().unwrap().push((key.to_string(), query.clone()));
+    }
+}
+
+/// Writes the log blocks the parser skipped to a side file, each after a
+/// `### <input>:<line>: <reason>` line, as material for parser bug reports.
+///
+/// A block is written as soon as the parser rejects it, under the input name
+/// last given to [`set_input`](Quarantine::set_input). The file and the block
+/// count are shared between clones. A write error is logged once and disables
+/// the quarantine, without failing the run.
+#[derive(Clone)]
+pub struct Quarantine(Arc<Mutex<QuarantineState>>);
+
+struct QuarantineState {
+    writer: Option<Box<dyn Write + Send>>,
+    input: String,
+    count: u64,
+}
+
+impl Quarantine {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(QuarantineState { writer: Some(Box::new(writer)), input: "-".to_string(), count: 0 })))
+    }
+
+    /// Names the input the blocks passed from now on come from.
+    pub fn set_input(&self, name: &str) {
+        self.0.lock().unwrap().input = name.to_string();
+    }
+
+    /// Returns the number of blocks written.
+    pub fn count(&self) -> u64 {
+        self.0.lock().unwrap().count
+    }
+
+    /// Flushes the writer.
+    pub fn flush(&self) -> std::io::Result<()> {
+        match &mut self.0.lock().unwrap().writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl QuerySink for Quarantine {
+    fn on_rejected(&mut self, block: &RejectedBlock) {
+        let mut state = self.0.lock().unwrap();
+        let state = &mut *state;
+        let Some(writer) = &mut state.writer else { return };
+        let newline = if block.raw.ends_with('\n') { "" } else { "\n" };
+        match write!(writer, "### {}:{}: {}\n{}{}", state.input, block.line, block.reason, block.raw, newline) {
+            Ok(()) => state.count += 1,
+            Err(e) => {
+                tracing::warn!("Could not write to the quarantine file, no longer writing to it: {}", e);
+                state.writer = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::aggregator::QueryStats;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Version written into new snapshots.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Borrowing counterpart of `Snapshot`, so statistics can be saved without cloning.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    created_at: DateTime<Utc>,
+    stats: &'a HashMap<String, QueryStats>,
+}
+
+/// Aggregated statistics saved to disk so runs can be merged or compared later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    /// Statistics keyed by fingerprint (or whatever key the run grouped by).
+    pub stats: HashMap<String, QueryStats>,
+}
+
+impl Snapshot {
+    pub fn new(stats: HashMap<String, QueryStats>) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            created_at: Utc::now(),
+            stats,
+        }
+    }
+
+    /// Writes the snapshot as JSON.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        write(path, self.created_at, &self.stats)
+    }
+
+    /// Reads a snapshot written by `save`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path).with_context(|| format!("Could not open {:?}", path))?;
+        let snapshot: Snapshot = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Invalid snapshot {:?}", path))?;
+        if snapshot.version > SNAPSHOT_VERSION {
+            bail!("Snapshot {:?} has unsupported version {}", path, snapshot.version);
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Saves statistics as a new snapshot.
+pub fn save_stats(path: &Path, stats: &HashMap<String, QueryStats>) -> anyhow::Result<()> {
+    write(path, Utc::now(), stats)
+}
+
+fn write(path: &Path, created_at: DateTime<Utc>, stats: &HashMap<String, QueryStats>) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path).with_context(|| format!("Could not create {:?}", path))?;
+    let snapshot = SnapshotRef { version: SNAPSHOT_VERSION, created_at, stats };
+    serde_json::to_writer(std::io::BufWriter::new(file), &snapshot)?;
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use crate::aggregator::{self, QueryStats};
+use anyhow::Context;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of partition files statistics are spread over.
+pub const DEFAULT_PARTITIONS: usize = 64;
+
+/// Aggregated statistics spilled to partitioned files in a temporary directory.
+///
+/// Every key always goes to the same partition, so each partition can be merged
+/// on its own and only one partition has to fit in memory at a time. The
+/// directory is removed when the store is dropped.
+pub struct SpillStore {
+    dir: PathBuf,
+    partitions: Vec<BufWriter<File>>,
+    spills: usize,
+    totals: (u64, f64),
+}
+
+impl SpillStore {
+    /// Creates a fresh directory for the partition files under `parent`.
+    pub fn create(parent: &Path, partitions: usize) -> anyhow::Result<Self> {
+        let dir = parent.join(format!("rs-slowquery-digest-{}-{}", std::process::id(), chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()));
+        std::fs::create_dir_all(&dir).with_context(|| format!("Could not create spill directory {:?}", dir))?;
+        let mut store = Self { dir, partitions: Vec::with_capacity(partitions), spills: 0, totals: (0, 0.0) };
+        for i in 0..partitions.max(1) {
+            let path = store.partition_path(i);
+            let file = File::create(&path).with_context(|| format!("Could not create {:?}", path))?;
+            store.partitions.push(BufWriter::new(file));
+        }
+        Ok(store)
+    }
+
+    fn partition_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("part-{:04}.jsonl", index))
+    }
+
+    /// Returns the directory holding the partition files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns how many times statistics were spilled.
+    pub fn spills(&self) -> usize {
+        self.spills
+    }
+
+    /// Returns the event count and total query time of everything spilled.
+    pub fn totals(&self) -> (u64, f64) {
+        self.totals
+    }
+
+    /// Appends the statistics to their partitions as JSON lines.
+    pub fn spill(&mut self, stats: HashMap<String, QueryStats>) -> anyhow::Result<()> {
+        let (count, time) = aggregator::totals(&stats);
+        let partitions = self.partitions.len();
+        for (key, stats) in stats {
+            let writer = &mut self.partitions[partition_of(&key, partitions)];
+            serde_json::to_writer(&mut *writer, &(key, stats))?;
+            writer.write_all(b"\n")?;
+        }
+        self.spills += 1;
+        self.totals = (self.totals.0 + count, self.totals.1 + time);
+        Ok(())
+    }
+
+    /// Merges each partition in turn, together with the entries of `remaining`
+    /// that belong to it, and passes the merged statistics to `visit`.
+    pub fn merge(mut self, mut remaining: HashMap<String, QueryStats>, mut visit: impl FnMut(HashMap<String, QueryStats>)) -> anyhow::Result<()> {
+        let partitions = self.partitions.len();
+        for writer in &mut self.partitions {
+            writer.flush()?;
+        }
+        for index in 0..partitions {
+            let path = self.partition_path(index);
+            let mut merged: HashMap<String, QueryStats> = HashMap::new();
+            let reader = BufReader::new(File::open(&path).with_context(|| format!("Could not open {:?}", path))?);
+            for line in reader.lines() {
+                let (key, stats): (String, QueryStats) = serde_json::from_str(&line?).with_context(|| format!("Corrupt spill file {:?}", path))?;
+                aggregator::merge_entry(&mut merged, key, stats);
+            }
+            let mine: Vec<String> = remaining.keys().filter(|key| partition_of(key, partitions) == index).cloned().collect();
+            let mine = mine.into_iter().filter_map(|key| remaining.remove_entry(&key)).collect();
+            aggregator::merge_stats(&mut merged, mine);
+            std::fs::remove_file(&path).ok();
+            visit(merged);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpillStore {
+    fn drop(&mut self) {
+        self.partitions.clear();
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn partition_of(key: &str, partitions: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % partitions as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Query;
+
+    fn stats_for(sql: &str, times: &[f64]) -> HashMap<String, QueryStats> {
+        let mut stats = HashMap::new();
+        for &query_time in times {
+            let query = Query { query_time, lock_time: 0.0, rows_sent: 1, rows_examined: 1, timestamp: None, user_host: String::new(), sql_text: sql.to_string(), ..Default::default() };
+            aggregator::add_query(&mut stats, sql.to_string(), query);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_spill_and_merge() {
+        let mut store = SpillStore::create(&std::env::temp_dir(), 4).unwrap();
+        let dir = store.dir().to_path_buf();
+        store.spill(stats_for("a", &[1.0, 2.0])).unwrap();
+        store.spill(stats_for("b", &[5.0])).unwrap();
+        store.spill(stats_for("a", &[3.0])).unwrap();
+        assert_eq!(store.totals(), (4, 11.0));
+
+        let mut merged = HashMap::new();
+        store.merge(stats_for("a", &[4.0]), |part| merged.extend(part)).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged["a"].count, 4);
+        assert_eq!(merged["a"].total_time, 10.0);
+        assert_eq!(merged["a"].all_query_times.len(), 4);
+        assert_eq!(merged["b"].max_time, 5.0);
+        assert!(!dir.exists());
+    }
+}
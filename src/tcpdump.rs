@@ -0,0 +1,697 @@
+use crate::audit::build_query;
+use crate::parser::{Query, QueryReader};
+use crate::timezone::Timezone;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Port MySQL listens on. On connections to other ports, the end with the
+/// lower port is taken to be the server, as clients use ephemeral ports.
+const MYSQL_PORT: u16 = 3306;
+
+/// Returns whether `head`, the start of a file, is a pcap or pcapng capture.
+pub fn is_pcap(head: &[u8]) -> bool {
+    matches!(head.get(..4), Some([0xd4, 0xc3, 0xb2, 0xa1] | [0xa1, 0xb2, 0xc3, 0xd4] | [0x4d, 0x3c, 0xb2, 0xa1] | [0xa1, 0xb2, 0x3c, 0x4d] | [0x0a, 0x0d, 0x0d, 0x0a]))
+}
+
+/// Connections followed at once; packets of further ones are ignored with a warning.
+pub const MAX_CONNECTIONS: usize = 100_000;
+
+/// Client bytes buffered for one packet; a longer packet is dropped.
+const MAX_PACKET: usize = 16 << 20;
+
+const COM_INIT_DB: u8 = 0x02;
+const COM_QUERY: u8 = 0x03;
+
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_SSL: u32 = 0x0000_0800;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x0020_0000;
+
+/// Reads the queries of MySQL protocol traffic captured with tcpdump, like
+/// pt-query-digest's `--type tcpdump`.
+///
+/// The capture is either a pcap file (`tcpdump -w`), or the text tcpdump
+/// prints with `-x -tttt`, where each packet is a line starting with the date
+/// and time followed by a hex dump of its IP datagram:
+///
+/// ```text
+/// tcpdump -i any -s 65535 -x -nn -q -tttt 'port 3306' > mysql.tcp.txt
+/// ```
+///
+/// Client packets are put back together per connection to read `COM_QUERY`
+/// statements; the query time runs from the query to the last packet of the
+/// server's response, and the query is logged at the end of it. An error
+/// response sets the error number, an OK packet the affected rows. The user,
+/// the database and the connection ID are read from the handshake and
+/// `COM_INIT_DB` of connections that start during the capture.
+///
+/// Encrypted connections, compressed protocol, and prepared statements
+/// (`COM_STMT_EXECUTE`) cannot be read and are left out.
+pub struct TcpdumpParser<R> {
+    reader: R,
+    /// The capture format, once the start of the input was seen.
+    capture: Option<Capture>,
+    /// Time and hex dump lines of the text packet being read.
+    text_packet: Option<(DateTime<Utc>, Vec<u8>)>,
+    line: String,
+    connections: HashMap<(Endpoint, Endpoint), Connection>,
+    ready: VecDeque<Query>,
+    bytes_read: u64,
+    skipped_blocks: u64,
+    omitted: u64,
+    log_timezone: Timezone,
+    done: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Capture {
+    Pcap { big_endian: bool, nanos: bool, link_type: u32 },
+    Text,
+}
+
+type Endpoint = (IpAddr, u16);
+
+/// What is known about one client connection.
+#[derive(Debug, Default)]
+struct Connection {
+    /// Client bytes that do not make up a whole packet yet.
+    buffer: Vec<u8>,
+    /// TCP sequence number of the next client byte, once seen.
+    next_seq: Option<u32>,
+    user: String,
+    db: Option<String>,
+    thread_id: Option<u64>,
+    /// The server greeted the client, so its next packet is the handshake response.
+    greeted: bool,
+    /// The connection switched to TLS, so nothing more can be read.
+    encrypted: bool,
+    pending: Option<Pending>,
+}
+
+/// A query waiting for the end of its response.
+#[derive(Debug)]
+struct Pending {
+    sql: String,
+    start: DateTime<Utc>,
+    /// Time of the last response packet so far.
+    end: Option<DateTime<Utc>>,
+    errno: Option<u32>,
+    rows_affected: Option<u64>,
+}
+
+/// The parts of a TCP segment needed to follow a connection.
+struct Segment<'a> {
+    src: Endpoint,
+    dst: Endpoint,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+impl<R: BufRead> TcpdumpParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            capture: None,
+            text_packet: None,
+            line: String::new(),
+            connections: HashMap::new(),
+            ready: VecDeque::new(),
+            bytes_read: 0,
+            skipped_blocks: 0,
+            omitted: 0,
+            log_timezone: Timezone::default(),
+            done: false,
+        }
+    }
+
+    /// Sets the timezone of the times in tcpdump text output (UTC by default).
+    /// Pcap files store UTC times.
+    pub fn with_log_timezone(mut self, timezone: Timezone) -> Self {
+        self.log_timezone = timezone;
+        self
+    }
+
+    /// Reads the next packet as its capture time and IP datagram.
+    fn next_packet(&mut self) -> Result<Option<(DateTime<Utc>, Vec<u8>)>> {
+        let capture = match self.capture {
+            Some(capture) => capture,
+            None => {
+                let capture = self.read_capture_header()?;
+                self.capture = Some(capture);
+                capture
+            }
+        };
+        match capture {
+            Capture::Pcap { big_endian, nanos, link_type } => loop {
+                let mut header = [0u8; 16];
+                if !self.read_exact_or_eof(&mut header)? {
+                    return Ok(None);
+                }
+                let field = |i: usize| {
+                    let bytes = [header[i], header[i + 1], header[i + 2], header[i + 3]];
+                    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+                };
+                let length = field(8) as usize;
+                if length > MAX_PACKET {
+                    bail!("Invalid pcap record of {} bytes", length);
+                }
+                let mut frame = vec![0u8; length];
+                if !self.read_exact_or_eof(&mut frame)? {
+                    bail!("The pcap file ends within a packet");
+                }
+                let nanos = if nanos { field(4) } else { field(4).saturating_mul(1000) };
+                let time = DateTime::from_timestamp(field(0) as i64, nanos).unwrap_or_default();
+                match link_payload(link_type, &frame) {
+                    Some(ip) => return Ok(Some((time, ip.to_vec()))),
+                    None => self.skipped_blocks += 1,
+                }
+            },
+            Capture::Text => loop {
+                self.line.clear();
+                let n = self.reader.read_line(&mut self.line)?;
+                self.bytes_read += n as u64;
+                if n == 0 {
+                    return Ok(self.text_packet.take());
+                }
+                let line = self.line.trim_end();
+                if line.starts_with(char::is_whitespace) {
+                    // `0x0010:  4500 0034 ...`, with `-X` followed by two spaces and ASCII
+                    let Some((_, hex)) = line.trim_start().split_once(':') else { continue };
+                    let hex = hex.trim_start();
+                    let hex = hex.split_once("  ").map_or(hex, |(hex, _)| hex);
+                    if let Some((_, bytes)) = &mut self.text_packet {
+                        if !decode_hex(hex, bytes) {
+                            self.text_packet = None;
+                            self.skipped_blocks += 1;
+                        }
+                    }
+                    continue;
+                }
+                let time = parse_text_time(line, self.log_timezone);
+                if time.is_none() && !line.is_empty() {
+                    self.skipped_blocks += 1;
+                }
+                let completed = std::mem::replace(&mut self.text_packet, time.map(|time| (time, Vec::new())));
+                if completed.is_some() {
+                    return Ok(completed);
+                }
+            },
+        }
+    }
+
+    /// Tells pcap files from text by their start, reading the pcap file header.
+    fn read_capture_header(&mut self) -> Result<Capture> {
+        let head = self.reader.fill_buf()?;
+        let magic: [u8; 4] = match head.get(..4) {
+            Some(magic) => magic.try_into().unwrap(),
+            None => return Ok(Capture::Text),
+        };
+        let (big_endian, nanos) = match magic {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            [0x0a, 0x0d, 0x0d, 0x0a] => bail!("pcapng captures are not supported; write pcap with `tcpdump -w` or convert with `editcap -F pcap`"),
+            _ => return Ok(Capture::Text),
+        };
+        let mut header = [0u8; 24];
+        if !self.read_exact_or_eof(&mut header)? {
+            bail!("The pcap file header is cut off");
+        }
+        let bytes = [header[20], header[21], header[22], header[23]];
+        let link_type = if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) };
+        Ok(Capture::Pcap { big_endian, nanos, link_type: link_type & 0x0fff_ffff })
+    }
+
+    /// Fills `buf`, or returns false at the end of the input.
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                if filled == 0 {
+                    return Ok(false);
+                }
+                bail!("The capture ends within a record");
+            }
+            let n = available.len().min(buf.len() - filled);
+            buf[filled..filled + n].copy_from_slice(&available[..n]);
+            self.reader.consume(n);
+            filled += n;
+        }
+        self.bytes_read += buf.len() as u64;
+        Ok(true)
+    }
+
+    /// Follows the connection a packet belongs to.
+    fn add_packet(&mut self, time: DateTime<Utc>, ip: &[u8]) {
+        let Some(segment) = tcp_segment(ip) else {
+            self.skipped_blocks += 1;
+            return;
+        };
+        let from_client = is_server(segment.dst, segment.src);
+        let key = if from_client { (segment.src, segment.dst) } else { (segment.dst, segment.src) };
+        if !self.connections.contains_key(&key) {
+            if self.connections.len() >= MAX_CONNECTIONS {
+                if self.omitted == 0 {
+                    tracing::warn!("More than {} connections; packets of later ones are ignored", MAX_CONNECTIONS);
+                }
+                self.omitted += 1;
+                return;
+            }
+            self.connections.insert(key, Connection::default());
+        }
+        if segment.flags & (TCP_FIN | TCP_RST) != 0 && segment.payload.is_empty() {
+            let mut connection = self.connections.remove(&key).unwrap();
+            self.ready.extend(connection.finish(key.0.0));
+            return;
+        }
+        let connection = self.connections.get_mut(&key).unwrap();
+        if from_client {
+            if segment.flags & TCP_SYN != 0 {
+                *connection = Connection { next_seq: Some(segment.seq.wrapping_add(1)), ..Default::default() };
+                return;
+            }
+            for packet in connection.reassemble(segment.seq, segment.payload) {
+                if let Some(query) = connection.client_packet(&packet, time, key.0.0) {
+                    self.ready.push_back(query);
+                }
+            }
+        } else if !segment.payload.is_empty() {
+            connection.server_segment(segment.payload, time);
+        }
+    }
+}
+
+impl Connection {
+    /// Adds client bytes in TCP order and returns the whole packets (sequence
+    /// ID and payload) they complete.
+    fn reassemble(&mut self, seq: u32, payload: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        if payload.is_empty() || self.encrypted {
+            return Vec::new();
+        }
+        match self.next_seq {
+            // A retransmission of bytes already seen
+            Some(next) if (seq.wrapping_sub(next) as i32) < 0 => return Vec::new(),
+            // Bytes were missed, so the buffered packet cannot be completed
+            Some(next) if seq != next => self.buffer.clear(),
+            _ => {}
+        }
+        self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+        self.buffer.extend_from_slice(payload);
+
+        let mut packets = Vec::new();
+        while self.buffer.len() >= 4 {
+            let length = u32::from_le_bytes([self.buffer[0], self.buffer[1], self.buffer[2], 0]) as usize;
+            if length > MAX_PACKET {
+                // Not at a packet boundary, as when the capture starts within a packet
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < 4 + length {
+                break;
+            }
+            let sequence_id = self.buffer[3];
+            let packet = self.buffer[4..4 + length].to_vec();
+            self.buffer.drain(..4 + length);
+            packets.push((sequence_id, packet));
+        }
+        packets
+    }
+
+    /// Handles a whole client packet, returning the previous query if this one ends it.
+    fn client_packet(&mut self, (sequence_id, payload): &(u8, Vec<u8>), time: DateTime<Utc>, client: IpAddr) -> Option<Query> {
+        if self.greeted && *sequence_id == 1 {
+            self.greeted = false;
+            self.handshake_response(payload);
+            return None;
+        }
+        // Other packets of the authentication exchange
+        if *sequence_id != 0 || payload.is_empty() {
+            return None;
+        }
+        let completed = self.finish(client);
+        match payload[0] {
+            COM_QUERY => {
+                let sql = String::from_utf8_lossy(&payload[1..]).into_owned();
+                self.pending = Some(Pending { sql, start: time, end: None, errno: None, rows_affected: None });
+            }
+            COM_INIT_DB => self.db = Some(String::from_utf8_lossy(&payload[1..]).into_owned()),
+            _ => {}
+        }
+        completed
+    }
+
+    /// Reads the user and database of a handshake response, or notes the switch to TLS.
+    fn handshake_response(&mut self, payload: &[u8]) {
+        let Some(capabilities) = payload.get(..4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())) else { return };
+        if capabilities & CLIENT_PROTOCOL_41 == 0 {
+            return;
+        }
+        // The SSL request stops after the fixed part
+        if capabilities & CLIENT_SSL != 0 && payload.len() <= 32 {
+            self.encrypted = true;
+            return;
+        }
+        let mut rest = payload.get(32..).unwrap_or_default();
+        let Some(user) = take_cstr(&mut rest) else { return };
+        self.user = user;
+        let auth_length = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+            take_lenenc(&mut rest)
+        } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+            rest.split_first().map(|(length, tail)| {
+                rest = tail;
+                *length as u64
+            })
+        } else {
+            take_cstr(&mut rest).map(|_| 0)
+        };
+        let Some(auth_length) = auth_length else { return };
+        rest = rest.get(auth_length as usize..).unwrap_or_default();
+        if capabilities & CLIENT_CONNECT_WITH_DB != 0 {
+            self.db = take_cstr(&mut rest).filter(|db| !db.is_empty());
+        }
+    }
+
+    /// Handles server bytes: the greeting, or the response to the pending query.
+    fn server_segment(&mut self, payload: &[u8], time: DateTime<Utc>) {
+        if let Some(pending) = &mut self.pending {
+            if pending.end.is_none() && payload.len() > 4 {
+                match payload[4] {
+                    0xff => pending.errno = payload.get(5..7).map(|code| u16::from_le_bytes([code[0], code[1]]) as u32),
+                    0x00 => pending.rows_affected = take_lenenc(&mut payload.get(5..).unwrap_or_default()),
+                    _ => {}
+                }
+            }
+            pending.end = Some(time);
+        } else if payload.len() > 5 && payload[3] == 0 && payload[4] == 0x0a {
+            // Handshake v10: protocol version, server version, connection ID
+            let mut rest = &payload[5..];
+            if take_cstr(&mut rest).is_some() {
+                self.thread_id = rest.get(..4).map(|id| u32::from_le_bytes(id.try_into().unwrap()) as u64);
+            }
+            self.greeted = true;
+        }
+    }
+
+    /// Returns the pending query if its response was seen.
+    fn finish(&mut self, client: IpAddr) -> Option<Query> {
+        let pending = self.pending.take()?;
+        let end = pending.end?;
+        let query_time = (end - pending.start).num_microseconds().unwrap_or_default().max(0) as f64 / 1e6;
+        let statistics = HashMap::from([("QUERY_TIME".to_string(), query_time.to_string())]);
+        let user_host = format!("{}[{}] @ {} []", self.user, self.user, client);
+        let mut query = build_query(pending.sql, self.db.as_deref(), user_host, Some(end), self.thread_id, &statistics)?;
+        query.errno = pending.errno;
+        if let Some(rows) = pending.rows_affected {
+            query.metrics.push(("Rows_affected".to_string(), rows as f64));
+        }
+        Some(query)
+    }
+}
+
+/// Returns whether `a` is the server end of a connection between `a` and `b`.
+fn is_server(a: Endpoint, b: Endpoint) -> bool {
+    match (a.1 == MYSQL_PORT, b.1 == MYSQL_PORT) {
+        (true, false) => true,
+        (false, true) => false,
+        _ => a.1 < b.1 || (a.1 == b.1 && a.0 < b.0),
+    }
+}
+
+/// Returns the IP datagram of a captured frame of the given link type.
+fn link_payload(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        // Ethernet, with any 802.1Q VLAN tags
+        1 => {
+            let mut offset = 12;
+            while matches!(frame.get(offset..offset + 2)?, [0x81, 0x00] | [0x88, 0xa8]) {
+                offset += 4;
+            }
+            matches!(frame.get(offset..offset + 2)?, [0x08, 0x00] | [0x86, 0xdd]).then(|| &frame[offset + 2..])
+        }
+        // BSD loopback
+        0 | 108 => frame.get(4..),
+        // Raw IP
+        12 | 101 | 228 | 229 => Some(frame),
+        // Linux cooked capture (`-i any`), v1 and v2
+        113 => frame.get(16..),
+        276 => frame.get(20..),
+        _ => None,
+    }
+}
+
+/// Returns the TCP segment of an IPv4 or IPv6 datagram.
+fn tcp_segment(ip: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header = ((ip[0] & 0x0f) as usize) * 4;
+            if header > ip.len() {
+                return None;
+            }
+            let fragment = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+            // Not TCP, or a fragment
+            if *ip.get(9)? != 6 || fragment & 0x3fff != 0 {
+                return None;
+            }
+            // Offloaded segments can be captured with a total length of 0
+            let total = (u16::from_be_bytes([ip[2], ip[3]]) as usize).clamp(header, ip.len());
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (IpAddr::V4(Ipv4Addr::from(src)), IpAddr::V4(Ipv4Addr::from(dst)), ip.get(header..total)?)
+        }
+        6 => {
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let length = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let end = if length == 0 { ip.len() } else { (40 + length).min(ip.len()) };
+            (IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), ip.get(40..end)?)
+        }
+        _ => return None,
+    };
+    let header = ((*tcp.get(12)? >> 4) as usize) * 4;
+    Some(Segment {
+        src: (src, u16::from_be_bytes([tcp[0], tcp[1]])),
+        dst: (dst, u16::from_be_bytes([tcp[2], tcp[3]])),
+        seq: u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?),
+        flags: *tcp.get(13)?,
+        payload: tcp.get(header..)?,
+    })
+}
+
+/// Parses the `2024-05-01 10:00:00.123456` a tcpdump `-tttt` packet line starts with.
+fn parse_text_time(line: &str, log_timezone: Timezone) -> Option<DateTime<Utc>> {
+    let mut fields = line.split_ascii_whitespace();
+    let time = format!("{} {}", fields.next()?, fields.next()?);
+    NaiveDateTime::parse_from_str(&time, "%Y-%m-%d %H:%M:%S%.f").ok().map(|naive| log_timezone.to_utc(&naive))
+}
+
+/// Appends the bytes of hex groups such as `4500 0034` to `out`, returning false on other text.
+fn decode_hex(hex: &str, out: &mut Vec<u8>) -> bool {
+    for group in hex.split_ascii_whitespace() {
+        if group.len() % 2 != 0 || !group.is_ascii() {
+            return false;
+        }
+        for i in (0..group.len()).step_by(2) {
+            match u8::from_str_radix(&group[i..i + 2], 16) {
+                Ok(byte) => out.push(byte),
+                Err(_) => return false,
+            }
+        }
+    }
+    true
+}
+
+/// Takes a NUL-terminated string off the front of `bytes`.
+fn take_cstr(bytes: &mut &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&b| b == 0)?;
+    let s = String::from_utf8_lossy(&bytes[..end]).into_owned();
+    *bytes = &bytes[end + 1..];
+    Some(s)
+}
+
+/// Takes a length-encoded integer off the front of `bytes`.
+fn take_lenenc(bytes: &mut &[u8]) -> Option<u64> {
+    let (first, rest) = bytes.split_first()?;
+    let width = match first {
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        0xfb | 0xff => return None,
+        _ => {
+            *bytes = rest;
+            return Some(*first as u64);
+        }
+    };
+    let value = rest.get(..width)?.iter().rev().fold(0u64, |value, &b| value << 8 | b as u64);
+    *bytes = &rest[width..];
+    Some(value)
+}
+
+impl<R: BufRead> Iterator for TcpdumpParser<R> {
+    type Item = Result<Query>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(query) = self.ready.pop_front() {
+                return Some(Ok(query));
+            }
+            if self.done {
+                return None;
+            }
+            match self.next_packet() {
+                Ok(Some((time, ip))) => self.add_packet(time, &ip),
+                Ok(None) => {
+                    // Queries whose connection was still open at the end of the capture
+                    self.done = true;
+                    let connections = std::mem::take(&mut self.connections);
+                    self.ready.extend(connections.into_iter().filter_map(|((client, _), mut connection)| connection.finish(client.0)));
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(anyhow!("Could not read the capture: {:#}", e)));
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> QueryReader for TcpdumpParser<R> {
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the packets skipped so far because they are not TCP over IP.
+    fn skipped_blocks(&self) -> u64 {
+        self.skipped_blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an Ethernet frame of an IPv4 TCP segment.
+    fn frame(src: ([u8; 4], u16), dst: ([u8; 4], u16), seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend([0x08, 0x00]);
+        frame.extend([0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 6, 0, 0]);
+        frame[16..18].copy_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        frame.extend(src.0);
+        frame.extend(dst.0);
+        frame.extend(src.1.to_be_bytes());
+        frame.extend(dst.1.to_be_bytes());
+        frame.extend(seq.to_be_bytes());
+        frame.extend([0, 0, 0, 0, 0x50, flags | 0x10, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend(payload);
+        frame
+    }
+
+    /// Builds a MySQL packet.
+    fn packet(sequence_id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(sequence_id);
+        packet.extend(payload);
+        packet
+    }
+
+    fn pcap(frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0];
+        for (micros, frame) in frames {
+            pcap.extend(1714557600u32.to_le_bytes());
+            pcap.extend(micros.to_le_bytes());
+            pcap.extend((frame.len() as u32).to_le_bytes());
+            pcap.extend((frame.len() as u32).to_le_bytes());
+            pcap.extend(frame);
+        }
+        pcap
+    }
+
+    #[test]
+    fn test_truncated_packets() {
+        // IHL of 15 (60 bytes) in a 20-byte packet
+        let mut ip = vec![0x4f, 0, 0, 20, 0, 0, 0x40, 0, 64, 6];
+        ip.resize(20, 0);
+        assert!(tcp_segment(&ip).is_none());
+        assert!(tcp_segment(&[0x60, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_decode_hex_rejects_non_ascii() {
+        let mut out = Vec::new();
+        assert!(!decode_hex("4é0 0034", &mut out));
+        assert!(decode_hex("4500 0034", &mut out));
+        assert_eq!(out, [0x45, 0x00, 0x00, 0x34]);
+    }
+
+    #[test]
+    fn test_pcap() {
+        let (client, server) = (([10, 0, 0, 5], 52000), ([10, 0, 0, 1], 3306));
+        let greeting = packet(0, b"\x0a8.0.36\0\x2a\0\0\0rest");
+        let mut login = vec![0u8; 32];
+        login[..4].copy_from_slice(&(CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB).to_le_bytes());
+        login.extend(b"app\0\x02ab");
+        login.extend(b"shop\0");
+        let query = packet(0, b"\x03SELECT * FROM orders WHERE id = 1");
+        let (first, second) = query.split_at(10);
+        let frames = [
+            (0, frame(client, server, 99, TCP_SYN, &[])),
+            (100, frame(server, client, 500, 0, &greeting)),
+            (200, frame(client, server, 100, 0, &packet(1, &login))),
+            (300, frame(server, client, 530, 0, &packet(2, b"\0\0\0\x02\0\0\0"))),
+            // A query split over two segments, with the first retransmitted
+            (1_000, frame(client, server, 100 + 4 + login.len() as u32, 0, first)),
+            (1_100, frame(client, server, 100 + 4 + login.len() as u32, 0, first)),
+            (1_200, frame(client, server, 100 + 4 + login.len() as u32 + 10, 0, second)),
+            (2_000, frame(server, client, 541, 0, &packet(1, b"\x01"))),
+            (251_200, frame(server, client, 546, 0, &packet(5, b"\xfe\0\0\x02\0"))),
+            (300_000, frame(client, server, 100 + 4 + login.len() as u32 + query.len() as u32, 0, &packet(0, b"\x03DELETE FROM carts"))),
+            (400_000, frame(server, client, 555, 0, &packet(1, b"\xff\x15\x04#42000denied"))),
+            (500_000, frame(client, server, 0, TCP_FIN, &[])),
+        ];
+        let input = pcap(&frames);
+        let mut parser = TcpdumpParser::new(input.as_slice());
+        let queries: Vec<Query> = parser.by_ref().map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 2);
+
+        let query = &queries[0];
+        assert_eq!(query.sql_text, "use shop;\nSELECT * FROM orders WHERE id = 1");
+        assert!((query.query_time - 0.25).abs() < 1e-9, "{}", query.query_time);
+        assert_eq!((query.user_host.as_str(), query.thread_id), ("app[app] @ 10.0.0.5 []", Some(42)));
+        assert_eq!(query.timestamp, Some("2024-05-01T10:00:00.251200Z".parse().unwrap()));
+        assert_eq!((queries[1].errno, queries[1].query_time), (Some(1045), 0.1));
+        assert_eq!(parser.bytes_read(), input.len() as u64);
+    }
+
+    #[test]
+    fn test_text() {
+        let text = "2024-05-01 10:00:00.000000 IP 10.0.0.5.52000 > 10.0.0.1.3306: tcp 20
+\t0x0000:  4500 003c 0000 4000 4006 0000 0a00 0005
+\t0x0010:  0a00 0001 cb20 0cea 0000 0064 0000 0000
+\t0x0020:  5010 ffff 0000 0000 1000 0000 0353 454c
+\t0x0030:  4543 5420 736c 6565 7028 3129
+2024-05-01 10:00:01.500000 IP 10.0.0.1.3306 > 10.0.0.5.52000: tcp 11
+\t0x0000:  4500 0033 0000 4000 4006 0000 0a00 0001
+\t0x0010:  0a00 0005 0cea cb20 0000 01f4 0000 0000
+\t0x0020:  5010 ffff 0000 0000 0700 0001 0000 0002
+\t0x0030:  0000 00
+";
+        let queries: Vec<Query> = TcpdumpParser::new(text.as_bytes()).map(Result::unwrap).collect();
+        assert_eq!(queries.len(), 1);
+        assert_eq!((queries[0].sql_text.as_str(), queries[0].query_time), ("SELECT sleep(1)", 1.5));
+        assert_eq!(queries[0].user_host, "[] @ 10.0.0.5 []");
+        assert_eq!(queries[0].metrics, [("Rows_affected".to_string(), 0.0)]);
+    }
+}
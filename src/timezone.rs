@@ -0,0 +1,117 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// A timezone given as a fixed offset (`+09:00`) or an IANA name (`Asia/Tokyo`).
+///
+/// Named zones apply the offset in effect at each timestamp, so daylight saving
+/// time is handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timezone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone::Fixed(FixedOffset::east_opt(0).unwrap())
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(offset) = s.parse::<FixedOffset>() {
+            return Ok(Timezone::Fixed(offset));
+        }
+        s.parse::<Tz>()
+            .map(Timezone::Named)
+            .map_err(|_| format!("Invalid timezone '{}': expected an offset such as +09:00 or a name such as Asia/Tokyo", s))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timezone::Fixed(offset) => write!(f, "{}", offset),
+            Timezone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl Timezone {
+    /// Interprets a timestamp without offset as local time in this timezone.
+    ///
+    /// Ambiguous times (when clocks go back) resolve to the earlier instant; times
+    /// skipped when clocks go forward use the offset in effect before the change.
+    pub fn to_utc(&self, naive: &NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            Timezone::Fixed(offset) => local_to_utc(offset, naive),
+            Timezone::Named(tz) => local_to_utc(tz, naive),
+        }
+    }
+
+    /// Returns the local date and time of a UTC timestamp in this timezone.
+    pub fn to_local(&self, ts: &DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Timezone::Fixed(offset) => ts.with_timezone(offset).naive_local(),
+            Timezone::Named(tz) => ts.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// Formats a UTC timestamp in this timezone with a `strftime`-style pattern.
+    pub fn format(&self, ts: &DateTime<Utc>, pattern: &str) -> String {
+        match self {
+            Timezone::Fixed(offset) => ts.with_timezone(offset).format(pattern).to_string(),
+            Timezone::Named(tz) => ts.with_timezone(tz).format(pattern).to_string(),
+        }
+    }
+}
+
+fn local_to_utc<Z: TimeZone>(tz: &Z, naive: &NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(naive).earliest() {
+        Some(local) => local.with_timezone(&Utc),
+        None => {
+            let hour = TimeDelta::hours(1);
+            match tz.from_local_datetime(&(*naive - hour)).earliest() {
+                Some(local) => local.with_timezone(&Utc) + hour,
+                None => naive.and_utc(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!("+09:00".parse::<Timezone>().unwrap(), Timezone::Fixed(FixedOffset::east_opt(9 * 3600).unwrap()));
+        assert_eq!("Asia/Tokyo".parse::<Timezone>().unwrap(), Timezone::Named(Tz::Asia__Tokyo));
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+    }
+
+    #[test]
+    fn test_named_timezone_follows_dst() {
+        let tz: Timezone = "Europe/Berlin".parse().unwrap();
+        let winter: DateTime<Utc> = "2024-01-15T12:00:00Z".parse().unwrap();
+        let summer: DateTime<Utc> = "2024-07-15T12:00:00Z".parse().unwrap();
+        assert_eq!(tz.format(&winter, "%H:%M %z"), "13:00 +0100");
+        assert_eq!(tz.format(&summer, "%H:%M %z"), "14:00 +0200");
+    }
+
+    #[test]
+    fn test_local_to_utc() {
+        let naive = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let tz: Timezone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(tz.to_utc(&naive("2024-07-15 14:00:00")).to_rfc3339(), "2024-07-15T12:00:00+00:00");
+        // 02:30 does not exist on 2024-03-31; the pre-change offset (+01:00) is used
+        assert_eq!(tz.to_utc(&naive("2024-03-31 02:30:00")).to_rfc3339(), "2024-03-31T01:30:00+00:00");
+        // 02:30 occurs twice on 2024-10-27; the earlier (+02:00) is used
+        assert_eq!(tz.to_utc(&naive("2024-10-27 02:30:00")).to_rfc3339(), "2024-10-27T00:30:00+00:00");
+        assert_eq!(Timezone::default().to_utc(&naive("2024-01-01 00:00:00")).to_rfc3339(), "2024-01-01T00:00:00+00:00");
+    }
+}
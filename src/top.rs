@@ -0,0 +1,95 @@
+use crate::daemon::format_interval;
+use crate::report::{RenderOptions, ReportItem};
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use std::time::Duration;
+
+/// Characters of the query ID shown; enough for `grep` and `extract --query`.
+const ID_WIDTH: usize = 16;
+
+/// Width used when the terminal size is unknown.
+const DEFAULT_WIDTH: usize = 120;
+
+/// Returns the width of the terminal, or a default when output is not a terminal.
+pub fn terminal_width() -> usize {
+    ratatui::crossterm::terminal::size().map_or(DEFAULT_WIDTH, |(columns, _)| columns as usize)
+}
+
+/// Writes one screen of the `--top` view: a header with the totals of the last
+/// `span`, then the heaviest queries of that window, one line each, cut to
+/// `width` columns.
+pub fn write_top(items: &[ReportItem], totals: (u64, f64), span: Duration, now: DateTime<Utc>, options: &RenderOptions, width: usize, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let (count, total_time) = totals;
+    let time = |seconds| options.time_unit.format(seconds);
+    writeln!(
+        writer,
+        "{} - last {}: {} queries, {} total, {:.1} q/s",
+        options.timezone.format(&now, "%H:%M:%S"),
+        format_interval(span),
+        count,
+        time(total_time),
+        count as f64 / span.as_secs_f64()
+    )?;
+    writeln!(writer)?;
+
+    let header = format!("{:>10} {:>5} {:>7} {:>10} {:>10}  {:<ID_WIDTH$}  ", "TOTAL", "SHARE", "COUNT", "MEAN", "P95", "QUERY ID");
+    let query_width = width.saturating_sub(header.len());
+    writeln!(writer, "{}QUERY", header)?;
+    for item in items {
+        let share = if total_time > 0.0 { item.total_time / total_time * 100.0 } else { 0.0 };
+        let query: String = item.display_query().split_whitespace().collect::<Vec<_>>().join(" ").chars().take(query_width).collect();
+        writeln!(
+            writer,
+            "{:>10} {:>4.0}% {:>7} {:>10} {:>10}  {:<ID_WIDTH$}  {}",
+            time(item.total_time),
+            share,
+            item.count,
+            time(item.mean_time),
+            time(item.p95),
+            &item.query_id[..ID_WIDTH.min(item.query_id.len())],
+            query
+        )?;
+    }
+    if items.is_empty() {
+        writeln!(writer, "(no queries logged in the last {})", format_interval(span))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::AlertWindow;
+    use crate::digest::DigestConfig;
+    use crate::parser::Query;
+    use crate::report::prepare_report_items;
+    use std::time::Instant;
+
+    #[test]
+    fn test_write_top() {
+        let window = AlertWindow::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let query = |sql: &str, query_time| Query { query_time, lock_time: 0.0, rows_sent: 1, rows_examined: 1, timestamp: None, user_host: String::new(), sql_text: sql.to_string(), ..Default::default() };
+        window.record("select ?", &query("SELECT 1", 9.0), start);
+        window.record("select * from orders where id = ?", &query("SELECT *\n  FROM orders WHERE id = 1", 2.0), start + Duration::from_secs(70));
+        window.record("select * from orders where id = ?", &query("SELECT * FROM orders WHERE id = 2", 1.0), start + Duration::from_secs(80));
+
+        // The first query fell out of the one-minute window
+        let stats = window.stats(Duration::from_secs(60), start + Duration::from_secs(90));
+        let totals = crate::aggregator::totals(&stats);
+        let items = prepare_report_items(stats, &DigestConfig::default());
+        let options = RenderOptions::default();
+        let now = "2024-05-01T10:00:00Z".parse().unwrap();
+        let mut out = Vec::new();
+        write_top(&items, totals, Duration::from_secs(60), now, &options, 90, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "10:00:00 - last 1m: 2 queries, 3.000s total, 0.0 q/s");
+        assert!(lines[2].starts_with("     TOTAL SHARE   COUNT       MEAN        P95  QUERY ID          QUERY"));
+        assert!(lines[3].starts_with("    3.000s  100%       2     1.500s "), "{}", lines[3]);
+        assert!(lines[3].ends_with("  SELECT * FROM orders WHE"), "{}", lines[3]);
+        assert_eq!(lines[3].chars().count(), 90);
+        assert_eq!(lines.len(), 4);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::diff::DiffFormat;
+use crate::report::{percentile, query_id, truncate_chars};
+use crate::snapshot::Snapshot;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::io::Write;
+use tabled::{Table, Tabled};
+
+/// When a fingerprint counts as steadily degrading.
+#[derive(Debug, Clone)]
+pub struct TrendOptions {
+    /// Minimum number of runs the fingerprint must appear in.
+    pub min_runs: usize,
+    /// Minimum relative P95 increase from its first to its last run (0.2 = 20%).
+    pub min_increase: f64,
+    /// Minimum share of run-to-run steps in which P95 went up.
+    pub min_rising_share: f64,
+}
+
+impl Default for TrendOptions {
+    fn default() -> Self {
+        Self { min_runs: 3, min_increase: 0.2, min_rising_share: 0.75 }
+    }
+}
+
+/// Metrics of a fingerprint in one run.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrendPoint {
+    pub count: u64,
+    pub p95: f64,
+}
+
+/// How a fingerprint evolved over the runs, one point per run (`None` where it
+/// did not appear).
+#[derive(Debug, Serialize)]
+pub struct TrendItem {
+    pub query_id: String,
+    pub fingerprint: String,
+    pub points: Vec<Option<TrendPoint>>,
+    /// Relative P95 change from the first to the last run it appeared in.
+    pub p95_change: f64,
+    /// True if P95 rose steadily according to the [`TrendOptions`].
+    pub degrading: bool,
+}
+
+/// Represents a row in the trend table.
+#[derive(Tabled)]
+struct Row {
+    #[tabled(rename = "Query ID")]
+    query_id: String,
+    #[tabled(rename = "Runs")]
+    runs: usize,
+    #[tabled(rename = "Count")]
+    count: String,
+    #[tabled(rename = "P95")]
+    p95: String,
+    #[tabled(rename = "P95 Trend")]
+    sparkline: String,
+    #[tabled(rename = "Change")]
+    change: String,
+    #[tabled(rename = "Query")]
+    query: String,
+}
+
+/// Follows every fingerprint through the snapshots, ordered by creation time.
+///
+/// Degrading fingerprints come first, then the rest, each by total time in the
+/// latest run they appeared in (ties broken by query ID). Returns the run times
+/// with the items.
+pub fn trend(mut snapshots: Vec<Snapshot>, options: &TrendOptions) -> (Vec<DateTime<Utc>>, Vec<TrendItem>) {
+    snapshots.sort_by_key(|snapshot| snapshot.created_at);
+    let runs: Vec<DateTime<Utc>> = snapshots.iter().map(|snapshot| snapshot.created_at).collect();
+    let keys: BTreeSet<String> = snapshots.iter().flat_map(|snapshot| snapshot.stats.keys().cloned()).collect();
+
+    let mut items: Vec<(f64, TrendItem)> = keys.into_iter().map(|fp| {
+        let mut latest_total = 0.0;
+        let points: Vec<Option<TrendPoint>> = snapshots.iter_mut().map(|snapshot| {
+            snapshot.stats.remove(&fp).map(|mut stats| {
+                stats.all_query_times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                latest_total = stats.total_time;
+                TrendPoint { count: stats.count, p95: percentile(&stats.all_query_times, 0.95) }
+            })
+        }).collect();
+        let (p95_change, degrading) = assess(&points, options);
+        (latest_total, TrendItem { query_id: query_id(&fp), fingerprint: fp, points, p95_change, degrading })
+    }).collect();
+
+    items.sort_by(|a, b| {
+        b.1.degrading.cmp(&a.1.degrading)
+            .then_with(|| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal))
+            .then_with(|| a.1.query_id.cmp(&b.1.query_id))
+    });
+    (runs, items.into_iter().map(|(_, item)| item).collect())
+}
+
+/// Returns the relative P95 change over the runs present, and whether it rose steadily.
+fn assess(points: &[Option<TrendPoint>], options: &TrendOptions) -> (f64, bool) {
+    let p95s: Vec<f64> = points.iter().flatten().map(|point| point.p95).collect();
+    let (Some(first), Some(last)) = (p95s.first(), p95s.last()) else { return (0.0, false) };
+    let change = if *first > 0.0 { last / first - 1.0 } else { 0.0 };
+    if p95s.len() < options.min_runs.max(2) {
+        return (change, false);
+    }
+    let rising = p95s.windows(2).filter(|pair| pair[1] > pair[0]).count();
+    let rising_share = rising as f64 / (p95s.len() - 1) as f64;
+    (change, change >= options.min_increase && rising_share >= options.min_rising_share)
+}
+
+/// Draws the P95 values as a sparkline, with `·` for runs the fingerprint was absent from.
+fn sparkline(points: &[Option<TrendPoint>]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let p95s = points.iter().flatten().map(|point| point.p95);
+    let (min, max) = p95s.fold((f64::MAX, f64::MIN), |(min, max), p95| (min.min(p95), max.max(p95)));
+    points.iter().map(|point| match point {
+        Some(point) if max > min => BARS[(((point.p95 - min) / (max - min)) * 7.0).round() as usize],
+        Some(_) => BARS[0],
+        None => '·',
+    }).collect()
+}
+
+/// Writes the first `limit` trend items (0 for all) in the given format.
+pub fn write_trend(runs: &[DateTime<Utc>], items: &[TrendItem], limit: usize, format: &DiffFormat, writer: &mut dyn Write) -> anyhow::Result<()> {
+    let items = if limit == 0 { items } else { &items[..limit.min(items.len())] };
+
+    match format {
+        DiffFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, &serde_json::json!({ "runs": runs, "items": items }))?;
+            writeln!(writer)?;
+        }
+        DiffFormat::Table => {
+            if let (Some(first), Some(last)) = (runs.first(), runs.last()) {
+                writeln!(writer, "{} runs from {} to {}", runs.len(), first.format("%Y-%m-%d %H:%M"), last.format("%Y-%m-%d %H:%M"))?;
+            }
+            let rows: Vec<Row> = items.iter().map(|item| {
+                let present: Vec<&TrendPoint> = item.points.iter().flatten().collect();
+                let first = present.first();
+                let last = present.last();
+                let query = truncate_chars(&item.fingerprint, 50);
+                Row {
+                    query_id: item.query_id.clone(),
+                    runs: present.len(),
+                    count: format!("{} -> {}", first.map_or(0, |p| p.count), last.map_or(0, |p| p.count)),
+                    p95: format!("{:.3}s -> {:.3}s", first.map_or(0.0, |p| p.p95), last.map_or(0.0, |p| p.p95)),
+                    sparkline: sparkline(&item.points),
+                    change: format!("{:+.0}%{}", item.p95_change * 100.0, if item.degrading { " DEGRADING" } else { "" }),
+                    query,
+                }
+            }).collect();
+            writeln!(writer, "{}", Table::new(rows))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator::QueryStats;
+    use std::collections::HashMap;
+
+    fn snapshot(day: u32, entries: &[(&str, f64)]) -> Snapshot {
+        let stats = entries.iter().map(|(fp, time)| {
+            (fp.to_string(), QueryStats { count: 1, total_time: *time, all_query_times: vec![*time], ..Default::default() })
+        }).collect::<HashMap<_, _>>();
+        Snapshot { created_at: format!("2024-05-{:02}T00:00:00Z", day).parse().unwrap(), ..Snapshot::new(stats) }
+    }
+
+    #[test]
+    fn test_trend_flags_steady_degradation() {
+        let snapshots = vec![
+            snapshot(3, &[("select slow", 1.3), ("select flat", 5.0)]),
+            snapshot(1, &[("select slow", 1.0), ("select flat", 5.0)]),
+            snapshot(2, &[("select slow", 1.2), ("select flat", 5.1), ("select once", 9.0)]),
+            snapshot(4, &[("select slow", 1.4), ("select flat", 4.9)]),
+        ];
+        let (runs, items) = trend(snapshots, &TrendOptions::default());
+
+        assert_eq!(runs.len(), 4);
+        assert!(runs.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(items[0].fingerprint, "select slow");
+        assert!(items[0].degrading);
+        assert!((items[0].p95_change - 0.4).abs() < 1e-9);
+        assert!(items.iter().skip(1).all(|item| !item.degrading));
+        assert_eq!(sparkline(&items[0].points), "▁▅▆█");
+        let once = items.iter().find(|item| item.fingerprint == "select once").unwrap();
+        assert_eq!(sparkline(&once.points), "·▁··");
+    }
+}
@@ -0,0 +1,130 @@
+use crate::report::{ReportItem, Triage};
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Triage state of queries kept outside the report, such as an export of the
+/// spreadsheet a team tracks slow queries in, keyed by query ID.
+///
+/// Keys may be a prefix of the query ID, as shown by `--top` or accepted by
+/// `extract --query`; the longest matching key wins.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TriageFile {
+    entries: BTreeMap<String, Triage>,
+}
+
+/// A row of a CSV triage file, with lowercase headers. Other columns are ignored.
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(alias = "id", alias = "query id")]
+    query_id: String,
+    owner: Option<String>,
+    ticket: Option<String>,
+    status: Option<String>,
+    notes: Option<String>,
+}
+
+impl TriageFile {
+    /// Loads a `.csv` file with a header row, or YAML for any other extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Could not read triage file {:?}", path))?;
+        let file = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+            Self::from_csv(&text)
+        } else {
+            Self::from_yaml(&text)
+        };
+        file.with_context(|| format!("Invalid triage file {:?}", path))
+    }
+
+    /// Parses a mapping of query IDs to their `owner`, `ticket`, `status` and `notes`.
+    pub fn from_yaml(text: &str) -> anyhow::Result<Self> {
+        let entries: Option<BTreeMap<String, Triage>> = serde_yaml::from_str(text)?;
+        Self::new(entries.unwrap_or_default())
+    }
+
+    /// Parses CSV with a `query_id` column and any of `owner`, `ticket`,
+    /// `status` and `notes`. Empty cells are treated as missing.
+    pub fn from_csv(text: &str) -> anyhow::Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(text.as_bytes());
+        let headers = reader.headers()?.iter().map(|header| header.to_ascii_lowercase()).collect();
+        reader.set_headers(headers);
+        let mut entries = BTreeMap::new();
+        for (number, row) in reader.deserialize::<Row>().enumerate() {
+            let row = row.with_context(|| format!("Row {}", number + 2))?;
+            entries.insert(row.query_id, Triage { owner: row.owner, ticket: row.ticket, status: row.status, notes: row.notes });
+        }
+        Self::new(entries)
+    }
+
+    fn new(entries: BTreeMap<String, Triage>) -> anyhow::Result<Self> {
+        let entries = entries
+            .into_iter()
+            .map(|(id, triage)| {
+                let id = id.trim().to_ascii_lowercase();
+                if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    anyhow::bail!("Invalid query ID {:?}", id);
+                }
+                Ok((id, triage.normalized()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entry for a query ID.
+    pub fn get(&self, query_id: &str) -> Option<&Triage> {
+        self.entries.iter().filter(|(id, _)| query_id.starts_with(id.as_str())).max_by_key(|(id, _)| id.len()).map(|(_, triage)| triage)
+    }
+
+    /// Sets the triage state of the items that have an entry, and returns how
+    /// many did.
+    pub fn apply(&self, items: &mut [ReportItem]) -> usize {
+        let mut matched = 0;
+        for item in items {
+            item.triage = self.get(&item.query_id).cloned();
+            matched += item.triage.is_some() as usize;
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml_and_csv() {
+        let yaml = "
+9b2e5a1f0c3d4e6f8a7b6c5d4e3f2a1b:
+  owner: alice
+  ticket: https://tracker.example.com/DBA-12
+  status: in progress
+9b2e5a1f:
+  status: wontfix
+  notes: batch job, runs at night
+";
+        let file = TriageFile::from_yaml(yaml).unwrap();
+        assert_eq!(file.len(), 2);
+        assert_eq!(file.get("9b2e5a1f0c3d4e6f8a7b6c5d4e3f2a1b").unwrap().owner.as_deref(), Some("alice"));
+        assert_eq!(file.get("9b2e5a1f00000000000000000000000").unwrap().status.as_deref(), Some("wontfix"));
+        assert!(file.get("0000").is_none());
+        assert!(TriageFile::from_yaml("not-hex:\n  owner: bob\n").is_err());
+        assert!(TriageFile::from_yaml("").unwrap().is_empty());
+
+        let csv = "Query ID,Owner,Status,Ticket,Notes,Last touched
+9B2E5A1F, bob ,open,,\"needs an index on (customer_id, created_at)\",2024-05-01
+";
+        let file = TriageFile::from_csv(csv).unwrap();
+        let triage = file.get("9b2e5a1f0c3d4e6f8a7b6c5d4e3f2a1b").unwrap();
+        assert_eq!(triage, &Triage { owner: Some("bob".to_string()), ticket: None, status: Some("open".to_string()), notes: Some("needs an index on (customer_id, created_at)".to_string()) });
+        assert!(TriageFile::from_csv("owner\nbob\n").unwrap_err().to_string().starts_with("Row 2"));
+    }
+}
@@ -0,0 +1,411 @@
+use crate::report::{self, ReportItem};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal};
+use std::path::PathBuf;
+
+/// Column the fingerprint list is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    TotalTime,
+    Count,
+    MeanTime,
+    P95,
+    RowsExamined,
+}
+
+impl SortKey {
+    fn next(self) -> Self {
+        match self {
+            SortKey::TotalTime => SortKey::Count,
+            SortKey::Count => SortKey::MeanTime,
+            SortKey::MeanTime => SortKey::P95,
+            SortKey::P95 => SortKey::RowsExamined,
+            SortKey::RowsExamined => SortKey::TotalTime,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortKey::TotalTime => "total time",
+            SortKey::Count => "count",
+            SortKey::MeanTime => "mean time",
+            SortKey::P95 => "P95",
+            SortKey::RowsExamined => "rows examined",
+        }
+    }
+
+    fn value(self, item: &ReportItem) -> f64 {
+        match self {
+            SortKey::TotalTime => item.total_time,
+            SortKey::Count => item.count as f64,
+            SortKey::MeanTime => item.mean_time,
+            SortKey::P95 => item.p95,
+            SortKey::RowsExamined => item.rows_examined as f64,
+        }
+    }
+}
+
+/// Case-insensitive substring filters applied to the fingerprint list.
+#[derive(Debug, Default)]
+pub struct Filters {
+    /// Matches the fingerprint text or a query ID prefix.
+    pub text: String,
+    /// Matches any of the fingerprint's `User@Host` values.
+    pub user: String,
+    /// Matches any of the fingerprint's databases.
+    pub db: String,
+}
+
+impl Filters {
+    fn matches(&self, item: &ReportItem) -> bool {
+        let text = self.text.to_lowercase();
+        let user = self.user.to_lowercase();
+        let db = self.db.to_lowercase();
+        (text.is_empty() || item.normalized_query.to_lowercase().contains(&text) || item.query_id.starts_with(&text))
+            && (user.is_empty() || item.users.iter().any(|u| u.to_lowercase().contains(&user)))
+            && (db.is_empty() || item.databases.iter().any(|d| d.to_lowercase().contains(&db)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Text,
+    User,
+    Db,
+}
+
+/// State of the explorer: the full item list and the current view of it.
+pub struct App {
+    items: Vec<ReportItem>,
+    visible: Vec<usize>,
+    sort: SortKey,
+    descending: bool,
+    filters: Filters,
+    input: Option<Input>,
+    table: TableState,
+    export_path: PathBuf,
+    status: String,
+}
+
+impl App {
+    /// Creates an explorer over `items`, exporting the current view to `export_path`.
+    pub fn new(items: Vec<ReportItem>, export_path: PathBuf) -> Self {
+        let mut app = Self {
+            items,
+            visible: Vec::new(),
+            sort: SortKey::TotalTime,
+            descending: true,
+            filters: Filters::default(),
+            input: None,
+            table: TableState::default(),
+            export_path,
+            status: String::new(),
+        };
+        app.refresh();
+        app
+    }
+
+    /// Recomputes the visible rows after a filter or sort change.
+    fn refresh(&mut self) {
+        let mut visible: Vec<usize> = (0..self.items.len()).filter(|&i| self.filters.matches(&self.items[i])).collect();
+        let sort = self.sort;
+        visible.sort_by(|&a, &b| {
+            let ordering = sort.value(&self.items[a]).partial_cmp(&sort.value(&self.items[b])).unwrap_or(std::cmp::Ordering::Equal);
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| self.items[a].rank.cmp(&self.items[b].rank))
+        });
+        self.visible = visible;
+        self.table.select(if self.visible.is_empty() { None } else { Some(self.table.selected().unwrap_or(0).min(self.visible.len() - 1)) });
+    }
+
+    fn selected(&self) -> Option<&ReportItem> {
+        self.table.selected().and_then(|i| self.visible.get(i)).map(|&i| &self.items[i])
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, self.visible.len() as isize - 1);
+        self.table.select(Some(next as usize));
+    }
+
+    /// Writes the visible rows, in their current order, as a JSON report.
+    fn export(&mut self) {
+        let items: Vec<&ReportItem> = self.visible.iter().map(|&i| &self.items[i]).collect();
+        let result = File::create(&self.export_path).map_err(anyhow::Error::from).and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), &items)?;
+            Ok(())
+        });
+        self.status = match result {
+            Ok(()) => format!("Exported {} fingerprints to {}", items.len(), self.export_path.display()),
+            Err(e) => format!("Export failed: {}", e),
+        };
+    }
+
+    /// Handles a key press. Returns false when the explorer should exit.
+    fn on_key(&mut self, code: KeyCode) -> bool {
+        if let Some(input) = self.input {
+            let field = match input {
+                Input::Text => &mut self.filters.text,
+                Input::User => &mut self.filters.user,
+                Input::Db => &mut self.filters.db,
+            };
+            match code {
+                KeyCode::Enter => self.input = None,
+                KeyCode::Esc => {
+                    field.clear();
+                    self.input = None;
+                }
+                KeyCode::Backspace => {
+                    field.pop();
+                }
+                KeyCode::Char(c) => field.push(c),
+                _ => {}
+            }
+            self.refresh();
+            return true;
+        }
+
+        self.status.clear();
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::PageDown => self.move_by(20),
+            KeyCode::PageUp => self.move_by(-20),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX / 2),
+            KeyCode::Char('s') => {
+                self.sort = self.sort.next();
+                self.refresh();
+            }
+            KeyCode::Char('r') => {
+                self.descending = !self.descending;
+                self.refresh();
+            }
+            KeyCode::Char('/') => self.input = Some(Input::Text),
+            KeyCode::Char('u') => self.input = Some(Input::User),
+            KeyCode::Char('d') => self.input = Some(Input::Db),
+            KeyCode::Char('c') => {
+                self.filters = Filters::default();
+                self.refresh();
+            }
+            KeyCode::Char('e') => self.export(),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [list, detail] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .areas(body);
+
+        let total_time: f64 = self.visible.iter().map(|&i| self.items[i].total_time).sum();
+        let mut summary = format!(
+            "{} of {} fingerprints, {:.3}s total | sort: {} {}",
+            self.visible.len(),
+            self.items.len(),
+            total_time,
+            self.sort.label(),
+            if self.descending { "desc" } else { "asc" }
+        );
+        for (label, value) in [("text", &self.filters.text), ("user", &self.filters.user), ("db", &self.filters.db)] {
+            if !value.is_empty() {
+                summary.push_str(&format!(" | {}: {}", label, value));
+            }
+        }
+        frame.render_widget(Paragraph::new(summary), header);
+
+        self.draw_list(frame, list);
+        self.draw_detail(frame, detail);
+
+        let help = match self.input {
+            Some(input) => {
+                let (label, value) = match input {
+                    Input::Text => ("Filter text", &self.filters.text),
+                    Input::User => ("Filter user", &self.filters.user),
+                    Input::Db => ("Filter db", &self.filters.db),
+                };
+                format!("{}: {}_  (Enter: apply, Esc: clear)", label, value)
+            }
+            None if !self.status.is_empty() => self.status.clone(),
+            None => "q: quit  j/k: move  s: sort  r: reverse  /: text  u: user  d: db  c: clear filters  e: export".to_string(),
+        };
+        frame.render_widget(Paragraph::new(help).style(Style::default().add_modifier(Modifier::REVERSED)), footer);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let header = Row::new(["Rank", "Count", "Total", "Mean", "P95", "Query"]).style(Style::default().add_modifier(Modifier::BOLD));
+        let rows = self.visible.iter().map(|&i| {
+            let item = &self.items[i];
+            Row::new([
+                Cell::from(item.rank.to_string()),
+                Cell::from(item.count.to_string()),
+                Cell::from(format!("{:.3}s", item.total_time)),
+                Cell::from(format!("{:.3}s", item.mean_time)),
+                Cell::from(format!("{:.3}s", item.p95)),
+                Cell::from(item.normalized_query.replace(['\n', '\r'], " ")),
+            ])
+        });
+        let widths = [
+            Constraint::Length(5),
+            Constraint::Length(7),
+            Constraint::Length(10),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Min(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::default().borders(Borders::ALL).title("Fingerprints"))
+            .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_detail(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::default().borders(Borders::ALL).title("Details");
+        let Some(item) = self.selected() else {
+            frame.render_widget(Paragraph::new("No matching fingerprints.").block(block), area);
+            return;
+        };
+
+        let mut lines = vec![
+            Line::from(format!("Query ID: {}", item.query_id)),
+            Line::from(format!("Rank: {}", item.rank)),
+            Line::from(format!("Time Range: {}", item.time_range)),
+            Line::from(format!("Count: {}", item.count)),
+            Line::from(format!("Total Time: {:.3}s", item.total_time)),
+            Line::from(format!("Mean Time: {:.3}s", item.mean_time)),
+        ];
+        for (p, value) in &item.percentiles {
+            lines.push(Line::from(format!("{}: {:.3}s", report::percentile_label(*p), value)));
+        }
+        lines.push(Line::from(format!("Total Lock Time: {:.3}s", item.total_lock_time)));
+        lines.push(Line::from(format!("Rows Sent/Examined: {}/{} (ratio {:.2})", item.rows_sent, item.rows_examined, item.ratio)));
+        lines.push(Line::from(format!("Users: {}", item.users.join(", "))));
+        lines.push(Line::from(format!("Databases: {}", item.databases.join(", "))));
+        lines.push(Line::from(""));
+        lines.push(Line::from("Normalized Query:").style(Style::default().add_modifier(Modifier::BOLD)));
+        lines.extend(item.normalized_query.lines().map(|l| Line::from(l.to_string())));
+        lines.push(Line::from(""));
+        lines.push(Line::from("Worst Case Example:").style(Style::default().add_modifier(Modifier::BOLD)));
+        lines.extend(item.worst_example_query.lines().map(|l| Line::from(l.to_string())));
+
+        frame.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: false }), area);
+    }
+}
+
+/// Runs the interactive explorer over `items` until the user quits.
+pub fn run(items: Vec<ReportItem>, export_path: PathBuf) -> anyhow::Result<()> {
+    if !std::io::stdout().is_terminal() {
+        anyhow::bail!("The interactive explorer needs a terminal");
+    }
+
+    let mut app = App::new(items, export_path);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> anyhow::Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.on_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(rank: usize, total_time: f64, count: u64, query: &str, user: &str, db: &str) -> ReportItem {
+        ReportItem {
+            rank,
+            query_id: format!("id{}", rank),
+            count,
+            total_time,
+            mean_time: total_time / count as f64,
+            p95: 0.0,
+            p99: 0.0,
+            percentiles: Vec::new(),
+            total_lock_time: 0.0,
+            mean_lock_time: 0.0,
+            rows_sent: 0,
+            rows_examined: 0,
+            ratio: 0.0,
+            score: 0.0,
+            badges: Vec::new(),
+            sources: Default::default(),
+            time_range: "N/A".to_string(),
+            example_query: String::new(),
+            worst_example_query: String::new(),
+            normalized_query: query.to_string(),
+            users: vec![user.to_string()],
+            databases: vec![db.to_string()],
+            explain: None,
+            explain_analyze: None,
+            first_seen: None,
+            last_seen: None,
+            review: None,
+            triage: None,
+            server: None,
+            query_cache: None,
+            errors: None,
+            metrics: Default::default(),
+            plan_flags: Default::default(),
+        }
+    }
+
+    fn visible_ranks(app: &App) -> Vec<usize> {
+        app.visible.iter().map(|&i| app.items[i].rank).collect()
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let items = vec![
+            item(1, 9.0, 1, "select * from orders", "app[app] @ web1 []", "shop"),
+            item(2, 5.0, 10, "update users set x = ?", "admin[admin] @ localhost []", "auth"),
+            item(3, 1.0, 3, "select * from users", "app[app] @ web2 []", "auth"),
+        ];
+        let mut app = App::new(items, PathBuf::from("unused.json"));
+        assert_eq!(visible_ranks(&app), vec![1, 2, 3]);
+
+        app.on_key(KeyCode::Char('s'));
+        assert_eq!(visible_ranks(&app), vec![2, 3, 1]);
+        app.on_key(KeyCode::Char('r'));
+        assert_eq!(visible_ranks(&app), vec![1, 3, 2]);
+
+        for code in [KeyCode::Char('/'), KeyCode::Char('U'), KeyCode::Char('s'), KeyCode::Enter] {
+            app.on_key(code);
+        }
+        assert_eq!(visible_ranks(&app), vec![3, 2]);
+
+        for code in [KeyCode::Char('u'), KeyCode::Char('a'), KeyCode::Char('p'), KeyCode::Enter] {
+            app.on_key(code);
+        }
+        assert_eq!(visible_ranks(&app), vec![3]);
+
+        app.on_key(KeyCode::Char('c'));
+        for code in [KeyCode::Char('d'), KeyCode::Char('s'), KeyCode::Char('h'), KeyCode::Enter] {
+            app.on_key(code);
+        }
+        assert_eq!(visible_ranks(&app), vec![1]);
+        assert_eq!(app.selected().unwrap().query_id, "id1");
+    }
+}
@@ -0,0 +1,74 @@
+//! JavaScript bindings for running the digest entirely in the browser.
+//!
+//! Build with `wasm-pack build --target web -- --no-default-features --features wasm`.
+//! Log contents are passed in as strings, so no file or network access is needed.
+
+use crate::digest::{Digest, DigestConfig};
+use crate::report::{self, OutputFormat};
+use wasm_bindgen::prelude::*;
+
+/// Returns the normalized fingerprint of a SQL statement.
+#[wasm_bindgen]
+pub fn fingerprint(sql: &str) -> String {
+    crate::fingerprint::fingerprint(sql)
+}
+
+/// Digests slow log text and returns the ranked report items as JSON, DDL
+/// statements included.
+#[wasm_bindgen]
+pub fn digest_json(log: &str, limit: usize) -> Result<String, JsError> {
+    let items = run(log, limit, true)?.finish();
+    serde_json::to_string(&items).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Digests slow log text and returns the HTML report, with DDL statements in
+/// its Schema Changes section.
+#[wasm_bindgen]
+pub fn digest_html(log: &str, limit: usize) -> Result<String, JsError> {
+    let digest = run(log, limit, false)?;
+    let render_options = digest.render_options();
+    let items = digest.finish();
+    report::render_report(&items, &OutputFormat::Html, &render_options).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn run(log: &str, limit: usize, include_ddl: bool) -> Result<Digest, JsError> {
+    let config = DigestConfig::builder().limit(limit).include_ddl(include_ddl).build().map_err(|e| JsError::new(&e.to_string()))?;
+    let mut digest = Digest::new(config);
+    digest.add_reader(log.as_bytes());
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "# Time: 2024-05-01T10:00:00Z
+# User@Host: app[app] @ localhost []
+# Query_time: 2.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10
+SELECT * FROM orders WHERE id = 1;
+# User@Host: app[app] @ localhost []
+# Query_time: 3.0  Lock_time: 0.0 Rows_sent: 1  Rows_examined: 10
+SELECT * FROM orders WHERE id = 2;
+# User@Host: app[app] @ localhost []
+# Query_time: 0.5  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 0
+UPDATE stock SET qty = 0;
+# User@Host: admin[admin] @ localhost []
+# Query_time: 9.0  Lock_time: 0.0 Rows_sent: 0  Rows_examined: 0
+ALTER TABLE orders ADD INDEX (customer_id);
+";
+
+    // The error paths build JavaScript `Error`s, which only exist in the browser
+    #[test]
+    fn test_digest_json() {
+        let Ok(json) = digest_json(LOG, 2) else { panic!("digest_json failed") };
+        let items: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["normalized_query"], fingerprint("ALTER TABLE orders ADD INDEX (customer_id);"));
+        assert_eq!((items[1]["count"].as_u64(), items[1]["total_time"].as_f64()), (Some(2), Some(5.0)));
+        assert_eq!(items[1]["normalized_query"], fingerprint("SELECT * FROM orders WHERE id = 7;"));
+
+        let Ok(html) = digest_html(LOG, 0) else { panic!("digest_html failed") };
+        assert!(html.contains("<html") && html.contains("UPDATE stock"));
+        assert!(html.contains("<h2>Schema Changes</h2>") && html.contains("ALTER TABLE orders"));
+    }
+}
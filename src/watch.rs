@@ -0,0 +1,90 @@
+use anyhow::Context;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+/// Waits for changes to a set of files.
+///
+/// The parent directories are watched so files replaced by editors or log
+/// rotation keep being noticed.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    files: Vec<PathBuf>,
+    debounce: Duration,
+}
+
+impl FileWatcher {
+    /// Starts watching `files`.
+    pub fn new(files: &[PathBuf]) -> anyhow::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).context("Could not start file watcher")?;
+
+        let files: Vec<PathBuf> = files.iter().map(|f| absolute(f)).collect();
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|f| f.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::NonRecursive).with_context(|| format!("Could not watch {:?}", dir))?;
+        }
+
+        Ok(Self { _watcher: watcher, events, files, debounce: Duration::from_millis(200) })
+    }
+
+    /// Blocks until one of the watched files is modified, created or removed.
+    ///
+    /// Events arriving within the debounce interval after the first are coalesced.
+    pub fn wait(&self) -> anyhow::Result<()> {
+        loop {
+            let event = self.events.recv().context("File watcher stopped")??;
+            if self.is_relevant(&event) {
+                break;
+            }
+        }
+        let deadline = Instant::now() + self.debounce;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            if self.events.recv_timeout(left).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_relevant(&self, event: &notify::Event) -> bool {
+        matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_) | EventKind::Remove(_))
+            && event.paths.iter().any(|p| self.files.iter().any(|f| f == p))
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_returns_after_write() {
+        let dir = std::env::temp_dir().join(format!("sqd-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("slow.log");
+        std::fs::write(&path, "a\n").unwrap();
+
+        let watcher = FileWatcher::new(std::slice::from_ref(&path)).unwrap();
+        let writer = {
+            let dir = dir.clone();
+            let path = path.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                std::fs::write(dir.join("other.log"), "x\n").unwrap();
+                std::fs::write(&path, "b\n").unwrap();
+            })
+        };
+
+        watcher.wait().unwrap();
+        writer.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}